@@ -0,0 +1,31 @@
+use ans::asm::*;
+use ans::rv32::RvXlen;
+
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let files: Vec<&String> = args[1..].iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    if files.len() != 2 {
+        println!("usage: asm <input.s> <output> [--raw] [--rv64]");
+        return;
+    }
+    let raw = args.iter().any(|arg| arg == "--raw");
+    let xlen = if args.iter().any(|arg| arg == "--rv64") {
+        RvXlen::Rv64
+    } else {
+        RvXlen::Rv32
+    };
+
+    let src = std::fs::read_to_string(files[0]).unwrap();
+    let prog = match assemble(&src, xlen) {
+        Ok(prog) => prog,
+        Err(e) => {
+            eprintln!("{}:{}", files[0], e);
+            std::process::exit(1);
+        },
+    };
+    let out = if raw { prog.to_image() } else { prog.to_elf() };
+    std::fs::write(files[1], out).unwrap();
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use ans::models::interp::*;
+use ans::isa::RvIsa;
+use ans::rv32::RvXlen;
+
+
+/// Number of instructions after which a test is considered to be hung.
+const MAX_INSTRS: usize = 10_000_000;
+
+/// Returns the name of a test (the ELF file name without extensions).
+fn test_name(elf: &Path) -> String {
+    let name = elf.file_name().unwrap().to_string_lossy();
+    let name = name.strip_suffix(".elf").unwrap_or(&name);
+    name.strip_suffix(".S").unwrap_or(name).to_string()
+}
+
+/// Returns the extension a test belongs to (the name of the directory that
+/// contains it, skipping the 'src' directory of the suite's layout).
+fn test_ext(elf: &Path) -> String {
+    let mut dirs = elf.parent().into_iter()
+        .flat_map(|dir| dir.iter().rev())
+        .map(|name| name.to_string_lossy());
+    match dirs.next() {
+        Some(name) if name == "src" => dirs.next().unwrap_or(name),
+        Some(name) => name,
+        None => ".".into(),
+    }.to_string()
+}
+
+/// Find the reference signature of a test, either next to the ELF file or
+/// in the reference directory.
+fn find_reference(elf: &Path, refs: Option<&Path>) -> Option<PathBuf> {
+    let file = format!("{}.reference_output", test_name(elf));
+    let mut paths = vec![elf.with_file_name(&file)];
+    if let Some(refs) = refs {
+        paths.push(refs.join(&file));
+        paths.push(refs.join(test_ext(elf)).join("references").join(&file));
+    }
+    paths.into_iter().find(|path| path.is_file())
+}
+
+/// Run a test and dump its signature, with 'width' bytes per line (as hex,
+/// most significant digit first).
+fn run_test(elf: &Path, isa: RvIsa, width: usize) -> Result<String, String> {
+    let mut vm = Interpreter::with_isa(isa);
+    vm.set_trace(false);
+    vm.set_user_emulation(false);
+    let info = vm.load_elf(elf.to_str().unwrap(), &[], &[]);
+    let symbol = |name: &str| info.symbols.get(name).copied()
+        .ok_or(format!("no '{}' symbol", name));
+    let (begin, end) = (symbol("begin_signature")?, symbol("end_signature")?);
+    match vm.run_for(MAX_INSTRS) {
+        Some(0) | None => {},
+        Some(status) => return Err(format!("exited with status {}", status)),
+    }
+    let ram = vm.memory();
+    let mut sig = String::new();
+    for addr in (begin..end).step_by(width) {
+        for off in (0..width).rev() {
+            let byte = ram.load8(addr as usize + off);
+            sig += &format!("{:02x}", byte);
+        }
+        sig += "\n";
+    }
+    Ok(sig)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let files: Vec<&String> = args[1..].iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    if files.is_empty() {
+        println!("usage: compliance [--isa=<ISA string>] [--refs=<dir>] \
+                  [--signatures=<dir>] <ELF files...>");
+        return;
+    }
+    let opt = |name: &str| args.iter().find_map(|arg| arg.strip_prefix(name));
+    // The register width is taken from the ELF class
+    let isa = match opt("--isa=") {
+        Some(s) => match RvIsa::parse(s) {
+            Ok(isa) => isa,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        },
+        None => RvIsa::full(RvXlen::Rv32),
+    };
+    let refs = opt("--refs=").map(Path::new);
+    let sigs = opt("--signatures=").map(Path::new);
+
+    // Number of passed and failed tests for each extension
+    let mut results: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for file in files {
+        let elf = Path::new(file);
+        let name = test_name(elf);
+        let reference = find_reference(elf, refs)
+            .map(|path| std::fs::read_to_string(path).unwrap());
+        // The reference determines the width of each line of the signature
+        let width = reference.as_ref()
+            .and_then(|r| r.lines().next())
+            .map(|line| line.trim().len() / 2)
+            .filter(|width| width.is_power_of_two())
+            .unwrap_or(4);
+        let res = run_test(elf, isa, width).and_then(|sig| {
+            if let Some(sigs) = sigs {
+                let path = sigs.join(format!("{}.signature", name));
+                std::fs::write(path, &sig).map_err(|e| e.to_string())?;
+            }
+            let reference = reference.ok_or("no reference signature")?;
+            let expected: Vec<String> = reference.lines()
+                .map(|line| line.trim().to_lowercase())
+                .filter(|line| !line.is_empty())
+                .collect();
+            match sig.lines().zip(&expected)
+                .position(|(line, exp)| line != exp)
+            {
+                Some(idx) => Err(format!("mismatch at line {}", idx + 1)),
+                None if sig.lines().count() != expected.len() => {
+                    Err("signature has the wrong length".into())
+                },
+                None => Ok(()),
+            }
+        });
+        let count = results.entry(test_ext(elf)).or_default();
+        match res {
+            Ok(()) => {
+                println!("PASS {}", name);
+                count.0 += 1;
+            },
+            Err(e) => {
+                println!("FAIL {}: {}", name, e);
+                count.1 += 1;
+            },
+        }
+    }
+
+    println!();
+    for (ext, (pass, fail)) in &results {
+        println!("{:<12} {:>4} passed {:>4} failed", ext, pass, fail);
+    }
+    if results.values().any(|(_, fail)| *fail != 0) {
+        std::process::exit(1);
+    }
+}
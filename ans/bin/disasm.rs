@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use object::{Object, ObjectSection, ObjectSymbol, SectionKind};
+use ans::rv32::*;
+
+
+/// Returns the label for an address (the nearest preceding symbol).
+fn label(symbols: &BTreeMap<u64, String>, addr: u64) -> Option<String> {
+    let (base, name) = symbols.range(..=addr).next_back()?;
+    if *base == addr {
+        Some(format!("<{}>", name))
+    } else {
+        Some(format!("<{}+{:#x}>", name, addr - base))
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let files: Vec<&String> = args[1..].iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    if files.len() != 1 {
+        println!("usage: disasm <ELF file> [--numeric]");
+        return;
+    }
+    // Use ABI register names unless asked otherwise (like objdump)
+    let abi = !args.iter().any(|arg| arg == "--numeric");
+
+    let elf_data = std::fs::read(files[0]).unwrap();
+    let elf = object::File::parse(&*elf_data).unwrap();
+    let xlen = if elf.is_64() { RvXlen::Rv64 } else { RvXlen::Rv32 };
+    let symbols: BTreeMap<u64, String> = elf.symbols()
+        .filter(|sym| !sym.name().unwrap_or("").is_empty())
+        .filter(|sym| sym.section_index().is_some())
+        .map(|sym| (sym.address(), sym.name().unwrap().to_string()))
+        .collect();
+
+    for section in elf.sections() {
+        if section.kind() != SectionKind::Text {
+            continue;
+        }
+        println!("\nDisassembly of section {}:", section.name().unwrap());
+        let data = section.data().unwrap();
+        let base = section.address();
+        let mut off = 0;
+        while off + 2 <= data.len() {
+            let addr = base + off as u64;
+            if let Some(name) = symbols.get(&addr) {
+                println!("\n{:08x} <{}>:", addr, name);
+            }
+            let lo = u16::from_le_bytes([data[off], data[off + 1]]);
+            let len = rv_instr_len(lo) as usize;
+            if off + len > data.len() {
+                break;
+            }
+            let (bits, inst) = match len {
+                2 => (format!("{:04x}    ", lo), 
+                      RvCompressedEncoding(lo).decode(xlen)),
+                _ => {
+                    let word = u32::from_le_bytes([data[off], data[off + 1], 
+                        data[off + 2], data[off + 3]]);
+                    (format!("{:08x}", word), RvEncoding(word).decode(xlen))
+                },
+            };
+            let text = match inst {
+                Ok(inst) if abi => format!("{:#}", inst),
+                Ok(inst) => format!("{}", inst),
+                Err(e) => format!("<{}>", e),
+            };
+            // Resolve the targets of branches and jumps
+            let target = match inst {
+                Ok(RvInstr::Branch(_, _, imm, _)) 
+                | Ok(RvInstr::Jal(_, imm)) => {
+                    let target = addr.wrapping_add(imm as i64 as u64);
+                    let name = label(&symbols, target).unwrap_or_default();
+                    format!("  # {:x} {}", target, name)
+                },
+                _ => String::new(),
+            };
+            println!("{:8x}:\t{}\t{}{}", addr, bits, text, target);
+            off += len;
+        }
+    }
+}
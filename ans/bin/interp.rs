@@ -1,14 +1,56 @@
-
 use ans::models::interp::*;
+use ans::isa::RvIsa;
+use ans::rv32::RvXlen;
 
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("usage: interp <ELF file>");
+    // Options come before the ELF file, and the remaining arguments are 
+    // passed to the program
+    let (opts, prog): (Vec<&String>, &[String]) = 
+        match args[1..].iter().position(|arg| !arg.starts_with("--")) {
+            Some(idx) => (args[1..=idx].iter().collect(), &args[idx + 1..]),
+            None => (args[1..].iter().collect(), &[]),
+        };
+    if prog.is_empty() {
+        println!("usage: interp [--isa=<ISA string>] [--timer-rate=<instrs>] \
+                  [--bare-metal] <ELF file> [args...]");
         return;
     }
-    let mut vm = Interpreter::new();
-    vm.load_elf(&args[1]);
-    vm.run();
+    // The register width is taken from the ELF class
+    let isa = match opts.iter().find_map(|arg| arg.strip_prefix("--isa=")) {
+        Some(s) => match RvIsa::parse(s) {
+            Ok(isa) => isa,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        },
+        None => RvIsa::full(RvXlen::Rv32),
+    };
+    let argv: Vec<&str> = prog.iter().map(|s| s.as_str()).collect();
+    let env: Vec<String> = std::env::vars()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    let envp: Vec<&str> = env.iter().map(|s| s.as_str()).collect();
+    let mut vm = Interpreter::with_isa(isa);
+    // Programs run as a Linux process, unless they are bare-metal programs
+    // (which handle their own traps)
+    vm.set_user_emulation(!opts.iter().any(|arg| *arg == "--bare-metal"));
+    // Number of retired instructions per tick of the CLINT timer
+    if let Some(rate) = opts.iter()
+        .find_map(|arg| arg.strip_prefix("--timer-rate="))
+    {
+        match rate.parse() {
+            Ok(rate) => vm.set_timer_rate(rate),
+            Err(e) => {
+                eprintln!("invalid timer rate: {}", e);
+                std::process::exit(1);
+            },
+        }
+    }
+    vm.load_elf(&prog[0], &argv, &envp);
+    if let Some(status) = vm.run() {
+        std::process::exit(status);
+    }
 }
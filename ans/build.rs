@@ -0,0 +1,425 @@
+//! Generates the instruction tables from the opcode specification.
+//!
+//! Each file in `opcodes/` describes the instructions in one extension (see
+//! `opcodes/README` for the format). The output is included by
+//! `src/opcodes.rs`, and contains:
+//!
+//! - `OPCODES`, a table of [RvOpcodeSpec] for the assembler and disassembler
+//! - `RvEncoding::decode_table()`, which matches an encoding against each
+//!   entry and builds the corresponding `RvInstr`
+//! - `RvInstr::encode_table()`, which matches an `RvInstr` against the
+//!   template of each entry and fills in the operand fields
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// How an operand is represented in an `RvInstr`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Reg,
+    FReg,
+    VReg,
+    Signed,
+    Unsigned,
+    Csr,
+    Bool,
+    Rm,
+}
+
+/// (name, RvOperand variant, bits in the encoding, type)
+const OPERANDS: &[(&str, &str, u32, Kind)] = &[
+    ("rd",     "Rd",     0x0000_0f80, Kind::Reg),
+    ("rs1",    "Rs1",    0x000f_8000, Kind::Reg),
+    ("rs2",    "Rs2",    0x01f0_0000, Kind::Reg),
+    ("rs3",    "Rs3",    0xf800_0000, Kind::Reg),
+    ("frd",    "Frd",    0x0000_0f80, Kind::FReg),
+    ("frs1",   "Frs1",   0x000f_8000, Kind::FReg),
+    ("frs2",   "Frs2",   0x01f0_0000, Kind::FReg),
+    ("frs3",   "Frs3",   0xf800_0000, Kind::FReg),
+    ("imm12",  "Imm12",  0xfff0_0000, Kind::Signed),
+    ("simm12", "Simm12", 0xfe00_0f80, Kind::Signed),
+    ("bimm12", "Bimm12", 0xfe00_0f80, Kind::Signed),
+    ("jimm20", "Jimm20", 0xffff_f000, Kind::Signed),
+    ("imm20",  "Imm20",  0xffff_f000, Kind::Unsigned),
+    ("shamt5", "Shamt5", 0x01f0_0000, Kind::Signed),
+    ("shamt6", "Shamt6", 0x03f0_0000, Kind::Signed),
+    ("csr",    "Csr",    0xfff0_0000, Kind::Csr),
+    ("zimm5",  "Zimm5",  0x000f_8000, Kind::Unsigned),
+    ("rm",     "Rm",     0x0000_7000, Kind::Rm),
+    ("aq",     "Aq",     0x0400_0000, Kind::Bool),
+    ("rl",     "Rl",     0x0200_0000, Kind::Bool),
+    ("fm",     "Fm",     0xf000_0000, Kind::Unsigned),
+    ("pred",   "Pred",   0x0f00_0000, Kind::Unsigned),
+    ("succ",   "Succ",   0x00f0_0000, Kind::Unsigned),
+    ("bs",     "Bs",     0xc000_0000, Kind::Unsigned),
+    ("vd",     "Vd",     0x0000_0f80, Kind::VReg),
+    ("vs1",    "Vs1",    0x000f_8000, Kind::VReg),
+    ("vs2",    "Vs2",    0x01f0_0000, Kind::VReg),
+    ("vs3",    "Vs3",    0x0000_0f80, Kind::VReg),
+    ("vm",     "Vm",     0x0200_0000, Kind::Bool),
+    ("simm5",  "Simm5",  0x000f_8000, Kind::Signed),
+    ("uimm5",  "Uimm5",  0x000f_8000, Kind::Signed),
+    ("zimm10", "Zimm10", 0x3ff0_0000, Kind::Unsigned),
+    ("zimm11", "Zimm11", 0x7ff0_0000, Kind::Unsigned),
+];
+
+/// Operands which may be omitted from the assembly syntax.
+const HIDDEN: &[&str] = &["aq", "rl", "fm"];
+
+/// Registers which are implied by the encoding, but appear in the syntax.
+const LITERALS: &[&str] = &["v0"];
+
+fn operand(name: &str) -> Option<&'static (&'static str, &'static str, u32, Kind)> {
+    OPERANDS.iter().find(|op| op.0 == name)
+}
+
+struct Entry {
+    name: String,
+    syntax: String,
+    /// "None", "Some(RvXlen::Rv32)" or "Some(RvXlen::Rv64)"
+    xlen: &'static str,
+    /// RvExt variant of the extension
+    ext: String,
+    mask: u32,
+    bits: u32,
+    /// Operands, in the order they appear in the syntax (followed by the
+    /// hidden operands)
+    operands: Vec<&'static str>,
+    /// Constructor name and arguments of the RvInstr
+    ctor: String,
+    args: Vec<String>,
+}
+impl Entry {
+    fn is_rv32(&self) -> bool { self.xlen != "Some(RvXlen::Rv64)" }
+    fn is_rv64(&self) -> bool { self.xlen != "Some(RvXlen::Rv32)" }
+
+    /// The template with each operand replaced by some expression.
+    fn template(&self, f: impl Fn(&str) -> String) -> String {
+        if self.args.is_empty() {
+            return format!("RvInstr::{}", self.ctor);
+        }
+        let args: Vec<String> = self.args.iter()
+            .map(|arg| if operand(arg).is_some() { f(arg) } else { arg.clone() })
+            .collect();
+        format!("RvInstr::{}({})", self.ctor, args.join(", "))
+    }
+}
+
+/// Split a list of arguments on the commas which aren't nested in
+/// parentheses.
+fn split_args(s: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut cur = String::new();
+    for c in s.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                res.push(cur.trim().to_string());
+                cur.clear();
+                continue;
+            },
+            _ => {},
+        }
+        cur.push(c);
+    }
+    if !cur.trim().is_empty() {
+        res.push(cur.trim().to_string());
+    }
+    res
+}
+
+fn parse_int(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_line(line: &str, xlen: &'static str, ext: &str)
+    -> Result<Entry, String>
+{
+    let (lhs, rhs) = line.split_once("=>")
+        .ok_or("expected '=> <instruction>'")?;
+    let mut tokens = lhs.split_whitespace().peekable();
+    let name = tokens.next().ok_or("expected a mnemonic")?.to_string();
+
+    // Operands (up to the first fixed field)
+    let mut syntax = Vec::new();
+    while let Some(tok) = tokens.next_if(|tok| !tok.contains('=')) {
+        syntax.push(tok);
+    }
+    let syntax = syntax.join(" ");
+    let mut operands = Vec::new();
+    for ident in syntax.split(|c: char| !c.is_ascii_alphanumeric()) {
+        if ident.is_empty() || LITERALS.contains(&ident) {
+            continue;
+        }
+        let (name, ..) = operand(ident)
+            .ok_or_else(|| format!("unknown operand '{}'", ident))?;
+        operands.push(*name);
+    }
+
+    // Fixed fields
+    let (mut mask, mut bits) = (0u32, 0u32);
+    for tok in tokens {
+        let (range, val) = tok.split_once('=')
+            .ok_or_else(|| format!("invalid field '{}'", tok))?;
+        let (hi, lo) = match range.split_once("..") {
+            Some((hi, lo)) => (hi.parse(), lo.parse()),
+            None => (range.parse(), range.parse()),
+        };
+        let (hi, lo): (u32, u32) = match (hi, lo) {
+            (Ok(hi), Ok(lo)) if hi >= lo && hi < 32 => (hi, lo),
+            _ => return Err(format!("invalid bit range '{}'", range)),
+        };
+        let val = parse_int(val)
+            .ok_or_else(|| format!("invalid value '{}'", val))?;
+        let width = hi - lo + 1;
+        let field = ((1u64 << width) - 1) as u32;
+        if val > field {
+            return Err(format!("{} does not fit in {}", val, range));
+        }
+        if mask & (field << lo) != 0 {
+            return Err(format!("bits {} are fixed twice", range));
+        }
+        mask |= field << lo;
+        bits |= val << lo;
+    }
+
+    // The RvInstr template
+    let rhs = rhs.trim();
+    let (ctor, args) = match rhs.split_once('(') {
+        Some((ctor, args)) => {
+            let args = args.strip_suffix(')')
+                .ok_or("expected ')' after the arguments")?;
+            (ctor.trim().to_string(), split_args(args))
+        },
+        None => (rhs.to_string(), Vec::new()),
+    };
+    for arg in &args {
+        match operand(arg) {
+            Some((name, ..)) if !operands.contains(name) => {
+                if !HIDDEN.contains(name) {
+                    return Err(format!("'{}' is missing from the syntax", arg));
+                }
+                operands.push(name);
+            },
+            _ => {},
+        }
+    }
+    for name in &operands {
+        if !args.iter().any(|arg| arg == name) {
+            return Err(format!("'{}' is not used by the instruction", name));
+        }
+        let (_, _, field, _) = operand(name).unwrap();
+        if mask & field != 0 {
+            return Err(format!("'{}' overlaps a fixed field", name));
+        }
+    }
+    if mask & 0x7f != 0x7f {
+        return Err("the opcode must be fixed".to_string());
+    }
+
+    let ext = ext.to_string();
+    Ok(Entry { name, syntax, xlen, ext, mask, bits, operands, ctor, args })
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=opcodes");
+    let mut files: Vec<_> = std::fs::read_dir("opcodes")
+        .expect("missing opcodes directory")
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+
+    let mut entries = Vec::new();
+    for path in &files {
+        let file = path.file_name().unwrap().to_str().unwrap();
+        let xlen = if file.starts_with("rv_") {
+            "None"
+        } else if file.starts_with("rv32_") {
+            "Some(RvXlen::Rv32)"
+        } else if file.starts_with("rv64_") {
+            "Some(RvXlen::Rv64)"
+        } else {
+            continue;
+        };
+        // The extension is named after the prefix (e.g. 'rv64_zba' is Zba)
+        let name = &file[file.find('_').unwrap() + 1..];
+        let ext = name[..1].to_uppercase() + &name[1..];
+        println!("cargo:rerun-if-changed={}", path.display());
+        let src = std::fs::read_to_string(path).unwrap();
+        for (idx, line) in src.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let entry = parse_line(line, xlen, &ext).unwrap_or_else(|msg| {
+                panic!("{}:{}: {}", path.display(), idx + 1, msg)
+            });
+            entries.push(entry);
+        }
+    }
+
+    // Every encoding must match at most one entry
+    for (i, a) in entries.iter().enumerate() {
+        for b in &entries[i + 1..] {
+            let same_xlen = (a.is_rv32() && b.is_rv32())
+                || (a.is_rv64() && b.is_rv64());
+            if same_xlen && (a.bits ^ b.bits) & a.mask & b.mask == 0 {
+                panic!("the encodings of '{}' and '{}' overlap", a.name, b.name);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    write_opcodes(&mut out, &entries).unwrap();
+    write_decoder(&mut out, &entries).unwrap();
+    write_encoder(&mut out, &entries).unwrap();
+    let path = Path::new(&std::env::var("OUT_DIR").unwrap()).join("opcodes.rs");
+    std::fs::write(path, out).unwrap();
+}
+
+fn write_opcodes(out: &mut String, entries: &[Entry]) -> std::fmt::Result {
+    writeln!(out, "/// All instructions in the opcode specification.")?;
+    writeln!(out, "pub const OPCODES: &[RvOpcodeSpec] = &[")?;
+    for e in entries {
+        let operands: Vec<String> = e.operands.iter()
+            .map(|name| format!("RvOperand::{}", operand(name).unwrap().1))
+            .collect();
+        writeln!(out, "    RvOpcodeSpec {{ name: {:?}, syntax: {:?}, \
+            mask: {:#010x}, bits: {:#010x}, xlen: {}, ext: RvExt::{}, \
+            operands: &[{}] }},",
+            e.name, e.syntax, e.mask, e.bits, e.xlen, e.ext,
+            operands.join(", "))?;
+    }
+    writeln!(out, "];")
+}
+
+fn write_decoder(out: &mut String, entries: &[Entry]) -> std::fmt::Result {
+    let mut by_opcode: BTreeMap<u32, Vec<&Entry>> = BTreeMap::new();
+    for e in entries {
+        by_opcode.entry(e.bits & 0x7f).or_default().push(e);
+    }
+    writeln!(out, "impl RvEncoding {{")?;
+    writeln!(out, "    /// Decode an instruction with the entries in [OPCODES].")?;
+    writeln!(out, "    pub(crate) fn decode_table(&self, isa: &RvIsa) \
+        -> Result<RvInstr, DecodeError> {{")?;
+    writeln!(out, "        let bits = self.0;")?;
+    writeln!(out, "        match bits & 0x7f {{")?;
+    for (opcode, entries) in &by_opcode {
+        let mut arms = Vec::new();
+        for e in entries {
+            let mut cond = Vec::new();
+            if e.xlen != "None" {
+                cond.push(format!("isa.xlen == {}", &e.xlen[5..e.xlen.len() - 1]));
+            }
+            if e.ext != "I" {
+                cond.push(format!("isa.has(RvExt::{})", e.ext));
+            }
+            let ctor = e.template(|name| {
+                let (_, variant, _, kind) = operand(name).unwrap();
+                let get = format!("RvOperand::{}.get(bits)", variant);
+                match kind {
+                    Kind::Reg      => format!("self.reg(RvOperand::{}, isa)?", variant),
+                    Kind::FReg     => format!("RvFReg({} as usize)", get),
+                    Kind::VReg     => format!("RvVReg({} as usize)", get),
+                    Kind::Signed   => format!("{} as i32", get),
+                    Kind::Unsigned => format!("{} as u32", get),
+                    Kind::Csr      => format!("RvCsr({} as u16)", get),
+                    Kind::Bool     => format!("{} != 0", get),
+                    Kind::Rm       => "self.rm()?".to_string(),
+                }
+            });
+            // The opcode has already been matched
+            match e.mask {
+                0x0000_007f => {},
+                0xffff_ffff => cond.insert(0, format!("bits == {:#010x}", e.bits)),
+                mask => cond.insert(0,
+                    format!("bits & {:#010x} == {:#010x}", mask, e.bits)),
+            }
+            let test = if cond.is_empty() {
+                "true".to_string()
+            } else {
+                cond.join(" && ")
+            };
+            arms.push((&e.name, test, ctor));
+        }
+        // A lone test becomes a guard (so the `if` isn't nested in the arm)
+        match &arms[..] {
+            [(name, test, ctor)] if test != "true" => {
+                writeln!(out, "            // {}", name)?;
+                writeln!(out, "            {:#04x} if {} => return Ok({}),",
+                    opcode, test, ctor)?;
+                continue;
+            },
+            _ => writeln!(out, "            {:#04x} => {{", opcode)?,
+        }
+        for (name, test, ctor) in arms {
+            writeln!(out, "                // {}", name)?;
+            if test == "true" {
+                writeln!(out, "                return Ok({});", ctor)?;
+            } else {
+                writeln!(out, "                if {} {{", test)?;
+                writeln!(out, "                    return Ok({});", ctor)?;
+                writeln!(out, "                }}")?;
+            }
+        }
+        writeln!(out, "            }},")?;
+    }
+    writeln!(out, "            _ => {{}},")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "        Err(illegal(bits, isa))")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")
+}
+
+fn write_encoder(out: &mut String, entries: &[Entry]) -> std::fmt::Result {
+    writeln!(out, "impl RvInstr {{")?;
+    writeln!(out, "    /// Encode an instruction with the entries in [OPCODES].")?;
+    writeln!(out, "    pub(crate) fn encode_table(&self, xlen: RvXlen) \
+        -> Result<RvEncoding, EncodeError> {{")?;
+    writeln!(out, "        match *self {{")?;
+    for e in entries {
+        let guard = match e.xlen {
+            "None" => String::new(),
+            xlen => format!(" if xlen == {}", &xlen[5..xlen.len() - 1]),
+        };
+        let mut body = format!("{:#010x}", e.bits);
+        for arg in &e.args {
+            if let Some((_, variant, _, kind)) = operand(arg) {
+                let val = match kind {
+                    Kind::Reg | Kind::FReg | Kind::VReg | Kind::Csr => {
+                        format!("{}.0 as i64", arg)
+                    },
+                    _ => format!("{} as i64", arg),
+                };
+                write!(body, "\n                | RvOperand::{}.set({})?",
+                    variant, val)?;
+            }
+        }
+        writeln!(out, "            // {}", e.name)?;
+        writeln!(out, "            {}{} => Ok(RvEncoding({})),",
+            e.template(|name| name.to_string()), guard, body)?;
+    }
+
+    // Instructions which are only valid on RV64 (unless there is an
+    // equivalent RV32 instruction)
+    let wildcard = |e: &Entry| e.template(|_| "_".to_string());
+    for e in entries.iter().filter(|e| !e.is_rv32()) {
+        let pat = wildcard(e);
+        if entries.iter().any(|other| other.is_rv32() && wildcard(other) == pat) {
+            continue;
+        }
+        writeln!(out, "            // {}", e.name)?;
+        writeln!(out, "            {} => Err(requires_rv64({:#010x})),",
+            pat, e.bits)?;
+    }
+    writeln!(out, "            _ => Err(invalid(self)),")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")
+}
@@ -0,0 +1,1270 @@
+//! A small two-pass assembler for RV32 and RV64 programs.
+//!
+//! The first pass assigns an address to every label, and the second pass
+//! expands pseudo-instructions into [RvInstr]s and encodes them with
+//! [RvInstr::encode]. The result is a [Program], which can be written out
+//! as a flat image or as an ELF executable (for [crate::mem::Memory]).
+//!
+//! The supported directives are `.text`, `.data`, `.section`, `.globl`,
+//! `.equ`/`.set`, `.byte`, `.half`, `.word`, `.dword`, `.zero`, `.align`,
+//! `.ascii` and `.asciz`. The entrypoint is the `_start` label (if there is
+//! one), or otherwise the start of the text section.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+use crate::rv32::*;
+use crate::opcodes::*;
+use crate::csr;
+
+/// Address of the text section.
+pub const TEXT_BASE: u64 = 0x0001_0000;
+
+/// Sections are aligned to pages (both in memory and in ELF files).
+const PAGE_SIZE: u64 = 0x1000;
+
+/// An error in the assembly source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    /// Line number (starting from 1).
+    pub line: usize,
+    /// Description of the problem.
+    pub msg: String,
+}
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Section { Text, Data }
+
+/// An assembled program.
+pub struct Program {
+    pub xlen: RvXlen,
+    /// Address of the entrypoint.
+    pub entry: u64,
+    pub text_base: u64,
+    pub text: Vec<u8>,
+    pub data_base: u64,
+    pub data: Vec<u8>,
+    /// Addresses of all labels.
+    pub symbols: BTreeMap<String, u64>,
+    /// Labels declared with `.globl`.
+    pub globals: BTreeSet<String>,
+}
+impl Program {
+    /// Returns a flat image of the program, starting at `text_base`.
+    pub fn to_image(&self) -> Vec<u8> {
+        let mut res = self.text.clone();
+        res.resize((self.data_base - self.text_base) as usize, 0);
+        res.extend_from_slice(&self.data);
+        res
+    }
+
+    /// Returns an ELF executable with a segment for each (non-empty) section.
+    pub fn to_elf(&self) -> Vec<u8> {
+        const SHT_PROGBITS: u32 = 1;
+        const SHT_SYMTAB: u32 = 2;
+        const SHT_STRTAB: u32 = 3;
+        const SHF_WRITE: u64 = 0x1;
+        const SHF_ALLOC: u64 = 0x2;
+        const SHF_EXECINSTR: u64 = 0x4;
+        const PF_X: u32 = 0x1;
+        const PF_W: u32 = 0x2;
+        const PF_R: u32 = 0x4;
+
+        let is64 = self.xlen == RvXlen::Rv64;
+        let (ehsize, phentsize, shentsize, symsize) = if is64 {
+            (64, 56, 64, 24)
+        } else {
+            (52, 32, 40, 16)
+        };
+
+        // Section contents are page-aligned in the file, so that segments
+        // can be mapped directly.
+        let text_off = PAGE_SIZE;
+        let data_off = text_off + align_up(self.text.len() as u64);
+        let sections = [
+            (".text", self.text_base, &self.text, text_off,
+                SHF_ALLOC | SHF_EXECINSTR, PF_R | PF_X),
+            (".data", self.data_base, &self.data, data_off,
+                SHF_ALLOC | SHF_WRITE, PF_R | PF_W),
+        ];
+
+        // Symbol table (local symbols must come first)
+        let mut strtab = vec![0u8];
+        let mut syms: Vec<(u32, u64, bool, u16)> = Vec::new();
+        let mut by_binding: Vec<_> = self.symbols.iter()
+            .map(|(name, addr)| (self.globals.contains(name), name, *addr))
+            .collect();
+        by_binding.sort_by_key(|s| s.0);
+        for (global, name, addr) in by_binding {
+            let shndx = if addr >= self.data_base { 2 } else { 1 };
+            syms.push((strtab.len() as u32, addr, global, shndx));
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+        let first_global = 1 + syms.iter().filter(|s| !s.2).count() as u32;
+
+        let mut shstrtab = vec![0u8];
+        let mut shname = |name: &str| {
+            let off = shstrtab.len() as u32;
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+            off
+        };
+        let names: Vec<u32> = [".text", ".data", ".symtab", ".strtab",
+            ".shstrtab"].iter().map(|n| shname(n)).collect();
+
+        let mut w = ElfWriter { buf: Vec::new(), is64 };
+
+        // File header
+        let segments: Vec<_> = sections.iter()
+            .filter(|s| !s.2.is_empty())
+            .collect();
+        let symtab_off = data_off + align_up(self.data.len() as u64);
+        let symtab_len = symsize * (syms.len() as u64 + 1);
+        let strtab_off = symtab_off + symtab_len;
+        let shstrtab_off = strtab_off + strtab.len() as u64;
+        let shoff = (shstrtab_off + shstrtab.len() as u64 + 7) & !7;
+        w.buf.extend_from_slice(b"\x7fELF");
+        w.u8(if is64 { 2 } else { 1 });
+        w.u8(1); // Little-endian
+        w.u8(1); // Version
+        w.buf.resize(16, 0);
+        w.u16(2); // ET_EXEC
+        w.u16(243); // EM_RISCV
+        w.u32(1);
+        w.addr(self.entry);
+        w.addr(ehsize);
+        w.addr(shoff);
+        w.u32(0);
+        w.u16(ehsize as u16);
+        w.u16(phentsize);
+        w.u16(segments.len() as u16);
+        w.u16(shentsize);
+        w.u16(6);
+        w.u16(5);
+
+        // Program headers
+        for &&(_, addr, data, off, _, flags) in &segments {
+            w.u32(1); // PT_LOAD
+            if is64 {
+                w.u32(flags);
+            }
+            w.addr(off);
+            w.addr(addr);
+            w.addr(addr);
+            w.addr(data.len() as u64);
+            w.addr(data.len() as u64);
+            if !is64 {
+                w.u32(flags);
+            }
+            w.addr(PAGE_SIZE);
+        }
+
+        // Section contents
+        for &(_, _, data, off, _, _) in &sections {
+            w.buf.resize(off as usize, 0);
+            w.buf.extend_from_slice(data);
+        }
+        w.buf.resize(symtab_off as usize, 0);
+        w.buf.resize(w.buf.len() + symsize as usize, 0);
+        for &(name, addr, global, shndx) in &syms {
+            let info = if global { 0x10 } else { 0x00 };
+            w.u32(name);
+            if is64 {
+                w.u8(info);
+                w.u8(0);
+                w.u16(shndx);
+                w.u64(addr);
+                w.u64(0);
+            } else {
+                w.u32(addr as u32);
+                w.u32(0);
+                w.u8(info);
+                w.u8(0);
+                w.u16(shndx);
+            }
+        }
+        w.buf.extend_from_slice(&strtab);
+        w.buf.extend_from_slice(&shstrtab);
+        w.buf.resize(shoff as usize, 0);
+
+        // Section headers
+        w.buf.resize(w.buf.len() + shentsize as usize, 0);
+        for (idx, &(_, addr, data, off, flags, _)) in sections.iter().enumerate() {
+            w.section(names[idx], SHT_PROGBITS, flags, addr, off,
+                data.len() as u64, 0, 0, 4, 0);
+        }
+        w.section(names[2], SHT_SYMTAB, 0, 0, symtab_off, symtab_len,
+            4, first_global, 8, symsize);
+        w.section(names[3], SHT_STRTAB, 0, 0, strtab_off,
+            strtab.len() as u64, 0, 0, 1, 0);
+        w.section(names[4], SHT_STRTAB, 0, 0, shstrtab_off,
+            shstrtab.len() as u64, 0, 0, 1, 0);
+        w.buf
+    }
+}
+
+/// Helper for writing little-endian ELF structures.
+struct ElfWriter {
+    buf: Vec<u8>,
+    is64: bool,
+}
+impl ElfWriter {
+    fn u8(&mut self, x: u8) { self.buf.push(x) }
+    fn u16(&mut self, x: u16) { self.buf.extend_from_slice(&x.to_le_bytes()) }
+    fn u32(&mut self, x: u32) { self.buf.extend_from_slice(&x.to_le_bytes()) }
+    fn u64(&mut self, x: u64) { self.buf.extend_from_slice(&x.to_le_bytes()) }
+
+    /// Write an address-sized field.
+    fn addr(&mut self, x: u64) {
+        if self.is64 { self.u64(x) } else { self.u32(x as u32) }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn section(&mut self, name: u32, kind: u32, flags: u64, addr: u64,
+               off: u64, size: u64, link: u32, info: u32, align: u64,
+               entsize: u64)
+    {
+        self.u32(name);
+        self.u32(kind);
+        self.addr(flags);
+        self.addr(addr);
+        self.addr(off);
+        self.addr(size);
+        self.u32(link);
+        self.u32(info);
+        self.addr(align);
+        self.addr(entsize);
+    }
+}
+
+/// A statement which emits bytes, recorded by the first pass.
+struct Stmt<'a> {
+    line: usize,
+    section: Section,
+    /// Offset of the statement in its section
+    offset: u64,
+    /// Number of bytes emitted by the statement
+    size: u64,
+    kind: StmtKind<'a>,
+}
+enum StmtKind<'a> {
+    /// Instruction or pseudo-instruction (mnemonic, operands)
+    Inst(&'a str, Vec<&'a str>),
+    /// List of integers with some size (in bytes)
+    Data(u64, Vec<&'a str>),
+    /// Literal bytes
+    Bytes(Vec<u8>),
+}
+
+/// Assemble a program.
+pub fn assemble(src: &str, xlen: RvXlen) -> Result<Program, AsmError> {
+    let mut asm = Assembler { xlen, symbols: BTreeMap::new(), resolve: false };
+    let mut stmts = Vec::new();
+    let mut labels: Vec<(usize, &str, Section, u64)> = Vec::new();
+    let mut globals = BTreeSet::new();
+    let mut section = Section::Text;
+    let mut size = [0u64; 2];
+
+    // First pass: determine the size of each statement
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |msg: String| AsmError { line: line_no, msg };
+        let mut line = strip_comment(line).trim();
+
+        // Labels
+        while let Some(pos) = line.find(':') {
+            let name = line[..pos].trim();
+            if !is_ident(name) {
+                break;
+            }
+            let dup = labels.iter().any(|l| l.1 == name)
+                || asm.symbols.contains_key(name);
+            if dup {
+                return Err(err(format!("redefinition of '{}'", name)));
+            }
+            labels.push((line_no, name, section, size[section as usize]));
+            line = line[pos + 1..].trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (head, rest) = match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], line[pos..].trim()),
+            None => (line, ""),
+        };
+        let offset = size[section as usize];
+        let kind = match head {
+            ".text" => { section = Section::Text; continue; },
+            ".data" => { section = Section::Data; continue; },
+            ".section" => {
+                section = if rest.starts_with(".text") {
+                    Section::Text
+                } else {
+                    Section::Data
+                };
+                continue;
+            },
+            ".globl" | ".global" => {
+                for name in split_args(rest) {
+                    globals.insert(name.to_string());
+                }
+                continue;
+            },
+            ".equ" | ".set" => {
+                let args = split_args(rest);
+                if args.len() != 2 || !is_ident(args[0]) {
+                    return Err(err(format!("expected '{} name, value'", head)));
+                }
+                let val = asm.eval(args[1]).map_err(err)?;
+                asm.symbols.insert(args[0].to_string(), val);
+                continue;
+            },
+            ".byte" => StmtKind::Data(1, split_args(rest)),
+            ".half" | ".short" => StmtKind::Data(2, split_args(rest)),
+            ".word" | ".long" => StmtKind::Data(4, split_args(rest)),
+            ".dword" | ".quad" => StmtKind::Data(8, split_args(rest)),
+            ".zero" | ".space" => {
+                let len = asm.eval(rest).map_err(err)?;
+                let len = usize::try_from(len)
+                    .map_err(|_| err(format!("invalid size {}", len)))?;
+                StmtKind::Bytes(vec![0; len])
+            },
+            ".align" | ".p2align" | ".balign" => {
+                let align = asm.eval(rest).map_err(err)?;
+                let align = if head == ".balign" { align } else { 1 << align };
+                if !(1..=PAGE_SIZE as i64).contains(&align)
+                    || align & (align - 1) != 0
+                {
+                    return Err(err(format!("invalid alignment {}", align)));
+                }
+                let pad = (align as u64 - offset % align as u64)
+                    % align as u64;
+                // Pad the text section with nops
+                if section == Section::Text && pad & 3 == 0 {
+                    let nop = RvInstr::OpImm(RvReg(0), RvReg(0), 0,
+                        RvALUOp::Add);
+                    let nop = nop.encode(xlen).unwrap().0.to_le_bytes();
+                    StmtKind::Bytes(nop.repeat(pad as usize / 4))
+                } else {
+                    StmtKind::Bytes(vec![0; pad as usize])
+                }
+            },
+            ".ascii" | ".asciz" | ".string" => {
+                let mut bytes = parse_string(rest).map_err(err)?;
+                if head != ".ascii" {
+                    bytes.push(0);
+                }
+                StmtKind::Bytes(bytes)
+            },
+            _ if head.starts_with('.') => {
+                return Err(err(format!("unknown directive '{}'", head)));
+            },
+            _ => StmtKind::Inst(head, split_args(rest)),
+        };
+        let len = match &kind {
+            StmtKind::Inst(m, args) => {
+                4 * asm.expand(m, args, 0).map_err(err)?.len() as u64
+            },
+            StmtKind::Data(width, args) => width * args.len() as u64,
+            StmtKind::Bytes(bytes) => bytes.len() as u64,
+        };
+        stmts.push(Stmt { line: line_no, section, offset, size: len, kind });
+        size[section as usize] += len;
+    }
+
+    // Assign addresses to labels
+    let text_base = TEXT_BASE;
+    let data_base = text_base + align_up(size[Section::Text as usize]);
+    let base = |section| match section {
+        Section::Text => text_base,
+        Section::Data => data_base,
+    };
+    let mut symbols = BTreeMap::new();
+    for &(line, name, section, offset) in &labels {
+        if asm.symbols.contains_key(name) {
+            return Err(AsmError {
+                line, msg: format!("redefinition of '{}'", name)
+            });
+        }
+        symbols.insert(name.to_string(), base(section) + offset);
+        asm.symbols.insert(name.to_string(), (base(section) + offset) as i64);
+    }
+
+    // Second pass: encode each statement
+    asm.resolve = true;
+    let mut out = [Vec::new(), Vec::new()];
+    for stmt in &stmts {
+        let err = |msg: String| AsmError { line: stmt.line, msg };
+        let pc = base(stmt.section) + stmt.offset;
+        let buf = &mut out[stmt.section as usize];
+        match &stmt.kind {
+            StmtKind::Inst(m, args) => {
+                let insts = asm.expand(m, args, pc).map_err(err)?;
+                if 4 * insts.len() as u64 != stmt.size {
+                    return Err(err(format!(
+                        "the expansion of '{}' depends on a label", m)));
+                }
+                for inst in insts {
+                    let enc = inst.encode(xlen)
+                        .map_err(|e| err(format!("{}: {}", m, e)))?;
+                    buf.extend_from_slice(&enc.0.to_le_bytes());
+                }
+            },
+            StmtKind::Data(width, args) => {
+                for arg in args {
+                    let val = asm.eval(arg).map_err(err)? as u64;
+                    buf.extend_from_slice(&val.to_le_bytes()[..*width as usize]);
+                }
+            },
+            StmtKind::Bytes(bytes) => buf.extend_from_slice(bytes),
+        }
+    }
+
+    let [text, data] = out;
+    let entry = symbols.get("_start").copied().unwrap_or(text_base);
+    Ok(Program {
+        xlen, entry, text_base, text, data_base, data, symbols, globals
+    })
+}
+
+/// Round up to a multiple of the page size.
+fn align_up(x: u64) -> u64 {
+    (x + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_str = false;
+    let mut prev = ' ';
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' if prev != '\\' => in_str = !in_str,
+            '#' if !in_str => return &line[..idx],
+            _ => {},
+        }
+        prev = c;
+    }
+    line
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {},
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+/// Split a list of operands on commas.
+fn split_args(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        return Vec::new();
+    }
+    s.split(',').map(|arg| arg.trim()).collect()
+}
+
+/// Parse a string literal (with C-style escapes).
+fn parse_string(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s.strip_prefix('"').and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string literal, found '{}'", s))?;
+    let mut res = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                _ => return Err(format!("invalid escape in {}", s)),
+            }
+        } else {
+            c
+        };
+        let mut buf = [0; 4];
+        res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Ok(res)
+}
+
+const ROUNDING_MODES: &[(&str, RvRoundingMode)] = &[
+    ("rne", RvRoundingMode::Rne), ("rtz", RvRoundingMode::Rtz),
+    ("rdn", RvRoundingMode::Rdn), ("rup", RvRoundingMode::Rup),
+    ("rmm", RvRoundingMode::Rmm), ("dyn", RvRoundingMode::Dyn),
+];
+
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+    table.iter().find(|(n, _)| *n == name).map(|(_, x)| *x)
+}
+
+/// Sign-extend the low 12 bits of a value.
+fn sext12(x: i64) -> i64 {
+    ((x as i32) << 20 >> 20) as i64
+}
+
+/// Split a PC-relative offset into an AUIPC immediate and a 12-bit offset.
+fn pcrel(off: i64) -> Result<(u32, i32), String> {
+    if off < i32::MIN as i64 || off > i32::MAX as i64 {
+        return Err(format!("offset {:#x} is out of range", off));
+    }
+    let lo = sext12(off);
+    let hi = ((off - lo) >> 12) as u32 & 0xf_ffff;
+    Ok((hi, lo as i32))
+}
+
+struct Assembler {
+    xlen: RvXlen,
+    /// Values of labels and constants
+    symbols: BTreeMap<String, i64>,
+    /// Report undefined symbols (otherwise, they are assumed to be zero)
+    resolve: bool,
+}
+impl Assembler {
+    /// Evaluate an expression.
+    ///
+    /// Expressions are sums of integers and symbols, or `%hi(...)` and
+    /// `%lo(...)` of an expression.
+    fn eval(&self, s: &str) -> Result<i64, String> {
+        let s = s.trim();
+        let func = |name: &str| s.strip_prefix(name)
+            .and_then(|s| s.trim().strip_prefix('('))
+            .and_then(|s| s.strip_suffix(')'));
+        if let Some(arg) = func("%hi") {
+            let val = self.eval(arg)?;
+            return Ok(((val - sext12(val)) >> 12) & 0xf_ffff);
+        }
+        if let Some(arg) = func("%lo") {
+            return Ok(sext12(self.eval(arg)?));
+        }
+
+        let mut res: i64 = 0;
+        let mut neg = false;
+        let mut start = 0;
+        let bytes = s.as_bytes();
+        for idx in 0..=bytes.len() {
+            if idx < bytes.len() && !matches!(bytes[idx], b'+' | b'-') {
+                continue;
+            }
+            let term = s[start..idx].trim();
+            if term.is_empty() {
+                if idx == bytes.len() || idx != start {
+                    return Err(format!("invalid expression '{}'", s));
+                }
+            } else {
+                let val = self.term(term)?;
+                res = if neg { res.wrapping_sub(val) } else { res.wrapping_add(val) };
+                neg = false;
+            }
+            if idx < bytes.len() && bytes[idx] == b'-' {
+                neg = !neg;
+            }
+            start = idx + 1;
+        }
+        Ok(res)
+    }
+
+    /// Evaluate a number or symbol.
+    fn term(&self, s: &str) -> Result<i64, String> {
+        let invalid = || format!("invalid number '{}'", s);
+        let (digits, radix) = if let Some(hex) = s.strip_prefix("0x") {
+            (hex, 16)
+        } else if let Some(bin) = s.strip_prefix("0b") {
+            (bin, 2)
+        } else {
+            (s, 10)
+        };
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            let digits = digits.replace('_', "");
+            return u64::from_str_radix(&digits, radix)
+                .map(|x| x as i64)
+                .map_err(|_| invalid());
+        }
+        if let Some(c) = s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+            let bytes = parse_string(&format!("\"{}\"", c))?;
+            return match bytes[..] {
+                [b] => Ok(b as i64),
+                _ => Err(invalid()),
+            };
+        }
+        if !is_ident(s) {
+            return Err(format!("invalid expression '{}'", s));
+        }
+        match self.symbols.get(s) {
+            Some(val) => Ok(*val),
+            None if self.resolve => Err(format!("undefined symbol '{}'", s)),
+            None => Ok(0),
+        }
+    }
+
+    /// Evaluate an immediate operand.
+    fn imm(&self, s: &str) -> Result<i32, String> {
+        let val = self.eval(s)?;
+        i32::try_from(val).map_err(|_| format!("immediate {} is out of range", s))
+    }
+
+    /// Evaluate a branch or jump target, relative to the program counter.
+    fn target(&self, s: &str, pc: u64) -> Result<i32, String> {
+        let off = self.eval(s)?.wrapping_sub(pc as i64);
+        i32::try_from(off).map_err(|_| format!("target {} is out of range", s))
+    }
+
+    fn reg(&self, s: &str) -> Result<RvReg, String> {
+        let idx = match s {
+            "fp" => Some(8),
+            _ => ABI_NAMES.iter().position(|n| *n == s)
+                .or_else(|| s.strip_prefix('x')?.parse().ok()),
+        };
+        match idx {
+            Some(idx) if idx < 32 => Ok(RvReg(idx)),
+            _ => Err(format!("invalid register '{}'", s)),
+        }
+    }
+
+    fn freg(&self, s: &str) -> Result<RvFReg, String> {
+        let idx = FP_ABI_NAMES.iter().position(|n| *n == s)
+            .or_else(|| s.strip_prefix('f')?.parse().ok());
+        match idx {
+            Some(idx) if idx < 32 => Ok(RvFReg(idx)),
+            _ => Err(format!("invalid floating-point register '{}'", s)),
+        }
+    }
+
+    fn vreg(&self, s: &str) -> Result<RvVReg, String> {
+        match s.strip_prefix('v').and_then(|n| n.parse().ok()) {
+            Some(idx) if idx < 32 => Ok(RvVReg(idx)),
+            _ => Err(format!("invalid vector register '{}'", s)),
+        }
+    }
+
+    fn csr(&self, s: &str) -> Result<RvCsr, String> {
+        if let Some(csr) = lookup(csr::NAMES, s) {
+            return Ok(RvCsr(csr));
+        }
+        match self.eval(s)? {
+            csr @ 0..=0xfff => Ok(RvCsr(csr as u16)),
+            _ => Err(format!("invalid CSR '{}'", s)),
+        }
+    }
+
+    /// Parse a memory operand of the form `offset(base)`.
+    fn mem(&self, s: &str) -> Result<(i32, RvReg), String> {
+        let pos = s.rfind('(')
+            .filter(|_| s.ends_with(')'))
+            .ok_or_else(|| format!("expected 'offset(base)', found '{}'", s))?;
+        let off = s[..pos].trim();
+        let off = if off.is_empty() { 0 } else { self.imm(off)? };
+        Ok((off, self.reg(s[pos + 1..s.len() - 1].trim())?))
+    }
+
+    /// Parse the address operand of an atomic instruction.
+    fn amo_addr(&self, s: &str) -> Result<RvReg, String> {
+        match self.mem(s)? {
+            (0, base) => Ok(base),
+            _ => Err(format!("expected '(base)', found '{}'", s)),
+        }
+    }
+
+    /// Parse the predecessor or successor set of a fence.
+    fn fence_set(&self, s: &str) -> Result<u32, String> {
+        let mut res = 0;
+        if s == "0" {
+            return Ok(0);
+        }
+        for c in s.chars() {
+            let bit = match c {
+                'i' => 0b1000,
+                'o' => 0b0100,
+                'r' => 0b0010,
+                'w' => 0b0001,
+                _ => return Err(format!("invalid fence set '{}'", s)),
+            };
+            res |= bit;
+        }
+        Ok(res)
+    }
+
+    /// Parse the vtype of a `vsetvli` (for example, `e32, m1, ta, ma`).
+    /// The LMUL defaults to 1, and the policies default to undisturbed.
+    fn vtype(&self, s: &str) -> Result<u32, String> {
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(self.eval(s)? as u32);
+        }
+        let mut res = 0;
+        for setting in s.split(',').map(str::trim) {
+            res |= match setting {
+                "e8"  => 0b000 << 3,
+                "e16" => 0b001 << 3,
+                "e32" => 0b010 << 3,
+                "e64" => 0b011 << 3,
+                "m1"  => 0b000,
+                "m2"  => 0b001,
+                "m4"  => 0b010,
+                "m8"  => 0b011,
+                "mf8" => 0b101,
+                "mf4" => 0b110,
+                "mf2" => 0b111,
+                "tu" | "mu" => 0,
+                "ta" => 1 << 6,
+                "ma" => 1 << 7,
+                _ => return Err(format!("invalid vtype '{}'", s)),
+            };
+        }
+        Ok(res)
+    }
+
+    /// Expand `li` into a sequence of instructions.
+    fn li(&self, rd: RvReg, val: i64) -> Vec<RvInstr> {
+        let fits_i32 = val as i32 as i64 == val;
+        if self.xlen == RvXlen::Rv32 || fits_i32 {
+            let val = val as i32;
+            let lo = sext12(val as i64) as i32;
+            let hi = (val.wrapping_sub(lo) as u32) >> 12;
+            let mut res = Vec::new();
+            if hi != 0 {
+                res.push(RvInstr::Lui(rd, hi));
+            }
+            if lo != 0 || hi == 0 {
+                let src = if hi != 0 { rd } else { RvReg(0) };
+                res.push(match self.xlen {
+                    RvXlen::Rv64 if hi != 0 => {
+                        RvInstr::OpImmW(rd, src, lo, RvALUOp::Add)
+                    },
+                    _ => RvInstr::OpImm(rd, src, lo, RvALUOp::Add),
+                });
+            }
+            return res;
+        }
+        // Materialize the upper bits, then shift them into place
+        let lo = sext12(val);
+        let hi = val.wrapping_sub(lo) >> 12;
+        let shamt = hi.trailing_zeros();
+        let mut res = self.li(rd, hi >> shamt);
+        res.push(RvInstr::OpImm(rd, rd, 12 + shamt as i32, RvALUOp::Sll));
+        if lo != 0 {
+            res.push(RvInstr::OpImm(rd, rd, lo as i32, RvALUOp::Add));
+        }
+        res
+    }
+
+    /// Expand an instruction or pseudo-instruction at some address.
+    fn expand(&self, m: &str, args: &[&str], pc: u64)
+        -> Result<Vec<RvInstr>, String>
+    {
+        let nargs = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!("'{}' expects {} operands", m, n))
+            }
+        };
+        let zero = RvReg(0);
+        let ra = RvReg(1);
+
+        // Pseudo-instructions
+        let res = match m {
+            "nop" => {
+                nargs(0)?;
+                RvInstr::OpImm(zero, zero, 0, RvALUOp::Add)
+            },
+            "li" => {
+                nargs(2)?;
+                let val = self.eval(args[1])?;
+                if self.xlen == RvXlen::Rv32
+                    && (val < i32::MIN as i64 || val > u32::MAX as i64)
+                {
+                    return Err(format!("immediate {} is out of range",
+                        args[1]));
+                }
+                return Ok(self.li(self.reg(args[0])?, val));
+            },
+            "la" => {
+                nargs(2)?;
+                let rd = self.reg(args[0])?;
+                let (hi, lo) = pcrel(self.eval(args[1])? - pc as i64)?;
+                return Ok(vec![
+                    RvInstr::Auipc(rd, hi),
+                    RvInstr::OpImm(rd, rd, lo, RvALUOp::Add),
+                ]);
+            },
+            "call" | "tail" => {
+                nargs(1)?;
+                let (hi, lo) = pcrel(self.eval(args[0])? - pc as i64)?;
+                let (rd, tmp) = match m {
+                    "call" => (ra, ra),
+                    _      => (zero, RvReg(6)),
+                };
+                return Ok(vec![
+                    RvInstr::Auipc(tmp, hi),
+                    RvInstr::Jalr(rd, tmp, lo),
+                ]);
+            },
+            "mv" => {
+                nargs(2)?;
+                RvInstr::OpImm(self.reg(args[0])?, self.reg(args[1])?, 0,
+                    RvALUOp::Add)
+            },
+            "not" => {
+                nargs(2)?;
+                RvInstr::OpImm(self.reg(args[0])?, self.reg(args[1])?, -1,
+                    RvALUOp::Xor)
+            },
+            "neg" | "negw" => {
+                nargs(2)?;
+                let (rd, rs) = (self.reg(args[0])?, self.reg(args[1])?);
+                if m == "neg" {
+                    RvInstr::Op(rd, zero, rs, RvALUOp::Sub)
+                } else {
+                    RvInstr::OpW(rd, zero, rs, RvALUOp::Sub)
+                }
+            },
+            "sext.w" => {
+                nargs(2)?;
+                RvInstr::OpImmW(self.reg(args[0])?, self.reg(args[1])?, 0,
+                    RvALUOp::Add)
+            },
+            "zext.b" => {
+                nargs(2)?;
+                RvInstr::OpImm(self.reg(args[0])?, self.reg(args[1])?, 0xff,
+                    RvALUOp::And)
+            },
+            "seqz" | "snez" | "sltz" | "sgtz" => {
+                nargs(2)?;
+                let (rd, rs) = (self.reg(args[0])?, self.reg(args[1])?);
+                match m {
+                    "seqz" => RvInstr::OpImm(rd, rs, 1, RvALUOp::Sltu),
+                    "snez" => RvInstr::Op(rd, zero, rs, RvALUOp::Sltu),
+                    "sltz" => RvInstr::Op(rd, rs, zero, RvALUOp::Slt),
+                    _      => RvInstr::Op(rd, zero, rs, RvALUOp::Slt),
+                }
+            },
+            "beqz" | "bnez" | "blez" | "bgez" | "bltz" | "bgtz" => {
+                nargs(2)?;
+                let rs = self.reg(args[0])?;
+                let off = self.target(args[1], pc)?;
+                let (rs1, rs2, op) = match m {
+                    "beqz" => (rs, zero, RvBranchOp::Eq),
+                    "bnez" => (rs, zero, RvBranchOp::Ne),
+                    "blez" => (zero, rs, RvBranchOp::Ge),
+                    "bgez" => (rs, zero, RvBranchOp::Ge),
+                    "bltz" => (rs, zero, RvBranchOp::Lt),
+                    _      => (zero, rs, RvBranchOp::Lt),
+                };
+                RvInstr::Branch(rs1, rs2, off, op)
+            },
+            "bgt" | "ble" | "bgtu" | "bleu" => {
+                nargs(3)?;
+                let (rs1, rs2) = (self.reg(args[0])?, self.reg(args[1])?);
+                let off = self.target(args[2], pc)?;
+                let op = match m {
+                    "bgt"  => RvBranchOp::Lt,
+                    "ble"  => RvBranchOp::Ge,
+                    "bgtu" => RvBranchOp::Ltu,
+                    _      => RvBranchOp::Geu,
+                };
+                RvInstr::Branch(rs2, rs1, off, op)
+            },
+            "j" => {
+                nargs(1)?;
+                RvInstr::Jal(zero, self.target(args[0], pc)?)
+            },
+            "jr" => {
+                nargs(1)?;
+                RvInstr::Jalr(zero, self.reg(args[0])?, 0)
+            },
+            "ret" => {
+                nargs(0)?;
+                RvInstr::Jalr(zero, ra, 0)
+            },
+            "csrr" => {
+                nargs(2)?;
+                RvInstr::Csr(self.reg(args[0])?, zero, self.csr(args[1])?,
+                    RvCsrOp::Rs)
+            },
+            "csrw" | "csrs" | "csrc" => {
+                nargs(2)?;
+                let op = match m {
+                    "csrw" => RvCsrOp::Rw,
+                    "csrs" => RvCsrOp::Rs,
+                    _      => RvCsrOp::Rc,
+                };
+                RvInstr::Csr(zero, self.reg(args[1])?, self.csr(args[0])?, op)
+            },
+            "csrwi" | "csrsi" | "csrci" => {
+                nargs(2)?;
+                let op = match m {
+                    "csrwi" => RvCsrOp::Rw,
+                    "csrsi" => RvCsrOp::Rs,
+                    _       => RvCsrOp::Rc,
+                };
+                RvInstr::CsrImm(zero, self.eval(args[1])? as u32,
+                    self.csr(args[0])?, op)
+            },
+            // The operands default to x0 (every address and address space)
+            "sfence.vma" if args.len() < 2 => {
+                let rs1 = match args.first() {
+                    Some(arg) => self.reg(arg)?,
+                    None => zero,
+                };
+                RvInstr::SfenceVma(rs1, zero)
+            },
+            "jal" if args.len() == 1 => {
+                RvInstr::Jal(ra, self.target(args[0], pc)?)
+            },
+            "jalr" if args.len() == 1 => {
+                RvInstr::Jalr(ra, self.reg(args[0])?, 0)
+            },
+            "jalr" if args.len() == 3 => {
+                RvInstr::Jalr(self.reg(args[0])?, self.reg(args[1])?,
+                    self.imm(args[2])?)
+            },
+            "fence" if args.is_empty() => {
+                RvInstr::Fence(0b0000, 0b1111, 0b1111)
+            },
+            "fence.tso" => {
+                nargs(0)?;
+                RvInstr::Fence(0b1000, 0b0011, 0b0011)
+            },
+            "fmv.s" | "fneg.s" | "fabs.s" | "fmv.d" | "fneg.d" | "fabs.d" => {
+                nargs(2)?;
+                let (rd, rs) = (self.freg(args[0])?, self.freg(args[1])?);
+                let fmt = if m.ends_with(".s") { RvFpFmt::S } else { RvFpFmt::D };
+                let op = match &m[..m.len() - 2] {
+                    "fmv"  => RvFpOp::Sgnj,
+                    "fneg" => RvFpOp::Sgnjn,
+                    _      => RvFpOp::Sgnjx,
+                };
+                RvInstr::FOp(rd, rs, rs, op, fmt)
+            },
+            _ => return self.expand_base(m, args, pc),
+        };
+        Ok(vec![res])
+    }
+
+    /// Assemble an instruction from [OPCODES].
+    fn expand_base(&self, m: &str, args: &[&str], pc: u64)
+        -> Result<Vec<RvInstr>, String>
+    {
+        let unknown = || format!("unknown instruction '{}'", m);
+        // Atomics may have an ordering suffix (for example, `amoadd.w.aqrl`)
+        let (spec, aq, rl) = match RvOpcodeSpec::lookup(m, self.xlen) {
+            Some(spec) => (spec, false, false),
+            None => {
+                let (name, ord) = m.rsplit_once('.').ok_or_else(unknown)?;
+                let (aq, rl) = match ord {
+                    "aq"   => (true, false),
+                    "rl"   => (false, true),
+                    "aqrl" => (true, true),
+                    _ => return Err(unknown()),
+                };
+                let spec = RvOpcodeSpec::lookup(name, self.xlen)
+                    .filter(|spec| spec.operands.contains(&RvOperand::Aq))
+                    .ok_or_else(unknown)?;
+                (spec, aq, rl)
+            },
+        };
+
+        let parts = spec.parts();
+        // A vtype is a list of settings (for example, `e32, m1, ta, ma`)
+        let vtype;
+        let mut args = args.to_vec();
+        if args.len() > parts.len() && matches!(parts.last(), 
+            Some(RvSyntax::Operand(RvOperand::Zimm10 | RvOperand::Zimm11)))
+        {
+            vtype = args[parts.len() - 1..].join(", ");
+            args.truncate(parts.len() - 1);
+            args.push(&vtype);
+        }
+        // The rounding mode and the vector mask may be omitted
+        let omitted = match parts.last() {
+            Some(RvSyntax::Operand(op)) if args.len() + 1 == parts.len() => {
+                match op {
+                    RvOperand::Rm => Some((*op, RvRoundingMode::Dyn as i64)),
+                    RvOperand::Vm => Some((*op, 1)),
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+        if args.len() != parts.len() && omitted.is_none() {
+            return Err(format!("'{}' expects {} operands", m, parts.len()));
+        }
+        let operand = |op: RvOperand, s: &str| -> Result<i64, String> {
+            Ok(match op {
+                RvOperand::Rd | RvOperand::Rs1 
+                | RvOperand::Rs2 | RvOperand::Rs3 => self.reg(s)?.0 as i64,
+                RvOperand::Frd | RvOperand::Frs1 
+                | RvOperand::Frs2 | RvOperand::Frs3 => self.freg(s)?.0 as i64,
+                RvOperand::Vd | RvOperand::Vs1 
+                | RvOperand::Vs2 | RvOperand::Vs3 => self.vreg(s)?.0 as i64,
+                RvOperand::Vm if s == "v0.t" => 0,
+                RvOperand::Vm => return Err(format!("invalid mask '{}'", s)),
+                RvOperand::Zimm10 | RvOperand::Zimm11 => self.vtype(s)? as i64,
+                RvOperand::Bimm12 | RvOperand::Jimm20 => {
+                    self.target(s, pc)? as i64
+                },
+                RvOperand::Csr => self.csr(s)?.0 as i64,
+                RvOperand::Rm => lookup(ROUNDING_MODES, s)
+                    .ok_or_else(|| format!("invalid rounding mode '{}'", s))?
+                    as i64,
+                RvOperand::Pred | RvOperand::Succ => self.fence_set(s)? as i64,
+                _ => self.eval(s)?,
+            })
+        };
+        let set = |op: RvOperand, val: i64| {
+            op.set(val).map_err(|e| format!("{}: {}", m, e))
+        };
+
+        let mut bits = spec.bits;
+        for (part, arg) in parts.iter().zip(args.iter().copied()) {
+            match *part {
+                RvSyntax::Operand(op) => bits |= set(op, operand(op, arg)?)?,
+                RvSyntax::Mem(Some(off), base) => {
+                    let (imm, reg) = self.mem(arg)?;
+                    bits |= set(off, imm as i64)? | set(base, reg.0 as i64)?;
+                },
+                RvSyntax::Mem(None, base) => {
+                    bits |= set(base, self.amo_addr(arg)?.0 as i64)?;
+                },
+                RvSyntax::Mask if arg == "v0" => {},
+                RvSyntax::Mask => return Err(format!("{}: expected v0", m)),
+            }
+        }
+        if let Some((op, val)) = omitted {
+            bits |= set(op, val)?;
+        }
+        if aq || rl {
+            bits |= set(RvOperand::Aq, aq as i64)? | set(RvOperand::Rl, rl as i64)?;
+        }
+        let inst = RvEncoding(bits).decode(self.xlen)
+            .map_err(|e| format!("{}: {}", m, e))?;
+        Ok(vec![inst])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::asm::*;
+
+    fn words(prog: &Program) -> Vec<u32> {
+        prog.text.chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn assemble_program() {
+        let src = r#"
+            .globl _start
+            .text
+        _start:
+            li    a0, 0x12345678
+            la    a1, msg       # pc-relative
+            lbu   a2, 0(a1)
+            call  f
+            li    t0, 93
+            ecall
+        f:  addi  a0, a0, 1
+            ret
+            .data
+        msg: .asciz "hi\n"
+        val: .word 1, 2, msg
+        "#;
+        let prog = assemble(src, RvXlen::Rv32).unwrap();
+        assert_eq!(words(&prog), [
+            0x1234_5537, // lui   a0, 0x12345
+            0x6785_0513, // addi  a0, a0, 0x678
+            0x0000_1597, // auipc a1, 0x1
+            0xff85_8593, // addi  a1, a1, -8
+            0x0005_c603, // lbu   a2, 0(a1)
+            0x0000_0097, // auipc ra, 0
+            0x0100_80e7, // jalr  ra, 16(ra)
+            0x05d0_0293, // li    t0, 93
+            0x0000_0073, // ecall
+            0x0015_0513, // addi  a0, a0, 1
+            0x0000_8067, // ret
+        ]);
+        assert_eq!(prog.entry, TEXT_BASE);
+        assert_eq!(prog.data_base, TEXT_BASE + 0x1000);
+        assert_eq!(prog.symbols["f"], TEXT_BASE + 0x24);
+        assert_eq!(prog.symbols["val"], prog.data_base + 4);
+        assert_eq!(prog.data, b"hi\n\0\x01\0\0\0\x02\0\0\0\0\x10\x01\0");
+        assert!(prog.globals.contains("_start"));
+
+        let image = prog.to_image();
+        assert_eq!(image.len(), 0x1010);
+        assert_eq!(&image[0x1000..], &prog.data[..]);
+    }
+
+    #[test]
+    fn assemble_forward_branches() {
+        let src = "
+            .equ COUNT, 3
+            beqz a0, done
+            li   a1, COUNT
+        loop:
+            addi a1, a1, -1
+            bnez a1, loop
+            j    done
+            .align 4
+        done:
+            ebreak
+        ";
+        let prog = assemble(src, RvXlen::Rv32).unwrap();
+        let text = words(&prog);
+        assert_eq!(text[0], 0x0205_0063); // beq  a0, zero, +32
+        assert_eq!(text[3], 0xfe05_9ee3); // bne  a1, zero, -4
+        assert_eq!(text[4], 0x0100_006f); // jal  zero, +16
+        assert_eq!(text[5], 0x0000_0013); // nop (padding)
+        assert_eq!(prog.symbols["done"], TEXT_BASE + 0x20);
+    }
+
+    #[test]
+    fn assemble_decodes() {
+        // Every instruction should survive a trip through the decoder
+        let src = "
+            add   t0, t1, t2
+            sraiw a0, a1, 3
+            sh2add.uw s0, s1, s2
+            cpopw a0, a1
+            zext.h a0, a1
+            sd    ra, -8(sp)
+            amoswap.d.aqrl a0, a1, (a2)
+            lr.w  a0, (a1)
+            sc.w.rl a2, a3, (a1)
+            csrrw a0, mscratch, a1
+            csrsi mstatus, 8
+            fence rw, w
+            fence.tso
+            fadd.d fa0, fa1, fa2, rtz
+            fmadd.s ft0, ft1, ft2, ft3
+            fcvt.l.d a0, fa0
+            fcvt.s.wu fa0, a0
+            fcvt.d.s fa0, fa1
+            fmv.x.w a0, fa0
+            fneg.d fa0, fa1
+            flw   fa0, 4(a0)
+            fsd   fa0, 8(sp)
+            feq.s a0, fa0, fa1
+        ";
+        let prog = assemble(src, RvXlen::Rv64).unwrap();
+        let insts: Vec<RvInstr> = words(&prog).iter()
+            .map(|w| RvEncoding(*w).decode(RvXlen::Rv64).unwrap())
+            .collect();
+        assert_eq!(insts.len(), 23);
+        assert_eq!(insts[1],
+            RvInstr::OpImmW(RvReg(10), RvReg(11), 3, RvALUOp::Sra));
+        assert_eq!(insts[6], RvInstr::Amo(RvReg(10), RvReg(12), RvReg(11),
+            RvAmoOp::Swap, RvWidth::Double, true, true));
+        assert_eq!(insts[9], RvInstr::Csr(RvReg(10), RvReg(11),
+            RvCsr(csr::MSCRATCH), RvCsrOp::Rw));
+        assert_eq!(insts[11], RvInstr::Fence(0b0000, 0b0011, 0b0001));
+        assert_eq!(insts[13], RvInstr::FArith(RvFReg(10), RvFReg(11),
+            RvFReg(12), RvFpArithOp::Add, RvFpFmt::D, RvRoundingMode::Rtz));
+        assert_eq!(insts[19],
+            RvInstr::FOp(RvFReg(10), RvFReg(11), RvFReg(11), RvFpOp::Sgnjn,
+                RvFpFmt::D));
+    }
+
+    #[test]
+    fn assemble_vector() {
+        let src = "
+            vsetvli t0, a0, e32, m2, ta, ma
+            vsetvli t0, a0, e8
+            vle32.v v2, (a1)
+            vadd.vv v4, v2, v6, v0.t
+            vmerge.vim v4, v2, -3, v0
+            vmacc.vx v4, a2, v2
+        ";
+        let prog = assemble(src, RvXlen::Rv32).unwrap();
+        let insts: Vec<RvInstr> = words(&prog).iter()
+            .map(|w| RvEncoding(*w).decode(RvXlen::Rv32).unwrap())
+            .collect();
+        assert_eq!(insts, [
+            RvInstr::VSetVli(RvReg(5), RvReg(10), 0xd1),
+            RvInstr::VSetVli(RvReg(5), RvReg(10), 0x00),
+            RvInstr::VLoad(RvVReg(2), RvReg(11), RvWidth::Word, true),
+            RvInstr::VOpVV(RvVReg(4), RvVReg(2), RvVReg(6), RvVOp::Add, 
+                false),
+            RvInstr::VOpVI(RvVReg(4), RvVReg(2), -3, RvVOp::Merge, false),
+            RvInstr::VOpVX(RvVReg(4), RvVReg(2), RvReg(12), RvVOp::Macc, 
+                true),
+        ]);
+
+        let err = |src: &str| assemble(src, RvXlen::Rv32).err().unwrap().msg;
+        assert_eq!(err("vadd.vv v1, v2, v32"), "invalid vector register 'v32'");
+        assert_eq!(err("vadd.vv v1, v2, v3, v1.t"), "invalid mask 'v1.t'");
+        assert_eq!(err("vsetvli t0, a0, e32, m3"), 
+            "invalid vtype 'e32, m3'");
+        assert_eq!(err("vmerge.vvm v1, v2, v3, v1"), 
+            "vmerge.vvm: expected v0");
+    }
+
+    #[test]
+    fn assemble_disassembly() {
+        // Displayed instructions must assemble back to the same instruction
+        let mut x: u32 = 1;
+        for _ in 0..100_000 {
+            // xorshift32
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            for xlen in [RvXlen::Rv32, RvXlen::Rv64] {
+                let inst = match RvEncoding(x | 0b11).decode(xlen) {
+                    // Targets are displayed as offsets rather than addresses
+                    Ok(RvInstr::Branch(..)) | Ok(RvInstr::Jal(..)) => continue,
+                    // The fm field is only displayed for fence.tso
+                    Ok(RvInstr::Fence(fm, ..)) if fm != 0 => continue,
+                    Ok(inst) => inst,
+                    Err(_) => continue,
+                };
+                for src in [format!("{}", inst), format!("{:#}", inst)] {
+                    let prog = assemble(&src, xlen)
+                        .unwrap_or_else(|e| panic!("{}: {}", src, e));
+                    let res = RvEncoding(words(&prog)[0]).decode(xlen);
+                    assert_eq!(res, Ok(inst), "{}", src);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn assemble_li() {
+        let li = |val: i64, xlen| {
+            let asm = Assembler { xlen, symbols: BTreeMap::new(), 
+                resolve: true };
+            asm.li(RvReg(10), val).len()
+        };
+        assert_eq!(li(0, RvXlen::Rv32), 1);
+        assert_eq!(li(-2048, RvXlen::Rv32), 1);
+        assert_eq!(li(0x1000, RvXlen::Rv32), 1);
+        assert_eq!(li(0x1001, RvXlen::Rv32), 2);
+        assert_eq!(li(0xffff_ffff, RvXlen::Rv32), 1);
+        assert_eq!(li(0x8000_0000, RvXlen::Rv64), 2);
+        assert_eq!(li(0x1234_5678_9abc_def0, RvXlen::Rv64), 8);
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let err = |src: &str| assemble(src, RvXlen::Rv32).err().unwrap();
+        assert_eq!(err("nop\nfoo a0, a1"), AsmError {
+            line: 2, msg: "unknown instruction 'foo'".to_string()
+        });
+        assert_eq!(err("addi a0, a0, 4096").line, 1);
+        assert_eq!(err("\n\nj missing").msg, "undefined symbol 'missing'");
+        assert_eq!(err("x:\nx:").line, 2);
+        assert_eq!(err("add a0, a1, a32").msg, "invalid register 'a32'");
+        assert_eq!(err("beq a0, a1, 3").line, 1);
+        assert_eq!(err("li a0, 0x100000000").line, 1);
+        assert_eq!(err("li a0, later\nlater:").msg,
+            "the expansion of 'li' depends on a label");
+        assert_eq!(err(".bogus").msg, "unknown directive '.bogus'");
+    }
+}
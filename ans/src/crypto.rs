@@ -0,0 +1,494 @@
+//! Primitives used by the scalar cryptography extensions.
+//!
+//! Each function implements one instruction from Zknd/Zkne (AES), Zknh
+//! (SHA-256 and SHA-512), Zksed (SM4) or Zksh (SM3). The AES and SM4
+//! instructions operate on one byte of 'rs2' (selected by 'bs'), which is
+//! transformed and rotated back into position before being XORed with 'rs1'.
+
+/// Multiply in GF(2^8), modulo the polynomial x^8 + 'poly'.
+const fn gf_mul(mut a: u8, mut b: u8, poly: u8) -> u8 {
+    let mut res = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            res ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= poly;
+        }
+        b >>= 1;
+    }
+    res
+}
+
+/// Multiplicative inverse in GF(2^8) (where the inverse of zero is zero).
+const fn gf_inv(x: u8, poly: u8) -> u8 {
+    // x^254 = x^-1
+    let (mut res, mut base, mut exp) = (1, x, 254);
+    while exp != 0 {
+        if exp & 1 != 0 {
+            res = gf_mul(res, base, poly);
+        }
+        base = gf_mul(base, base, poly);
+        exp >>= 1;
+    }
+    res
+}
+
+/// Multiply by a circulant matrix over GF(2) (where bit 'i' of the result
+/// is the parity of 'x' masked by 'row' rotated left by 'i').
+const fn circulant(x: u8, row: u8) -> u8 {
+    let mut res = 0;
+    let mut i = 0;
+    while i < 8 {
+        res |= ((row.rotate_left(i) & x).count_ones() as u8 & 1) << i;
+        i += 1;
+    }
+    res
+}
+
+/// The AES S-box and its inverse.
+const fn aes_sboxes() -> ([u8; 256], [u8; 256]) {
+    let (mut fwd, mut inv) = ([0; 256], [0; 256]);
+    let mut x = 0;
+    while x < 256 {
+        let b = gf_inv(x as u8, 0x1b);
+        let s = b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3)
+            ^ b.rotate_left(4) ^ 0x63;
+        fwd[x] = s;
+        inv[s as usize] = x as u8;
+        x += 1;
+    }
+    (fwd, inv)
+}
+
+/// The SM4 S-box (an affine transformation of the inverse in GF(2^8),
+/// modulo x^8 + x^7 + x^6 + x^5 + x^4 + x^2 + 1).
+const fn sm4_sbox() -> [u8; 256] {
+    let mut res = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        let y = gf_inv(circulant(x as u8, 0xa7) ^ 0xd3, 0xf5);
+        res[x] = circulant(y, 0xa7) ^ 0xd3;
+        x += 1;
+    }
+    res
+}
+
+const AES_SBOXES: ([u8; 256], [u8; 256]) = aes_sboxes();
+const AES_SBOX: [u8; 256] = AES_SBOXES.0;
+const AES_INV_SBOX: [u8; 256] = AES_SBOXES.1;
+const SM4_SBOX: [u8; 256] = sm4_sbox();
+
+/// Select byte 'bs' of a value.
+fn byte(x: u32, bs: u32) -> u8 {
+    (x >> (bs * 8)) as u8
+}
+
+/// The column produced by MixColumns from a single byte (in row 0).
+fn aes_mix_fwd(x: u8) -> u32 {
+    u32::from_le_bytes([
+        gf_mul(x, 2, 0x1b), x, x, gf_mul(x, 3, 0x1b),
+    ])
+}
+
+/// The column produced by InvMixColumns from a single byte (in row 0).
+fn aes_mix_inv(x: u8) -> u32 {
+    u32::from_le_bytes([
+        gf_mul(x, 0xe, 0x1b), gf_mul(x, 0x9, 0x1b),
+        gf_mul(x, 0xd, 0x1b), gf_mul(x, 0xb, 0x1b),
+    ])
+}
+
+/// AES final round encryption (SubBytes on one byte).
+pub fn aes32esi(rs1: u32, rs2: u32, bs: u32) -> u32 {
+    let x = AES_SBOX[byte(rs2, bs) as usize] as u32;
+    rs1 ^ x.rotate_left(bs * 8)
+}
+
+/// AES middle round encryption (SubBytes and MixColumns on one byte).
+pub fn aes32esmi(rs1: u32, rs2: u32, bs: u32) -> u32 {
+    let x = aes_mix_fwd(AES_SBOX[byte(rs2, bs) as usize]);
+    rs1 ^ x.rotate_left(bs * 8)
+}
+
+/// AES final round decryption (InvSubBytes on one byte).
+pub fn aes32dsi(rs1: u32, rs2: u32, bs: u32) -> u32 {
+    let x = AES_INV_SBOX[byte(rs2, bs) as usize] as u32;
+    rs1 ^ x.rotate_left(bs * 8)
+}
+
+/// AES middle round decryption (InvSubBytes and InvMixColumns on one byte).
+pub fn aes32dsmi(rs1: u32, rs2: u32, bs: u32) -> u32 {
+    let x = aes_mix_inv(AES_INV_SBOX[byte(rs2, bs) as usize]);
+    rs1 ^ x.rotate_left(bs * 8)
+}
+
+pub fn sha256sig0(x: u32) -> u32 {
+    x.rotate_right(7) ^ x.rotate_right(18) ^ (x >> 3)
+}
+pub fn sha256sig1(x: u32) -> u32 {
+    x.rotate_right(17) ^ x.rotate_right(19) ^ (x >> 10)
+}
+pub fn sha256sum0(x: u32) -> u32 {
+    x.rotate_right(2) ^ x.rotate_right(13) ^ x.rotate_right(22)
+}
+pub fn sha256sum1(x: u32) -> u32 {
+    x.rotate_right(6) ^ x.rotate_right(11) ^ x.rotate_right(25)
+}
+
+pub fn sha512sig0(x: u64) -> u64 {
+    x.rotate_right(1) ^ x.rotate_right(8) ^ (x >> 7)
+}
+pub fn sha512sig1(x: u64) -> u64 {
+    x.rotate_right(19) ^ x.rotate_right(61) ^ (x >> 6)
+}
+pub fn sha512sum0(x: u64) -> u64 {
+    x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)
+}
+pub fn sha512sum1(x: u64) -> u64 {
+    x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)
+}
+
+// On RV32, each SHA-512 function is computed in two halves. The 'h' and
+// 'l' forms take the half being computed in 'rs1' (and the other half in
+// 'rs2'), while the 'r' forms compute either half depending on the order
+// of the operands.
+
+pub fn sha512sig0h(rs1: u32, rs2: u32) -> u32 {
+    (rs1 >> 1) ^ (rs1 >> 7) ^ (rs1 >> 8) ^ (rs2 << 31) ^ (rs2 << 24)
+}
+pub fn sha512sig0l(rs1: u32, rs2: u32) -> u32 {
+    (rs1 >> 1) ^ (rs1 >> 7) ^ (rs1 >> 8)
+        ^ (rs2 << 31) ^ (rs2 << 25) ^ (rs2 << 24)
+}
+pub fn sha512sig1h(rs1: u32, rs2: u32) -> u32 {
+    (rs1 << 3) ^ (rs1 >> 6) ^ (rs1 >> 19) ^ (rs2 >> 29) ^ (rs2 << 13)
+}
+pub fn sha512sig1l(rs1: u32, rs2: u32) -> u32 {
+    (rs1 << 3) ^ (rs1 >> 6) ^ (rs1 >> 19)
+        ^ (rs2 >> 29) ^ (rs2 << 26) ^ (rs2 << 13)
+}
+pub fn sha512sum0r(rs1: u32, rs2: u32) -> u32 {
+    (rs1 << 25) ^ (rs1 << 30) ^ (rs1 >> 28)
+        ^ (rs2 >> 7) ^ (rs2 >> 2) ^ (rs2 << 4)
+}
+pub fn sha512sum1r(rs1: u32, rs2: u32) -> u32 {
+    (rs1 << 23) ^ (rs1 >> 14) ^ (rs1 >> 18)
+        ^ (rs2 >> 9) ^ (rs2 << 18) ^ (rs2 << 14)
+}
+
+/// SM4 encryption/decryption round (the non-linear and linear
+/// transformations, on one byte).
+pub fn sm4ed(rs1: u32, rs2: u32, bs: u32) -> u32 {
+    let x = SM4_SBOX[byte(rs2, bs) as usize] as u32;
+    let y = x ^ (x << 8) ^ (x << 2) ^ (x << 18)
+        ^ ((x & 0x3f) << 26) ^ ((x & 0xc0) << 10);
+    rs1 ^ y.rotate_left(bs * 8)
+}
+
+/// SM4 key schedule round (on one byte).
+pub fn sm4ks(rs1: u32, rs2: u32, bs: u32) -> u32 {
+    let x = SM4_SBOX[byte(rs2, bs) as usize] as u32;
+    let y = x ^ ((x & 0x07) << 29) ^ ((x & 0xfe) << 7)
+        ^ ((x & 0x01) << 23) ^ ((x & 0xf8) << 13);
+    rs1 ^ y.rotate_left(bs * 8)
+}
+
+/// SM3 permutation P0.
+pub fn sm3p0(x: u32) -> u32 {
+    x ^ x.rotate_left(9) ^ x.rotate_left(17)
+}
+
+/// SM3 permutation P1.
+pub fn sm3p1(x: u32) -> u32 {
+    x ^ x.rotate_left(15) ^ x.rotate_left(23)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Little-endian words (as loaded by a RISC-V core).
+    fn le_words(s: &str) -> [u32; 4] {
+        let b = hex(s);
+        let word = |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+        [word(0), word(4), word(8), word(12)]
+    }
+
+    /// Pad a message to a multiple of the block size (with the length of
+    /// the message in bits in the last 'block / 8' bytes).
+    fn pad(msg: &[u8], block: usize) -> Vec<u8> {
+        let mut res = msg.to_vec();
+        res.push(0x80);
+        while (res.len() + block / 8) & (block - 1) != 0 {
+            res.push(0);
+        }
+        let len = (msg.len() as u128 * 8).to_be_bytes();
+        res.extend_from_slice(&len[16 - block / 8..]);
+        res
+    }
+
+    /// One AES round on a state of four columns, where byte 'bs' of output
+    /// column 'j' comes from column 'j + shift * bs'.
+    fn aes_round(f: fn(u32, u32, u32) -> u32, s: [u32; 4], k: &[u32],
+                 shift: usize) -> [u32; 4] {
+        let col = |j: usize| (0..4).fold(k[j], |acc, bs| {
+            f(acc, s[(j + shift * bs) % 4], bs as u32)
+        });
+        [col(0), col(1), col(2), col(3)]
+    }
+
+    /// AES-128 with the RV32 instructions (FIPS-197, appendix C.1).
+    #[test]
+    fn aes128_kat() {
+        let key = le_words("000102030405060708090a0b0c0d0e0f");
+        let pt  = le_words("00112233445566778899aabbccddeeff");
+        let ct  = le_words("69c4e0d86a7b0430d8cdb78070b4c55a");
+
+        // Key expansion (SubWord is done with the final round instruction)
+        let sub_word = |w| (0..4).fold(0, |acc, bs| aes32esi(acc, w, bs));
+        let rcon = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+        let mut rk = key.to_vec();
+        for i in 4..44 {
+            let mut t = rk[i - 1];
+            if i & 3 == 0 {
+                t = sub_word(t.rotate_right(8)) ^ rcon[i / 4 - 1];
+            }
+            rk.push(rk[i - 4] ^ t);
+        }
+        assert_eq!(&rk[40..], &le_words("13111d7fe3944a17f307a78b4d2b30c5"));
+
+        let mut s = [0, 1, 2, 3].map(|j| pt[j] ^ rk[j]);
+        for round in 1..10 {
+            s = aes_round(aes32esmi, s, &rk[round * 4..], 1);
+        }
+        s = aes_round(aes32esi, s, &rk[40..], 1);
+        assert_eq!(s, ct);
+
+        // The equivalent inverse cipher uses InvMixColumns of the round keys
+        let inv_mix = |w| (0..4).fold(0, |acc, bs| aes32dsmi(acc, sub_word(w), bs));
+        let mut s = [0, 1, 2, 3].map(|j| ct[j] ^ rk[40 + j]);
+        for round in (1..10).rev() {
+            let k: Vec<u32> = rk[round * 4..round * 4 + 4].iter()
+                .map(|w| inv_mix(*w))
+                .collect();
+            s = aes_round(aes32dsmi, s, &k, 3);
+        }
+        s = aes_round(aes32dsi, s, &rk[..4], 3);
+        assert_eq!(s, pt);
+    }
+
+    #[test]
+    fn sha256_kat() {
+        const K: [u32; 64] = [
+            0x428a_2f98, 0x7137_4491, 0xb5c0_fbcf, 0xe9b5_dba5, 0x3956_c25b, 0x59f1_11f1,
+            0x923f_82a4, 0xab1c_5ed5, 0xd807_aa98, 0x1283_5b01, 0x2431_85be, 0x550c_7dc3,
+            0x72be_5d74, 0x80de_b1fe, 0x9bdc_06a7, 0xc19b_f174, 0xe49b_69c1, 0xefbe_4786,
+            0x0fc1_9dc6, 0x240c_a1cc, 0x2de9_2c6f, 0x4a74_84aa, 0x5cb0_a9dc, 0x76f9_88da,
+            0x983e_5152, 0xa831_c66d, 0xb003_27c8, 0xbf59_7fc7, 0xc6e0_0bf3, 0xd5a7_9147,
+            0x06ca_6351, 0x1429_2967, 0x27b7_0a85, 0x2e1b_2138, 0x4d2c_6dfc, 0x5338_0d13,
+            0x650a_7354, 0x766a_0abb, 0x81c2_c92e, 0x9272_2c85, 0xa2bf_e8a1, 0xa81a_664b,
+            0xc24b_8b70, 0xc76c_51a3, 0xd192_e819, 0xd699_0624, 0xf40e_3585, 0x106a_a070,
+            0x19a4_c116, 0x1e37_6c08, 0x2748_774c, 0x34b0_bcb5, 0x391c_0cb3, 0x4ed8_aa4a,
+            0x5b9c_ca4f, 0x682e_6ff3, 0x748f_82ee, 0x78a5_636f, 0x84c8_7814, 0x8cc7_0208,
+            0x90be_fffa, 0xa450_6ceb, 0xbef9_a3f7, 0xc671_78f2,
+        ];
+        let mut h: [u32; 8] = [
+            0x6a09_e667, 0xbb67_ae85, 0x3c6e_f372, 0xa54f_f53a,
+            0x510e_527f, 0x9b05_688c, 0x1f83_d9ab, 0x5be0_cd19,
+        ];
+        for block in pad(b"abc", 64).chunks(64) {
+            let mut w: Vec<u32> = block.chunks(4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            for t in 16..64 {
+                w.push(sha256sig1(w[t - 2]).wrapping_add(w[t - 7])
+                    .wrapping_add(sha256sig0(w[t - 15])).wrapping_add(w[t - 16]));
+            }
+            let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+            for t in 0..64 {
+                let ch = (e & f) ^ (!e & g);
+                let maj = (a & b) ^ (a & c) ^ (b & c);
+                let t1 = hh.wrapping_add(sha256sum1(e)).wrapping_add(ch)
+                    .wrapping_add(K[t]).wrapping_add(w[t]);
+                let t2 = sha256sum0(a).wrapping_add(maj);
+                hh = g; g = f; f = e; e = d.wrapping_add(t1);
+                d = c; c = b; b = a; a = t1.wrapping_add(t2);
+            }
+            for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+                *x = x.wrapping_add(y);
+            }
+        }
+        assert_eq!(h, [
+            0xba78_16bf, 0x8f01_cfea, 0x4141_40de, 0x5dae_2223,
+            0xb003_61a3, 0x9617_7a9c, 0xb410_ff61, 0xf200_15ad,
+        ]);
+    }
+
+    /// The SHA-512 functions from the RV32 instructions, which compute
+    /// each half of the result separately.
+    fn sha512_rv32(x: u64, f: fn(u32, u32) -> u32, g: fn(u32, u32) -> u32)
+        -> u64
+    {
+        let (hi, lo) = ((x >> 32) as u32, x as u32);
+        ((f(hi, lo) as u64) << 32) | g(lo, hi) as u64
+    }
+
+    #[test]
+    fn sha512_kat() {
+        const K: [u64; 80] = [
+            0x428a_2f98_d728_ae22, 0x7137_4491_23ef_65cd, 0xb5c0_fbcf_ec4d_3b2f,
+            0xe9b5_dba5_8189_dbbc, 0x3956_c25b_f348_b538, 0x59f1_11f1_b605_d019,
+            0x923f_82a4_af19_4f9b, 0xab1c_5ed5_da6d_8118, 0xd807_aa98_a303_0242,
+            0x1283_5b01_4570_6fbe, 0x2431_85be_4ee4_b28c, 0x550c_7dc3_d5ff_b4e2,
+            0x72be_5d74_f27b_896f, 0x80de_b1fe_3b16_96b1, 0x9bdc_06a7_25c7_1235,
+            0xc19b_f174_cf69_2694, 0xe49b_69c1_9ef1_4ad2, 0xefbe_4786_384f_25e3,
+            0x0fc1_9dc6_8b8c_d5b5, 0x240c_a1cc_77ac_9c65, 0x2de9_2c6f_592b_0275,
+            0x4a74_84aa_6ea6_e483, 0x5cb0_a9dc_bd41_fbd4, 0x76f9_88da_8311_53b5,
+            0x983e_5152_ee66_dfab, 0xa831_c66d_2db4_3210, 0xb003_27c8_98fb_213f,
+            0xbf59_7fc7_beef_0ee4, 0xc6e0_0bf3_3da8_8fc2, 0xd5a7_9147_930a_a725,
+            0x06ca_6351_e003_826f, 0x1429_2967_0a0e_6e70, 0x27b7_0a85_46d2_2ffc,
+            0x2e1b_2138_5c26_c926, 0x4d2c_6dfc_5ac4_2aed, 0x5338_0d13_9d95_b3df,
+            0x650a_7354_8baf_63de, 0x766a_0abb_3c77_b2a8, 0x81c2_c92e_47ed_aee6,
+            0x9272_2c85_1482_353b, 0xa2bf_e8a1_4cf1_0364, 0xa81a_664b_bc42_3001,
+            0xc24b_8b70_d0f8_9791, 0xc76c_51a3_0654_be30, 0xd192_e819_d6ef_5218,
+            0xd699_0624_5565_a910, 0xf40e_3585_5771_202a, 0x106a_a070_32bb_d1b8,
+            0x19a4_c116_b8d2_d0c8, 0x1e37_6c08_5141_ab53, 0x2748_774c_df8e_eb99,
+            0x34b0_bcb5_e19b_48a8, 0x391c_0cb3_c5c9_5a63, 0x4ed8_aa4a_e341_8acb,
+            0x5b9c_ca4f_7763_e373, 0x682e_6ff3_d6b2_b8a3, 0x748f_82ee_5def_b2fc,
+            0x78a5_636f_4317_2f60, 0x84c8_7814_a1f0_ab72, 0x8cc7_0208_1a64_39ec,
+            0x90be_fffa_2363_1e28, 0xa450_6ceb_de82_bde9, 0xbef9_a3f7_b2c6_7915,
+            0xc671_78f2_e372_532b, 0xca27_3ece_ea26_619c, 0xd186_b8c7_21c0_c207,
+            0xeada_7dd6_cde0_eb1e, 0xf57d_4f7f_ee6e_d178, 0x06f0_67aa_7217_6fba,
+            0x0a63_7dc5_a2c8_98a6, 0x113f_9804_bef9_0dae, 0x1b71_0b35_131c_471b,
+            0x28db_77f5_2304_7d84, 0x32ca_ab7b_40c7_2493, 0x3c9e_be0a_15c9_bebc,
+            0x431d_67c4_9c10_0d4c, 0x4cc5_d4be_cb3e_42b6, 0x597f_299c_fc65_7e2a,
+            0x5fcb_6fab_3ad6_faec, 0x6c44_198c_4a47_5817,
+        ];
+        let sig0 = |x| sha512_rv32(x, sha512sig0h, sha512sig0l);
+        let sig1 = |x| sha512_rv32(x, sha512sig1h, sha512sig1l);
+        let sum0 = |x| sha512_rv32(x, sha512sum0r, sha512sum0r);
+        let sum1 = |x| sha512_rv32(x, sha512sum1r, sha512sum1r);
+
+        let mut h: [u64; 8] = [
+            0x6a09_e667_f3bc_c908, 0xbb67_ae85_84ca_a73b,
+            0x3c6e_f372_fe94_f82b, 0xa54f_f53a_5f1d_36f1,
+            0x510e_527f_ade6_82d1, 0x9b05_688c_2b3e_6c1f,
+            0x1f83_d9ab_fb41_bd6b, 0x5be0_cd19_137e_2179,
+        ];
+        for block in pad(b"abc", 128).chunks(128) {
+            let mut w: Vec<u64> = block.chunks(8)
+                .map(|b| b.iter().fold(0, |acc, x| (acc << 8) | *x as u64))
+                .collect();
+            for t in 16..80 {
+                // The RV32 and RV64 instructions agree
+                assert_eq!(sig0(w[t - 15]), sha512sig0(w[t - 15]));
+                assert_eq!(sig1(w[t - 2]), sha512sig1(w[t - 2]));
+                w.push(sha512sig1(w[t - 2]).wrapping_add(w[t - 7])
+                    .wrapping_add(sha512sig0(w[t - 15])).wrapping_add(w[t - 16]));
+            }
+            let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+            for t in 0..80 {
+                assert_eq!(sum0(a), sha512sum0(a));
+                assert_eq!(sum1(e), sha512sum1(e));
+                let ch = (e & f) ^ (!e & g);
+                let maj = (a & b) ^ (a & c) ^ (b & c);
+                let t1 = hh.wrapping_add(sha512sum1(e)).wrapping_add(ch)
+                    .wrapping_add(K[t]).wrapping_add(w[t]);
+                let t2 = sha512sum0(a).wrapping_add(maj);
+                hh = g; g = f; f = e; e = d.wrapping_add(t1);
+                d = c; c = b; b = a; a = t1.wrapping_add(t2);
+            }
+            for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+                *x = x.wrapping_add(y);
+            }
+        }
+        assert_eq!(h, [
+            0xddaf_35a1_9361_7aba, 0xcc41_7349_ae20_4131,
+            0x12e6_fa4e_89a9_7ea2, 0x0a9e_eee6_4b55_d39a,
+            0x2192_992a_274f_c1a8, 0x36ba_3c23_a3fe_ebbd,
+            0x454d_4423_643c_e80e, 0x2a9a_c94f_a54c_a49f,
+        ]);
+    }
+
+    /// SM3 (GB/T 32905-2016, example 1).
+    #[test]
+    fn sm3_kat() {
+        let mut v: [u32; 8] = [
+            0x7380_166f, 0x4914_b2b9, 0x1724_42d7, 0xda8a_0600,
+            0xa96f_30bc, 0x1631_38aa, 0xe38d_ee4d, 0xb0fb_0e4e,
+        ];
+        for block in pad(b"abc", 64).chunks(64) {
+            let mut w: Vec<u32> = block.chunks(4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            for j in 16..68 {
+                let x = w[j - 16] ^ w[j - 9] ^ w[j - 3].rotate_left(15);
+                w.push(sm3p1(x) ^ w[j - 13].rotate_left(7) ^ w[j - 6]);
+            }
+            let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = v;
+            for j in 0..64 {
+                let (t, ff, gg) = if j < 16 {
+                    (0x79cc_4519u32, a ^ b ^ c, e ^ f ^ g)
+                } else {
+                    (0x7a87_9d8a, (a & b) | (a & c) | (b & c), (e & f) | (!e & g))
+                };
+                let ss1 = a.rotate_left(12).wrapping_add(e)
+                    .wrapping_add(t.rotate_left(j as u32 % 32)).rotate_left(7);
+                let ss2 = ss1 ^ a.rotate_left(12);
+                let tt1 = ff.wrapping_add(d).wrapping_add(ss2)
+                    .wrapping_add(w[j] ^ w[j + 4]);
+                let tt2 = gg.wrapping_add(h).wrapping_add(ss1).wrapping_add(w[j]);
+                d = c; c = b.rotate_left(9); b = a; a = tt1;
+                h = g; g = f.rotate_left(19); f = e; e = sm3p0(tt2);
+            }
+            for (x, y) in v.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+                *x ^= y;
+            }
+        }
+        assert_eq!(v, [
+            0x66c7_f0f4, 0x62ee_edd9, 0xd1f2_d46b, 0xdc10_e4e2,
+            0x4167_c487, 0x5cf2_f7a2, 0x297d_a02b, 0x8f4b_a8e0,
+        ]);
+    }
+
+    /// SM4 with the RV32 instructions (GB/T 32907-2016, example 1).
+    ///
+    /// The instructions operate on little-endian words, so the constants
+    /// are byte-swapped.
+    #[test]
+    fn sm4_kat() {
+        let key = le_words("0123456789abcdeffedcba9876543210");
+        let pt  = key;
+        let ct  = le_words("681edf34d206965e86b3e94f536e4246");
+
+        let fk = [0xa3b1_bac6u32, 0x56aa_3350, 0x677d_9197, 0xb270_22dc];
+        let mut k: Vec<u32> = (0..4).map(|i| key[i] ^ fk[i].swap_bytes()).collect();
+        for i in 0..32 {
+            let ck = u32::from_le_bytes([0, 1, 2, 3].map(|j| ((4 * i + j) * 7) as u8));
+            let x = k[i + 1] ^ k[i + 2] ^ k[i + 3] ^ ck;
+            k.push((0..4).fold(k[i], |acc, bs| sm4ks(acc, x, bs)));
+        }
+        let rk = &k[4..];
+
+        let crypt = |input: [u32; 4], rk: &mut dyn Iterator<Item = &u32>| {
+            let mut x = input.to_vec();
+            for (i, rk) in rk.enumerate() {
+                let t = x[i + 1] ^ x[i + 2] ^ x[i + 3] ^ rk;
+                x.push((0..4).fold(x[i], |acc, bs| sm4ed(acc, t, bs)));
+            }
+            [x[35], x[34], x[33], x[32]]
+        };
+        assert_eq!(crypt(pt, &mut rk.iter()), ct);
+        // Decryption uses the round keys in reverse order
+        assert_eq!(crypt(ct, &mut rk.iter().rev()), pt);
+    }
+}
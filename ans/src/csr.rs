@@ -0,0 +1,767 @@
+//! Control and status registers, and the privileged state of a hart (its
+//! privilege level and the trap causes).
+
+use crate::rv32::{RvCsr, RvXlen};
+use crate::isa::RvIsa;
+use crate::mem::RvAccess;
+
+pub const FFLAGS:    u16 = 0x001;
+pub const FRM:       u16 = 0x002;
+pub const FCSR:      u16 = 0x003;
+
+pub const VSTART:    u16 = 0x008;
+pub const VXSAT:     u16 = 0x009;
+pub const VXRM:      u16 = 0x00a;
+pub const VCSR:      u16 = 0x00f;
+pub const VL:        u16 = 0xc20;
+pub const VTYPE:     u16 = 0xc21;
+pub const VLENB:     u16 = 0xc22;
+
+pub const SSTATUS:    u16 = 0x100;
+pub const SIE:        u16 = 0x104;
+pub const STVEC:      u16 = 0x105;
+pub const SCOUNTEREN: u16 = 0x106;
+pub const SSCRATCH:   u16 = 0x140;
+pub const SEPC:       u16 = 0x141;
+pub const SCAUSE:     u16 = 0x142;
+pub const STVAL:      u16 = 0x143;
+pub const SIP:        u16 = 0x144;
+pub const SATP:       u16 = 0x180;
+
+pub const MSTATUS:    u16 = 0x300;
+pub const MISA:       u16 = 0x301;
+pub const MEDELEG:    u16 = 0x302;
+pub const MIDELEG:    u16 = 0x303;
+pub const MIE:        u16 = 0x304;
+pub const MTVEC:      u16 = 0x305;
+pub const MCOUNTEREN: u16 = 0x306;
+pub const MSTATUSH:   u16 = 0x310;
+pub const MSCRATCH:   u16 = 0x340;
+pub const MEPC:       u16 = 0x341;
+pub const MCAUSE:     u16 = 0x342;
+pub const MTVAL:      u16 = 0x343;
+pub const MIP:        u16 = 0x344;
+pub const PMPCFG0:    u16 = 0x3a0;
+pub const PMPCFG3:    u16 = 0x3a3;
+pub const PMPADDR0:   u16 = 0x3b0;
+pub const PMPADDR15:  u16 = 0x3bf;
+
+pub const MCYCLE:    u16 = 0xb00;
+pub const MINSTRET:  u16 = 0xb02;
+pub const MCYCLEH:   u16 = 0xb80;
+pub const MINSTRETH: u16 = 0xb82;
+
+pub const CYCLE:     u16 = 0xc00;
+pub const TIME:      u16 = 0xc01;
+pub const INSTRET:   u16 = 0xc02;
+pub const CYCLEH:    u16 = 0xc80;
+pub const TIMEH:     u16 = 0xc81;
+pub const INSTRETH:  u16 = 0xc82;
+
+pub const MVENDORID: u16 = 0xf11;
+pub const MARCHID:   u16 = 0xf12;
+pub const MIMPID:    u16 = 0xf13;
+pub const MHARTID:   u16 = 0xf14;
+
+/// Names of the CSRs (as used in assembly).
+pub const NAMES: &[(&str, u16)] = &[
+    ("fflags", FFLAGS), ("frm", FRM), ("fcsr", FCSR),
+    ("vstart", VSTART), ("vxsat", VXSAT), ("vxrm", VXRM), ("vcsr", VCSR),
+    ("vl", VL), ("vtype", VTYPE), ("vlenb", VLENB),
+    ("sstatus", SSTATUS), ("sie", SIE), ("stvec", STVEC),
+    ("scounteren", SCOUNTEREN), ("sscratch", SSCRATCH), ("sepc", SEPC),
+    ("scause", SCAUSE), ("stval", STVAL), ("sip", SIP), ("satp", SATP),
+    ("mstatus", MSTATUS), ("misa", MISA), ("medeleg", MEDELEG),
+    ("mideleg", MIDELEG), ("mie", MIE), ("mtvec", MTVEC),
+    ("mcounteren", MCOUNTEREN), ("mstatush", MSTATUSH),
+    ("mscratch", MSCRATCH), ("mepc", MEPC), ("mcause", MCAUSE),
+    ("mtval", MTVAL), ("mip", MIP),
+    ("pmpcfg0", PMPCFG0), ("pmpcfg1", PMPCFG0 + 1), ("pmpcfg2", PMPCFG0 + 2),
+    ("pmpcfg3", PMPCFG3),
+    ("pmpaddr0", PMPADDR0), ("pmpaddr1", PMPADDR0 + 1), 
+    ("pmpaddr2", PMPADDR0 + 2), ("pmpaddr3", PMPADDR0 + 3), 
+    ("pmpaddr4", PMPADDR0 + 4), ("pmpaddr5", PMPADDR0 + 5), 
+    ("pmpaddr6", PMPADDR0 + 6), ("pmpaddr7", PMPADDR0 + 7), 
+    ("pmpaddr8", PMPADDR0 + 8), ("pmpaddr9", PMPADDR0 + 9), 
+    ("pmpaddr10", PMPADDR0 + 10), ("pmpaddr11", PMPADDR0 + 11), 
+    ("pmpaddr12", PMPADDR0 + 12), ("pmpaddr13", PMPADDR0 + 13), 
+    ("pmpaddr14", PMPADDR0 + 14), ("pmpaddr15", PMPADDR15),
+    ("mcycle", MCYCLE), ("minstret", MINSTRET), ("mcycleh", MCYCLEH),
+    ("minstreth", MINSTRETH),
+    ("cycle", CYCLE), ("time", TIME), ("instret", INSTRET),
+    ("cycleh", CYCLEH), ("timeh", TIMEH), ("instreth", INSTRETH),
+    ("mvendorid", MVENDORID), ("marchid", MARCHID), ("mimpid", MIMPID),
+    ("mhartid", MHARTID),
+];
+
+// Fields of 'mstatus' (and 'sstatus')
+pub const MSTATUS_SIE:  u64 = 1 << 1;
+pub const MSTATUS_MIE:  u64 = 1 << 3;
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_SPP:  u64 = 1 << 8;
+pub const MSTATUS_VS:   u64 = 0b11 << 9;
+pub const MSTATUS_MPP:  u64 = 0b11 << 11;
+pub const MSTATUS_FS:   u64 = 0b11 << 13;
+pub const MSTATUS_MPRV: u64 = 1 << 17;
+pub const MSTATUS_SUM:  u64 = 1 << 18;
+pub const MSTATUS_MXR:  u64 = 1 << 19;
+pub const MSTATUS_TVM:  u64 = 1 << 20;
+pub const MSTATUS_TW:   u64 = 1 << 21;
+pub const MSTATUS_TSR:  u64 = 1 << 22;
+
+/// Fields of 'mstatus' which are visible in 'sstatus' (except for UXL and
+/// SD, which depend on XLEN).
+const SSTATUS_MASK: u64 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP 
+    | MSTATUS_VS | MSTATUS_FS | MSTATUS_SUM | MSTATUS_MXR;
+
+// Fields of a PMP configuration register
+pub const PMP_R:     u8 = 1 << 0;
+pub const PMP_W:     u8 = 1 << 1;
+pub const PMP_X:     u8 = 1 << 2;
+pub const PMP_A:     u8 = 0b11 << 3;
+pub const PMP_TOR:   u8 = 0b01 << 3;
+pub const PMP_NA4:   u8 = 0b10 << 3;
+pub const PMP_NAPOT: u8 = 0b11 << 3;
+pub const PMP_L:     u8 = 1 << 7;
+
+/// Number of PMP entries.
+pub const PMP_COUNT: usize = 16;
+
+/// Interrupts which can be delegated to supervisor mode.
+const S_INTERRUPTS: u64 = 0x222;
+
+/// Machine-level interrupts, which are raised by devices.
+const M_INTERRUPTS: u64 = 0x888;
+
+/// A privilege level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RvPrivilege {
+    User       = 0,
+    Supervisor = 1,
+    Machine    = 3,
+}
+impl RvPrivilege {
+    /// The privilege level encoded in the MPP or SPP field.
+    pub fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            0 => Self::User,
+            1 => Self::Supervisor,
+            _ => Self::Machine,
+        }
+    }
+}
+
+/// The cause of a synchronous exception (the exception code in 'xcause').
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvException {
+    InstrMisaligned     = 0,
+    InstrAccessFault    = 1,
+    IllegalInstruction  = 2,
+    Breakpoint          = 3,
+    LoadMisaligned      = 4,
+    LoadAccessFault     = 5,
+    StoreMisaligned     = 6,
+    StoreAccessFault    = 7,
+    UserEcall           = 8,
+    SupervisorEcall     = 9,
+    MachineEcall        = 11,
+    InstrPageFault      = 12,
+    LoadPageFault       = 13,
+    StorePageFault      = 15,
+}
+
+/// The cause of an interrupt (the bit in 'mip' and 'mie').
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvInterrupt {
+    SupervisorSoft  = 1,
+    MachineSoft     = 3,
+    SupervisorTimer = 5,
+    MachineTimer    = 7,
+    SupervisorExt   = 9,
+    MachineExt      = 11,
+}
+impl RvInterrupt {
+    /// All of the interrupts, in decreasing order of priority.
+    pub const ALL: [Self; 6] = [
+        Self::MachineExt, Self::MachineSoft, Self::MachineTimer,
+        Self::SupervisorExt, Self::SupervisorSoft, Self::SupervisorTimer,
+    ];
+}
+
+/// A trap (an exception, with the value written to 'xtval', or an 
+/// interrupt).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvTrap {
+    Exception(RvException, u64),
+    Interrupt(RvInterrupt),
+}
+
+/// Reasons why an access to a CSR is illegal.
+#[derive(Clone, Copy, Debug)]
+pub enum CsrError {
+    /// The CSR is not implemented.
+    Unimplemented(RvCsr),
+    /// The CSR is read-only.
+    ReadOnly(RvCsr),
+    /// The CSR is not accessible at the current privilege level.
+    Privileged(RvCsr),
+}
+
+/// Write-any-read-legal update; only the bits set in `mask` are writable.
+fn warl(old: u64, new: u64, mask: u64) -> u64 {
+    (old & !mask) | (new & mask)
+}
+
+/// Replace the lower half of a 64-bit counter.
+fn set_lo(ctr: u64, val: u64) -> u64 {
+    (ctr & 0xffff_ffff_0000_0000) | (val & 0xffff_ffff)
+}
+
+/// Replace the upper half of a 64-bit counter.
+fn set_hi(ctr: u64, val: u64) -> u64 {
+    (ctr & 0x0000_0000_ffff_ffff) | (val << 32)
+}
+
+/// The set of control and status registers for a single hart, and its
+/// current privilege level.
+///
+/// CSRs are either read-only, WARL (where writes to unsupported bits are
+/// ignored), or one half of a 64-bit counter. All CSRs are XLEN bits wide, 
+/// and the upper halves of counters only exist on RV32. The supervisor 
+/// CSRs 'sstatus', 'sie' and 'sip' are views of the machine CSRs.
+pub struct CsrFile {
+    pub xlen:     RvXlen,
+    /// Current privilege level.
+    pub prv:      RvPrivilege,
+
+    pub misa:     u64,
+    pub mhartid:  u64,
+    pub mstatus:  u64,
+    pub medeleg:  u64,
+    pub mideleg:  u64,
+    pub mie:      u64,
+    pub mip:      u64,
+    pub mtvec:    u64,
+    pub mcounteren: u64,
+    pub mscratch: u64,
+    pub mepc:     u64,
+    pub mcause:   u64,
+    pub mtval:    u64,
+
+    pub stvec:    u64,
+    pub scounteren: u64,
+    pub sscratch: u64,
+    pub sepc:     u64,
+    pub scause:   u64,
+    pub stval:    u64,
+    /// Address translation and protection (see [crate::mem::Mmu]).
+    pub satp:     u64,
+    /// Configuration of each PMP entry (see [CsrFile::pmp_permits]).
+    pub pmpcfg:   [u8; PMP_COUNT],
+    /// Address of each PMP entry (bits 2 and up of a physical address).
+    pub pmpaddr:  [u64; PMP_COUNT],
+
+    /// Accrued floating-point exception flags.
+    pub fflags:   u32,
+    /// Dynamic floating-point rounding mode.
+    pub frm:      u32,
+
+    /// Index of the first element to be executed by a vector instruction.
+    pub vstart:   u64,
+    /// Fixed-point saturation flag.
+    pub vxsat:    u32,
+    /// Fixed-point rounding mode.
+    pub vxrm:     u32,
+    /// Vector length.
+    pub vl:       u64,
+    /// Vector type (see [crate::rv32::RvVType]).
+    pub vtype:    u64,
+    /// Number of bytes in a vector register.
+    pub vlenb:    u64,
+
+    /// Number of clock cycles.
+    pub cycle:    u64,
+    /// Wall-clock time (in ticks of 'mtime', see [crate::mem::Clint]).
+    pub time:     u64,
+    /// Number of retired instructions.
+    pub instret:  u64,
+}
+impl CsrFile {
+    pub fn new(isa: &RvIsa) -> Self {
+        // UXL and SXL are hardwired to XLEN on RV64
+        let xl = match isa.xlen {
+            RvXlen::Rv32 => 0,
+            RvXlen::Rv64 => 0b1010 << 32,
+        };
+        Self {
+            xlen:     isa.xlen,
+            prv:      RvPrivilege::Machine,
+            // Supervisor and user modes are always implemented
+            misa:     isa.misa() | (1 << 18) | (1 << 20),
+            mhartid:  0,
+            mstatus:  xl | MSTATUS_MPP,
+            medeleg:  0,
+            mideleg:  0,
+            mie:      0,
+            mip:      0,
+            mtvec:    0,
+            mcounteren: 0,
+            mscratch: 0,
+            mepc:     0,
+            mcause:   0,
+            mtval:    0,
+            stvec:    0,
+            scounteren: 0,
+            sscratch: 0,
+            sepc:     0,
+            scause:   0,
+            stval:    0,
+            satp:     0,
+            pmpcfg:   [0; PMP_COUNT],
+            pmpaddr:  [0; PMP_COUNT],
+            fflags:   0,
+            frm:      0,
+            vstart:   0,
+            vxsat:    0,
+            vxrm:     0,
+            // The vector type is illegal until the first vsetvl
+            vl:       0,
+            vtype:    1 << (isa.xlen.bits() - 1),
+            vlenb:    isa.vlen as u64 / 8,
+            cycle:    0,
+            time:     0,
+            instret:  0,
+        }
+    }
+
+    /// Advance the counters after an instruction has been executed.
+    pub fn tick(&mut self, retired: bool) {
+        self.cycle   = self.cycle.wrapping_add(1);
+        if retired {
+            self.instret = self.instret.wrapping_add(1);
+        }
+    }
+
+    /// Set the machine-level interrupts which are pending in 'mip' (as 
+    /// raised by devices).
+    pub fn set_machine_interrupts(&mut self, pending: u64) {
+        self.mip = warl(self.mip, pending, M_INTERRUPTS);
+    }
+
+    /// Returns true if the single-letter extension is set in 'misa'.
+    fn has_ext(&self, letter: char) -> bool {
+        self.misa & (1 << (letter as u32 - 'a' as u32)) != 0
+    }
+
+    /// Value of the SD bit (set when the floating-point or vector state 
+    /// is dirty).
+    fn sd(&self) -> u64 {
+        let dirty = self.mstatus & MSTATUS_FS == MSTATUS_FS
+            || self.mstatus & MSTATUS_VS == MSTATUS_VS;
+        (dirty as u64) << (self.xlen.bits() - 1)
+    }
+
+    /// Mask of the bits in 'xepc' which can be nonzero (bit 1 is only 
+    /// writable with compressed instructions).
+    fn epc_mask(&self) -> u64 {
+        if self.has_ext('c') { !0b1 } else { !0b11 }
+    }
+
+    /// Returns true if the counter can be read at the current privilege 
+    /// level (as enabled by 'mcounteren' and 'scounteren').
+    fn counter_enabled(&self, csr: RvCsr) -> bool {
+        let bit = 1 << (csr.0 & 0x1f);
+        match self.prv {
+            RvPrivilege::Machine    => true,
+            RvPrivilege::Supervisor => self.mcounteren & bit != 0,
+            RvPrivilege::User       => {
+                self.mcounteren & self.scounteren & bit != 0
+            },
+        }
+    }
+
+    /// Write 'mstatus' (or the bits of 'sstatus' in 'mask').
+    ///
+    /// MPP only holds the levels which are implemented, and VS is only 
+    /// writable with the vector extension.
+    fn write_mstatus(&mut self, val: u64, mask: u64) {
+        let mut mask = mask & (SSTATUS_MASK | MSTATUS_MIE | MSTATUS_MPIE 
+            | MSTATUS_MPP | MSTATUS_MPRV | MSTATUS_TVM | MSTATUS_TW 
+            | MSTATUS_TSR);
+        if !self.has_ext('v') {
+            mask &= !MSTATUS_VS;
+        }
+        if (val & MSTATUS_MPP) >> 11 == 0b10 {
+            mask &= !MSTATUS_MPP;
+        }
+        self.mstatus = warl(self.mstatus, val, mask);
+    }
+
+    /// Returns true if a PMP entry is locked (and ignores writes).
+    fn pmp_locked(&self, idx: usize) -> bool {
+        self.pmpcfg[idx] & PMP_L != 0
+    }
+
+    /// Write the configuration of a PMP entry.
+    ///
+    /// The reserved combination of permissions W=1 and R=0 is not 
+    /// writable (W is cleared instead).
+    fn write_pmpcfg(&mut self, idx: usize, mut cfg: u8) {
+        if self.pmp_locked(idx) {
+            return;
+        }
+        if cfg & (PMP_R | PMP_W) == PMP_W {
+            cfg &= !PMP_W;
+        }
+        self.pmpcfg[idx] = cfg & !0b0110_0000;
+    }
+
+    /// Write the address of a PMP entry.
+    ///
+    /// The address is also locked when the next entry is a locked TOR 
+    /// region (which uses it as its lower bound).
+    fn write_pmpaddr(&mut self, idx: usize, val: u64) {
+        let tor_locked = idx + 1 < PMP_COUNT && self.pmp_locked(idx + 1) 
+            && self.pmpcfg[idx + 1] & PMP_A == PMP_TOR;
+        if self.pmp_locked(idx) || tor_locked {
+            return;
+        }
+        // Physical addresses have 34 bits on RV32 and 56 bits on RV64
+        self.pmpaddr[idx] = match self.xlen {
+            RvXlen::Rv32 => val & 0xffff_ffff,
+            RvXlen::Rv64 => val & ((1 << 54) - 1),
+        };
+    }
+
+    /// Returns the range of physical addresses matched by a PMP entry 
+    /// (which is empty when the entry is off).
+    fn pmp_range(&self, idx: usize) -> std::ops::Range<u64> {
+        let addr = self.pmpaddr[idx];
+        match self.pmpcfg[idx] & PMP_A {
+            PMP_TOR => {
+                let lo = if idx == 0 { 0 } else { self.pmpaddr[idx - 1] << 2 };
+                lo..addr << 2
+            },
+            PMP_NA4 => addr << 2..(addr << 2) + 4,
+            PMP_NAPOT => {
+                // The number of trailing ones encodes the size of the region
+                let ones = (!addr).trailing_zeros();
+                let base = (addr & !((1 << ones) - 1)) << 2;
+                base..base + (8 << ones)
+            },
+            _ => 0..0,
+        }
+    }
+
+    /// The privilege level of an access, which is given by MPP for loads 
+    /// and stores when MPRV is set.
+    pub fn access_prv(&self, access: RvAccess) -> RvPrivilege {
+        match access {
+            RvAccess::Load | RvAccess::Store 
+                if self.mstatus & MSTATUS_MPRV != 0 => 
+            {
+                RvPrivilege::from_bits(self.mstatus >> 11)
+            },
+            _ => self.prv,
+        }
+    }
+
+    /// Returns true if physical memory protection permits an access to 
+    /// 'len' bytes at a physical address.
+    ///
+    /// The first entry which matches any byte of the access determines 
+    /// whether it succeeds, and it fails unless that entry matches all of 
+    /// its bytes. Entries only apply to machine mode when they are locked. 
+    /// Accesses which don't match any entry succeed in machine mode, and 
+    /// in the other modes unless some entry is enabled (so that programs 
+    /// which don't configure the PMP can run in any mode).
+    pub fn pmp_permits(&self, addr: u64, len: u64, access: RvAccess, 
+                       prv: RvPrivilege) -> bool
+    {
+        let end = addr.saturating_add(len);
+        for idx in 0..PMP_COUNT {
+            let range = self.pmp_range(idx);
+            if addr >= range.end || end <= range.start {
+                continue;
+            }
+            if addr < range.start || end > range.end {
+                return false;
+            }
+            let cfg = self.pmpcfg[idx];
+            if prv == RvPrivilege::Machine && cfg & PMP_L == 0 {
+                return true;
+            }
+            return cfg & match access {
+                RvAccess::Fetch => PMP_X,
+                RvAccess::Load  => PMP_R,
+                RvAccess::Store => PMP_W,
+            } != 0;
+        }
+        prv == RvPrivilege::Machine 
+            || self.pmpcfg.iter().all(|cfg| cfg & PMP_A == 0)
+    }
+
+    pub fn read(&self, csr: RvCsr) -> Result<u64, CsrError> {
+        let rv32 = self.xlen == RvXlen::Rv32;
+        let fp = self.has_ext('f');
+        let vec = self.has_ext('v');
+        if csr.privilege() > self.prv as u16 {
+            return Err(CsrError::Privileged(csr));
+        }
+        let res = match csr.0 {
+            FFLAGS    if fp => self.fflags as u64,
+            FRM       if fp => self.frm as u64,
+            FCSR      if fp => ((self.frm << 5) | self.fflags) as u64,
+
+            VSTART    if vec => self.vstart,
+            VXSAT     if vec => self.vxsat as u64,
+            VXRM      if vec => self.vxrm as u64,
+            VCSR      if vec => ((self.vxrm << 1) | self.vxsat) as u64,
+            VL        if vec => self.vl,
+            VTYPE     if vec => self.vtype,
+            VLENB     if vec => self.vlenb,
+
+            SSTATUS   => {
+                // UXL is visible in 'sstatus' (on RV64)
+                let mask = SSTATUS_MASK | (0b11 << 32);
+                (self.mstatus & mask) | self.sd()
+            },
+            SIE       => self.mie & self.mideleg,
+            STVEC     => self.stvec,
+            SCOUNTEREN => self.scounteren,
+            SSCRATCH  => self.sscratch,
+            SEPC      => self.sepc & self.epc_mask(),
+            SCAUSE    => self.scause,
+            STVAL     => self.stval,
+            SIP       => self.mip & self.mideleg,
+            // Supervisor mode can't access 'satp' when trapped by TVM
+            SATP      if self.prv == RvPrivilege::Supervisor 
+                && self.mstatus & MSTATUS_TVM != 0 => {
+                return Err(CsrError::Privileged(csr));
+            },
+            SATP      => self.satp,
+
+            MVENDORID | MARCHID | MIMPID => 0,
+            MHARTID   => self.mhartid,
+            MISA      => self.misa,
+            MSTATUS   => self.mstatus | self.sd(),
+            // The fields in 'mstatush' (MBE and SBE) are hardwired to zero
+            MSTATUSH  if rv32 => 0,
+            MEDELEG   => self.medeleg,
+            MIDELEG   => self.mideleg,
+            MIE       => self.mie,
+            MIP       => self.mip,
+            MTVEC     => self.mtvec,
+            MCOUNTEREN => self.mcounteren,
+            MSCRATCH  => self.mscratch,
+            MEPC      => self.mepc & self.epc_mask(),
+            MCAUSE    => self.mcause,
+            MTVAL     => self.mtval,
+            // Each configuration register holds XLEN / 8 entries, so only 
+            // the even registers exist on RV64
+            PMPCFG0..=PMPCFG3 if rv32 || csr.0 & 1 == 0 => {
+                let idx = (csr.0 - PMPCFG0) as usize * 4;
+                let count = self.xlen.bits() as usize / 8;
+                self.pmpcfg[idx..idx + count].iter().rev()
+                    .fold(0, |acc, cfg| (acc << 8) | *cfg as u64)
+            },
+            PMPADDR0..=PMPADDR15 => self.pmpaddr[(csr.0 - PMPADDR0) as usize],
+
+            CYCLE | TIME | INSTRET | CYCLEH | TIMEH | INSTRETH 
+                if !self.counter_enabled(csr) => {
+                return Err(CsrError::Privileged(csr));
+            },
+            MCYCLE    | CYCLE    => self.cycle,
+            MINSTRET  | INSTRET  => self.instret,
+            TIME      => self.time,
+            MCYCLEH   | CYCLEH   if rv32 => self.cycle >> 32,
+            MINSTRETH | INSTRETH if rv32 => self.instret >> 32,
+            TIMEH     if rv32 => self.time >> 32,
+            _ => return Err(CsrError::Unimplemented(csr)),
+        };
+        Ok(res & self.xlen.mask())
+    }
+
+    pub fn write(&mut self, csr: RvCsr, val: u64) -> Result<(), CsrError> {
+        let rv32 = self.xlen == RvXlen::Rv32;
+        let fp = self.has_ext('f');
+        let vec = self.has_ext('v');
+        if csr.is_read_only() {
+            self.read(csr)?;
+            return Err(CsrError::ReadOnly(csr));
+        }
+        if csr.privilege() > self.prv as u16 {
+            return Err(CsrError::Privileged(csr));
+        }
+        match csr.0 {
+            // Only one configuration is supported
+            MISA      => {},
+            FFLAGS    if fp => self.fflags = val as u32 & 0x1f,
+            FRM       if fp => self.frm = val as u32 & 0x7,
+            FCSR      if fp => {
+                self.fflags = val as u32 & 0x1f;
+                self.frm = (val as u32 >> 5) & 0x7;
+            },
+            VSTART    if vec => self.vstart = val,
+            VXSAT     if vec => self.vxsat = val as u32 & 0x1,
+            VXRM      if vec => self.vxrm = val as u32 & 0x3,
+            VCSR      if vec => {
+                self.vxsat = val as u32 & 0x1;
+                self.vxrm = (val as u32 >> 1) & 0x3;
+            },
+
+            SSTATUS   => self.write_mstatus(val, SSTATUS_MASK),
+            SIE       => self.mie = warl(self.mie, val, self.mideleg),
+            // Only direct (0) and vectored (1) modes are legal
+            STVEC     => self.stvec = warl(self.stvec, val, !0b10),
+            SCOUNTEREN => self.scounteren = val & 0b111,
+            SSCRATCH  => self.sscratch = val,
+            SEPC      => self.sepc = warl(self.sepc, val, !0b1),
+            SCAUSE    => self.scause = val,
+            STVAL     => self.stval = val,
+            // Only the supervisor software interrupt can be raised
+            SIP       => {
+                self.mip = warl(self.mip, val, self.mideleg & 0b10);
+            },
+            // Only Sv32 (on RV32) and bare (on RV64) translation are 
+            // supported, and writes which select another mode are ignored
+            SATP      if self.prv == RvPrivilege::Supervisor 
+                && self.mstatus & MSTATUS_TVM != 0 => {
+                return Err(CsrError::Privileged(csr));
+            },
+            SATP      if rv32 => self.satp = val,
+            SATP      => if val >> 60 == 0 { self.satp = val },
+
+            // FS is not enforced
+            MSTATUS   => self.write_mstatus(val, !0),
+            MSTATUSH  if rv32 => {},
+            // Machine-mode environment calls can't be delegated
+            MEDELEG   => self.medeleg = val & 0xb3ff,
+            MIDELEG   => self.mideleg = val & S_INTERRUPTS,
+            MIE       => self.mie = val & 0xaaa,
+            // The machine-level interrupts are raised by devices
+            MIP       => self.mip = warl(self.mip, val, S_INTERRUPTS),
+            MTVEC     => self.mtvec = warl(self.mtvec, val, !0b10),
+            MCOUNTEREN => self.mcounteren = val & 0b111,
+            MSCRATCH  => self.mscratch = val,
+            MEPC      => self.mepc = warl(self.mepc, val, !0b1),
+            MCAUSE    => self.mcause = val,
+            MTVAL     => self.mtval = val,
+            PMPCFG0..=PMPCFG3 if rv32 || csr.0 & 1 == 0 => {
+                let idx = (csr.0 - PMPCFG0) as usize * 4;
+                let count = self.xlen.bits() as usize / 8;
+                for i in 0..count {
+                    self.write_pmpcfg(idx + i, (val >> (i * 8)) as u8);
+                }
+            },
+            PMPADDR0..=PMPADDR15 => {
+                self.write_pmpaddr((csr.0 - PMPADDR0) as usize, val);
+            },
+
+            MCYCLE    if rv32 => self.cycle = set_lo(self.cycle, val),
+            MINSTRET  if rv32 => self.instret = set_lo(self.instret, val),
+            MCYCLE    => self.cycle = val,
+            MINSTRET  => self.instret = val,
+            MCYCLEH   if rv32 => self.cycle = set_hi(self.cycle, val),
+            MINSTRETH if rv32 => self.instret = set_hi(self.instret, val),
+            _ => return Err(CsrError::Unimplemented(csr)),
+        }
+        Ok(())
+    }
+
+    /// Returns the highest-priority interrupt which is pending, enabled,
+    /// and not masked at the current privilege level.
+    ///
+    /// Interrupts at a higher privilege level than the current one are 
+    /// always taken, and interrupts at the current level are taken when 
+    /// they are enabled in 'mstatus' (by MIE or SIE). Interrupts delegated 
+    /// to supervisor mode are never taken in machine mode.
+    pub fn pending_interrupt(&self) -> Option<RvInterrupt> {
+        let pending = self.mip & self.mie;
+        let m_enabled = self.prv < RvPrivilege::Machine 
+            || self.mstatus & MSTATUS_MIE != 0;
+        let s_enabled = self.prv < RvPrivilege::Supervisor
+            || (self.prv == RvPrivilege::Supervisor 
+                && self.mstatus & MSTATUS_SIE != 0);
+        RvInterrupt::ALL.iter().copied().find(|irq| {
+            let bit = 1 << (*irq as u64);
+            pending & bit != 0 && if self.mideleg & bit != 0 {
+                s_enabled
+            } else {
+                m_enabled
+            }
+        })
+    }
+
+    /// Take a trap at the instruction at 'pc', returning the address of the
+    /// trap handler.
+    ///
+    /// Traps are taken in machine mode, unless they are delegated (by 
+    /// 'medeleg' or 'mideleg') and occur in supervisor or user mode. The 
+    /// previous privilege level and interrupt enable are saved in 'mstatus',
+    /// and interrupts are disabled at the new level.
+    pub fn trap(&mut self, trap: RvTrap, pc: u64) -> u64 {
+        let (code, tval, deleg, interrupt) = match trap {
+            RvTrap::Exception(e, tval) => (e as u64, tval, self.medeleg, 0),
+            RvTrap::Interrupt(irq) => {
+                (irq as u64, 0, self.mideleg, 1 << (self.xlen.bits() - 1))
+            },
+        };
+        let prv = self.prv as u64;
+        let delegated = (deleg >> code) & 1 != 0;
+        let tvec = if self.prv < RvPrivilege::Machine && delegated {
+            self.sepc = pc;
+            self.scause = interrupt | code;
+            self.stval = tval;
+            let sie = (self.mstatus & MSTATUS_SIE) << 4;
+            self.mstatus = warl(self.mstatus, sie | (prv << 8),
+                MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP);
+            self.prv = RvPrivilege::Supervisor;
+            self.stvec
+        } else {
+            self.mepc = pc;
+            self.mcause = interrupt | code;
+            self.mtval = tval;
+            let mie = (self.mstatus & MSTATUS_MIE) << 4;
+            self.mstatus = warl(self.mstatus, mie | (prv << 11),
+                MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+            self.prv = RvPrivilege::Machine;
+            self.mtvec
+        };
+        // Interrupts jump to an entry in the table of a vectored handler
+        match tvec & 0b11 {
+            1 if interrupt != 0 => (tvec & !0b11).wrapping_add(4 * code),
+            _ => tvec & !0b11,
+        }
+    }
+
+    /// Return from a machine-mode trap handler, returning the address to
+    /// return to.
+    ///
+    /// The privilege level and interrupt enable are restored from MPP and
+    /// MPIE. MPRV is cleared when returning to a lower privilege level.
+    pub fn mret(&mut self) -> u64 {
+        self.prv = RvPrivilege::from_bits(self.mstatus >> 11);
+        let mie = (self.mstatus & MSTATUS_MPIE) >> 4;
+        let mut mask = MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP;
+        if self.prv != RvPrivilege::Machine {
+            mask |= MSTATUS_MPRV;
+        }
+        self.mstatus = warl(self.mstatus, mie | MSTATUS_MPIE, mask);
+        self.mepc & self.epc_mask()
+    }
+
+    /// Return from a supervisor-mode trap handler, returning the address to
+    /// return to.
+    ///
+    /// The privilege level and interrupt enable are restored from SPP and
+    /// SPIE, and MPRV is cleared.
+    pub fn sret(&mut self) -> u64 {
+        self.prv = RvPrivilege::from_bits((self.mstatus & MSTATUS_SPP) >> 8);
+        let sie = (self.mstatus & MSTATUS_SPIE) >> 4;
+        self.mstatus = warl(self.mstatus, sie | MSTATUS_SPIE,
+            MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_MPRV);
+        self.sepc & self.epc_mask()
+    }
+}
@@ -0,0 +1,322 @@
+//! ISA strings and the set of extensions implemented by a model.
+
+use crate::rv32::RvXlen;
+
+/// An extension of the base integer instruction set.
+///
+/// The base ISA itself is included as [RvExt::I] (or [RvExt::E], which
+/// only has 16 integer registers).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvExt {
+    I,
+    E,
+    M,
+    A,
+    F,
+    D,
+    C,
+    V,
+    Zicsr,
+    Zifencei,
+    Zba,
+    Zbb,
+    Zbs,
+    Zknd,
+    Zkne,
+    Zknh,
+    Zksed,
+    Zksh,
+}
+impl RvExt {
+    /// All of the extensions, in the order they appear in an ISA string.
+    pub const ALL: [Self; 18] = [
+        Self::I, Self::E, Self::M, Self::A, Self::F, Self::D, Self::C,
+        Self::V, Self::Zicsr, Self::Zifencei, Self::Zba, Self::Zbb, Self::Zbs,
+        Self::Zknd, Self::Zkne, Self::Zknh, Self::Zksed, Self::Zksh,
+    ];
+
+    /// Name of the extension (as used in an ISA string).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::I        => "i",
+            Self::E        => "e",
+            Self::M        => "m",
+            Self::A        => "a",
+            Self::F        => "f",
+            Self::D        => "d",
+            Self::C        => "c",
+            Self::V        => "v",
+            Self::Zicsr    => "zicsr",
+            Self::Zifencei => "zifencei",
+            Self::Zba      => "zba",
+            Self::Zbb      => "zbb",
+            Self::Zbs      => "zbs",
+            Self::Zknd     => "zknd",
+            Self::Zkne     => "zkne",
+            Self::Zknh     => "zknh",
+            Self::Zksed    => "zksed",
+            Self::Zksh     => "zksh",
+        }
+    }
+
+    /// Returns true for extensions named by a single letter.
+    pub fn is_single_letter(&self) -> bool {
+        self.name().len() == 1
+    }
+
+    fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+/// Reasons why an ISA string is invalid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IsaError {
+    /// The string doesn't start with `rv32` or `rv64`, followed by the
+    /// base ISA (`i`, `e`, or `g` for `imafd_zicsr_zifencei`).
+    InvalidBase,
+    /// The named extension isn't supported.
+    Unknown(String),
+    /// The first extension depends on the second.
+    Requires(RvExt, RvExt),
+}
+impl std::fmt::Display for IsaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidBase => write!(f, "ISA string must start with \
+                rv32i, rv32e, rv32g, rv64i, rv64e or rv64g"),
+            Self::Unknown(name) => write!(f, "unsupported extension '{}'",
+                name),
+            Self::Requires(ext, dep) => write!(f, "'{}' requires '{}'",
+                ext.name(), dep.name()),
+        }
+    }
+}
+
+/// The register width and the set of extensions implemented by a hart,
+/// as described by an ISA string (for example, `rv32imac_zicsr_zba`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RvIsa {
+    pub xlen: RvXlen,
+    /// Set of [RvExt] (indexed by discriminant)
+    exts: u32,
+    /// Number of bits in a vector register (only used with V)
+    pub vlen: u32,
+}
+impl RvIsa {
+    /// The base integer ISA (without any extensions).
+    pub fn new(xlen: RvXlen) -> Self {
+        Self { xlen, exts: RvExt::I.bit(), vlen: 128 }
+    }
+
+    /// Every extension supported by this crate (with the I base).
+    pub fn full(xlen: RvXlen) -> Self {
+        let exts = RvExt::ALL.iter()
+            .filter(|ext| **ext != RvExt::E)
+            .fold(0, |acc, ext| acc | ext.bit());
+        Self { xlen, exts, vlen: 128 }
+    }
+
+    /// Number of integer registers (16 on the E base, otherwise 32).
+    pub fn regs(&self) -> usize {
+        if self.has(RvExt::E) { 16 } else { 32 }
+    }
+
+    /// Alignment (in bytes) of the stack pointer in the calling convention
+    /// (16, or 4 in the ilp32e ABI of the E base).
+    pub fn stack_align(&self) -> u64 {
+        if self.has(RvExt::E) { 4 } else { 16 }
+    }
+
+    /// Returns true if the extension is implemented.
+    pub fn has(&self, ext: RvExt) -> bool {
+        self.exts & ext.bit() != 0
+    }
+
+    /// Returns true if instructions from the extension are legal (the
+    /// instructions of the I base are also in the E base).
+    pub fn supports(&self, ext: RvExt) -> bool {
+        ext == RvExt::I || self.has(ext)
+    }
+
+    /// Add an extension to the set.
+    pub fn with(self, ext: RvExt) -> Self {
+        Self { exts: self.exts | ext.bit(), ..self }
+    }
+
+    /// Set the number of bits in a vector register (a power of two, 
+    /// and at least 128).
+    pub fn with_vlen(self, vlen: u32) -> Self {
+        assert!(vlen.is_power_of_two() && (128..=65536).contains(&vlen),
+            "invalid VLEN {}", vlen);
+        Self { vlen, ..self }
+    }
+
+    /// Parse an ISA string.
+    ///
+    /// Single-letter extensions follow the base ISA, and multi-letter
+    /// extensions are separated by underscores. The case of the string is
+    /// ignored. `g` stands for `imafd_zicsr_zifencei`, `b` stands for
+    /// `zba_zbb_zbs`, and `f` and `v` imply `zicsr`. The VLEN is 128 bits,
+    /// unless a larger one is given with `zvl<VLEN>b` (for example, 
+    /// `rv64gcv_zvl256b`).
+    pub fn parse(s: &str) -> Result<Self, IsaError> {
+        let s = s.to_ascii_lowercase();
+        let (xlen, rest) = if let Some(rest) = s.strip_prefix("rv32") {
+            (RvXlen::Rv32, rest)
+        } else if let Some(rest) = s.strip_prefix("rv64") {
+            (RvXlen::Rv64, rest)
+        } else {
+            return Err(IsaError::InvalidBase);
+        };
+        let mut parts = rest.split('_');
+        let mut letters = parts.next().unwrap().chars();
+
+        let mut res = Self::new(xlen);
+        match letters.next() {
+            Some('i') => {},
+            Some('e') => res = Self { exts: RvExt::E.bit(), ..res },
+            Some('g') => {
+                for ext in [RvExt::M, RvExt::A, RvExt::F, RvExt::D,
+                            RvExt::Zicsr, RvExt::Zifencei] {
+                    res = res.with(ext);
+                }
+            },
+            _ => return Err(IsaError::InvalidBase),
+        }
+        for c in letters {
+            res = match c {
+                'm' => res.with(RvExt::M),
+                'a' => res.with(RvExt::A),
+                'f' => res.with(RvExt::F),
+                'd' => res.with(RvExt::D),
+                'c' => res.with(RvExt::C),
+                'v' => res.with(RvExt::V),
+                'b' => res.with(RvExt::Zba).with(RvExt::Zbb).with(RvExt::Zbs),
+                _ => return Err(IsaError::Unknown(c.to_string())),
+            };
+        }
+        for name in parts {
+            let vlen = name.strip_prefix("zvl")
+                .and_then(|n| n.strip_suffix('b'))
+                .and_then(|n| n.parse::<u32>().ok())
+                .filter(|n| n.is_power_of_two() && (32..=65536).contains(n));
+            if let Some(vlen) = vlen {
+                res.vlen = res.vlen.max(vlen);
+                continue;
+            }
+            let ext = RvExt::ALL.iter()
+                .find(|ext| !ext.is_single_letter() && ext.name() == name)
+                .ok_or_else(|| IsaError::Unknown(name.to_string()))?;
+            res = res.with(*ext);
+        }
+
+        if res.has(RvExt::D) && !res.has(RvExt::F) {
+            return Err(IsaError::Requires(RvExt::D, RvExt::F));
+        }
+        if res.has(RvExt::F) || res.has(RvExt::V) {
+            res = res.with(RvExt::Zicsr);
+        }
+        Ok(res)
+    }
+
+    /// The value of the `misa` CSR.
+    ///
+    /// The B bit is only set if all of Zba, Zbb and Zbs are implemented.
+    pub fn misa(&self) -> u64 {
+        // The MXL field is in the uppermost bits
+        let mut res: u64 = match self.xlen {
+            RvXlen::Rv32 => 1 << 30,
+            RvXlen::Rv64 => 2 << 62,
+        };
+        for ext in RvExt::ALL.iter().filter(|ext| self.has(**ext)) {
+            if ext.is_single_letter() {
+                let letter = ext.name().as_bytes()[0];
+                res |= 1 << (letter - b'a');
+            }
+        }
+        if self.has(RvExt::Zba) && self.has(RvExt::Zbb) && self.has(RvExt::Zbs) {
+            res |= 1 << 1;
+        }
+        res
+    }
+}
+impl std::str::FromStr for RvIsa {
+    type Err = IsaError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+impl std::fmt::Display for RvIsa {
+    /// Formats the canonical ISA string (without any abbreviations).
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rv{}", self.xlen.bits())?;
+        for ext in RvExt::ALL.iter().filter(|ext| self.has(**ext)) {
+            if !ext.is_single_letter() {
+                write!(f, "_")?;
+            }
+            write!(f, "{}", ext.name())?;
+        }
+        if self.has(RvExt::V) && self.vlen > 128 {
+            write!(f, "_zvl{}b", self.vlen)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::isa::*;
+
+    #[test]
+    fn parse_isa() {
+        let isa = RvIsa::parse("rv32imac_zicsr_zba").unwrap();
+        assert_eq!(isa.xlen, RvXlen::Rv32);
+        assert!(isa.has(RvExt::M) && isa.has(RvExt::C) && isa.has(RvExt::Zba));
+        assert!(!isa.has(RvExt::F) && !isa.has(RvExt::Zbb));
+        assert_eq!(isa.to_string(), "rv32imac_zicsr_zba");
+
+        let isa: RvIsa = "RV64GC".parse().unwrap();
+        assert_eq!(isa.to_string(), "rv64imafdc_zicsr_zifencei");
+        assert_eq!("rv64gcbv_zknd_zkne_zknh_zksed_zksh".parse::<RvIsa>()
+            .unwrap(), RvIsa::full(RvXlen::Rv64));
+        assert_eq!(RvIsa::parse("rv32if").unwrap().to_string(),
+            "rv32if_zicsr");
+
+        let isa = RvIsa::parse("rv32emc").unwrap();
+        assert!(isa.has(RvExt::E) && !isa.has(RvExt::I));
+        assert_eq!(isa.regs(), 16);
+        assert_eq!(isa.to_string(), "rv32emc");
+        assert_eq!(RvIsa::full(RvXlen::Rv32).regs(), 32);
+
+        let isa = RvIsa::parse("rv64imv").unwrap();
+        assert!(isa.has(RvExt::V) && isa.has(RvExt::Zicsr));
+        assert_eq!(isa.vlen, 128);
+        let isa = RvIsa::parse("rv64gcv_zvl512b_zvl256b").unwrap();
+        assert_eq!(isa.vlen, 512);
+        assert_eq!(isa.to_string(), 
+            "rv64imafdcv_zicsr_zifencei_zvl512b");
+        assert_eq!(isa, RvIsa::parse("rv64gcv").unwrap().with_vlen(512));
+
+        assert_eq!(RvIsa::parse("rv128i"), Err(IsaError::InvalidBase));
+        assert_eq!(RvIsa::parse("rv32mi"), Err(IsaError::InvalidBase));
+        assert_eq!(RvIsa::parse("rv32ie"),
+            Err(IsaError::Unknown("e".to_string())));
+        assert_eq!(RvIsa::parse("rv32iv_zvl100b"),
+            Err(IsaError::Unknown("zvl100b".to_string())));
+        assert_eq!(RvIsa::parse("rv32i_zfoo"),
+            Err(IsaError::Unknown("zfoo".to_string())));
+        assert_eq!(RvIsa::parse("rv32id"),
+            Err(IsaError::Requires(RvExt::D, RvExt::F)));
+    }
+
+    #[test]
+    fn misa() {
+        let isa = RvIsa::parse("rv32imac").unwrap();
+        assert_eq!(isa.misa(), 0x4000_1105);
+        let isa = RvIsa::parse("rv32e").unwrap();
+        assert_eq!(isa.misa(), 0x4000_0010);
+        let isa = RvIsa::full(RvXlen::Rv64);
+        assert_eq!(isa.misa(), 0x8000_0000_0020_112f);
+    }
+}
@@ -3,6 +3,13 @@ pub mod storage;
 pub mod effects;
 pub mod mem;
 pub mod rv32;
+pub mod opcodes;
+pub mod isa;
+pub mod csr;
+pub mod softfloat;
+pub mod crypto;
+pub mod syscall;
+pub mod asm;
 
 pub mod models;
 
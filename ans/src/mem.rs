@@ -1,30 +1,371 @@
+use crate::rv32::RvXlen;
+use crate::csr::*;
+use std::collections::BTreeMap;
 
+/// The kind of a memory access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvAccess {
+    Fetch,
+    Load,
+    /// A store (or an atomic memory operation)
+    Store,
+}
+impl RvAccess {
+    /// The page fault raised by an access of this kind.
+    pub fn page_fault(&self) -> RvException {
+        match self {
+            Self::Fetch => RvException::InstrPageFault,
+            Self::Load  => RvException::LoadPageFault,
+            Self::Store => RvException::StorePageFault,
+        }
+    }
+
+    /// The access fault raised by an access of this kind.
+    pub fn access_fault(&self) -> RvException {
+        match self {
+            Self::Fetch => RvException::InstrAccessFault,
+            Self::Load  => RvException::LoadAccessFault,
+            Self::Store => RvException::StoreAccessFault,
+        }
+    }
+}
+
+// Bits of a page table entry
+pub const PTE_V: u32 = 1 << 0;
+pub const PTE_R: u32 = 1 << 1;
+pub const PTE_W: u32 = 1 << 2;
+pub const PTE_X: u32 = 1 << 3;
+pub const PTE_U: u32 = 1 << 4;
+pub const PTE_G: u32 = 1 << 5;
+pub const PTE_A: u32 = 1 << 6;
+pub const PTE_D: u32 = 1 << 7;
+
+/// Number of entries in the TLB.
+const TLB_SIZE: usize = 64;
+
+/// A cached translation of a 4 KiB virtual page.
+#[derive(Clone, Copy, Debug)]
+struct TlbEntry {
+    /// Virtual page number.
+    vpn: u64,
+    /// Address space of the translation.
+    asid: u64,
+    /// Physical address of the page.
+    base: u64,
+    /// Bits of the leaf page table entry.
+    pte: u32,
+    /// Level of the leaf page table entry (1 for a 4 MiB megapage).
+    level: u32,
+}
+
+/// Translation of virtual addresses with Sv32 paging.
+///
+/// Translations are cached in a direct-mapped TLB, which is only flushed 
+/// by [Mmu::flush] (for SFENCE.VMA). Page table entries are updated by 
+/// the page walk when their accessed or dirty bits need to be set.
 pub struct Mmu {
+    tlb: [Option<TlbEntry>; TLB_SIZE],
+}
+impl Default for Mmu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Mmu {
     pub fn new() -> Self {
         Self {
+            tlb: [None; TLB_SIZE],
+        }
+    }
+
+    /// Invalidate the cached translations for a virtual address, and/or 
+    /// for an address space (except for global mappings). Every 
+    /// translation is invalidated when neither is given.
+    pub fn flush(&mut self, vaddr: Option<u64>, asid: Option<u64>) {
+        for slot in self.tlb.iter_mut() {
+            let entry = match slot {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let addr_match = match vaddr {
+                Some(vaddr) => {
+                    let shift = 12 + 10 * entry.level;
+                    entry.vpn << 12 >> shift == vaddr >> shift
+                },
+                None => true,
+            };
+            let asid_match = match asid {
+                Some(asid) => entry.asid == asid && entry.pte & PTE_G == 0,
+                None => true,
+            };
+            if addr_match && asid_match {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Returns true if a leaf page table entry permits an access at some
+    /// privilege level.
+    ///
+    /// Supervisor mode can only access user pages when SUM is set (and 
+    /// can never execute them). Executable pages are readable when MXR is
+    /// set.
+    fn permits(pte: u32, access: RvAccess, prv: RvPrivilege, mstatus: u64) 
+        -> bool
+    {
+        let user = pte & PTE_U != 0;
+        let level_ok = match prv {
+            RvPrivilege::User       => user,
+            RvPrivilege::Supervisor => {
+                !user || (access != RvAccess::Fetch 
+                    && mstatus & MSTATUS_SUM != 0)
+            },
+            RvPrivilege::Machine    => true,
+        };
+        level_ok && match access {
+            RvAccess::Fetch => pte & PTE_X != 0,
+            RvAccess::Load  => {
+                pte & PTE_R != 0 
+                    || (mstatus & MSTATUS_MXR != 0 && pte & PTE_X != 0)
+            },
+            RvAccess::Store => pte & PTE_W != 0,
+        }
+    }
+
+    /// Translate a virtual address to a physical address.
+    ///
+    /// Addresses are only translated below machine mode, when Sv32 is 
+    /// enabled in 'satp'. Loads and stores use the privilege level in MPP 
+    /// when MPRV is set.
+    pub fn translate(&mut self, ram: &mut Memory, csr: &CsrFile, 
+                     vaddr: u64, access: RvAccess) -> Result<u64, RvTrap> 
+    {
+        let prv = csr.access_prv(access);
+        if prv == RvPrivilege::Machine || csr.xlen != RvXlen::Rv32 
+            || csr.satp >> 31 == 0 
+        {
+            return Ok(vaddr);
+        }
+        let vpn = vaddr >> 12;
+        let asid = (csr.satp >> 22) & 0x1ff;
+        let slot = vpn as usize % TLB_SIZE;
+        let entry = match self.tlb[slot] {
+            // Stores re-walk the page table to set the dirty bit
+            Some(entry) if entry.vpn == vpn 
+                && (entry.asid == asid || entry.pte & PTE_G != 0)
+                && (access != RvAccess::Store || entry.pte & PTE_D != 0) => 
+            {
+                if !Self::permits(entry.pte, access, prv, csr.mstatus) {
+                    return Err(RvTrap::Exception(access.page_fault(), vaddr));
+                }
+                entry
+            },
+            _ => {
+                let entry = Self::walk(ram, csr, vaddr, access, prv)?;
+                self.tlb[slot] = Some(entry);
+                entry
+            },
+        };
+        Ok(entry.base | (vaddr & 0xfff))
+    }
+
+    /// Walk the two-level page table to translate a virtual address.
+    fn walk(ram: &mut Memory, csr: &CsrFile, vaddr: u64, access: RvAccess,
+            prv: RvPrivilege) -> Result<TlbEntry, RvTrap> 
+    {
+        let fault = RvTrap::Exception(access.page_fault(), vaddr);
+        let vpn = [(vaddr >> 12) & 0x3ff, (vaddr >> 22) & 0x3ff];
+        let mut table = (csr.satp & 0x3f_ffff) << 12;
+        let mut level = 1;
+        loop {
+            // Page table accesses are checked by the PMP as supervisor mode
+            let addr = (table + vpn[level] * 4) as usize;
+            if !ram.contains(addr, 4) || !csr.pmp_permits(addr as u64, 4, 
+                RvAccess::Load, RvPrivilege::Supervisor) 
+            {
+                return Err(RvTrap::Exception(access.access_fault(), vaddr));
+            }
+            let pte = ram.load32(addr);
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(fault);
+            }
+            let ppn = (pte >> 10) as u64;
+            // A pointer to the next level of the page table
+            if pte & (PTE_R | PTE_X) == 0 {
+                if level == 0 {
+                    return Err(fault);
+                }
+                level -= 1;
+                table = ppn << 12;
+                continue;
+            }
+            // Megapages must be aligned to 4 MiB
+            if !Self::permits(pte, access, prv, csr.mstatus) 
+                || (level == 1 && ppn & 0x3ff != 0) 
+            {
+                return Err(fault);
+            }
+            let mut new = pte | PTE_A;
+            if access == RvAccess::Store {
+                new |= PTE_D;
+            }
+            if new != pte {
+                if !csr.pmp_permits(addr as u64, 4, RvAccess::Store, 
+                    RvPrivilege::Supervisor) 
+                {
+                    return Err(RvTrap::Exception(access.access_fault(), vaddr));
+                }
+                ram.store32(addr, new);
+            }
+            let base = match level {
+                1 => (ppn << 12) | (vpn[0] << 12),
+                _ => ppn << 12,
+            };
+            return Ok(TlbEntry {
+                vpn: vaddr >> 12,
+                asid: (csr.satp >> 22) & 0x1ff,
+                base,
+                pte: new,
+                level: level as u32,
+            });
+        }
+    }
+}
+
+
+/// Base address of the CLINT.
+pub const CLINT_BASE: u64 = 0x0200_0000;
+/// Size of the address range of the CLINT.
+const CLINT_SIZE: u64 = 0x1_0000;
+
+// Offsets of the CLINT registers
+const CLINT_MSIP:     u64 = 0x0000;
+const CLINT_MTIMECMP: u64 = 0x4000;
+const CLINT_MTIME:    u64 = 0xbff8;
+
+/// A memory-mapped core-local interruptor (for a single hart), which 
+/// raises the machine timer interrupt while 'mtime' is at least 
+/// 'mtimecmp', and the machine software interrupt while 'msip' is set.
+///
+/// Time advances by one tick for every 'rate' retired instructions.
+pub struct Clint {
+    pub mtime: u64,
+    pub mtimecmp: u64,
+    pub msip: bool,
+    /// Number of retired instructions per tick.
+    rate: u64,
+    /// Number of instructions retired since the last tick.
+    instrs: u64,
+}
+impl Default for Clint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Clint {
+    pub fn new() -> Self {
+        Self {
+            mtime: 0,
+            // No timer interrupt is pending until 'mtimecmp' is written
+            mtimecmp: u64::MAX,
+            msip: false,
+            rate: 1,
+            instrs: 0,
+        }
+    }
+
+    /// Set the number of retired instructions per tick of 'mtime'.
+    pub fn set_rate(&mut self, rate: u64) {
+        self.rate = rate.max(1);
+        self.instrs = 0;
+    }
+
+    /// Advance the time after an instruction has been retired.
+    pub fn tick(&mut self) {
+        self.instrs += 1;
+        if self.instrs >= self.rate {
+            self.instrs = 0;
+            self.mtime = self.mtime.wrapping_add(1);
+        }
+    }
+
+    /// Returns the machine interrupts which are pending (as the bits in 
+    /// 'mip').
+    pub fn pending(&self) -> u64 {
+        let timer = self.mtime >= self.mtimecmp;
+        ((timer as u64) << RvInterrupt::MachineTimer as u64)
+            | ((self.msip as u64) << RvInterrupt::MachineSoft as u64)
+    }
+
+    /// Returns true if some number of bytes at an address are inside the 
+    /// address range of the CLINT.
+    pub fn contains(&self, addr: u64, len: usize) -> bool {
+        addr >= CLINT_BASE && addr + len as u64 <= CLINT_BASE + CLINT_SIZE
+    }
+
+    /// Load 'len' bytes from a register. Unimplemented registers read as 
+    /// zero.
+    pub fn load(&self, addr: u64, len: usize) -> u64 {
+        let off = addr - CLINT_BASE;
+        let val = match off & !0b111 {
+            CLINT_MSIP     => self.msip as u64,
+            CLINT_MTIMECMP => self.mtimecmp,
+            CLINT_MTIME    => self.mtime,
+            _ => 0,
+        };
+        (val >> ((off & 0b111) * 8)) & (u64::MAX >> (64 - len * 8))
+    }
+
+    /// Store 'len' bytes to a register (where only bit 0 of 'msip' is 
+    /// implemented). Writes to unimplemented registers are ignored.
+    pub fn store(&mut self, addr: u64, val: u64, len: usize) {
+        let off = addr - CLINT_BASE;
+        let shift = (off & 0b111) * 8;
+        let mask = (u64::MAX >> (64 - len * 8)) << shift;
+        let update = |old: u64| (old & !mask) | ((val << shift) & mask);
+        match off & !0b111 {
+            CLINT_MSIP     => self.msip = update(self.msip as u64) & 1 != 0,
+            CLINT_MTIMECMP => self.mtimecmp = update(self.mtimecmp),
+            CLINT_MTIME    => self.mtime = update(self.mtime),
+            _ => {},
         }
     }
 }
 
 
 /// A simple emulated memory device.
+///
+/// The memory occupies the addresses starting at 'base' (which is zero,
+/// unless a program is loaded above the end of the memory).
 pub struct Memory {
+    pub base: usize,
     pub data: Vec<u8>,
 }
 impl Memory {
     pub fn new(size: usize) -> Self {
         Self {
+            base: 0,
             data: vec![0u8; size],
         }
     }
-    pub fn write(&mut self, off: usize, src: &[u8]) {
+    /// Offset of an address in the memory.
+    pub fn offset(&self, addr: usize) -> usize {
+        addr.wrapping_sub(self.base)
+    }
+    /// Returns true if some number of bytes at an address are inside the
+    /// memory.
+    pub fn contains(&self, addr: usize, len: usize) -> bool {
+        matches!(self.offset(addr).checked_add(len), 
+            Some(end) if end <= self.data.len())
+    }
+    pub fn write(&mut self, addr: usize, src: &[u8]) {
+        let off = self.offset(addr);
         let end = off + src.len();
         self.data[off..end].copy_from_slice(src);
     }
     pub fn read32(&self, addr: usize) -> u32 {
         use std::convert::TryInto;
+        let addr = self.offset(addr);
         let slice: [u8; 4] = self.data[addr..addr + 4].try_into().unwrap();
         let res = u32::from_ne_bytes(slice);
         res
@@ -32,50 +373,234 @@ impl Memory {
 
 }
 
+/// Information about an ELF file loaded into memory.
+pub struct ElfInfo {
+    /// Address of the entrypoint.
+    pub entry: u64,
+    /// Register width implied by the ELF class.
+    pub xlen: RvXlen,
+    /// End of the highest segment in memory (where the heap can start).
+    pub end: u64,
+    /// Addresses of the symbols in the symbol table.
+    pub symbols: BTreeMap<String, u64>,
+    /// Address of the program headers, if they are part of a segment.
+    pub phdr: Option<u64>,
+    /// Size of each program header.
+    pub phent: u64,
+    /// Contents of the program headers.
+    pub phdrs: Vec<u8>,
+}
+
 impl Memory {
-    pub fn load_elf(&mut self, filename: &str) -> u32 {
+    /// Load the segments of an ELF file.
+    ///
+    /// If the segments don't fit in the memory at its current base (for
+    /// example, bare-metal programs linked at 0x8000_0000), the memory is 
+    /// moved to start at the lowest segment (aligned to 1 MiB).
+    pub fn load_elf(&mut self, filename: &str) -> ElfInfo {
         use std::fs;
-        use object::{Object, ObjectSegment};
+        use object::{Object, ObjectSegment, ObjectSymbol};
         let elf_data = fs::read(filename).unwrap();
         let elf = object::File::parse(&*elf_data).unwrap();
+        // Find the program headers in the ELF header
+        let field = |off: usize, len: usize| {
+            elf_data[off..off + len].iter().rev()
+                .fold(0, |acc, b| (acc << 8) | *b as u64)
+        };
+        let (phoff, phent, phnum) = if elf.is_64() {
+            (field(0x20, 8), field(0x36, 2), field(0x38, 2))
+        } else {
+            (field(0x1c, 4), field(0x2a, 2), field(0x2c, 2))
+        };
+        let phdrs = elf_data[phoff as usize..(phoff + phent * phnum) as usize]
+            .to_vec();
+
+        let start = elf.segments()
+            .filter(|s| s.size() != 0)
+            .map(|s| s.address())
+            .min()
+            .unwrap_or(0) as usize;
+        if self.offset(start) >= self.data.len() {
+            self.base = start & !0xf_ffff;
+        }
+
+        let mut end = 0;
+        let mut phdr = None;
         for segment in elf.segments() {
             let addr = segment.address() as usize;
             let data = segment.data().unwrap();
             println!("Loading segment @ {:08x} ({:08x} bytes)", 
                      addr, data.len());
             self.write(addr, data);
+            end = end.max(segment.address() + segment.size());
+            let (off, len) = segment.file_range();
+            if phoff >= off && phoff + phdrs.len() as u64 <= off + len {
+                phdr = Some(segment.address() + (phoff - off));
+            }
+        }
+        ElfInfo {
+            entry: elf.entry(),
+            xlen: if elf.is_64() { RvXlen::Rv64 } else { RvXlen::Rv32 },
+            end,
+            symbols: elf.symbols()
+                .filter_map(|sym| Some((sym.name().ok()?.to_string(), 
+                    sym.address())))
+                .filter(|(name, _)| !name.is_empty())
+                .collect(),
+            phdr,
+            phent,
+            phdrs,
         }
-        elf.entry() as u32
     }
 }
 
 
 impl Memory {
     pub fn load8(&self, addr: usize) -> u8 {
+        let addr = self.offset(addr);
         self.data[addr]
     }
     pub fn load16(&self, addr: usize) -> u16 {
         use std::convert::TryInto;
+        let addr = self.offset(addr);
         let slice: [u8; 2] = self.data[addr..addr + 2].try_into().unwrap();
         let res = u16::from_ne_bytes(slice);
         res
     }
     pub fn load32(&self, addr: usize) -> u32 {
         use std::convert::TryInto;
+        let addr = self.offset(addr);
         let slice: [u8; 4] = self.data[addr..addr + 4].try_into().unwrap();
         let res = u32::from_ne_bytes(slice);
         res
     }
+    pub fn load64(&self, addr: usize) -> u64 {
+        use std::convert::TryInto;
+        let addr = self.offset(addr);
+        let slice: [u8; 8] = self.data[addr..addr + 8].try_into().unwrap();
+        u64::from_le_bytes(slice)
+    }
     pub fn store8(&mut self, addr: usize, val: u8) {
+        let addr = self.offset(addr);
         self.data[addr] = val;
     }
     pub fn store16(&mut self, addr: usize, val: u16) {
+        let addr = self.offset(addr);
         self.data[addr..addr + 2].copy_from_slice(&val.to_le_bytes());
     }
     pub fn store32(&mut self, addr: usize, val: u32) {
+        let addr = self.offset(addr);
         self.data[addr..addr + 4].copy_from_slice(&val.to_le_bytes());
     }
+    pub fn store64(&mut self, addr: usize, val: u64) {
+        let addr = self.offset(addr);
+        self.data[addr..addr + 8].copy_from_slice(&val.to_le_bytes());
+    }
 }
 
 
 
+#[cfg(test)]
+mod test {
+    use crate::mem::*;
+    use crate::isa::RvIsa;
+
+    #[test]
+    fn sv32_translation() {
+        let mut ram = Memory::new(0x40_0000);
+        let mut csr = CsrFile::new(&RvIsa::parse("rv32i_zicsr").unwrap());
+        let mut mmu = Mmu::new();
+        // 0x0040_0000: a read/write page, then a read-only user page
+        ram.store32(0x10_0004, 0x101 << 10 | PTE_V);
+        ram.store32(0x10_1000, 0x200 << 10 | PTE_R | PTE_W | PTE_V);
+        ram.store32(0x10_1004, 0x201 << 10 | PTE_U | PTE_R | PTE_V);
+        // 0x0080_0000: an execute-only megapage
+        ram.store32(0x10_0008, PTE_X | PTE_V);
+        csr.satp = 1 << 31 | 0x100;
+        csr.prv = RvPrivilege::Supervisor;
+        let mut tr = |ram: &mut Memory, csr: &CsrFile, vaddr, access| {
+            mmu.translate(ram, csr, vaddr, access)
+        };
+
+        assert_eq!(tr(&mut ram, &csr, 0x0040_0123, RvAccess::Load), 
+            Ok(0x20_0123));
+        assert_eq!(ram.load32(0x10_1000) & (PTE_A | PTE_D), PTE_A);
+        assert_eq!(tr(&mut ram, &csr, 0x0040_0004, RvAccess::Store), 
+            Ok(0x20_0004));
+        assert_eq!(ram.load32(0x10_1000) & (PTE_A | PTE_D), PTE_A | PTE_D);
+        assert_eq!(tr(&mut ram, &csr, 0x0040_0000, RvAccess::Fetch), 
+            Err(RvTrap::Exception(RvException::InstrPageFault, 0x0040_0000)));
+
+        // User pages are only accessible to supervisor mode with SUM
+        assert_eq!(tr(&mut ram, &csr, 0x0040_1000, RvAccess::Load), 
+            Err(RvTrap::Exception(RvException::LoadPageFault, 0x0040_1000)));
+        csr.mstatus |= MSTATUS_SUM;
+        assert_eq!(tr(&mut ram, &csr, 0x0040_1000, RvAccess::Load), 
+            Ok(0x20_1000));
+        assert_eq!(tr(&mut ram, &csr, 0x0040_1000, RvAccess::Store), 
+            Err(RvTrap::Exception(RvException::StorePageFault, 0x0040_1000)));
+
+        // Executable pages are readable with MXR
+        assert_eq!(tr(&mut ram, &csr, 0x0081_2344, RvAccess::Fetch), 
+            Ok(0x1_2344));
+        assert!(tr(&mut ram, &csr, 0x0081_2344, RvAccess::Load).is_err());
+        csr.mstatus |= MSTATUS_MXR;
+        assert_eq!(tr(&mut ram, &csr, 0x0081_2344, RvAccess::Load), 
+            Ok(0x1_2344));
+
+        // Machine mode is not translated
+        csr.prv = RvPrivilege::Machine;
+        assert_eq!(tr(&mut ram, &csr, 0x0040_0123, RvAccess::Load), 
+            Ok(0x40_0123));
+    }
+
+    #[test]
+    fn tlb_flush() {
+        let mut ram = Memory::new(0x40_0000);
+        let mut csr = CsrFile::new(&RvIsa::parse("rv32i_zicsr").unwrap());
+        let mut mmu = Mmu::new();
+        ram.store32(0x10_0000, 0x101 << 10 | PTE_V);
+        ram.store32(0x10_1004, 0x200 << 10 | PTE_R | PTE_V);
+        csr.satp = 1 << 31 | 0x100;
+        csr.prv = RvPrivilege::Supervisor;
+        assert_eq!(mmu.translate(&mut ram, &csr, 0x1008, RvAccess::Load), 
+            Ok(0x20_0008));
+        // The old translation is used until the TLB is flushed
+        ram.store32(0x10_1004, 0x300 << 10 | PTE_R | PTE_V);
+        assert_eq!(mmu.translate(&mut ram, &csr, 0x1008, RvAccess::Load), 
+            Ok(0x20_0008));
+        mmu.flush(Some(0x2000), None);
+        assert_eq!(mmu.translate(&mut ram, &csr, 0x1008, RvAccess::Load), 
+            Ok(0x20_0008));
+        mmu.flush(Some(0x1ffc), Some(0));
+        assert_eq!(mmu.translate(&mut ram, &csr, 0x1008, RvAccess::Load), 
+            Ok(0x30_0008));
+    }
+
+    #[test]
+    fn clint_registers() {
+        let mut clint = Clint::new();
+        clint.set_rate(3);
+        assert_eq!(clint.pending(), 0);
+        clint.store(CLINT_BASE + 0x4000, 2, 4);
+        clint.store(CLINT_BASE + 0x4004, 0, 4);
+        assert_eq!(clint.mtimecmp, 2);
+        for _ in 0..5 {
+            clint.tick();
+        }
+        assert_eq!(clint.load(CLINT_BASE + 0xbff8, 8), 1);
+        assert_eq!(clint.pending(), 0);
+        clint.tick();
+        assert_eq!(clint.pending(), 1 << 7);
+        // Only bit 0 of 'msip' is implemented
+        clint.store(CLINT_BASE, 0xffff_ffff, 4);
+        assert_eq!(clint.load(CLINT_BASE, 4), 1);
+        assert_eq!(clint.pending(), 1 << 7 | 1 << 3);
+        // The upper half of 'mtime' is a separate word on RV32
+        clint.store(CLINT_BASE + 0xbffc, 0x1234, 4);
+        assert_eq!(clint.mtime, 0x1234_0000_0002);
+        assert_eq!(clint.load(CLINT_BASE + 0xbffc, 2), 0x1234);
+        assert!(clint.contains(CLINT_BASE + 0xfff8, 8));
+        assert!(!clint.contains(CLINT_BASE + 0xfffc, 8));
+    }
+}
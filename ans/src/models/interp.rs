@@ -1,70 +1,371 @@
 
-use crate::{ mem::*, rv32::* };
+use crate::{ mem::*, rv32::*, csr::*, isa::*, crypto, syscall::* };
+use crate::softfloat::{ self, Format, RoundingMode };
 use object::{Object, ObjectSection};
 use object::elf::SHF_ALLOC;
 use std::fs;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
-pub struct RvRegs { data: [u32; 32] }
+/// Integer register file.
+///
+/// Registers hold XLEN-bit values: writes are truncated to XLEN bits. There
+/// are only 16 registers on RV32E (the decoder rejects any instruction
+/// which names x16-x31).
+pub struct RvRegs { xlen: RvXlen, data: Vec<u64> }
 impl RvRegs {
-    pub fn new() -> Self {
-        let mut res = Self { data: [0; 32] };
+    pub fn new(isa: &RvIsa) -> Self {
+        let mut res = Self { xlen: isa.xlen, data: vec![0; isa.regs()] };
+        // Returning from the program halts the machine, and the stack is
+        // below the initial stack pointer (ra and sp are x1 and x2 in every
+        // ABI, including ilp32e)
         res.data[1] = 0xdead_0000;
-        res.data[2] = 0x0030_0000;
+        res.data[2] = 0x0030_0000 & !(isa.stack_align() - 1);
         res
     }
-    pub fn read(&self, idx: RvReg) -> u32 {
+    pub fn read(&self, idx: RvReg) -> u64 {
         if idx.0 == 0 { 0 } else { self.data[idx.0] }
     }
-    pub fn write(&mut self, idx: RvReg, val: u32) {
-        assert!(idx.0 != 0);
-        self.data[idx.0] = val;
+    pub fn write(&mut self, idx: RvReg, val: u64) {
+        if idx.0 != 0 { self.data[idx.0] = val & self.xlen.mask(); }
+    }
+}
+
+/// Floating-point register file.
+///
+/// Single-precision values are NaN-boxed: the upper 32 bits of the register
+/// are set to all ones.
+pub struct RvFRegs { data: [u64; 32] }
+impl Default for RvFRegs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl RvFRegs {
+    pub fn new() -> Self {
+        Self { data: [0; 32] }
+    }
+    /// Read a value in some format.
+    ///
+    /// Single-precision values that are not properly NaN-boxed are read as
+    /// the canonical NaN.
+    pub fn read(&self, idx: RvFReg, fmt: RvFpFmt) -> u64 {
+        let val = self.data[idx.0];
+        match fmt {
+            RvFpFmt::D => val,
+            RvFpFmt::S => {
+                if val >> 32 == 0xffff_ffff {
+                    val & 0xffff_ffff
+                } else {
+                    Format::Single.canonical_nan()
+                }
+            },
+        }
+    }
+    /// Write a value in some format.
+    pub fn write(&mut self, idx: RvFReg, fmt: RvFpFmt, val: u64) {
+        self.data[idx.0] = match fmt {
+            RvFpFmt::D => val,
+            RvFpFmt::S => 0xffff_ffff_0000_0000 | (val & 0xffff_ffff),
+        };
+    }
+}
+
+/// Vector register file.
+///
+/// Each register holds VLEN bits, stored as little-endian bytes. The 
+/// registers in a group (when LMUL > 1) are consecutive, so the elements of
+/// a group are laid out as if it were a single long register.
+pub struct RvVRegs { data: Vec<u8> }
+impl RvVRegs {
+    pub fn new(isa: &RvIsa) -> Self {
+        Self { data: vec![0; 32 * isa.vlen as usize / 8] }
+    }
+    /// Offset of the element 'elem' of the group starting at 'idx'.
+    fn offset(&self, idx: RvVReg, elem: usize, sew: u32) -> usize {
+        idx.0 * (self.data.len() / 32) + elem * (sew as usize / 8)
+    }
+    /// Read an element of some width (zero-extended to 64 bits).
+    pub fn read(&self, idx: RvVReg, elem: usize, sew: u32) -> u64 {
+        let off = self.offset(idx, elem, sew);
+        self.data[off..off + sew as usize / 8].iter().rev()
+            .fold(0, |acc, b| (acc << 8) | *b as u64)
+    }
+    /// Write an element of some width (truncating the value).
+    pub fn write(&mut self, idx: RvVReg, elem: usize, sew: u32, val: u64) {
+        let off = self.offset(idx, elem, sew);
+        let bytes = val.to_le_bytes();
+        self.data[off..off + sew as usize / 8]
+            .copy_from_slice(&bytes[..sew as usize / 8]);
+    }
+    /// Read a bit of a mask register.
+    pub fn read_bit(&self, idx: RvVReg, elem: usize) -> bool {
+        let off = self.offset(idx, elem / 8, 8);
+        self.data[off] & (1 << (elem & 7)) != 0
+    }
+    /// Write a bit of a mask register.
+    pub fn write_bit(&mut self, idx: RvVReg, elem: usize, val: bool) {
+        let off = self.offset(idx, elem / 8, 8);
+        self.data[off] = (self.data[off] & !(1 << (elem & 7))) 
+            | ((val as u8) << (elem & 7));
     }
 }
 
 pub enum StepResult {
-    /// Increment the program counter
+    /// Increment the program counter (by the length of the instruction)
     Next,
     /// Write the program counter
-    Goto(u32),
+    Goto(u64),
     /// Terminate the machine
     Terminate,
+    /// The program exited (with a system call) with some status
+    Exit(i32),
+    /// The instruction at the program counter could not be decoded (or is
+    /// otherwise illegal)
+    IllegalInstruction(DecodeError),
+    /// The instruction raised an exception
+    Trap(RvTrap),
 }
 
-/// Simple interpreting-style evaluator/virtual machine for RV32I programs.
+/// Simple interpreting-style evaluator/virtual machine for RV32 and RV64 
+/// programs.
 ///
 /// In this model, [Interpreter::step] fetches and single instruction from
 /// memory and executes it (producing some effect on the [data-like] state of 
 /// the machine). Then, a [StepResult] indicates some effect on the control 
 /// state of the machine.
 ///
+/// By default, the machine runs like a user-mode process: ECALL performs a
+/// Linux system call, EBREAK terminates the machine, and other exceptions
+/// halt it. Bare-metal programs (see [Interpreter::set_user_emulation])
+/// handle their own exceptions and interrupts instead.
+///
 pub struct Interpreter {
+    /// Width of the integer registers and the implemented extensions.
+    isa: RvIsa,
     /// Program counter.
-    pc:  u32,
+    pc:  u64,
+    /// Length (in bytes) of the instruction at the program counter.
+    ilen: u64,
     /// Register file.
     reg: RvRegs,
+    /// Floating-point register file.
+    freg: RvFRegs,
+    /// Vector register file.
+    vreg: RvVRegs,
+    /// Control and status registers.
+    csr: CsrFile,
+    /// Reservation set held by the last LR instruction.
+    rsv: Option<u64>,
     /// Simple emulated memory device.
     ram: Memory,
+    /// Translation of virtual addresses.
+    mmu: Mmu,
+    /// Memory-mapped timer and software interrupts.
+    clint: Clint,
+    /// Open files and memory allocations of the emulated process.
+    sys: Syscalls,
+    /// Address of the HTIF 'tohost' register (for bare-metal programs).
+    tohost: Option<u64>,
+    /// Address of the HTIF 'fromhost' register.
+    fromhost: Option<u64>,
+    /// Emulate a Linux process instead of a bare-metal machine (see
+    /// [Interpreter::set_user_emulation]).
+    user_emulation: bool,
+    /// Print each executed instruction and the final registers.
+    trace: bool,
 }
 impl Interpreter {
-    pub fn new() -> Self {
+    /// Create a machine which implements every supported extension.
+    pub fn new(xlen: RvXlen) -> Self {
+        Self::with_isa(RvIsa::full(xlen))
+    }
+
+    /// Create a machine which only implements the extensions in the ISA.
+    pub fn with_isa(isa: RvIsa) -> Self {
         Self { 
+            isa,
             pc:  0,
-            reg: RvRegs::new(),
+            ilen: 4,
+            reg: RvRegs::new(&isa),
+            freg: RvFRegs::new(),
+            vreg: RvVRegs::new(&isa),
+            csr: CsrFile::new(&isa),
+            rsv: None,
             ram: Memory::new(0x0040_0000),
+            mmu: Mmu::new(),
+            clint: Clint::new(),
+            sys: Syscalls::new(),
+            tohost: None,
+            fromhost: None,
+            user_emulation: true,
+            trace: true,
+        }
+    }
+
+    /// Enable or disable the printing of each executed instruction.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Emulate a Linux process (the default), or a bare-metal machine.
+    ///
+    /// When a process is emulated, ECALL performs a system call, EBREAK
+    /// terminates the machine, exceptions halt it, and interrupts are
+    /// never taken. Otherwise, every trap is taken in machine mode (at the
+    /// address in 'mtvec', whatever its value).
+    pub fn set_user_emulation(&mut self, enable: bool) {
+        self.user_emulation = enable;
+    }
+
+    /// Set the number of retired instructions per tick of the timer.
+    pub fn set_timer_rate(&mut self, instrs_per_tick: u64) {
+        self.clint.set_rate(instrs_per_tick);
+    }
+
+    /// The emulated memory device.
+    pub fn memory(&self) -> &Memory {
+        &self.ram
+    }
+
+    /// Load an ELF file into memory, and build the initial stack of a
+    /// process with some arguments (including the program name) and
+    /// environment variables.
+    ///
+    /// Sets the program counter to the ELF entrypoint. The machine is reset
+    /// to RV32 or RV64 (depending on the ELF class) if necessary, keeping
+    /// the same set of extensions.
+    pub fn load_elf(&mut self, filename: &str, argv: &[&str], 
+                    envp: &[&str]) -> ElfInfo
+    {
+        let info = self.ram.load_elf(filename);
+        if info.xlen != self.isa.xlen {
+            self.isa.xlen = info.xlen;
+            self.reg  = RvRegs::new(&self.isa);
+            self.csr  = CsrFile::new(&self.isa);
+        }
+        self.pc = info.entry;
+        self.sys.set_brk(info.end);
+        self.tohost = info.symbols.get("tohost").copied();
+        self.fromhost = info.symbols.get("fromhost").copied();
+        // Bare-metal programs (loaded above the usual memory) set up their 
+        // own stack
+        if self.ram.base == 0 {
+            self.init_stack(&info, argv, envp);
+        }
+        info
+    }
+
+    /// Build the initial stack of a process (as in the System V ABI), and
+    /// point sp at it.
+    ///
+    /// The stack pointer points at argc, followed by the argv and envp
+    /// arrays (each terminated by a null pointer) and the auxiliary vector.
+    /// The strings they point to (and the 16 bytes for AT_RANDOM) are above
+    /// them, at the top of the stack. The stack pointer is aligned as the
+    /// ABI requires (to 4 bytes for ilp32e).
+    fn init_stack(&mut self, info: &ElfInfo, argv: &[&str], envp: &[&str]) {
+        use std::hash::{BuildHasher, Hasher};
+        const AT_NULL:   u64 = 0;
+        const AT_PHDR:   u64 = 3;
+        const AT_PHENT:  u64 = 4;
+        const AT_PHNUM:  u64 = 5;
+        const AT_PAGESZ: u64 = 6;
+        const AT_ENTRY:  u64 = 9;
+        const AT_RANDOM: u64 = 25;
+
+        fn push(ram: &mut Memory, sp: &mut u64, bytes: &[u8]) -> u64 {
+            *sp -= bytes.len() as u64;
+            ram.write(*sp as usize, bytes);
+            *sp
+        }
+        let mut sp = MMAP_BASE;
+        let mut strings = |strs: &[&str]| -> Vec<u64> {
+            strs.iter().map(|s| {
+                push(&mut self.ram, &mut sp, &[0]);
+                push(&mut self.ram, &mut sp, s.as_bytes())
+            }).collect()
+        };
+        let args = strings(argv);
+        let envs = strings(envp);
+        let mut random = Vec::new();
+        for _ in 0..2 {
+            let state = std::collections::hash_map::RandomState::new();
+            random.extend_from_slice(
+                &state.build_hasher().finish().to_le_bytes());
+        }
+        let random = push(&mut self.ram, &mut sp, &random);
+        // The program headers are copied if they weren't loaded
+        let phdr = match info.phdr {
+            Some(addr) => addr,
+            None => {
+                sp &= !7;
+                push(&mut self.ram, &mut sp, &info.phdrs)
+            },
+        };
+
+        let mut words = vec![args.len() as u64];
+        words.extend(args);
+        words.push(0);
+        words.extend(envs);
+        words.push(0);
+        words.extend_from_slice(&[
+            AT_PHDR, phdr,
+            AT_PHENT, info.phent,
+            AT_PHNUM, info.phdrs.len() as u64 / info.phent.max(1),
+            AT_PAGESZ, PAGE_SIZE,
+            AT_ENTRY, info.entry,
+            AT_RANDOM, random,
+            AT_NULL, 0,
+        ]);
+        let size = self.isa.xlen.bits() as u64 / 8;
+        let align = self.isa.stack_align();
+        let sp = (sp - words.len() as u64 * size) & !(align - 1);
+        for (idx, word) in words.iter().enumerate() {
+            let addr = (sp + idx as u64 * size) as usize;
+            match self.isa.xlen {
+                RvXlen::Rv32 => self.ram.store32(addr, *word as u32),
+                RvXlen::Rv64 => self.ram.store64(addr, *word),
+            }
+        }
+        self.reg.write(RvReg(2), sp);
+    }
+
+    /// Truncate a value to XLEN bits.
+    fn trunc(&self, x: u64) -> u64 {
+        x & self.isa.xlen.mask()
+    }
+
+    /// Interpret an XLEN-bit value as a signed integer.
+    fn signed(&self, x: u64) -> i64 {
+        match self.isa.xlen {
+            RvXlen::Rv32 => x as u32 as i32 as i64,
+            RvXlen::Rv64 => x as i64,
+        }
+    }
+
+    /// Compute the effective address 'rs1 + imm'.
+    fn eff_addr(&self, rs1: RvReg, imm: i32) -> u64 {
+        self.trunc(self.reg.read(rs1).wrapping_add(imm as i64 as u64))
+    }
+
+    /// Evaluate the result of some ALU operation on XLEN-bit values.
+    fn eval_alu_op(&self, x: u64, y: u64, op: RvALUOp) -> u64 {
+        match self.isa.xlen {
+            RvXlen::Rv32 => Self::eval_alu_op32(x as u32, y as u32, op) as u64,
+            RvXlen::Rv64 => Self::eval_alu_op64(x, y, op),
         }
     }
 
-    /// Load an RV32 ELF file into memory.
+    /// Evaluate the result of some word-sized ALU operation (on RV64).
     ///
-    /// Sets the program counter to the ELF entrypoint.
-    pub fn load_elf(&mut self, filename: &str) {
-        let entrypt = self.ram.load_elf(filename);
-        self.pc = entrypt;
+    /// The operation is performed on the lower 32 bits of the operands, and 
+    /// the result is sign-extended.
+    fn eval_alu_op_w(x: u64, y: u64, op: RvALUOp) -> u64 {
+        Self::eval_alu_op32(x as u32, y as u32, op) as i32 as i64 as u64
     }
 
-    /// Evaluate the result of some ALU operation
-    fn eval_alu_op(x: u32, y: u32, op: RvALUOp) -> u32 {
+    /// Evaluate the result of some ALU operation on 32-bit values.
+    fn eval_alu_op32(x: u32, y: u32, op: RvALUOp) -> u32 {
         use std::ops::{BitOr, BitAnd, BitXor};
         match op {
             RvALUOp::Add  => x.wrapping_add(y),
@@ -72,36 +373,1128 @@ impl Interpreter {
             RvALUOp::And  => x.bitand(y),
             RvALUOp::Or   => x.bitor(y),
             RvALUOp::Xor  => x.bitxor(y),
-            RvALUOp::Sll  => x.checked_shl(y).unwrap_or(0),
-            RvALUOp::Srl  => x.checked_shr(y).unwrap_or(0),
+            RvALUOp::Sll  => x.wrapping_shl(y),
+            RvALUOp::Srl  => x.wrapping_shr(y),
+            RvALUOp::Sra  => (x as i32).wrapping_shr(y) as u32,
             RvALUOp::Slt  => if (x as i32) < (y as i32) { 1 } else { 0 },
             RvALUOp::Sltu => if x < y { 1 } else { 0 },
-            _ => unimplemented!("{:?}", op),
+
+            RvALUOp::Mul  => x.wrapping_mul(y),
+            RvALUOp::Mulh => {
+                ((x as i32 as i64 * y as i32 as i64) >> 32) as u32
+            },
+            RvALUOp::Mulhsu => {
+                ((x as i32 as i64).wrapping_mul(y as i64) >> 32) as u32
+            },
+            RvALUOp::Mulhu => ((x as u64 * y as u64) >> 32) as u32,
+
+            // Division by zero and signed overflow don't trap; the results
+            // are defined by the spec.
+            RvALUOp::Div => match (x as i32, y as i32) {
+                (_, 0) => 0xffff_ffff,
+                (x, y) => x.wrapping_div(y) as u32,
+            },
+            RvALUOp::Divu => match y {
+                0 => 0xffff_ffff,
+                y => x / y,
+            },
+            RvALUOp::Rem => match (x as i32, y as i32) {
+                (x, 0) => x as u32,
+                (x, y) => x.wrapping_rem(y) as u32,
+            },
+            RvALUOp::Remu => match y {
+                0 => x,
+                y => x % y,
+            },
+
+            RvALUOp::Sh1add => (x << 1).wrapping_add(y),
+            RvALUOp::Sh2add => (x << 2).wrapping_add(y),
+            RvALUOp::Sh3add => (x << 3).wrapping_add(y),
+
+            RvALUOp::Andn => x & !y,
+            RvALUOp::Orn  => x | !y,
+            RvALUOp::Xnor => !(x ^ y),
+            RvALUOp::Clz  => x.leading_zeros(),
+            RvALUOp::Ctz  => x.trailing_zeros(),
+            RvALUOp::Cpop => x.count_ones(),
+            RvALUOp::Max  => (x as i32).max(y as i32) as u32,
+            RvALUOp::Maxu => x.max(y),
+            RvALUOp::Min  => (x as i32).min(y as i32) as u32,
+            RvALUOp::Minu => x.min(y),
+            RvALUOp::SextB => x as i8 as i32 as u32,
+            RvALUOp::SextH => x as i16 as i32 as u32,
+            RvALUOp::ZextH => x & 0xffff,
+            RvALUOp::Rol  => x.rotate_left(y & 0x1f),
+            RvALUOp::Ror  => x.rotate_right(y & 0x1f),
+            RvALUOp::OrcB => {
+                let mut res = 0;
+                for i in 0..4 {
+                    if (x >> (i * 8)) & 0xff != 0 {
+                        res |= 0xff << (i * 8);
+                    }
+                }
+                res
+            },
+            RvALUOp::Rev8 => x.swap_bytes(),
+
+            RvALUOp::Bclr => x & !(1 << (y & 0x1f)),
+            RvALUOp::Bext => (x >> (y & 0x1f)) & 1,
+            RvALUOp::Binv => x ^ (1 << (y & 0x1f)),
+            RvALUOp::Bset => x | (1 << (y & 0x1f)),
+
+            RvALUOp::Sha256Sig0 => crypto::sha256sig0(x),
+            RvALUOp::Sha256Sig1 => crypto::sha256sig1(x),
+            RvALUOp::Sha256Sum0 => crypto::sha256sum0(x),
+            RvALUOp::Sha256Sum1 => crypto::sha256sum1(x),
+            RvALUOp::Sha512Sig0h => crypto::sha512sig0h(x, y),
+            RvALUOp::Sha512Sig0l => crypto::sha512sig0l(x, y),
+            RvALUOp::Sha512Sig1h => crypto::sha512sig1h(x, y),
+            RvALUOp::Sha512Sig1l => crypto::sha512sig1l(x, y),
+            RvALUOp::Sha512Sum0r => crypto::sha512sum0r(x, y),
+            RvALUOp::Sha512Sum1r => crypto::sha512sum1r(x, y),
+            RvALUOp::Sm3P0 => crypto::sm3p0(x),
+            RvALUOp::Sm3P1 => crypto::sm3p1(x),
+
+            RvALUOp::AddUw | RvALUOp::Sh1addUw | RvALUOp::Sh2addUw 
+            | RvALUOp::Sh3addUw | RvALUOp::SllUw | RvALUOp::Sha512Sig0 
+            | RvALUOp::Sha512Sig1 | RvALUOp::Sha512Sum0 
+            | RvALUOp::Sha512Sum1 => {
+                unreachable!("{:?} is only defined on RV64", op)
+            },
+        }
+    }
+
+    /// Evaluate the result of some ALU operation on 64-bit values.
+    fn eval_alu_op64(x: u64, y: u64, op: RvALUOp) -> u64 {
+        use std::ops::{BitOr, BitAnd, BitXor};
+        match op {
+            RvALUOp::Add  => x.wrapping_add(y),
+            RvALUOp::Sub  => x.wrapping_sub(y),
+            RvALUOp::And  => x.bitand(y),
+            RvALUOp::Or   => x.bitor(y),
+            RvALUOp::Xor  => x.bitxor(y),
+            RvALUOp::Sll  => x.wrapping_shl(y as u32),
+            RvALUOp::Srl  => x.wrapping_shr(y as u32),
+            RvALUOp::Sra  => (x as i64).wrapping_shr(y as u32) as u64,
+            RvALUOp::Slt  => if (x as i64) < (y as i64) { 1 } else { 0 },
+            RvALUOp::Sltu => if x < y { 1 } else { 0 },
+
+            RvALUOp::Mul  => x.wrapping_mul(y),
+            RvALUOp::Mulh => {
+                ((x as i64 as i128 * y as i64 as i128) >> 64) as u64
+            },
+            RvALUOp::Mulhsu => {
+                ((x as i64 as i128).wrapping_mul(y as i128) >> 64) as u64
+            },
+            RvALUOp::Mulhu => ((x as u128 * y as u128) >> 64) as u64,
+
+            RvALUOp::Div => match (x as i64, y as i64) {
+                (_, 0) => u64::MAX,
+                (x, y) => x.wrapping_div(y) as u64,
+            },
+            RvALUOp::Divu => match y {
+                0 => u64::MAX,
+                y => x / y,
+            },
+            RvALUOp::Rem => match (x as i64, y as i64) {
+                (x, 0) => x as u64,
+                (x, y) => x.wrapping_rem(y) as u64,
+            },
+            RvALUOp::Remu => match y {
+                0 => x,
+                y => x % y,
+            },
+
+            RvALUOp::Sh1add => (x << 1).wrapping_add(y),
+            RvALUOp::Sh2add => (x << 2).wrapping_add(y),
+            RvALUOp::Sh3add => (x << 3).wrapping_add(y),
+
+            RvALUOp::Andn => x & !y,
+            RvALUOp::Orn  => x | !y,
+            RvALUOp::Xnor => !(x ^ y),
+            RvALUOp::Clz  => x.leading_zeros() as u64,
+            RvALUOp::Ctz  => x.trailing_zeros() as u64,
+            RvALUOp::Cpop => x.count_ones() as u64,
+            RvALUOp::Max  => (x as i64).max(y as i64) as u64,
+            RvALUOp::Maxu => x.max(y),
+            RvALUOp::Min  => (x as i64).min(y as i64) as u64,
+            RvALUOp::Minu => x.min(y),
+            RvALUOp::SextB => x as i8 as i64 as u64,
+            RvALUOp::SextH => x as i16 as i64 as u64,
+            RvALUOp::ZextH => x & 0xffff,
+            RvALUOp::Rol  => x.rotate_left((y & 0x3f) as u32),
+            RvALUOp::Ror  => x.rotate_right((y & 0x3f) as u32),
+            RvALUOp::OrcB => {
+                let mut res = 0;
+                for i in 0..8 {
+                    if (x >> (i * 8)) & 0xff != 0 {
+                        res |= 0xff << (i * 8);
+                    }
+                }
+                res
+            },
+            RvALUOp::Rev8 => x.swap_bytes(),
+
+            RvALUOp::Bclr => x & !(1 << (y & 0x3f)),
+            RvALUOp::Bext => (x >> (y & 0x3f)) & 1,
+            RvALUOp::Binv => x ^ (1 << (y & 0x3f)),
+            RvALUOp::Bset => x | (1 << (y & 0x3f)),
+
+            // The first operand is zero-extended from 32 bits
+            RvALUOp::AddUw    => (x & 0xffff_ffff).wrapping_add(y),
+            RvALUOp::Sh1addUw => ((x & 0xffff_ffff) << 1).wrapping_add(y),
+            RvALUOp::Sh2addUw => ((x & 0xffff_ffff) << 2).wrapping_add(y),
+            RvALUOp::Sh3addUw => ((x & 0xffff_ffff) << 3).wrapping_add(y),
+            RvALUOp::SllUw    => (x & 0xffff_ffff) << (y & 0x3f),
+
+            RvALUOp::Sha512Sig0 => crypto::sha512sig0(x),
+            RvALUOp::Sha512Sig1 => crypto::sha512sig1(x),
+            RvALUOp::Sha512Sum0 => crypto::sha512sum0(x),
+            RvALUOp::Sha512Sum1 => crypto::sha512sum1(x),
+            // The SHA-256 and SM3 functions sign-extend their result
+            RvALUOp::Sha256Sig0 | RvALUOp::Sha256Sig1 | RvALUOp::Sha256Sum0 
+            | RvALUOp::Sha256Sum1 | RvALUOp::Sm3P0 | RvALUOp::Sm3P1 => {
+                Self::eval_alu_op_w(x, y, op)
+            },
+
+            RvALUOp::Sha512Sig0h | RvALUOp::Sha512Sig0l | RvALUOp::Sha512Sig1h 
+            | RvALUOp::Sha512Sig1l | RvALUOp::Sha512Sum0r 
+            | RvALUOp::Sha512Sum1r => {
+                unreachable!("{:?} is only defined on RV32", op)
+            },
         }
     }
 
     /// Evaluate some condition
-    fn eval_branch_op(x: u32, y: u32, op: RvBranchOp) -> bool {
+    fn eval_branch_op(&self, x: u64, y: u64, op: RvBranchOp) -> bool {
         match op {
             RvBranchOp::Eq  => x == y,
             RvBranchOp::Ne  => x != y,
-            RvBranchOp::Lt  => (x as i32) < (y as i32),
-            RvBranchOp::Ge  => (x as i32) >= (y as i32),
+            RvBranchOp::Lt  => self.signed(x) < self.signed(y),
+            RvBranchOp::Ge  => self.signed(x) >= self.signed(y),
             RvBranchOp::Ltu => x < y,
             RvBranchOp::Geu => x >= y,
         }
     }
 
+    /// Translate the virtual address of an access to 'len' bytes, which 
+    /// must then be inside of the memory (or the registers of the CLINT,
+    /// for loads and stores) and permitted by physical memory protection.
+    fn translate(&mut self, addr: u64, len: usize, access: RvAccess) 
+        -> Result<usize, RvTrap> 
+    {
+        let paddr = self.mmu.translate(&mut self.ram, &self.csr, addr, access)?;
+        let prv = self.csr.access_prv(access);
+        let mapped = self.ram.contains(paddr as usize, len)
+            || (access != RvAccess::Fetch && self.clint.contains(paddr, len));
+        if !mapped || !self.csr.pmp_permits(paddr, len as u64, access, prv) {
+            return Err(RvTrap::Exception(access.access_fault(), addr));
+        }
+        Ok(paddr as usize)
+    }
+
+    /// Translate the address of each byte of an access. Only accesses which
+    /// cross a page boundary need more than one translation.
+    fn translate_bytes(&mut self, addr: u64, len: usize, access: RvAccess)
+        -> Result<Vec<usize>, RvTrap>
+    {
+        if (addr & 0xfff) as usize + len <= 0x1000 {
+            let base = self.translate(addr, len, access)?;
+            return Ok((base..base + len).collect());
+        }
+        (0..len as u64)
+            .map(|off| {
+                self.translate(self.trunc(addr.wrapping_add(off)), 1, access)
+            })
+            .collect()
+    }
+
+    /// Load a (zero-extended) value from memory.
+    fn load(&mut self, addr: u64, width: RvWidth) -> Result<u64, RvTrap> {
+        let len = width.bytes();
+        let addrs = self.translate_bytes(addr, len, RvAccess::Load)?;
+        let base = addrs[0];
+        if self.clint.contains(base as u64, len) {
+            return Ok(self.clint.load(base as u64, len));
+        }
+        let res = match len {
+            _ if addrs[len - 1] != base + len - 1 => {
+                addrs.iter().rev()
+                    .fold(0, |acc, addr| (acc << 8) | self.ram.load8(*addr) as u64)
+            },
+            1 => self.ram.load8(base) as u64,
+            2 => self.ram.load16(base) as u64,
+            4 => self.ram.load32(base) as u64,
+            _ => self.ram.load64(base),
+        };
+        Ok(res)
+    }
+
+    /// Store a value to memory, returning the physical address.
+    ///
+    /// Any store invalidates the reservation set held by this hart.
+    fn store(&mut self, addr: u64, val: u64, width: RvWidth) 
+        -> Result<usize, RvTrap> 
+    {
+        let len = width.bytes();
+        let addrs = self.translate_bytes(addr, len, RvAccess::Store)?;
+        let base = addrs[0];
+        match len {
+            _ if self.clint.contains(base as u64, len) => {
+                self.clint.store(base as u64, val, len);
+            },
+            _ if addrs[len - 1] != base + len - 1 => {
+                for (idx, addr) in addrs.iter().enumerate() {
+                    self.ram.store8(*addr, (val >> (idx * 8)) as u8);
+                }
+            },
+            1 => self.ram.store8(base, val as u8),
+            2 => self.ram.store16(base, val as u16),
+            4 => self.ram.store32(base, val as u32),
+            _ => self.ram.store64(base, val),
+        }
+        self.rsv = None;
+        Ok(base)
+    }
+
+    /// Evaluate the result of some atomic memory operation.
+    ///
+    /// Word-sized operands are sign-extended to 64 bits.
+    fn eval_amo_op(x: u64, y: u64, op: RvAmoOp) -> u64 {
+        match op {
+            RvAmoOp::Swap => y,
+            RvAmoOp::Add  => x.wrapping_add(y),
+            RvAmoOp::Xor  => x ^ y,
+            RvAmoOp::And  => x & y,
+            RvAmoOp::Or   => x | y,
+            RvAmoOp::Min  => (x as i64).min(y as i64) as u64,
+            RvAmoOp::Max  => (x as i64).max(y as i64) as u64,
+            RvAmoOp::Minu => x.min(y),
+            RvAmoOp::Maxu => x.max(y),
+        }
+    }
+
+    /// Get the effective address of an atomic memory operation, which 
+    /// must be naturally aligned.
+    fn amo_addr(&self, rs1: RvReg, width: RvWidth, cause: RvException) 
+        -> Result<u64, RvTrap>
+    {
+        let addr = self.reg.read(rs1);
+        if addr & (width.bytes() as u64 - 1) != 0 {
+            return Err(RvTrap::Exception(cause, addr));
+        }
+        Ok(addr)
+    }
+
+    /// Load the (sign-extended) operand of an atomic memory operation.
+    ///
+    /// The address is translated as a store for AMOs (which need write 
+    /// permission).
+    fn amo_load(&mut self, addr: u64, width: RvWidth, access: RvAccess) 
+        -> Result<u64, RvTrap> 
+    {
+        let len  = width.bytes();
+        let addr = self.translate(addr, len, access)?;
+        Ok(match width {
+            _ if self.clint.contains(addr as u64, len) => {
+                let val = self.clint.load(addr as u64, len);
+                if len == 4 { val as i32 as i64 as u64 } else { val }
+            },
+            RvWidth::Word   => self.ram.load32(addr) as i32 as i64 as u64,
+            RvWidth::Double => self.ram.load64(addr),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Execute an atomic instruction.
+    ///
+    /// The reservation set is the naturally-aligned word or doubleword 
+    /// containing the address. With only a single hart, the ordering bits 
+    /// have no effect.
+    fn exec_atomic(&mut self, inst: RvInstr) -> Result<(), RvTrap> {
+        match inst {
+            RvInstr::Lr(rd, rs1, width, _aq, _rl) => {
+                let addr = self.amo_addr(rs1, width, 
+                    RvException::LoadMisaligned)?;
+                let res  = self.amo_load(addr, width, RvAccess::Load)?;
+                self.reg.write(rd, res);
+                self.rsv = Some(addr);
+            },
+            RvInstr::Sc(rd, rs1, rs2, width, _aq, _rl) => {
+                let addr = self.amo_addr(rs1, width, 
+                    RvException::StoreMisaligned)?;
+                if self.rsv == Some(addr) {
+                    self.store(addr, self.reg.read(rs2), width)?;
+                    self.reg.write(rd, 0);
+                } else {
+                    self.reg.write(rd, 1);
+                }
+                self.rsv = None;
+            },
+            RvInstr::Amo(rd, rs1, rs2, op, width, _aq, _rl) => {
+                let addr = self.amo_addr(rs1, width, 
+                    RvException::StoreMisaligned)?;
+                let old  = self.amo_load(addr, width, RvAccess::Store)?;
+                let src  = match width {
+                    RvWidth::Word => self.reg.read(rs2) as u32 as i32 as i64 as u64,
+                    _ => self.reg.read(rs2),
+                };
+                let res  = Self::eval_amo_op(old, src, op);
+                self.store(addr, res, width)?;
+                self.reg.write(rd, old);
+            },
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Resolve the rounding mode for a floating-point operation.
+    fn rounding_mode(&self, rm: RvRoundingMode) -> RoundingMode {
+        let rm = match rm {
+            RvRoundingMode::Dyn => {
+                RvRoundingMode::try_from(self.csr.frm)
+                    .unwrap_or_else(|_| panic!("Invalid frm {:03b}", self.csr.frm))
+            },
+            rm => rm,
+        };
+        match rm {
+            RvRoundingMode::Rne => RoundingMode::NearestEven,
+            RvRoundingMode::Rtz => RoundingMode::TowardZero,
+            RvRoundingMode::Rdn => RoundingMode::Down,
+            RvRoundingMode::Rup => RoundingMode::Up,
+            RvRoundingMode::Rmm => RoundingMode::NearestMaxMagnitude,
+            RvRoundingMode::Dyn => panic!("Invalid dynamic rounding mode"),
+        }
+    }
+
+    /// Execute a floating-point instruction.
+    ///
+    /// Exception flags raised by the operation are accumulated in 'fflags'.
+    fn exec_fp(&mut self, inst: RvInstr) -> Result<(), RvTrap> {
+        let sf = |fmt: RvFpFmt| match fmt {
+            RvFpFmt::S => Format::Single,
+            RvFpFmt::D => Format::Double,
+        };
+        let mut fl = 0;
+        match inst {
+            RvInstr::FLoad(rd, rs1, imm, fmt) => {
+                let addr = self.eff_addr(rs1, imm);
+                let val = match fmt {
+                    RvFpFmt::S => self.load(addr, RvWidth::Word)?,
+                    RvFpFmt::D => self.load(addr, RvWidth::Double)?,
+                };
+                self.freg.write(rd, fmt, val);
+            },
+            RvInstr::FStore(rs1, rs2, imm, fmt) => {
+                // Stores move the raw bits, regardless of NaN-boxing
+                let addr = self.eff_addr(rs1, imm);
+                let val  = self.freg.read(rs2, RvFpFmt::D);
+                match fmt {
+                    RvFpFmt::S => self.store(addr, val, RvWidth::Word)?,
+                    RvFpFmt::D => self.store(addr, val, RvWidth::Double)?,
+                };
+            },
+            RvInstr::FArith(rd, rs1, rs2, op, fmt, rm) => {
+                let rm = self.rounding_mode(rm);
+                let x = self.freg.read(rs1, fmt);
+                let y = self.freg.read(rs2, fmt);
+                let f = match op {
+                    RvFpArithOp::Add => softfloat::add,
+                    RvFpArithOp::Sub => softfloat::sub,
+                    RvFpArithOp::Mul => softfloat::mul,
+                    RvFpArithOp::Div => softfloat::div,
+                };
+                self.freg.write(rd, fmt, f(sf(fmt), x, y, rm, &mut fl));
+            },
+            RvInstr::FSqrt(rd, rs1, fmt, rm) => {
+                let rm = self.rounding_mode(rm);
+                let x = self.freg.read(rs1, fmt);
+                self.freg.write(rd, fmt, softfloat::sqrt(sf(fmt), x, rm, &mut fl));
+            },
+            RvInstr::FOp(rd, rs1, rs2, op, fmt) => {
+                let x = self.freg.read(rs1, fmt);
+                let y = self.freg.read(rs2, fmt);
+                let sign = match fmt {
+                    RvFpFmt::S => 1 << 31,
+                    RvFpFmt::D => 1 << 63,
+                };
+                let res = match op {
+                    RvFpOp::Sgnj  => (x & !sign) | (y & sign),
+                    RvFpOp::Sgnjn => (x & !sign) | (!y & sign),
+                    RvFpOp::Sgnjx => x ^ (y & sign),
+                    RvFpOp::Min => softfloat::min_max(sf(fmt), x, y, false, &mut fl),
+                    RvFpOp::Max => softfloat::min_max(sf(fmt), x, y, true, &mut fl),
+                };
+                self.freg.write(rd, fmt, res);
+            },
+            RvInstr::FFma(rd, rs1, rs2, rs3, op, fmt, rm) => {
+                let rm = self.rounding_mode(rm);
+                let sign = match fmt {
+                    RvFpFmt::S => 1 << 31,
+                    RvFpFmt::D => 1 << 63,
+                };
+                let x = self.freg.read(rs1, fmt);
+                let y = self.freg.read(rs2, fmt);
+                let z = self.freg.read(rs3, fmt);
+                // Negate the product and/or the addend
+                let (x, z) = match op {
+                    RvFmaOp::Madd  => (x, z),
+                    RvFmaOp::Msub  => (x, z ^ sign),
+                    RvFmaOp::Nmsub => (x ^ sign, z),
+                    RvFmaOp::Nmadd => (x ^ sign, z ^ sign),
+                };
+                let res = softfloat::fma(sf(fmt), x, y, z, rm, &mut fl);
+                self.freg.write(rd, fmt, res);
+            },
+            RvInstr::FCmp(rd, rs1, rs2, op, fmt) => {
+                let x = self.freg.read(rs1, fmt);
+                let y = self.freg.read(rs2, fmt);
+                let res = match op {
+                    RvFpCmpOp::Eq => softfloat::eq(sf(fmt), x, y, &mut fl),
+                    RvFpCmpOp::Lt => softfloat::lt(sf(fmt), x, y, &mut fl),
+                    RvFpCmpOp::Le => softfloat::le(sf(fmt), x, y, &mut fl),
+                };
+                self.reg.write(rd, res as u64);
+            },
+            RvInstr::FClass(rd, rs1, fmt) => {
+                let x = self.freg.read(rs1, fmt);
+                self.reg.write(rd, softfloat::class_mask(sf(fmt), x) as u64);
+            },
+            RvInstr::FCvtToInt(rd, rs1, int, fmt, rm) => {
+                let rm = self.rounding_mode(rm);
+                let x = self.freg.read(rs1, fmt);
+                let res = softfloat::to_int(sf(fmt), x, int.is_signed(), 
+                    int.bits(), rm, &mut fl);
+                // Word results are sign-extended (even when unsigned)
+                let res = match int.bits() {
+                    32 => res as u32 as i32 as i64 as u64,
+                    _  => res,
+                };
+                self.reg.write(rd, res);
+            },
+            RvInstr::FCvtFromInt(rd, rs1, int, fmt, rm) => {
+                let rm = self.rounding_mode(rm);
+                let x = self.reg.read(rs1);
+                let res = softfloat::from_int(sf(fmt), x, int.is_signed(), 
+                    int.bits(), rm, &mut fl);
+                self.freg.write(rd, fmt, res);
+            },
+            RvInstr::FCvt(rd, rs1, dst, src, rm) => {
+                let rm = self.rounding_mode(rm);
+                let x = self.freg.read(rs1, src);
+                let res = softfloat::convert(sf(dst), sf(src), x, rm, &mut fl);
+                self.freg.write(rd, dst, res);
+            },
+            RvInstr::FMvToInt(rd, rs1, fmt) => {
+                let x = self.freg.read(rs1, RvFpFmt::D);
+                let res = match fmt {
+                    RvFpFmt::S => x as u32 as i32 as i64 as u64,
+                    RvFpFmt::D => x,
+                };
+                self.reg.write(rd, res);
+            },
+            RvInstr::FMvFromInt(rd, rs1, fmt) => {
+                let x = self.reg.read(rs1);
+                self.freg.write(rd, fmt, x);
+            },
+            _ => unreachable!(),
+        }
+        self.csr.fflags |= fl;
+        Ok(())
+    }
+
+    /// Perform a CSR operation with some source value.
+    ///
+    /// CSRRW does not read the CSR when 'rd' is x0, and CSRRS/CSRRC do not 
+    /// write the CSR when the source register is x0 (or the immediate is 0).
+    fn exec_csr_op(&mut self, rd: RvReg, csr: RvCsr, op: RvCsrOp, 
+                   src: u64, has_src: bool) -> Result<(), CsrError>
+    {
+        let (rden, wren) = match op {
+            RvCsrOp::Rw => (rd.0 != 0, true),
+            RvCsrOp::Rs | RvCsrOp::Rc => (true, has_src),
+        };
+        let res = if rden {
+            self.csr.read(csr)
+        } else {
+            Ok(0)
+        };
+        let res = res.and_then(|old| {
+            if wren {
+                let new = match op {
+                    RvCsrOp::Rw => src,
+                    RvCsrOp::Rs => old | src,
+                    RvCsrOp::Rc => old & !src,
+                };
+                self.csr.write(csr, new)?;
+            }
+            Ok(old)
+        });
+        self.reg.write(rd, res?);
+        Ok(())
+    }
+
+    /// Set the vector length and type, returning the new vector length.
+    ///
+    /// When 'avl' is `None`, the vector length is set to VLMAX. An 
+    /// unsupported type sets 'vill' (and the vector length is zero).
+    fn exec_vsetvl(&mut self, avl: Option<u64>, vtype: u64) -> u64 {
+        let vtype = RvVType(vtype);
+        if !vtype.is_valid() {
+            self.csr.vtype = 1 << (self.isa.xlen.bits() - 1);
+            self.csr.vl = 0;
+            return 0;
+        }
+        let vlmax = vtype.vlmax(self.isa.vlen) as u64;
+        self.csr.vtype = vtype.0;
+        self.csr.vl = avl.map_or(vlmax, |avl| avl.min(vlmax));
+        self.csr.vl
+    }
+
+    /// Evaluate a vector integer operation on two elements (and the old 
+    /// value of the destination element, for the multiply-add operations).
+    ///
+    /// 'x' is the element of 'vs2', and 'y' is the element of 'vs1' (or the 
+    /// scalar operand). The result is truncated to SEW bits when written.
+    fn eval_vop(x: u64, y: u64, d: u64, sew: u32, op: RvVOp) -> u64 {
+        let mask = u64::MAX >> (64 - sew);
+        let (x, y) = (x & mask, y & mask);
+        let sx = |v: u64| ((v << (64 - sew)) as i64) >> (64 - sew);
+        let sh = (y & (sew as u64 - 1)) as u32;
+        match op {
+            RvVOp::Add   => x.wrapping_add(y),
+            RvVOp::Sub   => x.wrapping_sub(y),
+            RvVOp::Rsub  => y.wrapping_sub(x),
+            RvVOp::Minu  => x.min(y),
+            RvVOp::Min   => sx(x).min(sx(y)) as u64,
+            RvVOp::Maxu  => x.max(y),
+            RvVOp::Max   => sx(x).max(sx(y)) as u64,
+            RvVOp::And   => x & y,
+            RvVOp::Or    => x | y,
+            RvVOp::Xor   => x ^ y,
+            RvVOp::Sll   => x << sh,
+            RvVOp::Srl   => x >> sh,
+            RvVOp::Sra   => (sx(x) >> sh) as u64,
+            RvVOp::Merge => y,
+            RvVOp::Mul   => x.wrapping_mul(y),
+            RvVOp::Mulh  => {
+                ((sx(x) as i128 * sx(y) as i128) >> sew) as u64
+            },
+            RvVOp::Mulhu => {
+                ((x as u128 * y as u128) >> sew) as u64
+            },
+            RvVOp::Mulhsu => {
+                ((sx(x) as i128 * y as i128) >> sew) as u64
+            },
+            RvVOp::Divu  => x.checked_div(y).unwrap_or(mask),
+            RvVOp::Div   => match (sx(x), sx(y)) {
+                (_, 0) => mask,
+                (x, y) => x.wrapping_div(y) as u64,
+            },
+            RvVOp::Remu  => if y == 0 { x } else { x % y },
+            RvVOp::Rem   => match (sx(x), sx(y)) {
+                (x, 0) => x as u64,
+                (x, y) => x.wrapping_rem(y) as u64,
+            },
+            RvVOp::Macc  => d.wrapping_add(y.wrapping_mul(x)),
+            RvVOp::Nmsac => d.wrapping_sub(y.wrapping_mul(x)),
+            RvVOp::Madd  => y.wrapping_mul(d).wrapping_add(x),
+            RvVOp::Nmsub => x.wrapping_sub(y.wrapping_mul(d)),
+            // The reductions combine elements with the underlying operation
+            RvVOp::Redsum  => x.wrapping_add(y),
+            RvVOp::Redand  => x & y,
+            RvVOp::Redor   => x | y,
+            RvVOp::Redxor  => x ^ y,
+            RvVOp::Redminu => x.min(y),
+            RvVOp::Redmin  => sx(x).min(sx(y)) as u64,
+            RvVOp::Redmaxu => x.max(y),
+            RvVOp::Redmax  => sx(x).max(sx(y)) as u64,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Evaluate a vector integer comparison on two elements.
+    fn eval_vcmp(x: u64, y: u64, sew: u32, op: RvVOp) -> bool {
+        let mask = u64::MAX >> (64 - sew);
+        let (x, y) = (x & mask, y & mask);
+        let sx = |v: u64| ((v << (64 - sew)) as i64) >> (64 - sew);
+        match op {
+            RvVOp::Mseq  => x == y,
+            RvVOp::Msne  => x != y,
+            RvVOp::Msltu => x < y,
+            RvVOp::Mslt  => sx(x) < sx(y),
+            RvVOp::Msleu => x <= y,
+            RvVOp::Msle  => sx(x) <= sx(y),
+            RvVOp::Msgtu => x > y,
+            RvVOp::Msgt  => sx(x) > sx(y),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Write the elements of a vector destination.
+    ///
+    /// The body elements are computed before any of them are written (so 
+    /// the sources may overlap the destination). Inactive elements and the 
+    /// tail are either undisturbed or filled with ones, depending on the 
+    /// policies in 'vtype'.
+    fn vwrite(&mut self, vd: RvVReg, vm: bool, sew: u32, 
+              f: impl Fn(&Self, usize) -> u64) 
+    {
+        let vtype = RvVType(self.csr.vtype);
+        let vl = self.csr.vl as usize;
+        let vlmax = vtype.vlmax(self.isa.vlen) as usize;
+        let res: Vec<(usize, u64)> = (self.csr.vstart as usize..vlmax)
+            .filter_map(|i| {
+                if i >= vl {
+                    if vtype.ta() { Some((i, u64::MAX)) } else { None }
+                } else if !vm && !self.vreg.read_bit(RvVReg(0), i) {
+                    if vtype.ma() { Some((i, u64::MAX)) } else { None }
+                } else {
+                    Some((i, f(self, i)))
+                }
+            })
+            .collect();
+        for (i, val) in res {
+            self.vreg.write(vd, i, sew, val);
+        }
+    }
+
+    /// Write the bits of a mask destination.
+    ///
+    /// Like [Interpreter::vwrite], except that the tail of a mask is always
+    /// agnostic (and extends to VLEN bits).
+    fn vwrite_mask(&mut self, vd: RvVReg, vm: bool, 
+                   f: impl Fn(&Self, usize) -> bool) 
+    {
+        let vtype = RvVType(self.csr.vtype);
+        let vl = self.csr.vl as usize;
+        let vlen = self.isa.vlen as usize;
+        let res: Vec<(usize, bool)> = (self.csr.vstart as usize..vlen)
+            .filter_map(|i| {
+                if i >= vl {
+                    Some((i, true))
+                } else if !vm && !self.vreg.read_bit(RvVReg(0), i) {
+                    if vtype.ma() { Some((i, true)) } else { None }
+                } else {
+                    Some((i, f(self, i)))
+                }
+            })
+            .collect();
+        for (i, val) in res {
+            self.vreg.write_bit(vd, i, val);
+        }
+    }
+
+    /// Execute a vector instruction.
+    ///
+    /// Instructions are illegal when 'vill' is set, or when they name a 
+    /// register group which is not aligned to the group size. Only SEW up 
+    /// to 64 bits is supported (ELEN is 64).
+    fn exec_vector(&mut self, inst: RvInstr) -> StepResult {
+        let illegal = |field| StepResult::IllegalInstruction(DecodeError {
+            bits: inst.encode(self.isa.xlen).map_or(0, |enc| enc.0),
+            field,
+            reason: DecodeReason::Reserved,
+        });
+        // Set the vector length and type
+        match inst {
+            RvInstr::VSetVli(rd, rs1, vtype) => {
+                let avl = match (rd.0, rs1.0) {
+                    (_, 0) if rd.0 != 0 => None,
+                    // Keep the current vector length
+                    (0, 0) => Some(self.csr.vl),
+                    _ => Some(self.reg.read(rs1)),
+                };
+                let vl = self.exec_vsetvl(avl, vtype as u64);
+                self.reg.write(rd, vl);
+                return StepResult::Next;
+            },
+            RvInstr::VSetIvli(rd, uimm, vtype) => {
+                let vl = self.exec_vsetvl(Some(uimm as u64), vtype as u64);
+                self.reg.write(rd, vl);
+                return StepResult::Next;
+            },
+            RvInstr::VSetVl(rd, rs1, rs2) => {
+                let avl = match (rd.0, rs1.0) {
+                    (_, 0) if rd.0 != 0 => None,
+                    (0, 0) => Some(self.csr.vl),
+                    _ => Some(self.reg.read(rs1)),
+                };
+                let vl = self.exec_vsetvl(avl, self.reg.read(rs2));
+                self.reg.write(rd, vl);
+                return StepResult::Next;
+            },
+            _ => {},
+        }
+
+        let vtype = RvVType(self.csr.vtype);
+        if !vtype.is_valid() {
+            return illegal(RvField::Funct7);
+        }
+        let sew = vtype.sew();
+        let (num, den) = vtype.lmul();
+        // Register groups must be aligned to the group size
+        let aligned = |reg: RvVReg, emul: (u32, u32)| {
+            emul.1 != 1 || reg.0 & (emul.0 as usize - 1) == 0
+        };
+        let lmul = (num, den);
+        let vl = self.csr.vl as usize;
+        let vstart = self.csr.vstart as usize;
+        // Scalar operands are sign-extended from XLEN bits
+        let scalar = |s: &Self, rs1: RvReg| s.signed(s.reg.read(rs1)) as u64;
+
+        match inst {
+            RvInstr::VLoad(vd, rs1, width, vm) 
+            | RvInstr::VLoadStrided(vd, rs1, _, width, vm)
+            | RvInstr::VStore(vd, rs1, width, vm) 
+            | RvInstr::VStoreStrided(vd, rs1, _, width, vm) => {
+                // The effective element width is set by the instruction, 
+                // with EMUL = (EEW / SEW) * LMUL.
+                let eew = width.bytes() as u32 * 8;
+                let (en, ed) = (eew * num, sew * den);
+                if en * 8 < ed || en > 8 * ed {
+                    return illegal(RvField::Funct3);
+                }
+                let emul = if en >= ed { (en / ed, 1) } else { (1, ed / en) };
+                if !aligned(vd, emul) || (!vm && vd.0 == 0 
+                    && matches!(inst, RvInstr::VLoad(..) | RvInstr::VLoadStrided(..))) 
+                {
+                    return illegal(RvField::Rd);
+                }
+                let stride = match inst {
+                    RvInstr::VLoadStrided(_, _, rs2, ..) 
+                    | RvInstr::VStoreStrided(_, _, rs2, ..) => self.reg.read(rs2),
+                    _ => width.bytes() as u64,
+                };
+                let base = self.reg.read(rs1);
+                let addr = move |s: &Self, i: usize| {
+                    s.trunc(base.wrapping_add(stride.wrapping_mul(i as u64)))
+                };
+                let active = |s: &Self, i: usize| {
+                    vm || s.vreg.read_bit(RvVReg(0), i)
+                };
+                // An exception at some element sets 'vstart' to its index, 
+                // after the elements before it have been loaded or stored
+                match inst {
+                    RvInstr::VLoad(..) | RvInstr::VLoadStrided(..) => {
+                        let mut vals = vec![0; vl];
+                        let mask: Vec<bool> = (0..vl)
+                            .map(|i| active(self, i))
+                            .collect();
+                        for i in (vstart..vl).filter(|i| mask[*i]) {
+                            match self.load(addr(self, i), width) {
+                                Ok(res) => vals[i] = res,
+                                Err(trap) => {
+                                    for j in (vstart..i).filter(|j| mask[*j]) {
+                                        self.vreg.write(vd, j, eew, vals[j]);
+                                    }
+                                    self.csr.vstart = i as u64;
+                                    return StepResult::Trap(trap);
+                                },
+                            }
+                        }
+                        self.vwrite(vd, vm, eew, |_, i| vals[i]);
+                    },
+                    _ => {
+                        for i in vstart..vl {
+                            if active(self, i) {
+                                let val = self.vreg.read(vd, i, eew);
+                                if let Err(trap) = self.store(addr(self, i), val, width) {
+                                    self.csr.vstart = i as u64;
+                                    return StepResult::Trap(trap);
+                                }
+                            }
+                        }
+                    },
+                }
+            },
+
+            RvInstr::VOpVV(vd, vs2, _, op, vm) 
+            | RvInstr::VOpVX(vd, vs2, _, op, vm)
+            | RvInstr::VOpVI(vd, vs2, _, op, vm) => {
+                // The second operand of each element
+                let y = move |s: &Self, i: usize| match inst {
+                    RvInstr::VOpVV(_, _, vs1, ..) => s.vreg.read(vs1, i, sew),
+                    RvInstr::VOpVX(_, _, rs1, ..) => scalar(s, rs1),
+                    RvInstr::VOpVI(_, _, imm, ..) => imm as i64 as u64,
+                    _ => unreachable!(),
+                };
+                // The first operand is a register group (except in
+                // reductions, which only read its first element)
+                let vs1_aligned = match inst {
+                    RvInstr::VOpVV(_, _, vs1, ..) => aligned(vs1, lmul),
+                    _ => true,
+                };
+                if op.is_reduction() {
+                    // The scalar result is in element 0 (only written when 
+                    // the vector length is nonzero)
+                    let vs1 = match inst {
+                        RvInstr::VOpVV(_, _, vs1, ..) => vs1,
+                        _ => unreachable!(),
+                    };
+                    if !aligned(vs2, lmul) {
+                        return illegal(RvField::Rs2);
+                    }
+                    if vl > 0 {
+                        let mut acc = self.vreg.read(vs1, 0, sew);
+                        for i in 0..vl {
+                            if vm || self.vreg.read_bit(RvVReg(0), i) {
+                                let x = self.vreg.read(vs2, i, sew);
+                                acc = Self::eval_vop(x, acc, 0, sew, op);
+                            }
+                        }
+                        self.vreg.write(vd, 0, sew, acc);
+                    }
+                } else if op.is_compare() {
+                    if !aligned(vs2, lmul) {
+                        return illegal(RvField::Rs2);
+                    }
+                    if !vs1_aligned {
+                        return illegal(RvField::Rs1);
+                    }
+                    self.vwrite_mask(vd, vm, |s, i| {
+                        Self::eval_vcmp(s.vreg.read(vs2, i, sew), y(s, i), sew, op)
+                    });
+                } else if op == RvVOp::Merge {
+                    // Elements are selected by the mask (which does not make 
+                    // any element inactive)
+                    if !aligned(vd, lmul) || !aligned(vs2, lmul) 
+                        || (!vm && vd.0 == 0) 
+                    {
+                        return illegal(RvField::Rd);
+                    }
+                    if !vs1_aligned {
+                        return illegal(RvField::Rs1);
+                    }
+                    self.vwrite(vd, true, sew, |s, i| {
+                        if vm || s.vreg.read_bit(RvVReg(0), i) {
+                            y(s, i)
+                        } else {
+                            s.vreg.read(vs2, i, sew)
+                        }
+                    });
+                } else {
+                    if !aligned(vd, lmul) || !aligned(vs2, lmul) 
+                        || (!vm && vd.0 == 0) 
+                    {
+                        return illegal(RvField::Rd);
+                    }
+                    if !vs1_aligned {
+                        return illegal(RvField::Rs1);
+                    }
+                    self.vwrite(vd, vm, sew, |s, i| {
+                        let x = s.vreg.read(vs2, i, sew);
+                        let d = s.vreg.read(vd, i, sew);
+                        Self::eval_vop(x, y(s, i), d, sew, op)
+                    });
+                }
+            },
+
+            RvInstr::VMvXS(rd, vs2) => {
+                let val = self.vreg.read(vs2, 0, sew);
+                let val = ((val << (64 - sew)) as i64) >> (64 - sew);
+                self.reg.write(rd, val as u64);
+            },
+            RvInstr::VMvSX(vd, rs1) => {
+                if vstart < vl {
+                    let val = scalar(self, rs1);
+                    self.vreg.write(vd, 0, sew, val);
+                }
+            },
+            RvInstr::VId(vd, vm) => {
+                if !aligned(vd, lmul) || (!vm && vd.0 == 0) {
+                    return illegal(RvField::Rd);
+                }
+                self.vwrite(vd, vm, sew, |_, i| i as u64);
+            },
+            _ => unreachable!(),
+        }
+        self.csr.vstart = 0;
+        StepResult::Next
+    }
+
+    /// Perform a Linux system call.
+    ///
+    /// The number is in a7 (or t0 on RV32E, which has no a7).
+    fn exec_syscall(&mut self) -> StepResult {
+        let num = if self.isa.regs() == 16 { RvReg(5) } else { RvReg(17) };
+        let num = self.reg.read(num);
+        let mut args = [0; 6];
+        for (idx, arg) in args.iter_mut().enumerate() {
+            *arg = self.reg.read(RvReg(10 + idx));
+        }
+        match self.sys.call(&mut self.ram, self.isa.xlen, num, args) {
+            SyscallResult::Return(val) => {
+                self.reg.write(RvReg(10), val as u64);
+                StepResult::Next
+            },
+            SyscallResult::Exit(status) => StepResult::Exit(status),
+        }
+    }
+
+    /// Handle a command written to 'tohost' by the program (using the HTIF
+    /// protocol of riscv-tests and Spike).
+    ///
+    /// The device is in bits 63:56 of the command, the command number in 
+    /// bits 55:48, and the payload in the rest. For device 0, an odd 
+    /// payload is an exit code (shifted left by one), and an even payload
+    /// points at a system call (eight 64-bit words: the number, then the 
+    /// arguments). The result of the system call replaces the number. 
+    /// Device 1 is the console, where command 1 writes a character.
+    ///
+    /// Commands are ignored when the registers or the system call are
+    /// outside of the memory.
+    fn exec_htif(&mut self, tohost: u64) -> StepResult {
+        if !self.ram.contains(tohost as usize, 8) {
+            return StepResult::Next;
+        }
+        let cmd = self.ram.load64(tohost as usize);
+        if cmd == 0 {
+            return StepResult::Next;
+        }
+        let (dev, op, payload) = (cmd >> 56, (cmd >> 48) & 0xff, 
+            cmd & 0xffff_ffff_ffff);
+        self.ram.store64(tohost as usize, 0);
+        let resp = match (dev, op) {
+            (0, 0) if payload & 1 != 0 => {
+                return StepResult::Exit((payload >> 1) as i32);
+            },
+            (0, 0) if !self.ram.contains(payload as usize, 64) => {
+                return StepResult::Next;
+            },
+            (0, 0) => {
+                let mut args = [0; 6];
+                for (idx, arg) in args.iter_mut().enumerate() {
+                    *arg = self.ram.load64((payload + 8 + idx as u64 * 8) 
+                        as usize);
+                }
+                let num = self.ram.load64(payload as usize);
+                match self.sys.call(&mut self.ram, self.isa.xlen, num, args) {
+                    SyscallResult::Return(val) => {
+                        self.ram.store64(payload as usize, val as u64);
+                    },
+                    SyscallResult::Exit(status) => {
+                        return StepResult::Exit(status);
+                    },
+                }
+                1
+            },
+            (1, 1) => {
+                use std::io::Write;
+                let mut out = std::io::stdout();
+                let _ = out.write_all(&[payload as u8])
+                    .and_then(|_| out.flush());
+                0x100 | (payload & 0xff)
+            },
+            _ => return StepResult::Next,
+        };
+        let fromhost = self.fromhost
+            .filter(|addr| self.ram.contains(*addr as usize, 8));
+        if let Some(fromhost) = fromhost {
+            let resp = (dev << 56) | (op << 48) | resp;
+            self.ram.store64(fromhost as usize, resp);
+        }
+        StepResult::Next
+    }
+
+    /// Fetch and decode the instruction at the address specified by the 
+    /// program counter.
+    ///
+    /// Instructions are fetched in 16-bit parcels, and compressed 
+    /// instructions are expanded into their 32-bit equivalents.
+    fn fetch(&mut self) -> Result<RvInstr, StepResult> {
+        let fetch16 = |s: &mut Self, addr: u64| {
+            s.translate(addr, 2, RvAccess::Fetch)
+                .map(|addr| s.ram.load16(addr))
+                .map_err(StepResult::Trap)
+        };
+        let lo = fetch16(self, self.pc)?;
+        self.ilen = rv_instr_len(lo) as u64;
+        let res = match self.ilen {
+            2 => RvCompressedEncoding(lo).decode_with(&self.isa),
+            _ => {
+                let hi = fetch16(self, self.trunc(self.pc.wrapping_add(2)))?;
+                RvEncoding((hi as u32) << 16 | lo as u32).decode_with(&self.isa)
+            },
+        };
+        res.map_err(StepResult::IllegalInstruction)
+    }
+
+    /// An instruction which can't be executed in the current state of the
+    /// machine (for example, an access to a CSR which doesn't exist).
+    fn illegal(&self, inst: RvInstr, field: RvField) -> StepResult {
+        StepResult::IllegalInstruction(DecodeError {
+            bits: inst.encode(self.isa.xlen).map_or(0, |enc| enc.0),
+            field,
+            reason: DecodeReason::Reserved,
+        })
+    }
+
+    /// Jump to some address, which must be aligned to four bytes (or two 
+    /// bytes, with compressed instructions).
+    fn jump(&self, tgt: u64) -> StepResult {
+        if tgt & 0b10 != 0 && !self.isa.has(RvExt::C) {
+            let trap = RvException::InstrMisaligned;
+            return StepResult::Trap(RvTrap::Exception(trap, tgt));
+        }
+        StepResult::Goto(tgt)
+    }
+
     /// Fetch and execute the instruction at the address specified by the 
     /// program counter, returning a [StepResult].
+    ///
+    /// Unless a Linux process is emulated, pending interrupts (including
+    /// the timer and software interrupts raised by the CLINT) are taken
+    /// before the instruction, and exceptions raised by the instruction are
+    /// taken instead of being returned (with a [StepResult::Goto] to the
+    /// trap handler).
     pub fn step(&mut self) -> StepResult {
-        let inst = RvEncoding(self.ram.read32(self.pc as usize)).decode();
-        println!("{:08x}: {:x?}", self.pc, inst);
+        self.csr.time = self.clint.mtime;
+        self.csr.set_machine_interrupts(self.clint.pending());
+        if !self.user_emulation {
+            if let Some(irq) = self.csr.pending_interrupt() {
+                let pc = self.csr.trap(RvTrap::Interrupt(irq), self.pc);
+                return StepResult::Goto(pc);
+            }
+        }
+        let res = match self.fetch() {
+            Ok(inst) => {
+                if self.trace {
+                    println!("{:08x}: {:#}", self.pc, inst);
+                }
+                self.execute(inst)
+            },
+            Err(res) => res,
+        };
+        // Instructions which raise an exception are not retired
+        let trap = match res {
+            StepResult::IllegalInstruction(e) => {
+                let trap = RvException::IllegalInstruction;
+                RvTrap::Exception(trap, e.bits as u64)
+            },
+            StepResult::Trap(trap) => trap,
+            _ => {
+                self.csr.tick(true);
+                self.clint.tick();
+                return res;
+            },
+        };
+        self.csr.tick(false);
+        if self.user_emulation {
+            return res;
+        }
+        if self.trace {
+            println!("{:08x}: {:x?}", self.pc, trap);
+        }
+        StepResult::Goto(self.csr.trap(trap, self.pc))
+    }
 
+    /// Execute a single instruction.
+    fn execute(&mut self, inst: RvInstr) -> StepResult {
         match inst {
             RvInstr::Op(rd, rs1, rs2, op) => {
                 self.reg.write(rd, 
-                    Self::eval_alu_op(
+                    self.eval_alu_op(
                         self.reg.read(rs1), 
                         self.reg.read(rs2), op)
                 );
@@ -109,79 +1502,1516 @@ impl Interpreter {
             },
             RvInstr::OpImm(rd, rs1, imm, op) => {
                 self.reg.write(rd, 
-                    Self::eval_alu_op( self.reg.read(rs1), imm as u32, op)
+                    self.eval_alu_op(self.reg.read(rs1), imm as i64 as u64, op)
+                );
+                StepResult::Next
+            }
+            RvInstr::OpW(rd, rs1, rs2, op) => {
+                self.reg.write(rd, 
+                    Self::eval_alu_op_w(
+                        self.reg.read(rs1), 
+                        self.reg.read(rs2), op)
+                );
+                StepResult::Next
+            },
+            RvInstr::OpImmW(rd, rs1, imm, op) => {
+                self.reg.write(rd, 
+                    Self::eval_alu_op_w(self.reg.read(rs1), imm as u64, op)
                 );
                 StepResult::Next
             }
+            RvInstr::OpBs(rd, rs1, rs2, bs, op) => {
+                let x = self.reg.read(rs1) as u32;
+                let y = self.reg.read(rs2) as u32;
+                let res = match op {
+                    RvBsOp::Aes32Esi  => crypto::aes32esi(x, y, bs),
+                    RvBsOp::Aes32Esmi => crypto::aes32esmi(x, y, bs),
+                    RvBsOp::Aes32Dsi  => crypto::aes32dsi(x, y, bs),
+                    RvBsOp::Aes32Dsmi => crypto::aes32dsmi(x, y, bs),
+                    RvBsOp::Sm4Ed     => crypto::sm4ed(x, y, bs),
+                    RvBsOp::Sm4Ks     => crypto::sm4ks(x, y, bs),
+                };
+                // The result is sign-extended on RV64
+                self.reg.write(rd, res as i32 as i64 as u64);
+                StepResult::Next
+            },
             RvInstr::Store(rs1, rs2, imm, width) => {
                 let val  = self.reg.read(rs2);
-                let addr = self.reg.read(rs1)
-                    .wrapping_add(imm as u32) as usize;
-                match width {
-                    RvWidth::Byte => self.ram.store8(addr, val as u8),
-                    RvWidth::Half => self.ram.store16(addr, val as u16),
-                    RvWidth::Word => self.ram.store32(addr, val),
+                let addr = self.eff_addr(rs1, imm);
+                let addr = match self.store(addr, val, width) {
+                    Ok(addr) => addr as u64,
+                    Err(trap) => return StepResult::Trap(trap),
+                };
+                // A command is sent once the last byte of 'tohost' is 
+                // written (by the upper word on RV32)
+                match self.tohost {
+                    Some(tohost) 
+                        if addr.wrapping_add(width.bytes() as u64) 
+                            == tohost.wrapping_add(8) => self.exec_htif(tohost),
+                    _ => StepResult::Next,
                 }
-                StepResult::Next
             },
             RvInstr::Load(rd, rs1, imm, width) => {
-                let addr = self.reg.read(rs1)
-                    .wrapping_add(imm as u32) as usize;
+                let addr = self.eff_addr(rs1, imm);
+                let val  = match self.load(addr, width) {
+                    Ok(val) => val,
+                    Err(trap) => return StepResult::Trap(trap),
+                };
                 let res  = match width {
-                    RvWidth::Byte => self.ram.load8(addr)  as u32,
-                    RvWidth::Half => self.ram.load16(addr) as u32,
-                    RvWidth::Word => self.ram.load32(addr) as u32,
+                    RvWidth::Byte => val as i8 as i64 as u64,
+                    RvWidth::Half => val as i16 as i64 as u64,
+                    RvWidth::Word => val as i32 as i64 as u64,
+                    _ => val,
                 };
                 self.reg.write(rd, res);
                 StepResult::Next
             },
             RvInstr::Jal(rd, imm) => {
-                println!("{}", imm);
-                if rd.0 != 0 {
-                    self.reg.write(rd, self.pc.wrapping_add(4));
+                let res = self.jump(self.trunc(
+                    self.pc.wrapping_add(imm as i64 as u64)));
+                if rd.0 != 0 && matches!(res, StepResult::Goto(_)) {
+                    self.reg.write(rd, self.pc.wrapping_add(self.ilen));
                 }
-                StepResult::Goto(self.pc.wrapping_add(imm as u32))
+                res
             },
             RvInstr::Branch(rs1, rs2, imm, op) => {
-                let res = Self::eval_branch_op(
+                let res = self.eval_branch_op(
                     self.reg.read(rs1), self.reg.read(rs2), op);
                 if res {
-                    StepResult::Goto(self.pc.wrapping_add(imm as u32))
+                    self.jump(self.trunc(
+                        self.pc.wrapping_add(imm as i64 as u64)))
                 } else {
                     StepResult::Next
                 }
             }
+            // The immediate is sign-extended from 32 bits on RV64
             RvInstr::Lui(rd, imm) => {
-                self.reg.write(rd, imm);
+                self.reg.write(rd, (imm << 12) as i32 as i64 as u64);
+                StepResult::Next
+            }
+            RvInstr::Auipc(rd, imm) => {
+                let off = (imm << 12) as i32 as i64 as u64;
+                self.reg.write(rd, self.pc.wrapping_add(off));
                 StepResult::Next
             }
             RvInstr::Jalr(rd, rs1, imm) => {
-                if rd.0 != 0 {
-                    self.reg.write(rd, self.pc.wrapping_add(4));
+                // NOTE: The target must be computed before the link 
+                // register is written, since 'rd' may be the same as 'rs1'.
+                let res = self.jump(self.eff_addr(rs1, imm) & !1);
+                if rd.0 != 0 && matches!(res, StepResult::Goto(_)) {
+                    self.reg.write(rd, self.pc.wrapping_add(self.ilen));
                 }
-                StepResult::Goto(
-                    self.reg.read(rs1).wrapping_add(imm as u32) & 0xffff_fffe
-                )
+                res
             }
-            _ => unimplemented!("{:x?}", inst),
+            // There is only a single hart, and no caches to maintain.
+            RvInstr::Fence(..) | RvInstr::FenceI => StepResult::Next,
+            RvInstr::Ecall if !self.user_emulation => {
+                let cause = match self.csr.prv {
+                    RvPrivilege::User       => RvException::UserEcall,
+                    RvPrivilege::Supervisor => RvException::SupervisorEcall,
+                    RvPrivilege::Machine    => RvException::MachineEcall,
+                };
+                StepResult::Trap(RvTrap::Exception(cause, 0))
+            },
+            RvInstr::Ecall => self.exec_syscall(),
+            RvInstr::Ebreak if !self.user_emulation => {
+                let trap = RvException::Breakpoint;
+                StepResult::Trap(RvTrap::Exception(trap, self.pc))
+            },
+            RvInstr::Ebreak => StepResult::Terminate,
+            RvInstr::Csr(rd, rs1, csr, op) => {
+                let src = self.reg.read(rs1);
+                match self.exec_csr_op(rd, csr, op, src, rs1.0 != 0) {
+                    Ok(()) => StepResult::Next,
+                    Err(_) => self.illegal(inst, RvField::Imm),
+                }
+            },
+            RvInstr::CsrImm(rd, imm, csr, op) => {
+                match self.exec_csr_op(rd, csr, op, imm as u64, imm != 0) {
+                    Ok(()) => StepResult::Next,
+                    Err(_) => self.illegal(inst, RvField::Imm),
+                }
+            },
+
+            // SRET is illegal in supervisor mode when trapped by TSR, WFI
+            // is illegal in user mode (or when trapped by TW), and 
+            // SFENCE.VMA is illegal in user mode (or when trapped by TVM).
+            RvInstr::Mret if self.csr.prv == RvPrivilege::Machine => {
+                StepResult::Goto(self.csr.mret())
+            },
+            RvInstr::Sret if self.csr.prv == RvPrivilege::Machine 
+                || (self.csr.prv == RvPrivilege::Supervisor 
+                    && self.csr.mstatus & MSTATUS_TSR == 0) => 
+            {
+                StepResult::Goto(self.csr.sret())
+            },
+            RvInstr::Wfi if self.csr.prv == RvPrivilege::Machine 
+                || (self.csr.prv == RvPrivilege::Supervisor 
+                    && self.csr.mstatus & MSTATUS_TW == 0) => 
+            {
+                StepResult::Next
+            },
+            RvInstr::SfenceVma(rs1, rs2) if self.csr.prv == RvPrivilege::Machine
+                || (self.csr.prv == RvPrivilege::Supervisor 
+                    && self.csr.mstatus & MSTATUS_TVM == 0) => 
+            {
+                let vaddr = Some(self.reg.read(rs1)).filter(|_| rs1.0 != 0);
+                let asid = Some(self.reg.read(rs2)).filter(|_| rs2.0 != 0);
+                self.mmu.flush(vaddr, asid);
+                StepResult::Next
+            },
+            RvInstr::Mret | RvInstr::Sret | RvInstr::Wfi 
+            | RvInstr::SfenceVma(..) => {
+                self.illegal(inst, RvField::Imm)
+            },
+
+            RvInstr::Lr(..) | RvInstr::Sc(..) | RvInstr::Amo(..) => {
+                match self.exec_atomic(inst) {
+                    Ok(()) => StepResult::Next,
+                    Err(trap) => StepResult::Trap(trap),
+                }
+            },
+
+            RvInstr::FLoad(..) | RvInstr::FStore(..)
+            | RvInstr::FArith(..) | RvInstr::FSqrt(..) | RvInstr::FOp(..)
+            | RvInstr::FFma(..) | RvInstr::FCmp(..) | RvInstr::FClass(..)
+            | RvInstr::FCvtToInt(..) | RvInstr::FCvtFromInt(..)
+            | RvInstr::FCvt(..) | RvInstr::FMvToInt(..)
+            | RvInstr::FMvFromInt(..) => {
+                // The dynamic rounding mode is illegal when 'frm' holds a 
+                // reserved value (or 'dyn')
+                let rm = match inst {
+                    RvInstr::FArith(.., rm) | RvInstr::FSqrt(.., rm)
+                    | RvInstr::FFma(.., rm) | RvInstr::FCvtToInt(.., rm)
+                    | RvInstr::FCvtFromInt(.., rm) | RvInstr::FCvt(.., rm) => rm,
+                    _ => RvRoundingMode::Rne,
+                };
+                if rm == RvRoundingMode::Dyn && self.csr.frm > 0b100 {
+                    return self.illegal(inst, RvField::Rm);
+                }
+                match self.exec_fp(inst) {
+                    Ok(()) => StepResult::Next,
+                    Err(trap) => StepResult::Trap(trap),
+                }
+            },
+
+            RvInstr::VSetVli(..) | RvInstr::VSetIvli(..) | RvInstr::VSetVl(..)
+            | RvInstr::VLoad(..) | RvInstr::VLoadStrided(..)
+            | RvInstr::VStore(..) | RvInstr::VStoreStrided(..)
+            | RvInstr::VOpVV(..) | RvInstr::VOpVX(..) | RvInstr::VOpVI(..)
+            | RvInstr::VMvXS(..) | RvInstr::VMvSX(..) | RvInstr::VId(..) => {
+                self.exec_vector(inst)
+            },
         }
     }
 
     /// Run the machine indefinitely until it halts.
-    pub fn run(&mut self) {
+    ///
+    /// Returns the exit status if the program exited with a system call.
+    pub fn run(&mut self) -> Option<i32> {
+        self.run_for(usize::MAX)
+    }
+
+    /// Run the machine until it halts, or until it has executed some 
+    /// number of instructions.
+    ///
+    /// Returns the exit status if the program exited with a system call.
+    pub fn run_for(&mut self, max_instrs: usize) -> Option<i32> {
         let mut instrs: usize = 0;
-        loop {
+        let mut status = None;
+        while instrs < max_instrs {
             if self.pc == 0xdead_0000 { break; }
             let res = self.step();
             instrs += 1;
             match res {
-                StepResult::Next      => self.pc = self.pc.wrapping_add(4),
+                StepResult::Next      => {
+                    self.pc = self.trunc(self.pc.wrapping_add(self.ilen))
+                },
                 StepResult::Goto(pc)  => self.pc = pc,
                 StepResult::Terminate => break,
+                StepResult::Exit(code) => {
+                    status = Some(code);
+                    break;
+                },
+                StepResult::IllegalInstruction(e) => {
+                    if self.trace {
+                        println!("{:08x}: {}", self.pc, e);
+                    }
+                    break;
+                },
+                StepResult::Trap(trap) => {
+                    if self.trace {
+                        println!("{:08x}: {:x?}", self.pc, trap);
+                    }
+                    break;
+                },
             }
         }
-        println!("{} instrs", instrs);
-        println!("{:08x?}", self.reg.data);
+        if self.trace {
+            println!("{} instrs", instrs);
+            println!("{:08x?}", self.reg.data);
+        }
+        status
     }
 }
 
+
+#[cfg(test)]
+mod test {
+    use crate::models::interp::*;
+
+    /// Store the words of a program at 0x1000, and point the program
+    /// counter at it.
+    fn load_words(vm: &mut Interpreter, prog: &[u32]) {
+        for (idx, word) in prog.iter().enumerate() {
+            vm.ram.store32(0x1000 + idx * 4, *word);
+        }
+        vm.pc = 0x1000;
+    }
+
+    /// Write an ELF image to a temporary file (with a name unique to the
+    /// test) and load it into the machine.
+    fn load_elf_program(vm: &mut Interpreter, elf: &[u8], tag: &str,
+                        argv: &[&str], envp: &[&str])
+    {
+        let path = std::env::temp_dir()
+            .join(format!("ans-{}-{}.elf", tag, std::process::id()));
+        std::fs::write(&path, elf).unwrap();
+        vm.load_elf(path.to_str().unwrap(), argv, envp);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rv32i_program() {
+        let prog: [u32; 7] = [
+            0x8000_0537, // lui   a0, 0x80000
+            0x4045_5593, // srai  a1, a0, 4
+            0x0000_0617, // auipc a2, 0
+            0x0106_4683, // lbu   a3, 16(a2)
+            0x0106_0703, // lb    a4, 16(a2)
+            0x0010_0073, // ebreak
+            0x0000_00f0,
+        ];
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        load_words(&mut vm, &prog);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(10)), 0x8000_0000);
+        assert_eq!(vm.reg.read(RvReg(11)), 0xf800_0000);
+        assert_eq!(vm.reg.read(RvReg(12)), 0x0000_1008);
+        assert_eq!(vm.reg.read(RvReg(13)), 0x0000_00f0);
+        assert_eq!(vm.reg.read(RvReg(14)), 0xffff_fff0);
+    }
+
+    #[test]
+    fn rv32c_program() {
+        let prog: [u16; 9] = [
+            0x4515,         // c.li   a0, 5
+            0x2019,         // c.jal  +6
+            0x9002,         // c.ebreak
+            0x0001,         // c.nop
+            0x0513, 0x0015, // addi   a0, a0, 1
+            0x85aa,         // c.mv   a1, a0
+            0x8082,         // c.jr   ra
+            0x0001,         // c.nop
+        ];
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        for (idx, parcel) in prog.iter().enumerate() {
+            vm.ram.store16(0x1000 + idx * 2, *parcel);
+        }
+        vm.pc = 0x1000;
+        vm.run();
+        assert_eq!(vm.pc, 0x1004);
+        assert_eq!(vm.reg.read(RvReg(1)), 0x1004);
+        assert_eq!(vm.reg.read(RvReg(10)), 6);
+        assert_eq!(vm.reg.read(RvReg(11)), 6);
+    }
+
+    #[test]
+    fn rv32a_program() {
+        let prog: [u32; 13] = [
+            0x0000_2537, // lui       a0, 0x2
+            0x0070_0693, // li        a3, 7
+            0xffd0_0893, // li        a7, -3
+            0x1405_25af, // lr.w.aq   a1, (a0)
+            0x0005_2223, // sw        zero, 4(a0)
+            0x18d5_262f, // sc.w      a2, a3, (a0)
+            0x1405_25af, // lr.w.aq   a1, (a0)
+            0x1ad5_272f, // sc.w.rl   a4, a3, (a0)
+            0x00d5_27af, // amoadd.w  a5, a3, (a0)
+            0x8115_282f, // amomin.w  a6, a7, (a0)
+            0xe715_22af, // amomaxu.w t0, a7, (a0)
+            0x0005_2303, // lw        t1, 0(a0)
+            0x0010_0073, // ebreak
+        ];
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        load_words(&mut vm, &prog);
+        vm.ram.store32(0x2000, 10);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(11)), 10);
+        assert_eq!(vm.reg.read(RvReg(12)), 1);
+        assert_eq!(vm.reg.read(RvReg(14)), 0);
+        assert_eq!(vm.reg.read(RvReg(15)), 7);
+        assert_eq!(vm.reg.read(RvReg(16)), 14);
+        assert_eq!(vm.reg.read(RvReg(5)), 0xffff_fffd);
+        assert_eq!(vm.reg.read(RvReg(6)), 0xffff_fffd);
+    }
+
+    #[test]
+    fn rv32fd_program() {
+        let prog: [u32; 20] = [
+            0x0000_27b7, // lui      a5, 0x2
+            0x0010_0513, // li       a0, 1
+            0x0030_0593, // li       a1, 3
+            0xd005_7553, // fcvt.s.w fa0, a0
+            0xd005_f5d3, // fcvt.s.w fa1, a1
+            0x00b5_0653, // fadd.s   fa2, fa0, fa1, rne
+            0xe006_0653, // fmv.x.w  a2, fa2
+            0xa0b5_16d3, // flt.s    a3, fa0, fa1
+            0xe006_1753, // fclass.s a4, fa2
+            0x4206_76d3, // fcvt.d.s fa3, fa2
+            0x6ad6_8743, // fmadd.d  fa4, fa3, fa3, fa3
+            0x00e7_b027, // fsd      fa4, 0(a5)
+            0x0007_b787, // fld      fa5, 0(a5)
+            0x0007_a807, // flw      fa6, 0(a5)
+            0x20a5_18d3, // fsgnjn.s fa7, fa0, fa0
+            0xc008_9853, // fcvt.w.s a6, fa7, rtz
+            0x18b5_7053, // fdiv.s   ft0, fa0, fa1
+            0x0010_28f3, // csrr     a7, fflags
+            0x20f7_80d3, // fsgnj.s  ft1, fa5, fa5
+            0x0010_0073, // ebreak
+        ];
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        load_words(&mut vm, &prog);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(12)), 0x4080_0000);
+        assert_eq!(vm.reg.read(RvReg(13)), 1);
+        assert_eq!(vm.reg.read(RvReg(14)), 1 << 6);
+        assert_eq!(vm.freg.data[15], 0x4034_0000_0000_0000);
+        assert_eq!(vm.freg.data[16], 0xffff_ffff_0000_0000);
+        assert_eq!(vm.freg.data[17], 0xffff_ffff_bf80_0000);
+        assert_eq!(vm.reg.read(RvReg(16)), 0xffff_ffff);
+        assert_eq!(vm.freg.data[0], 0xffff_ffff_3eaa_aaab);
+        assert_eq!(vm.reg.read(RvReg(17)), softfloat::flags::NX as u64);
+        assert_eq!(vm.freg.data[1], 0xffff_ffff_7fc0_0000);
+    }
+
+    #[test]
+    fn zb_program() {
+        let prog: [u32; 15] = [
+            0xffe0_0593, // li     a1, -2
+            0x0050_0613, // li     a2, 5
+            0x20b6_66b3, // sh3add a3, a2, a1
+            0x40c5_c733, // xnor   a4, a1, a2
+            0x6015_9793, // ctz    a5, a1
+            0x6045_9813, // sext.b a6, a1
+            0x0ac5_c8b3, // min    a7, a1, a2
+            0x0ac5_d2b3, // minu   t0, a1, a2
+            0x60b6_1333, // rol    t1, a2, a1
+            0x48c5_93b3, // bclr   t2, a1, a2
+            0x2876_5e13, // orc.b  t3, a2
+            0x6986_5e93, // rev8   t4, a2
+            0x40b6_6f33, // orn    t5, a2, a1
+            0x48c5_dfb3, // bext   t6, a1, a2
+            0x0010_0073, // ebreak
+        ];
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        load_words(&mut vm, &prog);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(13)), 38);
+        assert_eq!(vm.reg.read(RvReg(14)), 4);
+        assert_eq!(vm.reg.read(RvReg(15)), 1);
+        assert_eq!(vm.reg.read(RvReg(16)), 0xffff_fffe);
+        assert_eq!(vm.reg.read(RvReg(17)), 0xffff_fffe);
+        assert_eq!(vm.reg.read(RvReg(5)),  5);
+        assert_eq!(vm.reg.read(RvReg(6)),  0x4000_0001);
+        assert_eq!(vm.reg.read(RvReg(7)),  0xffff_ffde);
+        assert_eq!(vm.reg.read(RvReg(28)), 0x0000_00ff);
+        assert_eq!(vm.reg.read(RvReg(29)), 0x0500_0000);
+        assert_eq!(vm.reg.read(RvReg(30)), 5);
+        assert_eq!(vm.reg.read(RvReg(31)), 1);
+    }
+
+    #[test]
+    fn zicsr_program() {
+        let prog: [u32; 9] = [
+            0x3010_2573, // csrr   a0, misa
+            0xf140_25f3, // csrr   a1, mhartid
+            0x3402_d073, // csrwi  mscratch, 5
+            0x3401_6673, // csrrsi a2, mscratch, 2
+            0x3406_36f3, // csrrc  a3, mscratch, a2
+            0x3400_2773, // csrr   a4, mscratch
+            0xc020_27f3, // csrr   a5, instret
+            0x3405_9573, // csrrw  a0, mscratch, a1
+            0x0010_0073, // ebreak
+        ];
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        load_words(&mut vm, &prog);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(10)), 0x0000_0002);
+        assert_eq!(vm.reg.read(RvReg(11)), 0);
+        assert_eq!(vm.reg.read(RvReg(12)), 0x0000_0005);
+        assert_eq!(vm.reg.read(RvReg(13)), 0x0000_0007);
+        assert_eq!(vm.reg.read(RvReg(14)), 0x0000_0002);
+        assert_eq!(vm.reg.read(RvReg(15)), 6);
+        assert_eq!(vm.csr.mscratch, 0);
+        assert_eq!(vm.csr.instret, 9);
+    }
+
+    #[test]
+    fn rv64_program() {
+        let prog: [u32; 16] = [
+            0xfff0_0513, // li       a0, -1
+            0x0205_5593, // srli     a1, a0, 32
+            0x0015_861b, // addiw    a2, a1, 1
+            0x00b5_86bb, // addw     a3, a1, a1
+            0x8000_0737, // lui      a4, 0x80000
+            0xfee1_3c23, // sd       a4, -8(sp)
+            0xff81_3783, // ld       a5, -8(sp)
+            0xff81_6803, // lwu      a6, -8(sp)
+            0xff81_2883, // lw       a7, -8(sp)
+            0x4047_529b, // sraiw    t0, a4, 4
+            0x02a5_3333, // mulhu    t1, a0, a0
+            0x02b5_d3bb, // divuw    t2, a1, a1
+            0x00a1_3eaf, // amoadd.d t4, a0, (sp)
+            0x0001_3f03, // ld       t5, 0(sp)
+            0x0001_2685, // c.addiw  a3, 1; c.nop
+            0x0010_0073, // ebreak
+        ];
+        let mut vm = Interpreter::new(RvXlen::Rv64);
+        load_words(&mut vm, &prog);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(10)), 0xffff_ffff_ffff_ffff);
+        assert_eq!(vm.reg.read(RvReg(11)), 0x0000_0000_ffff_ffff);
+        assert_eq!(vm.reg.read(RvReg(12)), 0);
+        assert_eq!(vm.reg.read(RvReg(13)), 0xffff_ffff_ffff_ffff);
+        assert_eq!(vm.reg.read(RvReg(14)), 0xffff_ffff_8000_0000);
+        assert_eq!(vm.reg.read(RvReg(15)), 0xffff_ffff_8000_0000);
+        assert_eq!(vm.reg.read(RvReg(16)), 0x0000_0000_8000_0000);
+        assert_eq!(vm.reg.read(RvReg(17)), 0xffff_ffff_8000_0000);
+        assert_eq!(vm.reg.read(RvReg(5)),  0xffff_ffff_f800_0000);
+        assert_eq!(vm.reg.read(RvReg(6)),  0xffff_ffff_ffff_fffe);
+        assert_eq!(vm.reg.read(RvReg(7)),  1);
+        assert_eq!(vm.reg.read(RvReg(29)), 0);
+        assert_eq!(vm.reg.read(RvReg(30)), 0xffff_ffff_ffff_ffff);
+    }
+
+    #[test]
+    fn illegal_instruction() {
+        let prog: [u32; 3] = [
+            0x0010_0513, // li a0, 1
+            0xff81_3783, // ld a5, -8(sp)
+            0x0010_0073, // ebreak
+        ];
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        load_words(&mut vm, &prog);
+        assert!(matches!(vm.step(), StepResult::Next));
+        vm.pc += 4;
+        match vm.step() {
+            StepResult::IllegalInstruction(e) => {
+                assert_eq!(e.bits, 0xff81_3783);
+                assert_eq!(e.reason, DecodeReason::RequiresRv64);
+            },
+            _ => panic!("expected an illegal instruction"),
+        }
+        // Nothing was executed
+        assert_eq!(vm.reg.read(RvReg(15)), 0);
+
+        // The machine stops at the illegal instruction
+        vm.pc = 0x1000;
+        vm.run();
+        assert_eq!(vm.pc, 0x1004);
+        assert_eq!(vm.reg.read(RvReg(10)), 1);
+    }
+
+    #[test]
+    fn configured_isa() {
+        let prog: [u32; 4] = [
+            0x3010_2573, // csrr a0, misa
+            0x0030_25f3, // frcsr a1
+            0x02c5_8533, // mul  a0, a1, a2
+            0x0010_0073, // ebreak
+        ];
+        let mut vm = Interpreter::with_isa(RvIsa::parse("rv32ic_zicsr").unwrap());
+        load_words(&mut vm, &prog);
+        assert!(matches!(vm.step(), StepResult::Next));
+        // Supervisor and user modes are also implemented
+        assert_eq!(vm.reg.read(RvReg(10)), 0x4014_0104);
+        // The floating-point CSRs only exist with the F extension
+        assert!(vm.csr.read(RvCsr(crate::csr::FCSR)).is_err());
+
+        // Instructions from the M extension are illegal
+        vm.pc = 0x1008;
+        match vm.step() {
+            StepResult::IllegalInstruction(e) => {
+                assert_eq!(e.bits, 0x02c5_8533);
+                assert_eq!(e.reason, DecodeReason::Unsupported);
+            },
+            _ => panic!("expected an illegal instruction"),
+        }
+    }
+
+    #[test]
+    fn rv32e_program() {
+        let prog: [u32; 4] = [
+            0x00a0_0793, // li   a5, 10
+            0x00f7_8733, // add  a4, a5, a5
+            0x00f7_8833, // add  a6, a5, a5
+            0x0010_0073, // ebreak
+        ];
+        let mut vm = Interpreter::with_isa(RvIsa::parse("rv32e").unwrap());
+        load_words(&mut vm, &prog);
+        assert_eq!(vm.reg.read(RvReg(1)), 0xdead_0000);
+        assert_eq!(vm.reg.read(RvReg(2)), 0x0030_0000);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(14)), 20);
+        // The machine stops at the instruction which names x16
+        assert_eq!(vm.pc, 0x1008);
+        assert_eq!(vm.reg.data.len(), 16);
+    }
+
+    #[test]
+    fn rv32m_corner_cases() {
+        use RvALUOp::*;
+        let min = 0x8000_0000;
+        let neg1 = 0xffff_ffff;
+        assert_eq!(Interpreter::eval_alu_op32(7, 0, Div), neg1);
+        assert_eq!(Interpreter::eval_alu_op32(7, 0, Divu), neg1);
+        assert_eq!(Interpreter::eval_alu_op32(7, 0, Rem), 7);
+        assert_eq!(Interpreter::eval_alu_op32(7, 0, Remu), 7);
+        assert_eq!(Interpreter::eval_alu_op32(min, neg1, Div), min);
+        assert_eq!(Interpreter::eval_alu_op32(min, neg1, Rem), 0);
+        assert_eq!(Interpreter::eval_alu_op32(neg1, neg1, Mulh), 0);
+        assert_eq!(Interpreter::eval_alu_op32(neg1, neg1, Mulhu), neg1 - 1);
+        assert_eq!(Interpreter::eval_alu_op32(neg1, neg1, Mulhsu), neg1);
+        assert_eq!(Interpreter::eval_alu_op32(-7i32 as u32, 2, Rem), neg1);
+    }
+
+    #[test]
+    fn assembled_program() {
+        use crate::asm::assemble;
+        let src = r#"
+            .globl _start
+        _start:
+            la    s0, table
+            li    a0, 0
+            li    a1, 4
+        loop:
+            lw    t0, 0(s0)
+            add   a0, a0, t0
+            addi  s0, s0, 4
+            addi  a1, a1, -1
+            bnez  a1, loop
+            call  double
+            ebreak
+        double:
+            slli  a0, a0, 1
+            ret
+            .data
+        table:
+            .word 1, 2, 3, 0x100000
+        "#;
+        let prog = assemble(src, RvXlen::Rv32).unwrap();
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        load_elf_program(&mut vm, &prog.to_elf(), "assembled-program",
+            &["prog"], &[]);
+        assert_eq!(vm.pc, prog.symbols["_start"]);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(10)), 0x0020_000c);
+    }
+
+    #[test]
+    fn initial_stack() {
+        use crate::asm::assemble;
+        let src = "
+            .globl _start
+        _start:
+            ld    a0, 0(sp)     # argc
+            ld    t0, 16(sp)    # argv[1]
+            lbu   a1, 0(t0)
+            ebreak
+        ";
+        let prog = assemble(src, RvXlen::Rv64).unwrap();
+        let mut vm = Interpreter::new(RvXlen::Rv64);
+        load_elf_program(&mut vm, &prog.to_elf(), "initial-stack",
+            &["prog", "xyz", "-v"], &["HOME=/", "TERM=dumb"]);
+
+        let sp = vm.reg.read(RvReg(2));
+        assert_eq!(sp & 15, 0);
+        let word = |idx: u64| vm.ram.load64((sp + idx * 8) as usize);
+        let string = |addr: u64| {
+            let addr = addr as usize;
+            let len = vm.ram.data[addr..].iter().position(|b| *b == 0)
+                .unwrap();
+            String::from_utf8(vm.ram.data[addr..addr + len].to_vec()).unwrap()
+        };
+        assert_eq!(word(0), 3);
+        assert_eq!(string(word(1)), "prog");
+        assert_eq!(string(word(3)), "-v");
+        assert_eq!(word(4), 0);
+        assert_eq!(string(word(5)), "HOME=/");
+        assert_eq!(string(word(6)), "TERM=dumb");
+        assert_eq!(word(7), 0);
+
+        // Auxiliary vector
+        let auxv: Vec<(u64, u64)> = (0..7)
+            .map(|idx| (word(8 + 2 * idx), word(9 + 2 * idx)))
+            .collect();
+        let aux = |key| auxv.iter().find(|(k, _)| *k == key).unwrap().1;
+        assert_eq!(aux(9), prog.entry);
+        assert_eq!(aux(6), 0x1000);
+        assert_eq!(aux(4), 56);
+        assert_eq!(auxv.last(), Some(&(0, 0)));
+        // The first program header describes the text segment (PT_LOAD)
+        let phdr = aux(3) as usize;
+        assert_eq!(vm.ram.load32(phdr), 1);
+        assert_eq!(vm.ram.load64(phdr + 16), prog.text_base);
+
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(10)), 3);
+        assert_eq!(vm.reg.read(RvReg(11)), b'x' as u64);
+    }
+
+    #[test]
+    fn rv32e_initial_stack() {
+        use crate::asm::assemble;
+        let src = "
+            .globl _start
+        _start:
+            lw    a0, 0(sp)     # argc
+            lw    t0, 8(sp)     # argv[1]
+            lbu   a1, 0(t0)
+            ebreak
+        ";
+        let prog = assemble(src, RvXlen::Rv32).unwrap();
+        let mut vm = Interpreter::with_isa(RvIsa::parse("rv32e").unwrap());
+        load_elf_program(&mut vm, &prog.to_elf(), "rv32e-stack",
+            &["prog", "xyz", "-v"], &["A=1"]);
+
+        // The stack is only aligned to 4 bytes in the ilp32e ABI
+        let sp = vm.reg.read(RvReg(2));
+        assert_eq!(sp & 3, 0);
+        assert_ne!(sp & 15, 0);
+        vm.run();
+        assert_eq!(vm.reg.read(RvReg(10)), 3);
+        assert_eq!(vm.reg.read(RvReg(11)), b'x' as u64);
+    }
+
+    #[test]
+    fn htif_program() {
+        use crate::asm::assemble;
+        let src = r#"
+            .globl _start
+        _start:
+            la    t1, tohost
+            la    t2, fromhost
+            la    t0, magic     # write(1, msg, 3)
+            la    t3, msg
+            sw    t3, 16(t0)
+            sw    t0, 0(t1)
+            sw    zero, 4(t1)
+        wait:
+            lw    t3, 0(t2)
+            beqz  t3, wait
+            sw    zero, 0(t2)
+            lw    s0, 0(t0)
+            li    t0, 0x41      # putchar('A')
+            li    t3, 0x01010000
+            sw    t0, 0(t1)
+            sw    t3, 4(t1)
+            lw    s1, 0(t2)
+            li    t0, 11        # exit(5)
+            sw    t0, 0(t1)
+            sw    zero, 4(t1)
+        halt:
+            j     halt
+            .data
+            .align 3
+        tohost:   .dword 0
+        fromhost: .dword 0
+        magic:    .dword 64, 1, 0, 3, 0, 0, 0, 0
+        msg:      .ascii "ok\n"
+        "#;
+        let mut prog = assemble(src, RvXlen::Rv32).unwrap();
+        // Also run the program at the usual address for bare-metal programs
+        // (which is above the end of the memory)
+        for base in [prog.text_base, 0x8000_0000] {
+            let off = base - prog.text_base;
+            prog.text_base += off;
+            prog.data_base += off;
+            prog.entry += off;
+            for addr in prog.symbols.values_mut() {
+                *addr += off;
+            }
+            let mut vm = Interpreter::new(RvXlen::Rv32);
+            load_elf_program(&mut vm, &prog.to_elf(), "htif-program",
+                &["prog"], &[]);
+            assert_eq!(vm.tohost, Some(prog.symbols["tohost"]));
+
+            assert_eq!(vm.run(), Some(5));
+            assert_eq!(vm.reg.read(RvReg(8)), 3);
+            assert_eq!(vm.reg.read(RvReg(9)), 0x141);
+            assert_eq!(vm.ram.load64(prog.symbols["tohost"] as usize), 0);
+            if base == 0x8000_0000 {
+                assert_eq!(vm.ram.base, 0x8000_0000);
+            }
+        }
+    }
+
+    #[test]
+    fn htif_bad_pointers() {
+        use crate::asm::assemble;
+        let src = r#"
+            .globl _start
+        _start:
+            la    t1, tohost
+            li    t0, 0x7ffffff8    # system call outside of the memory
+            sw    t0, 0(t1)
+            sw    zero, 4(t1)
+            la    t0, magic         # write(1, 0x7ffffff8, 3)
+            sw    t0, 0(t1)
+            sw    zero, 4(t1)
+            lw    s0, 0(t0)
+            li    t0, 15            # exit(7)
+            sw    t0, 0(t1)
+            sw    zero, 4(t1)
+        halt:
+            j     halt
+            .data
+            .align 3
+        tohost:   .dword 0
+        magic:    .dword 64, 1, 0x7ffffff8, 3, 0, 0, 0, 0
+        "#;
+        let prog = assemble(src, RvXlen::Rv32).unwrap();
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        load_elf_program(&mut vm, &prog.to_elf(), "htif-bad-pointers",
+            &["prog"], &[]);
+        // 'fromhost' is outside of the memory too
+        vm.fromhost = Some(0x7fff_fff8);
+        assert_eq!(vm.run(), Some(7));
+        assert_eq!(vm.reg.read(RvReg(8)), -EFAULT as u32 as u64);
+    }
+
+    #[test]
+    fn crypto_program() {
+        use crate::asm::assemble;
+        let (x, y) = (0x1234_5678u32, 0xdead_beefu32);
+        for xlen in [RvXlen::Rv32, RvXlen::Rv64] {
+            let mut src = format!("
+                li    a1, {:#x}
+                li    a2, {:#x}
+                sha256sig0 a0, a1
+                sm3p0 a3, a2
+                sm4ed a4, a1, a2, 2
+            ", x, y);
+            src += match xlen {
+                RvXlen::Rv32 => "
+                    aes32esmi a5, a1, a2, 1
+                    sha512sig0h a6, a1, a2
+                    ebreak
+                ",
+                RvXlen::Rv64 => "
+                    slli  a1, a1, 32
+                    or    a1, a1, a2
+                    sha512sum1 a5, a1
+                    ebreak
+                ",
+            };
+            let prog = assemble(&src, xlen).unwrap();
+            let mut vm = Interpreter::new(xlen);
+            vm.ram.write(prog.text_base as usize, &prog.to_image());
+            vm.pc = prog.entry;
+            vm.run();
+
+            // The 32-bit results are sign-extended on RV64
+            let sext = |v: u32| (v as i32 as i64 as u64) & xlen.mask();
+            assert_eq!(vm.reg.read(RvReg(10)), sext(crypto::sha256sig0(x)));
+            assert_eq!(vm.reg.read(RvReg(13)), sext(crypto::sm3p0(y)));
+            assert_eq!(vm.reg.read(RvReg(14)), sext(crypto::sm4ed(x, y, 2)));
+            if xlen == RvXlen::Rv32 {
+                assert_eq!(vm.reg.read(RvReg(15)), 
+                    crypto::aes32esmi(x, y, 1) as u64);
+                assert_eq!(vm.reg.read(RvReg(16)), 
+                    crypto::sha512sig0h(x, y) as u64);
+            } else {
+                let xy = ((x as u64) << 32) | y as u64;
+                assert_eq!(vm.reg.read(RvReg(15)), crypto::sha512sum1(xy));
+            }
+        }
+    }
+
+    #[test]
+    fn vector_loop() {
+        use crate::asm::assemble;
+        // c[i] = a[i] + 3 * b[2 * i], strip-mined with LMUL = 2
+        let n = 37;
+        let a: Vec<String> = (0..n).map(|i| (i * 5).to_string()).collect();
+        let b: Vec<String> = (0..2 * n).map(|i| (i - 20).to_string()).collect();
+        let src = format!("
+                li    a0, {}
+                la    a1, a
+                la    a2, b
+                la    a3, c
+                li    t1, 3
+                li    t2, 8
+            loop:
+                vsetvli t0, a0, e32, m2, ta, ma
+                vle32.v v2, (a1)
+                vlse32.v v4, (a2), t2
+                vmacc.vx v2, t1, v4
+                vse32.v v2, (a3)
+                sub   a0, a0, t0
+                slli  t3, t0, 2
+                add   a1, a1, t3
+                add   a3, a3, t3
+                slli  t3, t3, 1
+                add   a2, a2, t3
+                bnez  a0, loop
+                ebreak
+                .data
+            a:  .word {}
+            b:  .word {}
+            c:  .zero {}
+        ", n, a.join(", "), b.join(", "), n * 4);
+        for xlen in [RvXlen::Rv32, RvXlen::Rv64] {
+            let prog = assemble(&src, xlen).unwrap();
+            let mut vm = Interpreter::new(xlen);
+            vm.ram.write(prog.text_base as usize, &prog.to_image());
+            vm.pc = prog.entry;
+            vm.run();
+
+            let c = prog.symbols["c"] as usize;
+            for i in 0..n {
+                let val = i * 5 + 3 * (2 * i - 20);
+                assert_eq!(vm.ram.load32(c + i as usize * 4), val as u32);
+            }
+            // The last iteration has a shorter vector length
+            assert_eq!(vm.csr.vl, (n % 8) as u64);
+        }
+    }
+
+    #[test]
+    fn vector_policies() {
+        use crate::asm::assemble;
+        let src = "
+            vsetivli t0, 4, e32, m1, ta, ma
+            vmv.v.i  v1, 7
+            vid.v    v2
+            vmsleu.vi v0, v2, 1
+            vsetivli t0, 3, e32, m1, tu, mu
+            vmv.v.i  v3, 5
+            vadd.vv  v1, v1, v2, v0.t
+            vsetivli t0, 3, e32, m1, ta, ma
+            vadd.vi  v3, v2, 1, v0.t
+            vredsum.vs v4, v2, v1
+            vmv.x.s  a0, v4
+            vredmaxu.vs v5, v2, v3, v0.t
+            vmv.x.s  a1, v5
+            vmerge.vxm v6, v2, a0, v0
+            vsetvli  t1, zero, e8, m1, ta, ma
+            vmv.v.i  v7, -2
+            vmv.x.s  a2, v7
+            ebreak
+        ";
+        let prog = assemble(src, RvXlen::Rv64).unwrap();
+        let mut vm = Interpreter::new(RvXlen::Rv64);
+        vm.ram.write(prog.text_base as usize, &prog.to_image());
+        vm.pc = prog.entry;
+        vm.run();
+
+        let elems = |vm: &Interpreter, v| -> Vec<u64> {
+            (0..4).map(|i| vm.vreg.read(RvVReg(v), i, 32)).collect()
+        };
+        // The tail of a mask is agnostic (filled with ones)
+        assert_eq!(vm.vreg.read(RvVReg(0), 0, 8), 0b1111_0011);
+        // Undisturbed inactive elements and tail
+        assert_eq!(elems(&vm, 1), [7, 8, 7, 7]);
+        assert_eq!(elems(&vm, 3)[..2], [1, 2]);
+        // Agnostic inactive elements and tail
+        assert_eq!(elems(&vm, 3)[2..], [0xffff_ffff, 0xffff_ffff]);
+        // Reductions (only over the active elements)
+        assert_eq!(vm.reg.read(RvReg(10)), 10);
+        assert_eq!(vm.reg.read(RvReg(11)), 1);
+        assert_eq!(elems(&vm, 6)[..3], [10, 10, 2]);
+        // vsetvli with rs1 = x0 sets the maximum vector length
+        assert_eq!(vm.reg.read(RvReg(6)), 16);
+        assert_eq!(vm.reg.read(RvReg(12)), -2i64 as u64);
+    }
+
+    #[test]
+    fn vector_illegal() {
+        use crate::asm::assemble;
+        let src = "
+            vadd.vv  v1, v2, v3
+            vsetvli  t0, a0, e32, m2, ta, ma
+            vadd.vv  v1, v2, v4
+            vsetvli  t0, a0, 0x20
+            vsetvli  t0, a0, e8, m8, ta, ma
+            vadd.vv  v0, v8, v31
+            vmseq.vv v0, v8, v31
+            vmerge.vvm v8, v16, v31, v0
+        ";
+        let prog = assemble(src, RvXlen::Rv32).unwrap();
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        vm.ram.write(prog.text_base as usize, &prog.to_image());
+        vm.reg.write(RvReg(10), 100);
+
+        // 'vill' is set after reset
+        vm.pc = prog.entry;
+        assert!(matches!(vm.step(), StepResult::IllegalInstruction(_)));
+        vm.pc += 4;
+        assert!(matches!(vm.step(), StepResult::Next));
+        assert_eq!(vm.reg.read(RvReg(5)), 8);
+        // v1 is not aligned to a group of two registers
+        vm.pc += 4;
+        match vm.step() {
+            StepResult::IllegalInstruction(e) => {
+                assert_eq!(e.bits, 0x0222_00d7);
+                assert_eq!(e.reason, DecodeReason::Reserved);
+            },
+            _ => panic!("expected an illegal instruction"),
+        }
+        // An unsupported type sets 'vill'
+        vm.pc += 4;
+        assert!(matches!(vm.step(), StepResult::Next));
+        assert_eq!(vm.reg.read(RvReg(5)), 0);
+        assert_eq!(vm.csr.vtype, 1 << 31);
+        // v31 is not aligned to a group of eight registers (for the normal,
+        // compare and merge operations)
+        vm.pc += 4;
+        assert!(matches!(vm.step(), StepResult::Next));
+        for _ in 0..3 {
+            vm.pc += 4;
+            match vm.step() {
+                StepResult::IllegalInstruction(e) => {
+                    assert_eq!(e.field, RvField::Rs1);
+                },
+                _ => panic!("expected an illegal instruction"),
+            }
+        }
+    }
+
+    #[test]
+    fn syscall_program() {
+        use crate::asm::assemble;
+        let src = r#"
+                li    a7, 214       # brk(0)
+                li    a0, 0
+                ecall
+                mv    s0, a0
+                addi  a0, a0, 64    # brk(end + 64)
+                ecall
+                sub   s1, a0, s0
+                li    a7, 64        # write(1, msg, 3)
+                li    a0, 1
+                la    a1, msg
+                li    a2, 3
+                ecall
+                mv    s2, a0
+                li    a7, 57        # close(42)
+                li    a0, 42
+                ecall
+                mv    s3, a0
+                li    a7, 93        # exit(3)
+                li    a0, 3
+                ecall
+                ebreak
+                .data
+            msg: .asciz "hi\n"
+        "#;
+        for xlen in [RvXlen::Rv32, RvXlen::Rv64] {
+            let prog = assemble(src, xlen).unwrap();
+            let mut vm = Interpreter::new(xlen);
+            vm.ram.write(prog.text_base as usize, &prog.to_image());
+            vm.pc = prog.entry;
+            assert_eq!(vm.run(), Some(3));
+            assert_eq!(vm.reg.read(RvReg(8)), BRK_BASE);
+            assert_eq!(vm.reg.read(RvReg(9)), 64);
+            assert_eq!(vm.reg.read(RvReg(18)), 3);
+            assert_eq!(vm.reg.read(RvReg(19)), (-EBADF as u64) & xlen.mask());
+        }
+    }
+
+    #[test]
+    fn assembled_li() {
+        use crate::asm::assemble;
+        let rv32: &[u64] = &[
+            0, 0x7ff, 0xffff_f800, 0x8000_0000, 0x1234_5678, 0xffff_ffff,
+        ];
+        let rv64: &[u64] = &[
+            0x8000_0000, 0xffff_ffff_8000_0001, 0x1_0000_0000,
+            0x1234_5678_9abc_def0, 0x7fff_ffff_ffff_ffff,
+            0xffff_ffff_ffff_f800,
+        ];
+        for &(xlen, values) in &[(RvXlen::Rv32, rv32), (RvXlen::Rv64, rv64)] {
+            let mut src = String::new();
+            for (idx, val) in values.iter().enumerate() {
+                src += &format!("li x{}, {:#x}\n", idx + 10, val);
+            }
+            src += "ebreak\n";
+            let prog = assemble(&src, xlen).unwrap();
+            let mut vm = Interpreter::new(xlen);
+            vm.ram.write(prog.text_base as usize, &prog.to_image());
+            vm.pc = prog.entry;
+            vm.run();
+            for (idx, val) in values.iter().enumerate() {
+                assert_eq!(vm.reg.read(RvReg(idx + 10)), *val);
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_run() {
+        use crate::asm::assemble;
+        let src = r#"
+            li    a0, 0
+        loop:
+            addi  a0, a0, 1
+            j     loop
+        "#;
+        let prog = assemble(src, RvXlen::Rv32).unwrap();
+        let mut vm = Interpreter::new(RvXlen::Rv32);
+        vm.set_trace(false);
+        vm.ram.write(prog.text_base as usize, &prog.to_image());
+        vm.pc = prog.entry;
+        assert_eq!(vm.run_for(21), None);
+        assert_eq!(vm.reg.read(RvReg(10)), 10);
+        assert_eq!(vm.pc, prog.symbols["loop"]);
+        assert_eq!(vm.memory().load32(vm.pc as usize), 0x0015_0513);
+    }
+
+    /// Assemble a bare-metal program and load it into a new machine.
+    ///
+    /// The program can jump to 'halt' to exit with status 0 (through the
+    /// HTIF).
+    fn load_program(src: &str, isa: &str) -> (Interpreter, crate::asm::Program) {
+        let src = src.to_string() + "
+            .text
+        halt:
+            li    t6, 1
+            la    t5, tohost
+            sw    t6, 0(t5)
+            sw    zero, 4(t5)
+            .data
+            .align 3
+        tohost:
+            .dword 0
+        ";
+        let isa = RvIsa::parse(isa).unwrap();
+        let prog = crate::asm::assemble(&src, isa.xlen).unwrap();
+        let mut vm = Interpreter::with_isa(isa);
+        vm.set_trace(false);
+        vm.set_user_emulation(false);
+        vm.ram.write(prog.text_base as usize, &prog.to_image());
+        vm.pc = prog.entry;
+        vm.tohost = Some(prog.symbols["tohost"]);
+        (vm, prog)
+    }
+
+    #[test]
+    fn trap_handler() {
+        let src = r#"
+            la    t0, handler
+            csrw  mtvec, t0
+            la    s1, log
+            .word 0x0000000b    # custom-0
+        ecall:
+            ecall
+        ebreak:
+            ebreak
+            csrr  a0, 0x7c0
+            li    t0, 0x11002
+        lr:
+            lr.w  a0, (t0)
+            la    t0, misaligned
+            addi  t0, t0, 2
+        jalr:
+            jalr  ra, 0(t0)
+        misaligned:
+            li    a1, 1
+            j     halt
+        handler:
+            csrr  t1, mcause
+            csrr  t2, mtval
+            sw    t1, 0(s1)
+            sw    t2, 4(s1)
+            addi  s1, s1, 8
+            csrr  t3, mepc
+            addi  t3, t3, 4
+            csrw  mepc, t3
+            mret
+            .data
+        log:
+            .zero 48
+        "#;
+        let (mut vm, prog) = load_program(src, "rv32ia_zicsr");
+        assert_eq!(vm.run(), Some(0));
+        let log: Vec<u32> = (0..12)
+            .map(|idx| vm.ram.load32(prog.symbols["log"] as usize + idx * 4))
+            .collect();
+        let sym = |name: &str| prog.symbols[name] as u32;
+        assert_eq!(log, [
+            2, 0x0000_000b,
+            11, 0,
+            3, sym("ebreak"),
+            2, 0x7c00_2573,
+            4, 0x11002,
+            0, sym("misaligned") + 2,
+        ]);
+        // The faulting instructions have no effect, and aren't retired
+        assert_eq!(vm.reg.read(RvReg(10)), 0);
+        assert_eq!(vm.reg.read(RvReg(1)), 0xdead_0000);
+        assert_eq!(vm.reg.read(RvReg(11)), 1);
+        assert_eq!(vm.csr.mepc, sym("jalr") as u64 + 4);
+        assert_eq!(vm.csr.cycle - vm.csr.instret, 6);
+    }
+
+    #[test]
+    fn bare_metal_traps() {
+        let src = "
+            ecall
+            ebreak
+        ";
+        let (mut vm, prog) = load_program(src, "rv32i_zicsr");
+        // Traps are taken even though 'mtvec' was never written
+        assert!(matches!(vm.step(), StepResult::Goto(0)));
+        assert_eq!(vm.csr.mcause, 11);
+        assert_eq!(vm.csr.mepc, prog.entry);
+        vm.pc = prog.entry + 4;
+        assert!(matches!(vm.step(), StepResult::Goto(0)));
+        assert_eq!(vm.csr.mcause, 3);
+
+        // A process stops at the breakpoint instead
+        vm.set_user_emulation(true);
+        assert!(matches!(vm.step(), StepResult::Terminate));
+        assert_eq!(vm.csr.mepc, prog.entry + 4);
+    }
+
+    #[test]
+    fn access_fault() {
+        let src = r#"
+            la    t0, handler
+            csrw  mtvec, t0
+            la    s1, log
+            la    s0, next
+            li    t0, 0x7ffffff0    # outside of the memory
+        load:
+            lw    a0, 0(t0)
+        next:
+            la    s0, fetch
+        store:
+            sw    t0, 4(t0)
+        fetch:
+            la    s0, halt
+            jr    t0
+        handler:
+            csrr  t1, mcause
+            csrr  t2, mtval
+            csrr  t3, mepc
+            sw    t1, 0(s1)
+            sw    t2, 4(s1)
+            sw    t3, 8(s1)
+            addi  s1, s1, 12
+            jr    s0
+            .data
+        log:
+            .zero 36
+        "#;
+        let (mut vm, prog) = load_program(src, "rv32i_zicsr");
+        assert_eq!(vm.run_for(1000), Some(0));
+        let log: Vec<u32> = (0..9)
+            .map(|idx| vm.ram.load32(prog.symbols["log"] as usize + idx * 4))
+            .collect();
+        let sym = |name| prog.symbols[name] as u32;
+        assert_eq!(log, [
+            5, 0x7fff_fff0, sym("load"),
+            7, 0x7fff_fff4, sym("store"),
+            1, 0x7fff_fff0, 0x7fff_fff0,
+        ]);
+        // The faulting load has no effect
+        assert_eq!(vm.reg.read(RvReg(10)), 0);
+    }
+
+    #[test]
+    fn privilege_modes() {
+        let src = r#"
+            la    t0, mhandler
+            csrw  mtvec, t0
+            la    t0, shandler
+            csrw  stvec, t0
+            li    t0, 0x100         # delegate ecalls from user mode
+            csrw  medeleg, t0
+            la    s1, log
+            li    t0, 0x1800        # MPP = user
+            csrc  mstatus, t0
+            la    t0, user
+            csrw  mepc, t0
+            mret
+        user:
+            ecall
+            csrr  a0, mstatus
+            wfi
+            csrr  a1, cycle
+            ebreak
+        mhandler:
+            csrr  t1, mcause
+            csrr  t2, mstatus
+            sw    t1, 0(s1)
+            sw    t2, 4(s1)
+            addi  s1, s1, 8
+            li    t3, 3
+            beq   t1, t3, exit
+            csrr  t3, mepc
+            addi  t3, t3, 4
+            csrw  mepc, t3
+            mret
+        exit:
+            j     halt
+        shandler:
+            csrr  t1, scause
+            csrr  t2, sstatus
+            sw    t1, 0(s1)
+            sw    t2, 4(s1)
+            addi  s1, s1, 8
+            csrr  t3, sepc
+            addi  t3, t3, 4
+            csrw  sepc, t3
+            sret
+            .data
+        log:
+            .zero 40
+        "#;
+        let (mut vm, prog) = load_program(src, "rv32i_zicsr");
+        assert_eq!(vm.run(), Some(0));
+        let log: Vec<u32> = (0..10)
+            .map(|idx| vm.ram.load32(prog.symbols["log"] as usize + idx * 4))
+            .collect();
+        // The ecall is taken in supervisor mode (with SPP = user), and the
+        // other exceptions in machine mode (with MPP = user, and SPIE set 
+        // by SRET)
+        assert_eq!(log, [
+            8, 0x00, 
+            2, 0x20, 
+            2, 0x20, 
+            2, 0x20, 
+            3, 0x20,
+        ]);
+        assert_eq!(vm.csr.prv, RvPrivilege::Machine);
+        assert_eq!(vm.reg.read(RvReg(10)), 0);
+        assert_eq!(vm.csr.mepc, prog.symbols["user"] + 16);
+        assert_eq!(vm.csr.sepc, prog.symbols["user"] + 4);
+    }
+
+    #[test]
+    fn supervisor_interrupt() {
+        let src = r#"
+            la    t0, mhandler
+            csrw  mtvec, t0
+            la    t0, shandler
+            csrw  stvec, t0
+            li    t0, 2             # delegate supervisor software interrupts
+            csrw  mideleg, t0
+            csrw  mie, t0
+            li    t0, 0x1000        # MPP = supervisor
+            csrc  mstatus, t0
+            la    t0, super
+            csrw  mepc, t0
+            mret
+        super:
+            csrsi sip, 2
+            li    a0, 1             # not interrupted (SIE is clear)
+            csrsi sstatus, 2
+            li    a1, 1
+        wait:
+            j     wait
+        shandler:
+            csrr  s0, scause
+            csrr  s1, sepc
+            csrci sip, 2
+            ebreak
+        mhandler:
+            csrr  s2, mcause
+            j     halt
+        "#;
+        let (mut vm, prog) = load_program(src, "rv32i_zicsr");
+        assert_eq!(vm.run_for(1000), Some(0));
+        assert_eq!(vm.reg.read(RvReg(10)), 1);
+        assert_eq!(vm.reg.read(RvReg(11)), 0);
+        assert_eq!(vm.reg.read(RvReg(8)), 0x8000_0001);
+        assert_eq!(vm.reg.read(RvReg(9)), prog.symbols["super"] + 12);
+        assert_eq!(vm.reg.read(RvReg(18)), 3);
+        // SIE was saved in SPIE and cleared
+        assert_eq!(vm.csr.mstatus & (MSTATUS_SIE | MSTATUS_SPIE), 
+            MSTATUS_SPIE);
+    }
+
+    #[test]
+    fn page_fault() {
+        let src = r#"
+            li    t0, 0x200000      # identity megapage for the first 4 MiB
+            li    t1, 0xcf
+            sw    t1, 0(t0)
+            li    t0, 0x80000200
+            csrw  satp, t0
+            la    t0, handler
+            csrw  mtvec, t0
+            li    t0, 0x1000        # MPP = supervisor
+            csrc  mstatus, t0
+            la    t0, super
+            csrw  mepc, t0
+            mret
+        super:
+            sfence.vma
+            li    t0, 0x400010
+        load:
+            lw    a0, 0(t0)
+            li    a1, 1
+        handler:
+            csrr  s0, mcause
+            csrr  s1, mtval
+            csrr  s2, mepc
+            j     halt
+        "#;
+        let (mut vm, prog) = load_program(src, "rv32i_zicsr");
+        assert_eq!(vm.run_for(1000), Some(0));
+        assert_eq!(vm.reg.read(RvReg(11)), 0);
+        assert_eq!(vm.reg.read(RvReg(8)), 13);
+        assert_eq!(vm.reg.read(RvReg(9)), 0x40_0010);
+        assert_eq!(vm.reg.read(RvReg(18)), prog.symbols["load"]);
+        assert_eq!(vm.ram.load32(0x20_0000), 0xcf);
+    }
+
+    #[test]
+    fn pmp_regions() {
+        let src = r#"
+            la    t0, handler
+            csrw  mtvec, t0
+            la    s1, log
+            li    t0, 0x801ff       # 4 KiB at 0x200000
+            csrw  pmpaddr0, t0
+            li    t0, 0x100000      # below 0x400000 (from pmpaddr1)
+            csrw  pmpaddr2, t0
+            li    t0, 0x0f0098      # locked NAPOT without access, TOR with RWX
+            csrw  pmpcfg0, t0
+            li    t0, 0x200000
+            lw    a0, 0(t0)
+            csrw  pmpaddr0, zero
+            csrr  s2, pmpaddr0
+            csrr  s3, pmpcfg0
+            li    t0, 0x1800        # MPP = user
+            csrc  mstatus, t0
+            la    t0, user
+            csrw  mepc, t0
+            mret
+        user:
+            li    t0, 0x100000
+            lw    a1, 0(t0)
+            li    t0, 0x200ffc
+            sw    a1, 0(t0)
+            li    t0, 0x400000
+            lw    a2, 0(t0)
+            ecall
+        handler:
+            csrr  t1, mcause
+            csrr  t2, mtval
+            sw    t1, 0(s1)
+            sw    t2, 4(s1)
+            addi  s1, s1, 8
+            li    t3, 8
+            beq   t1, t3, done
+            csrr  t3, mepc
+            addi  t3, t3, 4
+            csrw  mepc, t3
+            mret
+        done:
+            j     halt
+            .data
+        log:
+            .zero 32
+        "#;
+        let (mut vm, prog) = load_program(src, "rv32i_zicsr");
+        vm.ram.store32(0x10_0000, 0x1234_5678);
+        assert_eq!(vm.run_for(1000), Some(0));
+        let log: Vec<u32> = (0..8)
+            .map(|idx| vm.ram.load32(prog.symbols["log"] as usize + idx * 4))
+            .collect();
+        // Locked entries apply to machine mode, and other modes can only
+        // access the matching regions
+        assert_eq!(log, [5, 0x20_0000, 7, 0x20_0ffc, 5, 0x40_0000, 8, 0]);
+        assert_eq!(vm.reg.read(RvReg(10)), 0);
+        assert_eq!(vm.reg.read(RvReg(11)), 0x1234_5678);
+        assert_eq!(vm.reg.read(RvReg(18)), 0x801ff);
+        assert_eq!(vm.reg.read(RvReg(19)), 0x0f_0098);
+        assert_eq!(vm.ram.load32(0x20_0ffc), 0);
+    }
+
+    #[test]
+    fn clint_interrupts() {
+        let src = r#"
+            la    t0, handler
+            csrw  mtvec, t0
+            li    s0, 0x2000000     # msip
+            li    t0, 0x4000
+            add   s1, s0, t0        # mtimecmp
+            li    t0, 5
+            sw    t0, 0(s1)
+            sw    zero, 4(s1)
+            li    t0, 0x88          # machine timer and software interrupts
+            csrw  mie, t0
+            csrsi mstatus, 8
+        wait:
+            li    t0, 3
+            blt   a0, t0, wait
+            li    t0, 1
+            sw    t0, 0(s0)
+            nop
+            j     halt
+        handler:
+            csrr  t2, mcause
+            andi  t2, t2, 0xff
+            li    t3, 7
+            bne   t2, t3, soft
+            addi  a0, a0, 1
+            csrr  s2, time
+            lw    t4, 0(s1)
+            addi  t4, t4, 5
+            sw    t4, 0(s1)
+            mret
+        soft:
+            addi  a1, a1, 1
+            sw    zero, 0(s0)
+            mret
+        "#;
+        let (mut vm, _) = load_program(src, "rv32i_zicsr");
+        vm.set_timer_rate(10);
+        assert_eq!(vm.run_for(10_000), Some(0));
+        assert_eq!(vm.reg.read(RvReg(10)), 3);
+        assert_eq!(vm.reg.read(RvReg(11)), 1);
+        assert!((15..17).contains(&vm.reg.read(RvReg(18))));
+        assert_eq!(vm.clint.mtimecmp, 20);
+        assert_eq!(vm.clint.mtime, vm.csr.instret / 10);
+        assert!(!vm.clint.msip);
+    }
+
+    #[test]
+    fn timer_interrupt_at_reset_vector() {
+        let src = r#"
+            la    s0, halt
+            li    t0, 0x2004000     # mtimecmp
+            li    t1, 3
+            sw    t1, 0(t0)
+            sw    zero, 4(t0)
+            li    t0, 0x80
+            csrw  mie, t0
+            csrsi mstatus, 8
+        wait:
+            j     wait
+        "#;
+        let (mut vm, prog) = load_program(src, "rv32i_zicsr");
+        // The handler is at address 0 ('mtvec' is never written)
+        vm.ram.store32(0x0, 0x3420_2573);  // csrr a0, mcause
+        vm.ram.store32(0x4, 0x0004_0067);  // jr   s0
+        assert_eq!(vm.run_for(1000), Some(0));
+        assert_eq!(vm.reg.read(RvReg(10)), 0x8000_0007);
+        assert_eq!(vm.csr.mepc, prog.symbols["wait"]);
+    }
+}
@@ -0,0 +1,408 @@
+//! Instruction tables generated from the opcode specification.
+//!
+//! Each instruction is described once, in the files under `opcodes/` (see
+//! `opcodes/README` for the format). The build script turns them into
+//! [OPCODES] (used by the assembler and disassembler), and into the tables
+//! used by [RvEncoding::decode] and [RvInstr::encode]. Adding instructions
+//! to an extension only means adding lines to a file.
+
+use crate::rv32::*;
+use crate::isa::*;
+
+/// An operand (a field of an encoding which isn't fixed).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvOperand {
+    Rd,
+    Rs1,
+    Rs2,
+    Rs3,
+    Frd,
+    Frs1,
+    Frs2,
+    Frs3,
+    /// I-type immediate
+    Imm12,
+    /// S-type immediate
+    Simm12,
+    /// B-type (branch) offset
+    Bimm12,
+    /// J-type (jump) offset
+    Jimm20,
+    /// U-type immediate (the upper 20 bits of a value)
+    Imm20,
+    /// Shift amount (RV32, or a word-sized shift on RV64)
+    Shamt5,
+    /// Shift amount (RV64)
+    Shamt6,
+    /// CSR address
+    Csr,
+    /// Immediate operand of a CSR instruction
+    Zimm5,
+    /// Rounding mode
+    Rm,
+    /// Acquire bit
+    Aq,
+    /// Release bit
+    Rl,
+    /// Fence mode
+    Fm,
+    /// Predecessor set of a fence
+    Pred,
+    /// Successor set of a fence
+    Succ,
+    /// Byte select (of the AES and SM4 instructions)
+    Bs,
+    Vd,
+    Vs1,
+    Vs2,
+    /// Vector register holding the data of a store
+    Vs3,
+    /// Vector mask enable (set when the instruction is unmasked)
+    Vm,
+    /// Immediate operand of a vector instruction
+    Simm5,
+    /// Unsigned immediate operand of a vector instruction (a shift amount)
+    Uimm5,
+    /// Vector type of `vsetivli`
+    Zimm10,
+    /// Vector type of `vsetvli`
+    Zimm11,
+}
+
+/// Sign-extend the low 'bits' bits of a value.
+fn sext(x: u32, bits: u32) -> i64 {
+    (((x << (32 - bits)) as i32) >> (32 - bits)) as i64
+}
+
+impl RvOperand {
+    /// The field reported when this operand cannot be encoded.
+    fn field(&self) -> RvField {
+        match self {
+            Self::Rd | Self::Frd | Self::Vd | Self::Vs3 => RvField::Rd,
+            Self::Rs1 | Self::Frs1 | Self::Vs1 => RvField::Rs1,
+            Self::Rs2 | Self::Frs2 | Self::Vs2 => RvField::Rs2,
+            Self::Rs3 | Self::Frs3 | Self::Aq | Self::Rl 
+            | Self::Bs | Self::Vm => RvField::Funct7,
+            Self::Rm => RvField::Rm,
+            _ => RvField::Imm,
+        }
+    }
+
+    /// Extract the value of this operand from an encoding.
+    pub fn get(&self, bits: u32) -> i64 {
+        let field = |lo: u32, width: u32| ((bits >> lo) & ((1 << width) - 1)) as i64;
+        match self {
+            Self::Rd | Self::Frd | Self::Vd | Self::Vs3 => field(7, 5),
+            Self::Rs1 | Self::Frs1 | Self::Zimm5 
+            | Self::Vs1 | Self::Uimm5 => field(15, 5),
+            Self::Rs2 | Self::Frs2 | Self::Shamt5 | Self::Vs2 => field(20, 5),
+            Self::Rs3 | Self::Frs3 => field(27, 5),
+            Self::Imm12 => sext(bits >> 20, 12),
+            Self::Simm12 => sext(((bits >> 25) << 5) | ((bits >> 7) & 0x1f), 12),
+            Self::Bimm12 => {
+                let imm12   = (bits >> 31) << 12;
+                let imm11   = ((bits >> 7) & 0x1) << 11;
+                let imm10_5 = ((bits >> 25) & 0x3f) << 5;
+                let imm4_1  = ((bits >> 8) & 0xf) << 1;
+                sext(imm12 | imm11 | imm10_5 | imm4_1, 13)
+            },
+            Self::Jimm20 => {
+                let imm20    = (bits >> 31) << 20;
+                let imm19_12 = ((bits >> 12) & 0xff) << 12;
+                let imm11    = ((bits >> 20) & 0x1) << 11;
+                let imm10_1  = ((bits >> 21) & 0x3ff) << 1;
+                sext(imm20 | imm19_12 | imm11 | imm10_1, 21)
+            },
+            Self::Imm20 => field(12, 20),
+            Self::Shamt6 => field(20, 6),
+            Self::Csr => field(20, 12),
+            Self::Rm => field(12, 3),
+            Self::Aq => field(26, 1),
+            Self::Rl => field(25, 1),
+            Self::Fm => field(28, 4),
+            Self::Pred => field(24, 4),
+            Self::Succ => field(20, 4),
+            Self::Bs => field(30, 2),
+            Self::Vm => field(25, 1),
+            Self::Simm5 => sext(bits >> 15, 5),
+            Self::Zimm10 => field(20, 10),
+            Self::Zimm11 => field(20, 11),
+        }
+    }
+
+    /// Place a value in the field(s) of this operand.
+    pub fn set(&self, val: i64) -> Result<u32, EncodeError> {
+        let fail = |reason| Err(EncodeError { field: self.field(), reason });
+        // Signed immediates (and the required alignment)
+        let (bits, align) = match self {
+            Self::Simm5 => (5, 1),
+            Self::Imm12 | Self::Simm12 => (12, 1),
+            Self::Bimm12 => (13, 2),
+            Self::Jimm20 => (21, 2),
+            _ => (0, 1),
+        };
+        if bits != 0 {
+            if val < -(1 << (bits - 1)) || val >= 1 << (bits - 1) {
+                return fail(EncodeReason::OutOfRange);
+            }
+            if val & (align - 1) != 0 {
+                return fail(EncodeReason::Misaligned);
+            }
+        }
+        let x = val as u32;
+        let place = |lo: u32, width: u32| {
+            if val < 0 || val >= 1 << width {
+                return fail(EncodeReason::OutOfRange);
+            }
+            Ok(x << lo)
+        };
+        match self {
+            Self::Rd | Self::Frd | Self::Vd | Self::Vs3 => place(7, 5),
+            Self::Rs1 | Self::Frs1 | Self::Zimm5 
+            | Self::Vs1 | Self::Uimm5 => place(15, 5),
+            Self::Rs2 | Self::Frs2 | Self::Shamt5 | Self::Vs2 => place(20, 5),
+            Self::Rs3 | Self::Frs3 => place(27, 5),
+            Self::Imm12 => Ok((x & 0xfff) << 20),
+            Self::Simm12 => Ok(((x & 0xfe0) << 20) | ((x & 0x1f) << 7)),
+            Self::Bimm12 => {
+                let imm12   = (x >> 12) & 0x1;
+                let imm11   = (x >> 11) & 0x1;
+                let imm10_5 = (x >> 5)  & 0x3f;
+                let imm4_1  = (x >> 1)  & 0xf;
+                Ok((imm12 << 31) | (imm10_5 << 25) | (imm4_1 << 8) | (imm11 << 7))
+            },
+            Self::Jimm20 => {
+                let imm20    = (x >> 20) & 0x1;
+                let imm19_12 = (x >> 12) & 0xff;
+                let imm11    = (x >> 11) & 0x1;
+                let imm10_1  = (x >> 1)  & 0x3ff;
+                Ok((imm20 << 31) | (imm10_1 << 21) | (imm11 << 20) | (imm19_12 << 12))
+            },
+            Self::Imm20 => place(12, 20),
+            Self::Shamt6 => place(20, 6),
+            Self::Csr => place(20, 12),
+            Self::Rm => place(12, 3),
+            Self::Aq => place(26, 1),
+            Self::Rl => place(25, 1),
+            Self::Fm => place(28, 4),
+            Self::Pred => place(24, 4),
+            Self::Succ => place(20, 4),
+            Self::Bs => place(30, 2),
+            Self::Vm => place(25, 1),
+            Self::Simm5 => Ok((x & 0x1f) << 15),
+            Self::Zimm10 => place(20, 10),
+            Self::Zimm11 => place(20, 11),
+        }
+    }
+}
+
+/// A part of the assembly syntax of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvSyntax {
+    Operand(RvOperand),
+    /// A memory operand, `offset(base)` (or `(base)` if there is no offset)
+    Mem(Option<RvOperand>, RvOperand),
+    /// The mask register of an instruction which is always masked (`v0`)
+    Mask,
+}
+
+/// An instruction in the opcode specification.
+#[derive(Clone, Copy, Debug)]
+pub struct RvOpcodeSpec {
+    /// Mnemonic (without the `.aq` and `.rl` suffixes of atomics)
+    pub name: &'static str,
+    /// Operands in assembly syntax (for example, `rd, imm12(rs1)`)
+    pub syntax: &'static str,
+    /// The bits which are fixed in the encoding
+    pub mask: u32,
+    /// The values of the fixed bits
+    pub bits: u32,
+    /// The only XLEN where the instruction exists (if any)
+    pub xlen: Option<RvXlen>,
+    /// The extension which the instruction belongs to
+    pub ext: RvExt,
+    /// The operands in the order they appear in the syntax, followed by
+    /// any operands which don't appear (`aq`, `rl` and `fm`)
+    pub operands: &'static [RvOperand],
+}
+impl RvOpcodeSpec {
+    pub fn is_valid(&self, xlen: RvXlen) -> bool {
+        self.xlen.is_none() || self.xlen == Some(xlen)
+    }
+
+    /// Find the instruction with some encoding.
+    pub fn find(bits: u32, xlen: RvXlen) -> Option<&'static Self> {
+        OPCODES.iter()
+            .find(|spec| bits & spec.mask == spec.bits && spec.is_valid(xlen))
+    }
+
+    /// Find the instruction with some mnemonic.
+    pub fn lookup(name: &str, xlen: RvXlen) -> Option<&'static Self> {
+        OPCODES.iter()
+            .find(|spec| spec.name == name && spec.is_valid(xlen))
+    }
+
+    /// Split the syntax into its parts.
+    pub fn parts(&self) -> Vec<RvSyntax> {
+        let mut operands = self.operands.iter().copied();
+        let mut next = || operands.next().unwrap();
+        let mut res = Vec::new();
+        for part in self.syntax.split(", ").filter(|part| !part.is_empty()) {
+            res.push(match part.find('(') {
+                Some(0) => RvSyntax::Mem(None, next()),
+                Some(_) => {
+                    let off = next();
+                    RvSyntax::Mem(Some(off), next())
+                },
+                None if part == "v0" => RvSyntax::Mask,
+                None => RvSyntax::Operand(next()),
+            });
+        }
+        res
+    }
+}
+
+impl RvEncoding {
+    /// Extract an integer register operand, which must exist in the ISA.
+    fn reg(&self, op: RvOperand, isa: &RvIsa) -> Result<RvReg, DecodeError> {
+        let idx = op.get(self.0) as usize;
+        if idx >= isa.regs() {
+            let reason = DecodeReason::Reserved;
+            return Err(DecodeError { bits: self.0, field: op.field(), reason });
+        }
+        Ok(RvReg(idx))
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/opcodes.rs"));
+
+/// Find the reason why an encoding doesn't match any instruction.
+///
+/// The fields are compared in order (the opcode, funct3, funct7 and the
+/// register fields), and the first one that rules out every instruction
+/// is reported. It is reported as unsupported if an instruction from an
+/// extension outside of the ISA matches it, or as requiring RV64 if an
+/// RV64 instruction matches it.
+pub(crate) fn illegal(bits: u32, isa: &RvIsa) -> DecodeError {
+    let error = |field, reason| DecodeError { bits, field, reason };
+    match RvEncoding(bits).opcode() {
+        Err(e) => return e,
+        Ok(RvOpcode::CUSTOM_0 | RvOpcode::CUSTOM_1
+           | RvOpcode::CUSTOM_2 | RvOpcode::CUSTOM_3) => {
+            return error(RvField::Opcode, DecodeReason::Unsupported);
+        },
+        Ok(RvOpcode::RES_1 | RvOpcode::RES_2) => {
+            return error(RvField::Opcode, DecodeReason::Reserved);
+        },
+        Ok(_) => {},
+    }
+
+    let regions = [
+        (RvField::Opcode, 0x0000_007f),
+        (RvField::Funct3, 0x0000_7000),
+        (RvField::Funct7, 0xfe00_0000),
+        (RvField::Rs2,    0x01f0_0000),
+        (RvField::Rs1,    0x000f_8000),
+        (RvField::Rd,     0x0000_0f80),
+    ];
+    let xlen = isa.xlen;
+    let (mut legal, mut disabled): (Vec<&RvOpcodeSpec>, _) = OPCODES.iter()
+        .filter(|spec| spec.is_valid(xlen))
+        .partition(|spec| isa.supports(spec.ext));
+    let mut rv64: Vec<&RvOpcodeSpec> = OPCODES.iter()
+        .filter(|spec| xlen == RvXlen::Rv32 && spec.is_valid(RvXlen::Rv64))
+        .collect();
+    for (field, region) in regions {
+        let matches = |spec: &&RvOpcodeSpec| {
+            (bits ^ spec.bits) & spec.mask & region == 0
+        };
+        legal.retain(matches);
+        disabled.retain(matches);
+        rv64.retain(matches);
+        if legal.is_empty() {
+            let reason = if !disabled.is_empty() {
+                DecodeReason::Unsupported
+            } else if !rv64.is_empty() {
+                DecodeReason::RequiresRv64
+            } else if field == RvField::Opcode {
+                DecodeReason::Unsupported
+            } else {
+                DecodeReason::Reserved
+            };
+            return error(field, reason);
+        }
+    }
+    // Bits outside of these fields (for example, the shift amount of an
+    // RV64 shift, or an immediate function code)
+    let reason = if !disabled.is_empty() {
+        DecodeReason::Unsupported
+    } else if !rv64.is_empty() {
+        DecodeReason::RequiresRv64
+    } else {
+        DecodeReason::Reserved
+    };
+    error(RvField::Imm, reason)
+}
+
+/// The error for an instruction which is only valid on RV64.
+fn requires_rv64(bits: u32) -> EncodeError {
+    let field = illegal(bits, &RvIsa::full(RvXlen::Rv32)).field;
+    EncodeError { field, reason: EncodeReason::RequiresRv64 }
+}
+
+/// The error for an instruction which doesn't match any template.
+fn invalid(inst: &RvInstr) -> EncodeError {
+    let field = match inst {
+        RvInstr::Op(..) | RvInstr::OpW(..) => RvField::Funct7,
+        _ => RvField::Funct3,
+    };
+    EncodeError { field, reason: EncodeReason::Invalid }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opcodes::*;
+
+    #[test]
+    fn operand_fields() {
+        // Every operand can be extracted after it has been placed
+        let ops = [
+            (RvOperand::Imm12, -2048), (RvOperand::Simm12, 2047),
+            (RvOperand::Bimm12, -4096), (RvOperand::Bimm12, 4094),
+            (RvOperand::Jimm20, -(1 << 20)), (RvOperand::Jimm20, 0x7_f7fe),
+            (RvOperand::Imm20, 0xf_ffff), (RvOperand::Shamt6, 63),
+            (RvOperand::Csr, 0xfff), (RvOperand::Rs3, 31),
+            (RvOperand::Fm, 0b1000), (RvOperand::Aq, 1),
+            (RvOperand::Bs, 3), (RvOperand::Simm5, -16), (RvOperand::Simm5, 15),
+            (RvOperand::Zimm11, 0x7ff), (RvOperand::Vm, 1),
+        ];
+        for (op, val) in ops {
+            assert_eq!(op.get(op.set(val).unwrap()), val, "{:?}", op);
+        }
+        assert!(RvOperand::Bimm12.set(3).is_err());
+        assert!(RvOperand::Shamt5.set(-1).is_err());
+    }
+
+    #[test]
+    fn opcode_table() {
+        for spec in OPCODES {
+            // The encoding with all operands set to zero decodes to this
+            // instruction, and is encoded the same way
+            let xlen = spec.xlen.unwrap_or(RvXlen::Rv64);
+            let inst = RvEncoding(spec.bits).decode(xlen)
+                .unwrap_or_else(|e| panic!("{}: {}", spec.name, e));
+            assert_eq!(inst.encode(xlen), Ok(RvEncoding(spec.bits)),
+                "{}", spec.name);
+            assert_eq!(RvOpcodeSpec::find(spec.bits, xlen).unwrap().name,
+                spec.name);
+            assert_eq!(RvOpcodeSpec::lookup(spec.name, xlen).unwrap().bits,
+                spec.bits);
+        }
+        let lw = RvOpcodeSpec::lookup("lw", RvXlen::Rv32).unwrap();
+        assert_eq!(lw.parts(), [
+            RvSyntax::Operand(RvOperand::Rd),
+            RvSyntax::Mem(Some(RvOperand::Imm12), RvOperand::Rs1),
+        ]);
+        assert!(RvOpcodeSpec::lookup("ld", RvXlen::Rv32).is_none());
+    }
+}
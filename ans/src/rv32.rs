@@ -1,40 +1,48 @@
+use std::convert::TryFrom;
+
+use crate::opcodes::*;
+use crate::isa::*;
+
 
 #[repr(usize)]
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, Debug)]
 pub enum RvOpcode {
-    LOAD       = 0b00000, // [lb, lh, lw, lbu, lhu]
+    LOAD       = 0b00000, // [lb, lh, lw, lbu, lhu, lwu, ld]
     LOAD_FP    = 0b00001,
     CUSTOM_0   = 0b00010,
     MISC_MEM   = 0b00011, // [fence, fence.i]
     OP_IMM     = 0b00100, // [addi, slti, sltiu, xori, ori, andi]
     AUIPC      = 0b00101, 
-    OP_IMM_32  = 0b00110,
-    STORE      = 0b01000, // [sb, sh, sw]
+    OP_IMM_32  = 0b00110, // [addiw, slliw, srliw, sraiw]
+    STORE      = 0b01000, // [sb, sh, sw, sd]
     STORE_FP   = 0b01001,
     CUSTOM_1   = 0b01010,
-    AMO        = 0b01011,
+    AMO        = 0b01011, // [lr.w, sc.w, amo*.w, lr.d, sc.d, amo*.d]
     OP         = 0b01100, // [add, sub, sll, slt, sltu, xor, srl, sra, or, and]
+                          // [mul, mulh, mulhsu, mulhu, div, divu, rem, remu]
     LUI        = 0b01101,
-    OP_32      = 0b01110,
+    OP_32      = 0b01110, // [addw, subw, sllw, srlw, sraw]
+                          // [mulw, divw, divuw, remw, remuw]
     MADD       = 0b10000,
     MSUB       = 0b10001,
     NMSUB      = 0b10010,
     NMADD      = 0b10011,
     OP_FP      = 0b10100,
-    RES_0      = 0b10101,
+    OP_V       = 0b10101, // [vsetvli, vadd.vv, ...]
     CUSTOM_2   = 0b10110,
     BRANCH     = 0b11000, // [beq, bne, blt, bge, bltu, bgeu]
     JALR       = 0b11001,
     RES_1      = 0b11010,
     JAL        = 0b11011,
-    SYSTEM     = 0b11100,
+    SYSTEM     = 0b11100, // [ecall, ebreak, csrrw, csrrs, csrrc, ...]
     RES_2      = 0b11101,
     CUSTOM_3   = 0b11110,
 }
-impl From<u32> for RvOpcode {
-    fn from(x: u32) -> Self {
-        match x {
+impl TryFrom<u32> for RvOpcode {
+    type Error = ();
+    fn try_from(x: u32) -> Result<Self, ()> {
+        Ok(match x {
          0b00000 => Self::LOAD,
          0b00001 => Self::LOAD_FP,
          0b00010 => Self::CUSTOM_0,
@@ -54,7 +62,7 @@ impl From<u32> for RvOpcode {
          0b10010 => Self::NMSUB,
          0b10011 => Self::NMADD,
          0b10100 => Self::OP_FP,
-         0b10101 => Self::RES_0,
+         0b10101 => Self::OP_V,
          0b10110 => Self::CUSTOM_2,
          0b11000 => Self::BRANCH,
          0b11001 => Self::JALR,
@@ -63,21 +71,130 @@ impl From<u32> for RvOpcode {
          0b11100 => Self::SYSTEM,
          0b11101 => Self::RES_2,
          0b11110 => Self::CUSTOM_3,
-         _ => unimplemented!(),
+         _ => return Err(()),
+        })
+    }
+}
+
+/// The width of the integer registers.
+///
+/// Encodings that differ between RV32 and RV64 are decoded according to 
+/// some [RvXlen].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvXlen {
+    Rv32,
+    Rv64,
+}
+impl RvXlen {
+    /// Number of bits in a register.
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::Rv32 => 32,
+            Self::Rv64 => 64,
+        }
+    }
+    /// Mask for the bits in a register.
+    pub fn mask(&self) -> u64 {
+        match self {
+            Self::Rv32 => 0xffff_ffff,
+            Self::Rv64 => 0xffff_ffff_ffff_ffff,
         }
     }
 }
 
-pub enum RvEncodingFormat { R, I, S, U, B, J }
+/// A field in an instruction encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvField {
+    /// Major opcode (or the quadrant of a compressed instruction)
+    Opcode,
+    Rd,
+    Rs1,
+    Rs2,
+    Funct3,
+    /// Upper bits of an R-type encoding (funct7, funct5 or fmt)
+    Funct7,
+    /// Rounding mode
+    Rm,
+    /// Immediate (or a function code packed into the immediate)
+    Imm,
+}
+
+/// Reasons why an encoding cannot be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeReason {
+    /// The encoding is reserved (or otherwise illegal).
+    Reserved,
+    /// The encoding belongs to an extension that isn't supported.
+    Unsupported,
+    /// The encoding is only valid on RV64.
+    RequiresRv64,
+}
+
+/// An encoding which does not correspond to any supported instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    /// The raw bits of the encoding (compressed encodings occupy the low 
+    /// 16 bits).
+    pub bits: u32,
+    /// The field that could not be decoded.
+    pub field: RvField,
+    /// Why the field could not be decoded.
+    pub reason: DecodeReason,
+}
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "illegal instruction {:08x} ({:?} in {:?} field)", 
+               self.bits, self.reason, self.field)
+    }
+}
+
+/// Reasons why an instruction cannot be encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodeReason {
+    /// The operand does not fit in the field.
+    OutOfRange,
+    /// The operand is not a multiple of the required alignment.
+    Misaligned,
+    /// There is no encoding for this combination of operands.
+    Invalid,
+    /// The instruction is only valid on RV64.
+    RequiresRv64,
+}
+
+/// An instruction which cannot be represented by any encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodeError {
+    /// The field that could not be encoded.
+    pub field: RvField,
+    /// Why the field could not be encoded.
+    pub reason: EncodeReason,
+}
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot encode instruction ({:?} in {:?} field)", 
+               self.reason, self.field)
+    }
+}
+
+pub enum RvEncodingFormat { R, R4, I, S, U, B, J }
 
 #[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct RvEncoding(pub u32);
 impl RvEncoding {
-    pub fn fmt(&self) -> RvEncodingFormat {
-        match self.opcode() {
-            RvOpcode::OP     => RvEncodingFormat::R,
+    pub fn fmt(&self) -> Result<RvEncodingFormat, DecodeError> {
+        Ok(match self.opcode()? {
+            RvOpcode::OP 
+            | RvOpcode::OP_32
+            | RvOpcode::OP_FP
+            | RvOpcode::AMO  => RvEncodingFormat::R,
+            RvOpcode::MADD
+            | RvOpcode::MSUB
+            | RvOpcode::NMSUB
+            | RvOpcode::NMADD => RvEncodingFormat::R4,
             RvOpcode::JAL    => RvEncodingFormat::J,
-            RvOpcode::STORE  => RvEncodingFormat::S,
+            RvOpcode::STORE
+            | RvOpcode::STORE_FP => RvEncodingFormat::S,
             RvOpcode::BRANCH => RvEncodingFormat::B,
 
             RvOpcode::AUIPC 
@@ -85,16 +202,20 @@ impl RvEncoding {
 
             RvOpcode::MISC_MEM
             | RvOpcode::OP_IMM
+            | RvOpcode::OP_IMM_32
             | RvOpcode::JALR
             | RvOpcode::SYSTEM
+            | RvOpcode::LOAD_FP
             | RvOpcode::LOAD => RvEncodingFormat::I,
-            _ => unimplemented!(),
-        }
+            _ => {
+                return Err(self.error(RvField::Opcode, DecodeReason::Reserved));
+            },
+        })
     }
-    pub fn opcode(&self) -> RvOpcode {
-        RvOpcode::from(
+    pub fn opcode(&self) -> Result<RvOpcode, DecodeError> {
+        RvOpcode::try_from(
             (self.0 & 0b0000_000_00000_00000_000_00000_11111_00) >> 2
-        )
+        ).map_err(|_| self.error(RvField::Opcode, DecodeReason::Unsupported))
     }
     pub fn rd(&self) -> RvReg {
         RvReg(
@@ -120,15 +241,37 @@ impl RvEncoding {
     pub fn f7(&self) -> u32 {
         (self.0 & 0b1111_111_00000_00000_000_00000_00000_00) >> 25
     }
+    pub fn rs3(&self) -> RvReg {
+        RvReg(
+            ((self.0 & 0b1111_100_00000_00000_000_00000_00000_00) >> 27)
+            as usize
+        )
+    }
+    pub fn frd(&self) -> RvFReg { RvFReg(self.rd().0) }
+    pub fn frs1(&self) -> RvFReg { RvFReg(self.rs1().0) }
+    pub fn frs2(&self) -> RvFReg { RvFReg(self.rs2().0) }
+    pub fn frs3(&self) -> RvFReg { RvFReg(self.rs3().0) }
+    /// Rounding mode field
+    pub fn rm(&self) -> Result<RvRoundingMode, DecodeError> {
+        RvRoundingMode::try_from(self.f3())
+            .map_err(|_| self.error(RvField::Rm, DecodeReason::Reserved))
+    }
     pub fn uimm(&self) -> u32 {
         (self.0 & 0b1111_1111_1111_1111_1111_0000_0000_0000) >> 12
     }
+    pub fn shamt(&self) -> u32 {
+        (self.0 & 0b0000_000_11111_00000_000_00000_00000_00) >> 20
+    }
+    /// 6-bit shift amount (for RV64).
+    pub fn shamt64(&self) -> u32 {
+        (self.0 & 0b0000_001_11111_00000_000_00000_00000_00) >> 20
+    }
 
-    pub fn simm(&self) -> i32 {
+    pub fn simm(&self) -> Result<i32, DecodeError> {
         fn sext32(x: u32, bits: u32) -> i32 {
             ((x << (32 - bits)) as i32) >> (32 - bits)
         }
-        match self.fmt() {
+        Ok(match self.fmt()? {
             RvEncodingFormat::I => {
                 let imm = (self.0 & 0xfff0_0000) >> 20;
                 let imm = sext32(imm, 12);
@@ -146,9 +289,7 @@ impl RvEncoding {
                 let imm11    = ((self.0 & 0x0010_0000) >> 20) << 11;
                 let imm19_12 = ((self.0 & 0x000f_f000) >> 12) << 12;
                 let tmp = imm20 | imm19_12 | imm11 | imm10_1;
-                println!("{:08x}", tmp);
                 let imm = sext32(tmp, 21);
-                println!("{:08x} ({})", imm, imm);
                 imm
             },
             RvEncodingFormat::B => { 
@@ -157,73 +298,410 @@ impl RvEncoding {
                 let imm4_1  = ((self.0 & 0x0000_0f00) >> 8) << 1;
                 let imm11   = ((self.0 & 0x0000_0080) >> 7) << 11;
                 let tmp = imm12 | imm11 | imm10_5 | imm4_1;
-                let imm = sext32(tmp, 12);
+                let imm = sext32(tmp, 13);
                 imm
             },
-            _ => unimplemented!(),
-        }
+            // There is no signed immediate in these formats
+            _ => return Err(self.error(RvField::Imm, DecodeReason::Reserved)),
+        })
     }
 }
 
 impl RvEncoding {
-    pub fn decode(&self) -> RvInstr {
-        match self.opcode() {
-            RvOpcode::OP => {
-                let alu_op = RvALUOp::from((self.f3(), self.f7()));
-                RvInstr::Op(self.rd(), self.rs1(), self.rs2(), alu_op)
-            },
-            RvOpcode::OP_IMM => {
-                let alu_op = RvALUOp::from((self.f3(), 0b0000000));
-                RvInstr::OpImm(self.rd(), self.rs1(), self.simm(), alu_op)
-            },
-            RvOpcode::LOAD => {
-                let w   = RvWidth::from(self.f3());
-                println!("{:08x}", self.0);
-                RvInstr::Load(self.rd(), self.rs1(), self.simm(), w)
-            },
-            RvOpcode::STORE => {
-                let w   = RvWidth::from(self.f3());
-                RvInstr::Store(self.rs1(), self.rs2(), self.simm(), w)
-            },
-            RvOpcode::JAL => {
-                RvInstr::Jal(self.rd(), self.simm())
-            },
-            RvOpcode::LUI => {
-                RvInstr::Lui(self.rd(), self.uimm())
-            }
-            RvOpcode::BRANCH => {
-                let br_op = RvBranchOp::from(self.f3());
-                RvInstr::Branch(self.rs1(), self.rs2(), self.simm(), br_op)
-            }
-            RvOpcode::JALR => {
-                assert!(self.f3() == 0b000);
-                RvInstr::Jalr(self.rd(), self.rs1(), self.simm())
+    /// Create a [DecodeError] for this encoding.
+    fn error(&self, field: RvField, reason: DecodeReason) -> DecodeError {
+        DecodeError { bits: self.0, field, reason }
+    }
+
+    /// Decode an instruction (from any of the supported extensions).
+    ///
+    /// The instructions are described by the opcode specification (see
+    /// [crate::opcodes]).
+    pub fn decode(&self, xlen: RvXlen) -> Result<RvInstr, DecodeError> {
+        self.decode_table(&RvIsa::full(xlen))
+    }
+
+    /// Decode an instruction, treating instructions from extensions outside
+    /// of the ISA as illegal.
+    pub fn decode_with(&self, isa: &RvIsa) -> Result<RvInstr, DecodeError> {
+        self.decode_table(isa)
+    }
+}
+
+/// Returns the length (in bytes) of an instruction, given the first 16-bit 
+/// parcel of its encoding.
+pub fn rv_instr_len(parcel: u16) -> u32 {
+    if parcel & 0b11 == 0b11 { 4 } else { 2 }
+}
+
+/// A 16-bit compressed instruction encoding (from the C extension).
+///
+/// Each compressed instruction is expanded into the equivalent [RvInstr].
+#[repr(transparent)]
+pub struct RvCompressedEncoding(pub u16);
+impl RvCompressedEncoding {
+    /// Extract the bits in the range [hi:lo].
+    fn bits(&self, hi: u32, lo: u32) -> u32 {
+        ((self.0 as u32) >> lo) & ((1 << (hi - lo + 1)) - 1)
+    }
+    pub fn quadrant(&self) -> u32 {
+        self.bits(1, 0)
+    }
+    pub fn f3(&self) -> u32 {
+        self.bits(15, 13)
+    }
+    /// Full-width 'rd' or 'rs1' field.
+    pub fn rd(&self) -> RvReg {
+        RvReg(self.bits(11, 7) as usize)
+    }
+    /// Full-width 'rs2' field.
+    pub fn rs2(&self) -> RvReg {
+        RvReg(self.bits(6, 2) as usize)
+    }
+    /// Compressed 'rd'/'rs1' field (x8-x15).
+    pub fn rs1p(&self) -> RvReg {
+        RvReg(self.bits(9, 7) as usize + 8)
+    }
+    /// Compressed 'rd'/'rs2' field (x8-x15).
+    pub fn rs2p(&self) -> RvReg {
+        RvReg(self.bits(4, 2) as usize + 8)
+    }
+
+    /// 6-bit signed immediate used by C.ADDI, C.LI, C.ANDI.
+    fn imm6(&self) -> i32 {
+        let imm = (self.bits(12, 12) << 5) | self.bits(6, 2);
+        ((imm << 26) as i32) >> 26
+    }
+    /// 6-bit shift amount used by C.SLLI, C.SRLI, C.SRAI.
+    fn shamt(&self, xlen: RvXlen) -> Result<i32, DecodeError> {
+        if self.bits(12, 12) != 0 && xlen == RvXlen::Rv32 {
+            return Err(self.error(RvField::Imm, DecodeReason::RequiresRv64));
+        }
+        Ok(((self.bits(12, 12) << 5) | self.bits(6, 2)) as i32)
+    }
+    /// Jump offset used by C.J and C.JAL.
+    fn jimm(&self) -> i32 {
+        let imm = (self.bits(12, 12) << 11)
+            | (self.bits(11, 11) << 4)
+            | (self.bits(10, 9)  << 8)
+            | (self.bits(8, 8)   << 10)
+            | (self.bits(7, 7)   << 6)
+            | (self.bits(6, 6)   << 7)
+            | (self.bits(5, 3)   << 1)
+            | (self.bits(2, 2)   << 5);
+        ((imm << 20) as i32) >> 20
+    }
+    /// Branch offset used by C.BEQZ and C.BNEZ.
+    fn bimm(&self) -> i32 {
+        let imm = (self.bits(12, 12) << 8)
+            | (self.bits(11, 10) << 3)
+            | (self.bits(6, 5)   << 6)
+            | (self.bits(4, 3)   << 1)
+            | (self.bits(2, 2)   << 5);
+        ((imm << 23) as i32) >> 23
+    }
+    /// Word offset used by C.LW and C.SW.
+    fn lwimm(&self) -> i32 {
+        ((self.bits(12, 10) << 3)
+            | (self.bits(6, 6) << 2)
+            | (self.bits(5, 5) << 6)) as i32
+    }
+    /// Doubleword offset used by C.FLD, C.FSD, C.LD and C.SD.
+    fn ldimm(&self) -> i32 {
+        ((self.bits(12, 10) << 3) | (self.bits(6, 5) << 6)) as i32
+    }
+    /// Doubleword offset used by C.FLDSP and C.LDSP.
+    fn ldspimm(&self) -> i32 {
+        ((self.bits(12, 12) << 5)
+            | (self.bits(6, 5) << 3)
+            | (self.bits(4, 2) << 6)) as i32
+    }
+    /// Doubleword offset used by C.FSDSP and C.SDSP.
+    fn sdspimm(&self) -> i32 {
+        ((self.bits(12, 10) << 3) | (self.bits(9, 7) << 6)) as i32
+    }
+    /// Word offset used by C.LWSP.
+    fn lwspimm(&self) -> i32 {
+        ((self.bits(12, 12) << 5)
+            | (self.bits(6, 4) << 2)
+            | (self.bits(3, 2) << 6)) as i32
+    }
+    /// Word offset used by C.SWSP.
+    fn swspimm(&self) -> i32 {
+        ((self.bits(12, 9) << 2) | (self.bits(8, 7) << 6)) as i32
+    }
+
+    fn error(&self, field: RvField, reason: DecodeReason) -> DecodeError {
+        DecodeError { bits: self.0 as u32, field, reason }
+    }
+
+    pub fn decode(&self, xlen: RvXlen) -> Result<RvInstr, DecodeError> {
+        let sp = RvReg(2);
+        let zero = RvReg(0);
+        let rv64 = xlen == RvXlen::Rv64;
+        Ok(match (self.quadrant(), self.f3()) {
+            // C.ADDI4SPN
+            (0b00, 0b000) => {
+                let imm = (self.bits(12, 11) << 4)
+                    | (self.bits(10, 7) << 6)
+                    | (self.bits(6, 6)  << 2)
+                    | (self.bits(5, 5)  << 3);
+                if imm == 0 {
+                    return Err(self.error(RvField::Imm, DecodeReason::Reserved));
+                }
+                RvInstr::OpImm(self.rs2p(), sp, imm as i32, RvALUOp::Add)
+            },
+            // C.LW
+            (0b00, 0b010) => {
+                RvInstr::Load(self.rs2p(), self.rs1p(), self.lwimm(), 
+                    RvWidth::Word)
+            },
+            // C.SW
+            (0b00, 0b110) => {
+                RvInstr::Store(self.rs1p(), self.rs2p(), self.lwimm(), 
+                    RvWidth::Word)
             },
-            _ => unimplemented!("Unimplemented opcode {:?}", self.opcode()),
+            // C.FLD
+            (0b00, 0b001) => {
+                RvInstr::FLoad(RvFReg(self.rs2p().0), self.rs1p(), 
+                    self.ldimm(), RvFpFmt::D)
+            },
+            // C.LD
+            (0b00, 0b011) if rv64 => {
+                RvInstr::Load(self.rs2p(), self.rs1p(), self.ldimm(), 
+                    RvWidth::Double)
+            },
+            // C.FLW
+            (0b00, 0b011) => {
+                RvInstr::FLoad(RvFReg(self.rs2p().0), self.rs1p(), 
+                    self.lwimm(), RvFpFmt::S)
+            },
+            // C.FSD
+            (0b00, 0b101) => {
+                RvInstr::FStore(self.rs1p(), RvFReg(self.rs2p().0), 
+                    self.ldimm(), RvFpFmt::D)
+            },
+            // C.SD
+            (0b00, 0b111) if rv64 => {
+                RvInstr::Store(self.rs1p(), self.rs2p(), self.ldimm(), 
+                    RvWidth::Double)
+            },
+            // C.FSW
+            (0b00, 0b111) => {
+                RvInstr::FStore(self.rs1p(), RvFReg(self.rs2p().0), 
+                    self.lwimm(), RvFpFmt::S)
+            },
+
+            // C.NOP, C.ADDI
+            (0b01, 0b000) => {
+                RvInstr::OpImm(self.rd(), self.rd(), self.imm6(), 
+                    RvALUOp::Add)
+            },
+            // C.ADDIW
+            (0b01, 0b001) if rv64 => {
+                if self.rd().0 == 0 {
+                    return Err(self.error(RvField::Rd, DecodeReason::Reserved));
+                }
+                RvInstr::OpImmW(self.rd(), self.rd(), self.imm6(), 
+                    RvALUOp::Add)
+            },
+            // C.JAL
+            (0b01, 0b001) => RvInstr::Jal(RvReg(1), self.jimm()),
+            // C.LI
+            (0b01, 0b010) => {
+                RvInstr::OpImm(self.rd(), zero, self.imm6(), RvALUOp::Add)
+            },
+            // C.ADDI16SP
+            (0b01, 0b011) if self.rd().0 == 2 => {
+                let imm = (self.bits(12, 12) << 9)
+                    | (self.bits(6, 6) << 4)
+                    | (self.bits(5, 5) << 6)
+                    | (self.bits(4, 3) << 7)
+                    | (self.bits(2, 2) << 5);
+                if imm == 0 {
+                    return Err(self.error(RvField::Imm, DecodeReason::Reserved));
+                }
+                let imm = ((imm << 22) as i32) >> 22;
+                RvInstr::OpImm(sp, sp, imm, RvALUOp::Add)
+            },
+            // C.LUI
+            (0b01, 0b011) => {
+                if self.imm6() == 0 {
+                    return Err(self.error(RvField::Imm, DecodeReason::Reserved));
+                }
+                RvInstr::Lui(self.rd(), (self.imm6() as u32) & 0x000f_ffff)
+            },
+            (0b01, 0b100) => {
+                let rd = self.rs1p();
+                match (self.bits(11, 10), self.bits(12, 12), self.bits(6, 5)) {
+                    // C.SRLI
+                    (0b00, _, _) => {
+                        RvInstr::OpImm(rd, rd, self.shamt(xlen)?, RvALUOp::Srl)
+                    },
+                    // C.SRAI
+                    (0b01, _, _) => {
+                        RvInstr::OpImm(rd, rd, self.shamt(xlen)?, RvALUOp::Sra)
+                    },
+                    // C.ANDI
+                    (0b10, _, _) => {
+                        RvInstr::OpImm(rd, rd, self.imm6(), RvALUOp::And)
+                    },
+                    // C.SUB, C.XOR, C.OR, C.AND
+                    (0b11, 0, f2) => {
+                        let op = match f2 {
+                            0b00 => RvALUOp::Sub,
+                            0b01 => RvALUOp::Xor,
+                            0b10 => RvALUOp::Or,
+                            _    => RvALUOp::And,
+                        };
+                        RvInstr::Op(rd, rd, self.rs2p(), op)
+                    },
+                    // C.SUBW, C.ADDW
+                    (0b11, 1, f2 @ (0b00 | 0b01)) if rv64 => {
+                        let op = match f2 {
+                            0b00 => RvALUOp::Sub,
+                            _    => RvALUOp::Add,
+                        };
+                        RvInstr::OpW(rd, rd, self.rs2p(), op)
+                    },
+                    // C.SUBW, C.ADDW on RV32
+                    (0b11, 1, 0b00 | 0b01) => {
+                        return Err(self.error(RvField::Funct3, 
+                            DecodeReason::RequiresRv64));
+                    },
+                    _ => {
+                        return Err(self.error(RvField::Funct3, 
+                            DecodeReason::Reserved));
+                    },
+                }
+            },
+            // C.J
+            (0b01, 0b101) => RvInstr::Jal(zero, self.jimm()),
+            // C.BEQZ
+            (0b01, 0b110) => {
+                RvInstr::Branch(self.rs1p(), zero, self.bimm(), 
+                    RvBranchOp::Eq)
+            },
+            // C.BNEZ
+            (0b01, 0b111) => {
+                RvInstr::Branch(self.rs1p(), zero, self.bimm(), 
+                    RvBranchOp::Ne)
+            },
+
+            // C.SLLI
+            (0b10, 0b000) => {
+                RvInstr::OpImm(self.rd(), self.rd(), self.shamt(xlen)?, 
+                    RvALUOp::Sll)
+            },
+            // C.LWSP
+            (0b10, 0b010) => {
+                if self.rd().0 == 0 {
+                    return Err(self.error(RvField::Rd, DecodeReason::Reserved));
+                }
+                RvInstr::Load(self.rd(), sp, self.lwspimm(), RvWidth::Word)
+            },
+            (0b10, 0b100) => {
+                match (self.bits(12, 12), self.rd().0, self.rs2().0) {
+                    (0, 0, 0) => {
+                        return Err(self.error(RvField::Rs1, DecodeReason::Reserved));
+                    },
+                    // C.JR
+                    (0, _, 0) => RvInstr::Jalr(zero, self.rd(), 0),
+                    // C.MV
+                    (0, _, _) => {
+                        RvInstr::Op(self.rd(), zero, self.rs2(), RvALUOp::Add)
+                    },
+                    // C.EBREAK
+                    (1, 0, 0) => RvInstr::Ebreak,
+                    // C.JALR
+                    (1, _, 0) => RvInstr::Jalr(RvReg(1), self.rd(), 0),
+                    // C.ADD
+                    (_, _, _) => {
+                        RvInstr::Op(self.rd(), self.rd(), self.rs2(), 
+                            RvALUOp::Add)
+                    },
+                }
+            },
+            // C.SWSP
+            (0b10, 0b110) => {
+                RvInstr::Store(sp, self.rs2(), self.swspimm(), RvWidth::Word)
+            },
+            // C.FLDSP
+            (0b10, 0b001) => {
+                RvInstr::FLoad(RvFReg(self.rd().0), sp, self.ldspimm(), 
+                    RvFpFmt::D)
+            },
+            // C.LDSP
+            (0b10, 0b011) if rv64 => {
+                if self.rd().0 == 0 {
+                    return Err(self.error(RvField::Rd, DecodeReason::Reserved));
+                }
+                RvInstr::Load(self.rd(), sp, self.ldspimm(), RvWidth::Double)
+            },
+            // C.FLWSP
+            (0b10, 0b011) => {
+                RvInstr::FLoad(RvFReg(self.rd().0), sp, self.lwspimm(), 
+                    RvFpFmt::S)
+            },
+            // C.FSDSP
+            (0b10, 0b101) => {
+                RvInstr::FStore(sp, RvFReg(self.rs2().0), self.sdspimm(), 
+                    RvFpFmt::D)
+            },
+            // C.SDSP
+            (0b10, 0b111) if rv64 => {
+                RvInstr::Store(sp, self.rs2(), self.sdspimm(), RvWidth::Double)
+            },
+            // C.FSWSP
+            (0b10, 0b111) => {
+                RvInstr::FStore(sp, RvFReg(self.rs2().0), self.swspimm(), 
+                    RvFpFmt::S)
+            },
+            // Quadrant 0b11 holds the uncompressed encodings
+            _ => return Err(self.error(RvField::Opcode, DecodeReason::Reserved)),
+        })
+    }
+
+    /// Decode an instruction, treating it as illegal if the C extension (or
+    /// the extension of the expanded instruction) is outside of the ISA.
+    pub fn decode_with(&self, isa: &RvIsa) -> Result<RvInstr, DecodeError> {
+        if !isa.has(RvExt::C) {
+            return Err(self.error(RvField::Opcode, DecodeReason::Unsupported));
         }
+        // The expanded instruction is checked against the ISA (for example,
+        // C.FLD requires D, and C.MV can't name x16-x31 on RV32E)
+        let inst = self.decode(isa.xlen)?;
+        let enc = inst.encode(isa.xlen)
+            .expect("compressed instructions expand to valid instructions");
+        enc.decode_with(isa)
+            .map_err(|e| DecodeError { bits: self.0 as u32, ..e })
     }
 }
 
 
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RvWidth {
     Byte,
     Half,
     Word,
+    Double,
+    ByteUnsigned,
+    HalfUnsigned,
+    WordUnsigned,
 }
-impl From<u32> for RvWidth {
-    fn from(x: u32) -> Self {
-        match x {
-            0b000 => Self::Byte,
-            0b001 => Self::Half,
-            0b010 => Self::Word,
-            _ => unimplemented!(),
+impl RvWidth {
+    /// Number of bytes in the access.
+    pub fn bytes(&self) -> usize {
+        match self {
+            Self::Byte | Self::ByteUnsigned => 1,
+            Self::Half | Self::HalfUnsigned => 2,
+            Self::Word | Self::WordUnsigned => 4,
+            Self::Double => 8,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RvALUOp {
     Add,
     Sub,
@@ -238,29 +716,184 @@ pub enum RvALUOp {
 
     Or,
     And,
+
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+
+    // Zba
+    Sh1add,
+    Sh2add,
+    Sh3add,
+
+    // Zbb
+    Andn,
+    Orn,
+    Xnor,
+    Clz,
+    Ctz,
+    Cpop,
+    Max,
+    Maxu,
+    Min,
+    Minu,
+    SextB,
+    SextH,
+    ZextH,
+    Rol,
+    Ror,
+    OrcB,
+    Rev8,
+
+    // Zbs
+    Bclr,
+    Bext,
+    Binv,
+    Bset,
+
+    // Zba (RV64 only)
+    AddUw,
+    Sh1addUw,
+    Sh2addUw,
+    Sh3addUw,
+    SllUw,
+
+    // Zknh
+    Sha256Sig0,
+    Sha256Sig1,
+    Sha256Sum0,
+    Sha256Sum1,
+
+    // Zknh (RV32 only, on the halves of a 64-bit value)
+    Sha512Sig0h,
+    Sha512Sig0l,
+    Sha512Sig1h,
+    Sha512Sig1l,
+    Sha512Sum0r,
+    Sha512Sum1r,
+
+    // Zknh (RV64 only)
+    Sha512Sig0,
+    Sha512Sig1,
+    Sha512Sum0,
+    Sha512Sum1,
+
+    // Zksh
+    Sm3P0,
+    Sm3P1,
+}
+impl RvALUOp {
+    /// Returns true for operations that only have a single operand.
+    pub fn is_unary(&self) -> bool {
+        matches!(self, Self::Clz | Self::Ctz | Self::Cpop | Self::SextB 
+            | Self::SextH | Self::ZextH | Self::OrcB | Self::Rev8 
+            | Self::Sha256Sig0 | Self::Sha256Sig1 | Self::Sha256Sum0 
+            | Self::Sha256Sum1 | Self::Sha512Sig0 | Self::Sha512Sig1 
+            | Self::Sha512Sum0 | Self::Sha512Sum1 | Self::Sm3P0 | Self::Sm3P1)
+    }
+    /// Returns true for operations that have a word-sized variant on RV64.
+    pub fn has_word_form(&self) -> bool {
+        matches!(self, Self::Add | Self::Sub | Self::Sll | Self::Srl 
+            | Self::Sra | Self::Mul | Self::Div | Self::Divu | Self::Rem 
+            | Self::Remu | Self::Rol | Self::Ror | Self::Clz | Self::Ctz 
+            | Self::Cpop)
+    }
 }
-impl From<(u32, u32)> for RvALUOp {
-    fn from(x: (u32, u32)) -> Self {
-        match x {
-            (0b000, 0b0000000) => Self::Add,
-            (0b000, 0b0100000) => Self::Sub,
 
-            (0b001, 0b0000000) => Self::Sll,
-            (0b010, 0b0000000) => Self::Slt,
-            (0b011, 0b0000000) => Self::Sltu,
-            (0b100, 0b0000000) => Self::Xor,
+/// Operations on a single byte of 'rs2', selected by an immediate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvBsOp {
+    // Zkne (RV32 only)
+    Aes32Esi,
+    Aes32Esmi,
+    // Zknd (RV32 only)
+    Aes32Dsi,
+    Aes32Dsmi,
+    // Zksed
+    Sm4Ed,
+    Sm4Ks,
+}
+
+/// Vector integer operations.
+///
+/// Each operation has some of the vector-vector, vector-scalar and 
+/// vector-immediate forms ([RvInstr::VOpVV], [RvInstr::VOpVX] and 
+/// [RvInstr::VOpVI]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvVOp {
+    Add,
+    Sub,
+    /// Reverse subtract (the scalar or immediate minus the vector)
+    Rsub,
+    Minu,
+    Min,
+    Maxu,
+    Max,
+    And,
+    Or,
+    Xor,
+    Sll,
+    Srl,
+    Sra,
+    /// Select the second operand where the mask is set, and the first 
+    /// (vs2) elsewhere. Unmasked, this moves the second operand (`vmv.v.*`).
+    Merge,
+
+    // Comparisons (which write a mask)
+    Mseq,
+    Msne,
+    Msltu,
+    Mslt,
+    Msleu,
+    Msle,
+    Msgtu,
+    Msgt,
 
-            (0b101, 0b0000000) => Self::Srl,
-            (0b101, 0b0100000) => Self::Sra,
+    Mul,
+    Mulh,
+    Mulhu,
+    Mulhsu,
+    Divu,
+    Div,
+    Remu,
+    Rem,
 
-            (0b110, 0b0000000) => Self::Or,
-            (0b111, 0b0000000) => Self::And,
-            _ => unimplemented!("ALU op f3={:03b} f7={:07b}", x.0, x.1),
-        }
+    // Multiply-add (which also read the destination)
+    Macc,
+    Nmsac,
+    Madd,
+    Nmsub,
+
+    // Reductions (of vs2 into element 0, starting from element 0 of vs1)
+    Redsum,
+    Redand,
+    Redor,
+    Redxor,
+    Redminu,
+    Redmin,
+    Redmaxu,
+    Redmax,
+}
+impl RvVOp {
+    /// Returns true for comparisons, which write a mask register.
+    pub fn is_compare(&self) -> bool {
+        matches!(self, Self::Mseq | Self::Msne | Self::Msltu | Self::Mslt 
+            | Self::Msleu | Self::Msle | Self::Msgtu | Self::Msgt)
+    }
+    /// Returns true for reductions.
+    pub fn is_reduction(&self) -> bool {
+        matches!(self, Self::Redsum | Self::Redand | Self::Redor 
+            | Self::Redxor | Self::Redminu | Self::Redmin | Self::Redmaxu 
+            | Self::Redmax)
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RvBranchOp {
     Eq,
     Ne,
@@ -269,36 +902,257 @@ pub enum RvBranchOp {
     Ltu,
     Geu,
 }
-impl From<u32> for RvBranchOp {
-    fn from(x: u32) -> Self {
-        match x {
-            0b000 => Self::Eq,
-            0b001 => Self::Ne,
-            0b010 => unimplemented!(),
-            0b011 => unimplemented!(),
-            0b100 => Self::Lt,
-            0b101 => Self::Ge,
-            0b110 => Self::Ltu,
-            0b111 => Self::Geu,
-            _ => unimplemented!(),
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvAmoOp {
+    Swap,
+    Add,
+    Xor,
+    And,
+    Or,
+    Min,
+    Max,
+    Minu,
+    Maxu,
+}
+
+/// Floating-point formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpFmt {
+    /// Single-precision
+    S,
+    /// Double-precision
+    D,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvRoundingMode {
+    /// Round to nearest, ties to even
+    Rne = 0b000,
+    /// Round towards zero
+    Rtz = 0b001,
+    /// Round down
+    Rdn = 0b010,
+    /// Round up
+    Rup = 0b011,
+    /// Round to nearest, ties to max magnitude
+    Rmm = 0b100,
+    /// Dynamic rounding mode (from the 'frm' CSR)
+    Dyn = 0b111,
+}
+impl TryFrom<u32> for RvRoundingMode {
+    type Error = ();
+    fn try_from(x: u32) -> Result<Self, ()> {
+        Ok(match x {
+            0b000 => Self::Rne,
+            0b001 => Self::Rtz,
+            0b010 => Self::Rdn,
+            0b011 => Self::Rup,
+            0b100 => Self::Rmm,
+            0b111 => Self::Dyn,
+            _ => return Err(()),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpOp {
+    Sgnj,
+    Sgnjn,
+    Sgnjx,
+    Min,
+    Max,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpCmpOp {
+    Eq,
+    Lt,
+    Le,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFmaOp {
+    Madd,
+    Msub,
+    Nmsub,
+    Nmadd,
+}
+
+/// Integer operand types for floating-point conversions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvFpInt {
+    /// Signed word
+    W,
+    /// Unsigned word
+    Wu,
+    /// Signed doubleword
+    L,
+    /// Unsigned doubleword
+    Lu,
+}
+impl RvFpInt {
+    /// Number of bits in the integer.
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::W | Self::Wu => 32,
+            Self::L | Self::Lu => 64,
         }
     }
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Self::W | Self::L)
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RvCsrOp {
+    /// Atomic read/write
+    Rw,
+    /// Atomic read and set bits
+    Rs,
+    /// Atomic read and clear bits
+    Rc,
+}
 
 
+/// ABI names of the integer registers.
+pub const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
 
-#[derive(Clone, Copy, Debug)]
+/// ABI names of the floating-point registers.
+pub const FP_ABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7",
+    "fs0", "fs1", "fa0", "fa1", "fa2", "fa3", "fa4", "fa5",
+    "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7",
+    "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct RvReg(pub usize);
+impl RvReg {
+    /// Returns the name of this register (`x10`, or `a0` if `abi` is set).
+    pub fn name(&self, abi: bool) -> String {
+        if abi { ABI_NAMES[self.0].to_string() } else { format!("x{}", self.0) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RvFReg(pub usize);
+impl RvFReg {
+    /// Returns the name of this register (`f10`, or `fa0` if `abi` is set).
+    pub fn name(&self, abi: bool) -> String {
+        if abi { FP_ABI_NAMES[self.0].to_string() } else { format!("f{}", self.0) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RvVReg(pub usize);
+impl RvVReg {
+    /// Returns the name of this register (`v10`).
+    pub fn name(&self) -> String {
+        format!("v{}", self.0)
+    }
+}
+
+/// The address of a control and status register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RvCsr(pub u16);
+impl RvCsr {
+    /// Returns true if this CSR is read-only.
+    pub fn is_read_only(&self) -> bool {
+        (self.0 >> 10) & 0b11 == 0b11
+    }
+    /// Returns the lowest privilege level that can access this CSR.
+    pub fn privilege(&self) -> u16 {
+        (self.0 >> 8) & 0b11
+    }
+}
+
+/// The type of the vector registers (the `vtype` CSR, or the immediate of
+/// `vsetvli`).
+///
+/// Only the element widths up to 64 bits (ELEN) are supported. Any other 
+/// bits (including `vill`) make the type invalid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RvVType(pub u64);
+impl RvVType {
+    /// Selected element width (SEW), in bits.
+    pub fn sew(&self) -> u32 {
+        8 << ((self.0 >> 3) & 0b111)
+    }
+    /// Register group multiplier (LMUL), as a fraction.
+    pub fn lmul(&self) -> (u32, u32) {
+        match self.0 & 0b111 {
+            0b101 => (1, 8),
+            0b110 => (1, 4),
+            0b111 => (1, 2),
+            vlmul => (1 << vlmul, 1),
+        }
+    }
+    /// Tail agnostic
+    pub fn ta(&self) -> bool {
+        self.0 & (1 << 6) != 0
+    }
+    /// Mask agnostic
+    pub fn ma(&self) -> bool {
+        self.0 & (1 << 7) != 0
+    }
+    /// Returns true if this type is supported.
+    pub fn is_valid(&self) -> bool {
+        let (_, den) = self.lmul();
+        self.0 >> 8 == 0 && self.0 & 0b111 != 0b100 
+            && self.sew() <= 64 && self.sew() * den <= 64
+    }
+    /// Number of elements in a register group, with some VLEN.
+    pub fn vlmax(&self, vlen: u32) -> u32 {
+        let (num, den) = self.lmul();
+        vlen * num / (den * self.sew())
+    }
+}
+impl std::fmt::Display for RvVType {
+    /// Formats the type in assembly syntax (for example, `e32, m1, ta, ma`).
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 >> 8 != 0 || self.0 & 0b111 == 0b100 || self.sew() > 64 {
+            return write!(f, "{:#x}", self.0);
+        }
+        let lmul = match self.lmul() {
+            (num, 1) => format!("m{}", num),
+            (_, den) => format!("mf{}", den),
+        };
+        write!(f, "e{}, {}, {}, {}", self.sew(), lmul,
+            if self.ta() { "ta" } else { "tu" },
+            if self.ma() { "ma" } else { "mu" })
+    }
+}
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RvInstr {
     /// ALU operation
     Op(RvReg, RvReg, RvReg, RvALUOp),
 
     /// ALU operation with immediate
     OpImm(RvReg, RvReg, i32, RvALUOp),
+    /// Word-sized ALU operation (RV64 only)
+    OpW(RvReg, RvReg, RvReg, RvALUOp),
+    /// Word-sized ALU operation with immediate (RV64 only)
+    OpImmW(RvReg, RvReg, i32, RvALUOp),
+    /// Byte-select operation (rd, rs1, rs2, bs, op)
+    OpBs(RvReg, RvReg, RvReg, u32, RvBsOp),
     /// Memory load
     Load(RvReg, RvReg, i32, RvWidth),
     /// Jump-and-link register
@@ -306,6 +1160,8 @@ pub enum RvInstr {
 
     /// Load upper immediate
     Lui(RvReg, u32),
+    /// Add upper immediate to program counter
+    Auipc(RvReg, u32),
 
     /// Memory store
     Store(RvReg, RvReg, i32, RvWidth),
@@ -315,6 +1171,1160 @@ pub enum RvInstr {
 
     /// Conditional branch
     Branch(RvReg, RvReg, i32, RvBranchOp),
+
+    /// Memory ordering fence (fm, predecessor set, successor set)
+    Fence(u32, u32, u32),
+    /// Instruction fetch fence
+    FenceI,
+    /// Environment call
+    Ecall,
+    /// Environment breakpoint
+    Ebreak,
+    /// Return from a supervisor-mode trap
+    Sret,
+    /// Return from a machine-mode trap
+    Mret,
+    /// Wait for an interrupt
+    Wfi,
+    /// Fence for updates to the page tables (virtual address, address 
+    /// space)
+    SfenceVma(RvReg, RvReg),
+
+    /// CSR operation
+    Csr(RvReg, RvReg, RvCsr, RvCsrOp),
+    /// CSR operation with immediate
+    CsrImm(RvReg, u32, RvCsr, RvCsrOp),
+
+    /// Load-reserved (rd, rs1, width, aq, rl)
+    Lr(RvReg, RvReg, RvWidth, bool, bool),
+    /// Store-conditional (rd, rs1, rs2, width, aq, rl)
+    Sc(RvReg, RvReg, RvReg, RvWidth, bool, bool),
+    /// Atomic memory operation (rd, rs1, rs2, op, width, aq, rl)
+    Amo(RvReg, RvReg, RvReg, RvAmoOp, RvWidth, bool, bool),
+
+    /// Floating-point load (frd, rs1, imm, fmt)
+    FLoad(RvFReg, RvReg, i32, RvFpFmt),
+    /// Floating-point store (rs1, frs2, imm, fmt)
+    FStore(RvReg, RvFReg, i32, RvFpFmt),
+    /// Floating-point arithmetic (frd, frs1, frs2, op, fmt, rm)
+    FArith(RvFReg, RvFReg, RvFReg, RvFpArithOp, RvFpFmt, RvRoundingMode),
+    /// Floating-point square root (frd, frs1, fmt, rm)
+    FSqrt(RvFReg, RvFReg, RvFpFmt, RvRoundingMode),
+    /// Floating-point sign injection, minimum and maximum 
+    /// (frd, frs1, frs2, op, fmt)
+    FOp(RvFReg, RvFReg, RvFReg, RvFpOp, RvFpFmt),
+    /// Fused multiply-add (frd, frs1, frs2, frs3, op, fmt, rm)
+    FFma(RvFReg, RvFReg, RvFReg, RvFReg, RvFmaOp, RvFpFmt, RvRoundingMode),
+    /// Floating-point comparison (rd, frs1, frs2, op, fmt)
+    FCmp(RvReg, RvFReg, RvFReg, RvFpCmpOp, RvFpFmt),
+    /// Floating-point classify (rd, frs1, fmt)
+    FClass(RvReg, RvFReg, RvFpFmt),
+    /// Convert floating-point to integer (rd, frs1, int, fmt, rm)
+    FCvtToInt(RvReg, RvFReg, RvFpInt, RvFpFmt, RvRoundingMode),
+    /// Convert integer to floating-point (frd, rs1, int, fmt, rm)
+    FCvtFromInt(RvFReg, RvReg, RvFpInt, RvFpFmt, RvRoundingMode),
+    /// Convert between floating-point formats 
+    /// (frd, frs1, destination fmt, source fmt, rm)
+    FCvt(RvFReg, RvFReg, RvFpFmt, RvFpFmt, RvRoundingMode),
+    /// Move floating-point bits to an integer register (rd, frs1, fmt)
+    FMvToInt(RvReg, RvFReg, RvFpFmt),
+    /// Move integer bits to a floating-point register (frd, rs1, fmt)
+    FMvFromInt(RvFReg, RvReg, RvFpFmt),
+
+    // In the vector instructions, 'vm' is set when the instruction is 
+    // unmasked. Otherwise, only the elements selected by v0 are active.
+
+    /// Set the vector length and type (rd, rs1, vtypei)
+    VSetVli(RvReg, RvReg, u32),
+    /// Set the vector length and type, with an immediate length 
+    /// (rd, uimm, vtypei)
+    VSetIvli(RvReg, u32, u32),
+    /// Set the vector length and type from registers (rd, rs1, rs2)
+    VSetVl(RvReg, RvReg, RvReg),
+    /// Unit-stride vector load (vd, rs1, width, vm)
+    VLoad(RvVReg, RvReg, RvWidth, bool),
+    /// Strided vector load (vd, rs1, rs2, width, vm)
+    VLoadStrided(RvVReg, RvReg, RvReg, RvWidth, bool),
+    /// Unit-stride vector store (vs3, rs1, width, vm)
+    VStore(RvVReg, RvReg, RvWidth, bool),
+    /// Strided vector store (vs3, rs1, rs2, width, vm)
+    VStoreStrided(RvVReg, RvReg, RvReg, RvWidth, bool),
+    /// Vector-vector operation (vd, vs2, vs1, op, vm)
+    VOpVV(RvVReg, RvVReg, RvVReg, RvVOp, bool),
+    /// Vector-scalar operation (vd, vs2, rs1, op, vm)
+    VOpVX(RvVReg, RvVReg, RvReg, RvVOp, bool),
+    /// Vector-immediate operation (vd, vs2, imm, op, vm)
+    VOpVI(RvVReg, RvVReg, i32, RvVOp, bool),
+    /// Move element 0 of a vector to an integer register (rd, vs2)
+    VMvXS(RvReg, RvVReg),
+    /// Move an integer register to element 0 of a vector (vd, rs1)
+    VMvSX(RvVReg, RvReg),
+    /// Write the index of each element (vd, vm)
+    VId(RvVReg, bool),
+}
+
+impl RvInstr {
+    /// Encode this instruction.
+    ///
+    /// Compressed encodings are never used. Decoding the result with the 
+    /// same XLEN yields an identical [RvInstr].
+    pub fn encode(&self, xlen: RvXlen) -> Result<RvEncoding, EncodeError> {
+        self.encode_table(xlen)
+    }
+}
+
+impl std::fmt::Display for RvInstr {
+    /// Format an instruction in GNU assembler syntax.
+    ///
+    /// Registers are printed with their ABI names when the alternate flag 
+    /// is used (`{:#}`). Branch and jump targets are printed as offsets 
+    /// from the address of the instruction. Common pseudo-instructions are
+    /// recognized (for example, `addi x0, x0, 0` is printed as `nop`), and
+    /// everything else is printed with the syntax from [OPCODES].
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let abi = f.alternate();
+        let x = |r: RvReg| r.name(abi);
+        let fr = |r: RvFReg| r.name(abi);
+        let fmt = |fmt: RvFpFmt| match fmt {
+            RvFpFmt::S => "s",
+            RvFpFmt::D => "d",
+        };
+        let csr = |csr: RvCsr| match crate::csr::NAMES.iter()
+            .find(|(_, addr)| *addr == csr.0)
+        {
+            Some((name, _)) => name.to_string(),
+            None => format!("{:#x}", csr.0),
+        };
+        let csr_op = |op: RvCsrOp| match op {
+            RvCsrOp::Rw => "w",
+            RvCsrOp::Rs => "s",
+            RvCsrOp::Rc => "c",
+        };
+        let zero = RvReg(0);
+        let ra = RvReg(1);
+
+        match *self {
+            RvInstr::OpImm(rd, rs1, 0, RvALUOp::Add) 
+                if rd == zero && rs1 == zero => write!(f, "nop"),
+            RvInstr::OpImm(rd, rs1, imm, RvALUOp::Add) if rs1 == zero => 
+                write!(f, "li {}, {}", x(rd), imm),
+            RvInstr::OpImm(rd, rs1, 0, RvALUOp::Add) => 
+                write!(f, "mv {}, {}", x(rd), x(rs1)),
+            RvInstr::OpImm(rd, rs1, -1, RvALUOp::Xor) => 
+                write!(f, "not {}, {}", x(rd), x(rs1)),
+            RvInstr::OpImm(rd, rs1, 1, RvALUOp::Sltu) => 
+                write!(f, "seqz {}, {}", x(rd), x(rs1)),
+            RvInstr::Op(rd, rs1, rs2, RvALUOp::Sub) if rs1 == zero => 
+                write!(f, "neg {}, {}", x(rd), x(rs2)),
+            RvInstr::Op(rd, rs1, rs2, RvALUOp::Sltu) if rs1 == zero => 
+                write!(f, "snez {}, {}", x(rd), x(rs2)),
+            RvInstr::OpW(rd, rs1, rs2, RvALUOp::Sub) if rs1 == zero => 
+                write!(f, "negw {}, {}", x(rd), x(rs2)),
+            RvInstr::OpImmW(rd, rs1, 0, RvALUOp::Add) => 
+                write!(f, "sext.w {}, {}", x(rd), x(rs1)),
+
+            RvInstr::Jalr(rd, rs1, 0) if rd == zero && rs1 == ra => 
+                write!(f, "ret"),
+            RvInstr::Jalr(rd, rs1, 0) if rd == zero => 
+                write!(f, "jr {}", x(rs1)),
+            RvInstr::Jalr(rd, rs1, 0) if rd == ra => 
+                write!(f, "jalr {}", x(rs1)),
+            RvInstr::Jal(rd, imm) if rd == zero => write!(f, "j {}", imm),
+            RvInstr::Jal(rd, imm) if rd == ra => write!(f, "jal {}", imm),
+            RvInstr::Branch(rs1, rs2, imm, RvBranchOp::Eq) if rs2 == zero => 
+                write!(f, "beqz {}, {}", x(rs1), imm),
+            RvInstr::Branch(rs1, rs2, imm, RvBranchOp::Ne) if rs2 == zero => 
+                write!(f, "bnez {}, {}", x(rs1), imm),
+
+            RvInstr::Fence(0b0000, 0b1111, 0b1111) => write!(f, "fence"),
+            RvInstr::Fence(0b1000, 0b0011, 0b0011) => write!(f, "fence.tso"),
+            RvInstr::SfenceVma(rs1, rs2) if rs1 == zero && rs2 == zero => 
+                write!(f, "sfence.vma"),
+            RvInstr::Csr(rd, rs1, c, RvCsrOp::Rs) if rs1 == zero => 
+                write!(f, "csrr {}, {}", x(rd), csr(c)),
+            RvInstr::Csr(rd, rs1, c, op) if rd == zero => 
+                write!(f, "csr{} {}, {}", csr_op(op), csr(c), x(rs1)),
+            RvInstr::CsrImm(rd, imm, c, op) if rd == zero => 
+                write!(f, "csr{}i {}, {}", csr_op(op), csr(c), imm),
+
+            RvInstr::FOp(rd, rs1, rs2, op, fm) if rs1 == rs2 
+                && matches!(op, RvFpOp::Sgnj | RvFpOp::Sgnjn | RvFpOp::Sgnjx) => 
+            {
+                let name = match op {
+                    RvFpOp::Sgnj  => "fmv",
+                    RvFpOp::Sgnjn => "fneg",
+                    _             => "fabs",
+                };
+                write!(f, "{}.{} {}, {}", name, fmt(fm), fr(rd), fr(rs1))
+            },
+            _ => self.fmt_spec(f, abi),
+        }
+    }
+}
+impl RvInstr {
+    /// Format an instruction with the syntax of its entry in [OPCODES].
+    fn fmt_spec(&self, f: &mut std::fmt::Formatter<'_>, abi: bool) 
+        -> std::fmt::Result 
+    {
+        let found = [RvXlen::Rv64, RvXlen::Rv32].iter().find_map(|&xlen| {
+            let enc = self.encode(xlen).ok()?;
+            Some((enc, RvOpcodeSpec::find(enc.0, xlen)?))
+        });
+        let (enc, spec) = match found {
+            Some(found) => found,
+            None => return write!(f, "{:?}", self),
+        };
+        // The fence mode is only printed by the handwritten cases above
+        if spec.operands.contains(&RvOperand::Fm) 
+            && RvOperand::Fm.get(enc.0) != 0 
+        {
+            return write!(f, "{:?}", self);
+        }
+        let operand = |op: RvOperand| {
+            let val = op.get(enc.0);
+            match op {
+                RvOperand::Rd | RvOperand::Rs1 
+                | RvOperand::Rs2 | RvOperand::Rs3 => RvReg(val as usize).name(abi),
+                RvOperand::Frd | RvOperand::Frs1 
+                | RvOperand::Frs2 | RvOperand::Frs3 => RvFReg(val as usize).name(abi),
+                RvOperand::Vd | RvOperand::Vs1 
+                | RvOperand::Vs2 | RvOperand::Vs3 => RvVReg(val as usize).name(),
+                RvOperand::Zimm10 | RvOperand::Zimm11 => {
+                    RvVType(val as u64).to_string()
+                },
+                RvOperand::Imm20 => format!("{:#x}", val),
+                RvOperand::Csr => match crate::csr::NAMES.iter()
+                    .find(|(_, addr)| *addr as i64 == val)
+                {
+                    Some((name, _)) => name.to_string(),
+                    None => format!("{:#x}", val),
+                },
+                RvOperand::Pred | RvOperand::Succ => {
+                    let res: String = "iorw".chars().enumerate()
+                        .filter(|(idx, _)| val & (0b1000 >> idx) != 0)
+                        .map(|(_, c)| c)
+                        .collect();
+                    if res.is_empty() { "0".to_string() } else { res }
+                },
+                RvOperand::Rm => match val {
+                    0b000 => "rne",
+                    0b001 => "rtz",
+                    0b010 => "rdn",
+                    0b011 => "rup",
+                    0b100 => "rmm",
+                    _     => "dyn",
+                }.to_string(),
+                _ => val.to_string(),
+            }
+        };
+
+        let ord = match (RvOperand::Aq.get(enc.0), RvOperand::Rl.get(enc.0)) {
+            _ if !spec.operands.contains(&RvOperand::Aq) => "",
+            (0, 0) => "",
+            (1, 0) => ".aq",
+            (0, _) => ".rl",
+            _      => ".aqrl",
+        };
+        let mut parts = Vec::new();
+        for part in spec.parts() {
+            match part {
+                // The rounding mode is omitted when it is dynamic
+                RvSyntax::Operand(RvOperand::Rm) 
+                    if RvOperand::Rm.get(enc.0) == 0b111 => {},
+                // Likewise, the mask is omitted when unmasked
+                RvSyntax::Operand(RvOperand::Vm) => {
+                    if RvOperand::Vm.get(enc.0) == 0 {
+                        parts.push("v0.t".to_string());
+                    }
+                },
+                RvSyntax::Operand(op) => parts.push(operand(op)),
+                RvSyntax::Mask => parts.push("v0".to_string()),
+                RvSyntax::Mem(Some(off), base) => {
+                    parts.push(format!("{}({})", operand(off), operand(base)));
+                },
+                RvSyntax::Mem(None, base) => {
+                    parts.push(format!("({})", operand(base)));
+                },
+            }
+        }
+        if parts.is_empty() {
+            write!(f, "{}{}", spec.name, ord)
+        } else {
+            write!(f, "{}{} {}", spec.name, ord, parts.join(", "))
+        }
+    }
 }
 
 
+
+
+#[cfg(test)]
+mod test {
+    use crate::rv32::*;
+
+    #[test]
+    fn decode_rv32i() {
+        // auipc t0, 0x12345
+        assert!(matches!(RvEncoding(0x1234_5297).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Auipc(RvReg(5), 0x12345)));
+        // lbu t1, -1(t2)
+        assert!(matches!(RvEncoding(0xfff3_c303).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Load(RvReg(6), RvReg(7), -1, RvWidth::ByteUnsigned)));
+        // srai t0, t1, 7
+        assert!(matches!(RvEncoding(0x4073_5293).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(5), RvReg(6), 7, RvALUOp::Sra)));
+        // fence rw, rw
+        assert!(matches!(RvEncoding(0x0330_000f).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Fence(0b0000, 0b0011, 0b0011)));
+        assert!(matches!(RvEncoding(0x0000_0073).decode(RvXlen::Rv32).unwrap(), RvInstr::Ecall));
+        assert!(matches!(RvEncoding(0x0010_0073).decode(RvXlen::Rv32).unwrap(), RvInstr::Ebreak));
+        assert!(matches!(RvEncoding(0x3020_0073).decode(RvXlen::Rv32).unwrap(), RvInstr::Mret));
+        assert!(matches!(RvEncoding(0x1020_0073).decode(RvXlen::Rv32).unwrap(), RvInstr::Sret));
+        assert!(matches!(RvEncoding(0x1050_0073).decode(RvXlen::Rv32).unwrap(), RvInstr::Wfi));
+    }
+
+    #[test]
+    fn decode_rv32a() {
+        // lr.w.aq a1, (a0)
+        assert!(matches!(RvEncoding(0x1405_25af).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Lr(RvReg(11), RvReg(10), RvWidth::Word, true, false)));
+        // sc.w.rl a2, a3, (a0)
+        assert!(matches!(RvEncoding(0x1ad5_262f).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Sc(RvReg(12), RvReg(10), RvReg(13), RvWidth::Word, 
+                false, true)));
+        // amoor.w.aqrl zero, a3, (a0)
+        assert!(matches!(RvEncoding(0x46d5_202f).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Amo(RvReg(0), RvReg(10), RvReg(13), RvAmoOp::Or, 
+                RvWidth::Word, true, true)));
+    }
+
+    #[test]
+    fn decode_zicsr() {
+        // csrr a0, misa
+        assert!(matches!(RvEncoding(0x3010_2573).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Csr(RvReg(10), RvReg(0), RvCsr(0x301), RvCsrOp::Rs)));
+        // csrrwi zero, mscratch, 5
+        assert!(matches!(RvEncoding(0x3402_d073).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::CsrImm(RvReg(0), 5, RvCsr(0x340), RvCsrOp::Rw)));
+        // csrrc a3, mscratch, a2
+        assert!(matches!(RvEncoding(0x3406_36f3).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Csr(RvReg(13), RvReg(12), RvCsr(0x340), RvCsrOp::Rc)));
+    }
+
+    #[test]
+    fn decode_rv32m() {
+        // mulhsu a0, a1, a2
+        assert!(matches!(RvEncoding(0x02c5_a533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(12), RvALUOp::Mulhsu)));
+        // remu a0, a1, a2
+        assert!(matches!(RvEncoding(0x02c5_f533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(12), RvALUOp::Remu)));
+    }
+
+    #[test]
+    fn decode_zb() {
+        // sh2add a0, a1, a2
+        assert!(matches!(RvEncoding(0x20c5_c533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(12), RvALUOp::Sh2add)));
+        // andn a0, a1, a2
+        assert!(matches!(RvEncoding(0x40c5_f533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(12), RvALUOp::Andn)));
+        // maxu a0, a1, a2
+        assert!(matches!(RvEncoding(0x0ac5_f533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(12), RvALUOp::Maxu)));
+        // clz a0, a1
+        assert!(matches!(RvEncoding(0x6005_9513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::Clz)));
+        // cpop a0, a1
+        assert!(matches!(RvEncoding(0x6025_9513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::Cpop)));
+        // sext.h a0, a1
+        assert!(matches!(RvEncoding(0x6055_9513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::SextH)));
+        // zext.h a0, a1
+        assert!(matches!(RvEncoding(0x0805_c533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(0), RvALUOp::ZextH)));
+        // rori a0, a1, 7
+        assert!(matches!(RvEncoding(0x6075_d513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 7, RvALUOp::Ror)));
+        // orc.b a0, a1
+        assert!(matches!(RvEncoding(0x2875_d513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::OrcB)));
+        // rev8 a0, a1
+        assert!(matches!(RvEncoding(0x6985_d513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::Rev8)));
+        // bexti a0, a1, 3
+        assert!(matches!(RvEncoding(0x4835_d513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 3, RvALUOp::Bext)));
+        // binv a0, a1, a2
+        assert!(matches!(RvEncoding(0x68c5_9533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(12), RvALUOp::Binv)));
+        // bseti a0, a1, 31
+        assert!(matches!(RvEncoding(0x29f5_9513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 31, RvALUOp::Bset)));
+    }
+
+    #[test]
+    fn decode_zk() {
+        // aes32esmi a0, a1, a2, 3
+        assert!(matches!(RvEncoding(0xe6c5_8533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpBs(RvReg(10), RvReg(11), RvReg(12), 3, 
+                RvBsOp::Aes32Esmi)));
+        assert!(RvEncoding(0xe6c5_8533).decode(RvXlen::Rv64).is_err());
+        // sm4ks a0, a1, a2, 1
+        assert!(matches!(RvEncoding(0x74c5_8533).decode(RvXlen::Rv64).unwrap(), 
+            RvInstr::OpBs(RvReg(10), RvReg(11), RvReg(12), 1, RvBsOp::Sm4Ks)));
+        // sha256sig0 a0, a1
+        assert!(matches!(RvEncoding(0x1025_9513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::Sha256Sig0)));
+        // sha512sum1r a0, a1, a2
+        assert!(matches!(RvEncoding(0x52c5_8533).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(12), RvALUOp::Sha512Sum1r)));
+        // sha512sig0 a0, a1
+        assert!(matches!(RvEncoding(0x1065_9513).decode(RvXlen::Rv64).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::Sha512Sig0)));
+        assert_eq!(RvEncoding(0x1065_9513).decode(RvXlen::Rv32).unwrap_err()
+            .reason, DecodeReason::RequiresRv64);
+        // sm3p1 a0, a1
+        assert!(matches!(RvEncoding(0x1095_9513).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::Sm3P1)));
+
+        // The extensions are checked separately
+        let isa = RvIsa::parse("rv32i_zknh").unwrap();
+        assert!(RvEncoding(0x1025_9513).decode_with(&isa).is_ok());
+        assert_eq!(RvEncoding(0xe6c5_8533).decode_with(&isa).unwrap_err()
+            .reason, DecodeReason::Unsupported);
+        assert_eq!(RvEncoding(0x1095_9513).decode_with(&isa).unwrap_err()
+            .reason, DecodeReason::Unsupported);
+    }
+
+    #[test]
+    fn decode_v() {
+        let rv32 = RvXlen::Rv32;
+        // vsetvli a0, a1, e32, m1, ta, ma
+        assert!(matches!(RvEncoding(0x0d05_f557).decode(rv32).unwrap(), 
+            RvInstr::VSetVli(RvReg(10), RvReg(11), 0xd0)));
+        // vsetivli a0, 4, e32, m1, tu, mu
+        assert!(matches!(RvEncoding(0xc102_7557).decode(rv32).unwrap(), 
+            RvInstr::VSetIvli(RvReg(10), 4, 0x10)));
+        // vadd.vv v1, v2, v3
+        assert!(matches!(RvEncoding(0x0221_80d7).decode(rv32).unwrap(), 
+            RvInstr::VOpVV(RvVReg(1), RvVReg(2), RvVReg(3), RvVOp::Add, true)));
+        // vle32.v v1, (a0), v0.t
+        assert!(matches!(RvEncoding(0x0005_6087).decode(rv32).unwrap(), 
+            RvInstr::VLoad(RvVReg(1), RvReg(10), RvWidth::Word, false)));
+        // vmv.v.i v4, -1
+        assert!(matches!(RvEncoding(0x5e0f_b257).decode(rv32).unwrap(), 
+            RvInstr::VOpVI(RvVReg(4), RvVReg(0), -1, RvVOp::Merge, true)));
+        // vmv.x.s a0, v2
+        assert!(matches!(RvEncoding(0x4220_2557).decode(rv32).unwrap(), 
+            RvInstr::VMvXS(RvReg(10), RvVReg(2))));
+
+        // The vector extension is checked separately
+        let isa = RvIsa::parse("rv32gc").unwrap();
+        assert_eq!(RvEncoding(0x0221_80d7).decode_with(&isa).unwrap_err()
+            .reason, DecodeReason::Unsupported);
+        // The width of a vector load doesn't overlap with FLW/FLD
+        assert!(matches!(RvEncoding(0x0005_2087).decode_with(&isa).unwrap(), 
+            RvInstr::FLoad(RvFReg(1), RvReg(10), 0, RvFpFmt::S)));
+    }
+
+    #[test]
+    fn vtype() {
+        assert_eq!(RvVType(0xd0).to_string(), "e32, m1, ta, ma");
+        assert_eq!(RvVType(0x0f).to_string(), "e16, mf2, tu, mu");
+        assert_eq!(RvVType(0x03).vlmax(128), 128);
+        assert_eq!(RvVType(0x17).vlmax(128), 2);
+        assert!(RvVType(0x1b).is_valid());
+        // SEW > ELEN, and SEW > LMUL * ELEN
+        assert!(!RvVType(0x20).is_valid());
+        assert!(!RvVType(0x1d).is_valid());
+        assert!(!RvVType(0x04).is_valid());
+        assert!(!RvVType(1 << 31).is_valid());
+    }
+
+    #[test]
+    fn decode_rv64() {
+        let rv64 = RvXlen::Rv64;
+        // addiw a2, a1, 1
+        assert!(matches!(RvEncoding(0x0015_861b).decode(rv64).unwrap(), 
+            RvInstr::OpImmW(RvReg(12), RvReg(11), 1, RvALUOp::Add)));
+        // sraiw t0, a4, 4
+        assert!(matches!(RvEncoding(0x4047_529b).decode(rv64).unwrap(), 
+            RvInstr::OpImmW(RvReg(5), RvReg(14), 4, RvALUOp::Sra)));
+        // divuw t2, a1, a1
+        assert!(matches!(RvEncoding(0x02b5_d3bb).decode(rv64).unwrap(), 
+            RvInstr::OpW(RvReg(7), RvReg(11), RvReg(11), RvALUOp::Divu)));
+        // slli a0, a1, 63
+        assert!(matches!(RvEncoding(0x03f5_9513).decode(rv64).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 63, RvALUOp::Sll)));
+        // ld a5, -8(sp)
+        assert!(matches!(RvEncoding(0xff81_3783).decode(rv64).unwrap(), 
+            RvInstr::Load(RvReg(15), RvReg(2), -8, RvWidth::Double)));
+        // lwu a6, -8(sp)
+        assert!(matches!(RvEncoding(0xff81_6803).decode(rv64).unwrap(), 
+            RvInstr::Load(RvReg(16), RvReg(2), -8, RvWidth::WordUnsigned)));
+        // sd a4, -8(sp)
+        assert!(matches!(RvEncoding(0xfee1_3c23).decode(rv64).unwrap(), 
+            RvInstr::Store(RvReg(2), RvReg(14), -8, RvWidth::Double)));
+        // amoadd.d t4, a0, (sp)
+        assert!(matches!(RvEncoding(0x00a1_3eaf).decode(rv64).unwrap(), 
+            RvInstr::Amo(RvReg(29), RvReg(2), RvReg(10), RvAmoOp::Add, 
+                RvWidth::Double, false, false)));
+        // zext.h a0, a1
+        assert!(matches!(RvEncoding(0x0805_c53b).decode(rv64).unwrap(), 
+            RvInstr::Op(RvReg(10), RvReg(11), RvReg(0), RvALUOp::ZextH)));
+        // rev8 a0, a1
+        assert!(matches!(RvEncoding(0x6b85_d513).decode(rv64).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::Rev8)));
+        // slli.uw a0, a1, 40
+        assert!(matches!(RvEncoding(0x0a85_951b).decode(rv64).unwrap(), 
+            RvInstr::OpImm(RvReg(10), RvReg(11), 40, RvALUOp::SllUw)));
+        // fmv.x.d a0, fa1
+        assert!(matches!(RvEncoding(0xe205_8553).decode(rv64).unwrap(), 
+            RvInstr::FMvToInt(RvReg(10), RvFReg(11), RvFpFmt::D)));
+        // fcvt.l.d a0, fa1
+        assert!(matches!(RvEncoding(0xc225_f553).decode(rv64).unwrap(), 
+            RvInstr::FCvtToInt(RvReg(10), RvFReg(11), RvFpInt::L, 
+                RvFpFmt::D, RvRoundingMode::Dyn)));
+
+        // c.addiw a3, 1 (c.jal on RV32)
+        assert!(matches!(RvCompressedEncoding(0x2685).decode(rv64).unwrap(), 
+            RvInstr::OpImmW(RvReg(13), RvReg(13), 1, RvALUOp::Add)));
+        assert!(matches!(RvCompressedEncoding(0x2685).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Jal(RvReg(1), _)));
+        // c.ld a0, 8(a1) (c.flw on RV32)
+        assert!(matches!(RvCompressedEncoding(0x6588).decode(rv64).unwrap(), 
+            RvInstr::Load(RvReg(10), RvReg(11), 8, RvWidth::Double)));
+        // c.subw a0, a1
+        assert!(matches!(RvCompressedEncoding(0x9d0d).decode(rv64).unwrap(), 
+            RvInstr::OpW(RvReg(10), RvReg(10), RvReg(11), RvALUOp::Sub)));
+    }
+
+    #[test]
+    fn decode_rv32c() {
+        assert_eq!(rv_instr_len(0x1141), 2);
+        assert_eq!(rv_instr_len(0x0513), 4);
+        // c.addi sp, -16
+        assert!(matches!(RvCompressedEncoding(0x1141).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(2), RvReg(2), -16, RvALUOp::Add)));
+        // c.addi16sp sp, -16
+        assert!(matches!(RvCompressedEncoding(0x717d).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(2), RvReg(2), -16, RvALUOp::Add)));
+        // c.swsp a0, 12(sp)
+        assert!(matches!(RvCompressedEncoding(0xc62a).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Store(RvReg(2), RvReg(10), 12, RvWidth::Word)));
+        // c.lwsp a0, 12(sp)
+        assert!(matches!(RvCompressedEncoding(0x4532).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Load(RvReg(10), RvReg(2), 12, RvWidth::Word)));
+        // c.lw a5, 0(a0)
+        assert!(matches!(RvCompressedEncoding(0x411c).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Load(RvReg(15), RvReg(10), 0, RvWidth::Word)));
+        // c.beqz a5, +8
+        assert!(matches!(RvCompressedEncoding(0xc781).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Branch(RvReg(15), RvReg(0), 8, RvBranchOp::Eq)));
+        // c.srai a5, 3
+        assert!(matches!(RvCompressedEncoding(0x878d).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::OpImm(RvReg(15), RvReg(15), 3, RvALUOp::Sra)));
+        // c.sub a5, a4
+        assert!(matches!(RvCompressedEncoding(0x8f99).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Op(RvReg(15), RvReg(15), RvReg(14), RvALUOp::Sub)));
+        // c.lui a5, 0xfffff
+        assert!(matches!(RvCompressedEncoding(0x77fd).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Lui(RvReg(15), 0xfffff)));
+        // c.jr ra
+        assert!(matches!(RvCompressedEncoding(0x8082).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::Jalr(RvReg(0), RvReg(1), 0)));
+        // c.fsdsp fs0, 8(sp)
+        assert!(matches!(RvCompressedEncoding(0xa422).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::FStore(RvReg(2), RvFReg(8), 8, RvFpFmt::D)));
+        // c.fld fa5, 16(a0)
+        assert!(matches!(RvCompressedEncoding(0x291c).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::FLoad(RvFReg(15), RvReg(10), 16, RvFpFmt::D)));
+    }
+
+    #[test]
+    fn decode_rv32fd() {
+        // fmadd.d fa4, fa3, fa3, fa3
+        assert!(matches!(RvEncoding(0x6ad6_8743).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::FFma(RvFReg(14), RvFReg(13), RvFReg(13), RvFReg(13), 
+                RvFmaOp::Madd, RvFpFmt::D, RvRoundingMode::Rne)));
+        // fcvt.d.s fa3, fa2
+        assert!(matches!(RvEncoding(0x4206_76d3).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::FCvt(RvFReg(13), RvFReg(12), RvFpFmt::D, RvFpFmt::S, 
+                RvRoundingMode::Dyn)));
+        // fcvt.w.s a6, fa7, rtz
+        assert!(matches!(RvEncoding(0xc008_9853).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::FCvtToInt(RvReg(16), RvFReg(17), RvFpInt::W, 
+                RvFpFmt::S, RvRoundingMode::Rtz)));
+        // flw fa6, 0(a5)
+        assert!(matches!(RvEncoding(0x0007_a807).decode(RvXlen::Rv32).unwrap(), 
+            RvInstr::FLoad(RvFReg(16), RvReg(15), 0, RvFpFmt::S)));
+    }
+
+    #[test]
+    fn decode_errors() {
+        let rv32 = RvXlen::Rv32;
+        let decode = |bits, xlen| {
+            let e = RvEncoding(bits).decode(xlen).unwrap_err();
+            assert_eq!(e.bits, bits);
+            (e.field, e.reason)
+        };
+        let decode_c = |bits, xlen| {
+            let e = RvCompressedEncoding(bits).decode(xlen).unwrap_err();
+            assert_eq!(e.bits, bits as u32);
+            (e.field, e.reason)
+        };
+
+        // Reserved major opcode
+        assert_eq!(decode(0x0000_006b, rv32), 
+            (RvField::Opcode, DecodeReason::Reserved));
+        // custom-0
+        assert_eq!(decode(0x0000_000b, rv32), 
+            (RvField::Opcode, DecodeReason::Unsupported));
+        // Prefix of a 64-bit encoding
+        assert_eq!(decode(0x0000_007f, rv32), 
+            (RvField::Opcode, DecodeReason::Unsupported));
+        // ld a5, -8(sp)
+        assert_eq!(decode(0xff81_3783, rv32), 
+            (RvField::Funct3, DecodeReason::RequiresRv64));
+        // addiw a2, a1, 1
+        assert_eq!(decode(0x0015_861b, rv32), 
+            (RvField::Opcode, DecodeReason::RequiresRv64));
+        // jalr with funct3=001
+        assert_eq!(decode(0x0000_1067, rv32), 
+            (RvField::Funct3, DecodeReason::Reserved));
+        // add with funct7=0000010
+        assert_eq!(decode(0x0400_0033, rv32), 
+            (RvField::Funct7, DecodeReason::Reserved));
+        // fadd.s with rm=101
+        assert_eq!(decode(0x00b5_5553, rv32), 
+            (RvField::Rm, DecodeReason::Reserved));
+        // fcvt.l.d a0, fa1
+        assert_eq!(decode(0xc225_f553, rv32), 
+            (RvField::Rs2, DecodeReason::RequiresRv64));
+
+        // The all-zeroes parcel is defined to be illegal
+        assert_eq!(decode_c(0x0000, rv32), 
+            (RvField::Imm, DecodeReason::Reserved));
+        // c.lui a5, 0
+        assert_eq!(decode_c(0x6781, rv32), 
+            (RvField::Imm, DecodeReason::Reserved));
+        // c.slli a0, 32
+        assert_eq!(decode_c(0x1502, rv32), 
+            (RvField::Imm, DecodeReason::RequiresRv64));
+        assert!(matches!(RvCompressedEncoding(0x1502).decode(RvXlen::Rv64), 
+            Ok(RvInstr::OpImm(RvReg(10), RvReg(10), 32, RvALUOp::Sll))));
+    }
+
+    #[test]
+    fn decode_isa() {
+        let isa = RvIsa::parse("rv32imc_zicsr_zba").unwrap();
+        let decode = |bits| {
+            RvEncoding(bits).decode_with(&isa).map_err(|e| (e.field, e.reason))
+        };
+        let decode_c = |bits| {
+            RvCompressedEncoding(bits).decode_with(&isa)
+                .map_err(|e| (e.field, e.reason))
+        };
+
+        // mul a0, a1, a2
+        assert!(decode(0x02c5_8533).is_ok());
+        // sh1add a0, a1, a2
+        assert!(decode(0x20c5_a533).is_ok());
+        // andn a0, a1, a2
+        assert_eq!(decode(0x40c5_f533),
+            Err((RvField::Funct7, DecodeReason::Unsupported)));
+        // lr.w a0, (a1)
+        assert_eq!(decode(0x1005_a52f),
+            Err((RvField::Opcode, DecodeReason::Unsupported)));
+        // fadd.s fa0, fa1, fa2
+        assert_eq!(decode(0x00c5_f553),
+            Err((RvField::Opcode, DecodeReason::Unsupported)));
+        // fence.i
+        assert_eq!(decode(0x0000_100f),
+            Err((RvField::Funct3, DecodeReason::Unsupported)));
+        // ld a5, -8(sp) still requires RV64
+        assert_eq!(decode(0xff81_3783),
+            Err((RvField::Funct3, DecodeReason::RequiresRv64)));
+
+        // c.addi a0, 1
+        assert!(decode_c(0x0505).is_ok());
+        // c.fld fa0, 0(a1)
+        assert_eq!(decode_c(0x2188),
+            Err((RvField::Opcode, DecodeReason::Unsupported)));
+        let isa = RvIsa::parse("rv32im").unwrap();
+        assert_eq!(RvCompressedEncoding(0x0505).decode_with(&isa)
+            .map_err(|e| e.reason), Err(DecodeReason::Unsupported));
+
+        // There are no registers above x15 on RV32E
+        let isa = RvIsa::parse("rv32ec").unwrap();
+        // add a0, a1, a2
+        assert!(RvEncoding(0x00c5_8533).decode_with(&isa).is_ok());
+        // add a0, a1, a6
+        let e = RvEncoding(0x0105_8533).decode_with(&isa).unwrap_err();
+        assert_eq!((e.field, e.reason), (RvField::Rs2, DecodeReason::Reserved));
+        // c.mv a0, a6
+        let e = RvCompressedEncoding(0x8542).decode_with(&isa).unwrap_err();
+        assert_eq!(e.bits, 0x8542);
+        assert_eq!((e.field, e.reason), (RvField::Rs2, DecodeReason::Reserved));
+    }
+
+    /// Check that an instruction survives being encoded and decoded.
+    fn roundtrip(inst: RvInstr, xlen: RvXlen) {
+        let enc = inst.encode(xlen)
+            .unwrap_or_else(|e| panic!("{:x?}: {}", inst, e));
+        match enc.decode(xlen) {
+            Ok(res) => assert_eq!(res, inst, "{:08x}", enc.0),
+            Err(e) => panic!("{:x?}: {}", inst, e),
+        }
+    }
+
+    #[test]
+    fn encode_known() {
+        let rv32 = RvXlen::Rv32;
+        // auipc t0, 0x12345
+        assert_eq!(RvInstr::Auipc(RvReg(5), 0x12345).encode(rv32), 
+            Ok(RvEncoding(0x1234_5297)));
+        // lbu t1, -1(t2)
+        assert_eq!(RvInstr::Load(RvReg(6), RvReg(7), -1, RvWidth::ByteUnsigned)
+            .encode(rv32), Ok(RvEncoding(0xfff3_c303)));
+        // srai t0, t1, 7
+        assert_eq!(RvInstr::OpImm(RvReg(5), RvReg(6), 7, RvALUOp::Sra)
+            .encode(rv32), Ok(RvEncoding(0x4073_5293)));
+        // sd a4, -8(sp)
+        assert_eq!(RvInstr::Store(RvReg(2), RvReg(14), -8, RvWidth::Double)
+            .encode(RvXlen::Rv64), Ok(RvEncoding(0xfee1_3c23)));
+        // rev8 a0, a1
+        assert_eq!(RvInstr::OpImm(RvReg(10), RvReg(11), 0, RvALUOp::Rev8)
+            .encode(RvXlen::Rv64), Ok(RvEncoding(0x6b85_d513)));
+        // fmadd.d fa4, fa3, fa3, fa3
+        assert_eq!(RvInstr::FFma(RvFReg(14), RvFReg(13), RvFReg(13), 
+            RvFReg(13), RvFmaOp::Madd, RvFpFmt::D, RvRoundingMode::Rne)
+            .encode(rv32), Ok(RvEncoding(0x6ad6_8743)));
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let (a0, a1, a2) = (RvReg(10), RvReg(11), RvReg(12));
+        let (fa0, fa1, fa2, fa3) = (RvFReg(10), RvFReg(11), RvFReg(12), 
+            RvFReg(13));
+        let alu_ops = [
+            RvALUOp::Add, RvALUOp::Sub, RvALUOp::Sll, RvALUOp::Slt, 
+            RvALUOp::Sltu, RvALUOp::Xor, RvALUOp::Srl, RvALUOp::Sra, 
+            RvALUOp::Or, RvALUOp::And, RvALUOp::Mul, RvALUOp::Mulh, 
+            RvALUOp::Mulhsu, RvALUOp::Mulhu, RvALUOp::Div, RvALUOp::Divu, 
+            RvALUOp::Rem, RvALUOp::Remu, RvALUOp::Sh1add, RvALUOp::Sh2add, 
+            RvALUOp::Sh3add, RvALUOp::Andn, RvALUOp::Orn, RvALUOp::Xnor, 
+            RvALUOp::Clz, RvALUOp::Ctz, RvALUOp::Cpop, RvALUOp::Max, 
+            RvALUOp::Maxu, RvALUOp::Min, RvALUOp::Minu, RvALUOp::SextB, 
+            RvALUOp::SextH, RvALUOp::ZextH, RvALUOp::Rol, RvALUOp::Ror, 
+            RvALUOp::OrcB, RvALUOp::Rev8, RvALUOp::Bclr, RvALUOp::Bext, 
+            RvALUOp::Binv, RvALUOp::Bset, RvALUOp::AddUw, RvALUOp::Sh1addUw, 
+            RvALUOp::Sh2addUw, RvALUOp::Sh3addUw, RvALUOp::SllUw, 
+            RvALUOp::Sha256Sig0, RvALUOp::Sha256Sig1, RvALUOp::Sha256Sum0, 
+            RvALUOp::Sha256Sum1, RvALUOp::Sha512Sig0h, RvALUOp::Sha512Sig0l, 
+            RvALUOp::Sha512Sig1h, RvALUOp::Sha512Sig1l, RvALUOp::Sha512Sum0r, 
+            RvALUOp::Sha512Sum1r, RvALUOp::Sha512Sig0, RvALUOp::Sha512Sig1, 
+            RvALUOp::Sha512Sum0, RvALUOp::Sha512Sum1, RvALUOp::Sm3P0, 
+            RvALUOp::Sm3P1,
+        ];
+        let simm12 = [-2048, -1, 0, 1, 2047];
+        let rms = [
+            RvRoundingMode::Rne, RvRoundingMode::Rtz, RvRoundingMode::Rdn, 
+            RvRoundingMode::Rup, RvRoundingMode::Rmm, RvRoundingMode::Dyn,
+        ];
+
+        for xlen in [RvXlen::Rv32, RvXlen::Rv64] {
+            let rv64 = xlen == RvXlen::Rv64;
+            let shamts = [0, 1, xlen.bits() as i32 - 1];
+            let widths = [
+                RvWidth::Byte, RvWidth::Half, RvWidth::Word, 
+                RvWidth::ByteUnsigned, RvWidth::HalfUnsigned,
+            ];
+            let mut insts = Vec::new();
+
+            // Register-register and register-immediate ALU operations
+            for op in alu_ops {
+                match op {
+                    RvALUOp::ZextH => {
+                        insts.push(RvInstr::Op(a0, a1, RvReg(0), op));
+                    },
+                    RvALUOp::AddUw | RvALUOp::Sh1addUw 
+                    | RvALUOp::Sh2addUw | RvALUOp::Sh3addUw if rv64 => {
+                        insts.push(RvInstr::Op(a0, a1, a2, op));
+                    },
+                    RvALUOp::SllUw if rv64 => {
+                        for imm in [0, 1, 63] {
+                            insts.push(RvInstr::OpImm(a0, a1, imm, op));
+                        }
+                    },
+                    RvALUOp::Sha512Sig0 | RvALUOp::Sha512Sig1 
+                    | RvALUOp::Sha512Sum0 | RvALUOp::Sha512Sum1 => if rv64 {
+                        insts.push(RvInstr::OpImm(a0, a1, 0, op));
+                    },
+                    RvALUOp::Sha512Sig0h | RvALUOp::Sha512Sig0l 
+                    | RvALUOp::Sha512Sig1h | RvALUOp::Sha512Sig1l 
+                    | RvALUOp::Sha512Sum0r | RvALUOp::Sha512Sum1r => if !rv64 {
+                        insts.push(RvInstr::Op(a0, a1, a2, op));
+                    },
+                    _ if op.is_unary() => {
+                        insts.push(RvInstr::OpImm(a0, a1, 0, op));
+                    },
+                    RvALUOp::Add | RvALUOp::Slt | RvALUOp::Sltu 
+                    | RvALUOp::Xor | RvALUOp::Or | RvALUOp::And => {
+                        insts.push(RvInstr::Op(a0, a1, a2, op));
+                        for imm in simm12 {
+                            insts.push(RvInstr::OpImm(a0, a1, imm, op));
+                        }
+                    },
+                    RvALUOp::Sll | RvALUOp::Srl | RvALUOp::Sra 
+                    | RvALUOp::Ror | RvALUOp::Bclr | RvALUOp::Bext 
+                    | RvALUOp::Binv | RvALUOp::Bset => {
+                        insts.push(RvInstr::Op(a0, a1, a2, op));
+                        for imm in shamts {
+                            insts.push(RvInstr::OpImm(a0, a1, imm, op));
+                        }
+                    },
+                    RvALUOp::AddUw | RvALUOp::Sh1addUw | RvALUOp::Sh2addUw 
+                    | RvALUOp::Sh3addUw | RvALUOp::SllUw => {},
+                    _ => {
+                        insts.push(RvInstr::Op(a0, a1, a2, op));
+                    },
+                }
+                if rv64 && op.has_word_form() {
+                    if op.is_unary() {
+                        insts.push(RvInstr::OpImmW(a0, a1, 0, op));
+                    } else {
+                        insts.push(RvInstr::OpW(a0, a1, a2, op));
+                    }
+                    match op {
+                        RvALUOp::Add => for imm in simm12 {
+                            insts.push(RvInstr::OpImmW(a0, a1, imm, op));
+                        },
+                        RvALUOp::Sll | RvALUOp::Srl 
+                        | RvALUOp::Sra | RvALUOp::Ror => for imm in [0, 31] {
+                            insts.push(RvInstr::OpImmW(a0, a1, imm, op));
+                        },
+                        _ => {},
+                    }
+                }
+            }
+
+            // Byte-select operations (AES is only defined on RV32)
+            let bs_ops: &[RvBsOp] = if rv64 {
+                &[RvBsOp::Sm4Ed, RvBsOp::Sm4Ks]
+            } else {
+                &[RvBsOp::Aes32Esi, RvBsOp::Aes32Esmi, RvBsOp::Aes32Dsi, 
+                  RvBsOp::Aes32Dsmi, RvBsOp::Sm4Ed, RvBsOp::Sm4Ks]
+            };
+            for &op in bs_ops {
+                for bs in 0..4 {
+                    insts.push(RvInstr::OpBs(a0, a1, a2, bs, op));
+                }
+            }
+
+            // Loads, stores and jumps
+            for imm in simm12 {
+                for w in widths {
+                    insts.push(RvInstr::Load(a0, a1, imm, w));
+                }
+                for w in &widths[..3] {
+                    insts.push(RvInstr::Store(a0, a1, imm, *w));
+                }
+                if rv64 {
+                    insts.push(RvInstr::Load(a0, a1, imm, RvWidth::Double));
+                    insts.push(RvInstr::Load(a0, a1, imm, 
+                        RvWidth::WordUnsigned));
+                    insts.push(RvInstr::Store(a0, a1, imm, RvWidth::Double));
+                }
+                insts.push(RvInstr::Jalr(a0, a1, imm));
+                insts.push(RvInstr::FLoad(fa0, a1, imm, RvFpFmt::S));
+                insts.push(RvInstr::FStore(a1, fa0, imm, RvFpFmt::D));
+            }
+            for imm in [0, 1, 0x80000, 0xfffff] {
+                insts.push(RvInstr::Lui(a0, imm));
+                insts.push(RvInstr::Auipc(a0, imm));
+            }
+            for imm in [-(1 << 20), -2, 0, 2, 2046, 2048, (1 << 20) - 2] {
+                insts.push(RvInstr::Jal(RvReg(1), imm));
+            }
+            let branch_ops = [
+                RvBranchOp::Eq, RvBranchOp::Ne, RvBranchOp::Lt, 
+                RvBranchOp::Ge, RvBranchOp::Ltu, RvBranchOp::Geu,
+            ];
+            for op in branch_ops {
+                for imm in [-4096, -2, 0, 2, 2048, 4094] {
+                    insts.push(RvInstr::Branch(a0, a1, imm, op));
+                }
+            }
+
+            // System instructions
+            insts.push(RvInstr::Fence(0b1000, 0b0011, 0b0011));
+            insts.push(RvInstr::Fence(0b0000, 0b1111, 0b1111));
+            insts.push(RvInstr::FenceI);
+            insts.push(RvInstr::Ecall);
+            insts.push(RvInstr::Ebreak);
+            insts.push(RvInstr::Sret);
+            insts.push(RvInstr::Mret);
+            insts.push(RvInstr::Wfi);
+            insts.push(RvInstr::SfenceVma(a0, a1));
+            for op in [RvCsrOp::Rw, RvCsrOp::Rs, RvCsrOp::Rc] {
+                for csr in [RvCsr(0x000), RvCsr(0x300), RvCsr(0xfff)] {
+                    insts.push(RvInstr::Csr(a0, a1, csr, op));
+                    insts.push(RvInstr::CsrImm(a0, 31, csr, op));
+                }
+            }
+
+            // Atomics
+            let amo_widths: &[RvWidth] = if rv64 { 
+                &[RvWidth::Word, RvWidth::Double] 
+            } else { 
+                &[RvWidth::Word] 
+            };
+            let amo_ops = [
+                RvAmoOp::Swap, RvAmoOp::Add, RvAmoOp::Xor, RvAmoOp::And, 
+                RvAmoOp::Or, RvAmoOp::Min, RvAmoOp::Max, RvAmoOp::Minu, 
+                RvAmoOp::Maxu,
+            ];
+            for &w in amo_widths {
+                for (aq, rl) in [(false, false), (true, false), (true, true)] {
+                    insts.push(RvInstr::Lr(a0, a1, w, aq, rl));
+                    insts.push(RvInstr::Sc(a0, a1, a2, w, aq, rl));
+                    for op in amo_ops {
+                        insts.push(RvInstr::Amo(a0, a1, a2, op, w, aq, rl));
+                    }
+                }
+            }
+
+            // Floating-point
+            let ints: &[RvFpInt] = if rv64 {
+                &[RvFpInt::W, RvFpInt::Wu, RvFpInt::L, RvFpInt::Lu]
+            } else {
+                &[RvFpInt::W, RvFpInt::Wu]
+            };
+            for fmt in [RvFpFmt::S, RvFpFmt::D] {
+                for rm in rms {
+                    for op in [RvFpArithOp::Add, RvFpArithOp::Sub, 
+                               RvFpArithOp::Mul, RvFpArithOp::Div] {
+                        insts.push(RvInstr::FArith(fa0, fa1, fa2, op, fmt, rm));
+                    }
+                    for op in [RvFmaOp::Madd, RvFmaOp::Msub, 
+                               RvFmaOp::Nmsub, RvFmaOp::Nmadd] {
+                        insts.push(RvInstr::FFma(fa0, fa1, fa2, fa3, op, 
+                            fmt, rm));
+                    }
+                    insts.push(RvInstr::FSqrt(fa0, fa1, fmt, rm));
+                    for &int in ints {
+                        insts.push(RvInstr::FCvtToInt(a0, fa1, int, fmt, rm));
+                        insts.push(RvInstr::FCvtFromInt(fa0, a1, int, fmt, 
+                            rm));
+                    }
+                    for src in [RvFpFmt::S, RvFpFmt::D] {
+                        if src != fmt {
+                            insts.push(RvInstr::FCvt(fa0, fa1, fmt, src, rm));
+                        }
+                    }
+                }
+                for op in [RvFpOp::Sgnj, RvFpOp::Sgnjn, RvFpOp::Sgnjx, 
+                           RvFpOp::Min, RvFpOp::Max] {
+                    insts.push(RvInstr::FOp(fa0, fa1, fa2, op, fmt));
+                }
+                for op in [RvFpCmpOp::Eq, RvFpCmpOp::Lt, RvFpCmpOp::Le] {
+                    insts.push(RvInstr::FCmp(a0, fa1, fa2, op, fmt));
+                }
+                insts.push(RvInstr::FClass(a0, fa1, fmt));
+                if fmt == RvFpFmt::S || rv64 {
+                    insts.push(RvInstr::FMvToInt(a0, fa1, fmt));
+                    insts.push(RvInstr::FMvFromInt(fa0, a1, fmt));
+                }
+            }
+
+            // Vector instructions
+            let (v1, v2, v3) = (RvVReg(1), RvVReg(2), RvVReg(3));
+            let vops = [
+                RvVOp::Add, RvVOp::Sub, RvVOp::Rsub, RvVOp::Minu, RvVOp::Min, 
+                RvVOp::Maxu, RvVOp::Max, RvVOp::And, RvVOp::Or, RvVOp::Xor, 
+                RvVOp::Sll, RvVOp::Srl, RvVOp::Sra, RvVOp::Mseq, RvVOp::Msne, 
+                RvVOp::Msltu, RvVOp::Mslt, RvVOp::Msleu, RvVOp::Msle, 
+                RvVOp::Msgtu, RvVOp::Msgt, RvVOp::Mul, RvVOp::Mulh, 
+                RvVOp::Mulhu, RvVOp::Mulhsu, RvVOp::Divu, RvVOp::Div, 
+                RvVOp::Remu, RvVOp::Rem, RvVOp::Macc, RvVOp::Nmsac, 
+                RvVOp::Madd, RvVOp::Nmsub, RvVOp::Redsum, RvVOp::Redand, 
+                RvVOp::Redor, RvVOp::Redxor, RvVOp::Redminu, RvVOp::Redmin, 
+                RvVOp::Redmaxu, RvVOp::Redmax,
+            ];
+            insts.push(RvInstr::VSetVli(a0, a1, 0xd0));
+            insts.push(RvInstr::VSetVli(a0, a1, 0x7ff));
+            insts.push(RvInstr::VSetIvli(a0, 31, 0x3ff));
+            insts.push(RvInstr::VSetVl(a0, a1, a2));
+            for vm in [false, true] {
+                for w in [RvWidth::Byte, RvWidth::Half, RvWidth::Word, 
+                          RvWidth::Double] {
+                    insts.push(RvInstr::VLoad(v1, a0, w, vm));
+                    insts.push(RvInstr::VLoadStrided(v1, a0, a1, w, vm));
+                    insts.push(RvInstr::VStore(v1, a0, w, vm));
+                    insts.push(RvInstr::VStoreStrided(v1, a0, a1, w, vm));
+                }
+                for op in vops {
+                    match op {
+                        RvVOp::Rsub | RvVOp::Msgtu | RvVOp::Msgt => {},
+                        _ => insts.push(RvInstr::VOpVV(v1, v2, v3, op, vm)),
+                    }
+                    match op {
+                        _ if op.is_reduction() => {},
+                        _ => insts.push(RvInstr::VOpVX(v1, v2, a0, op, vm)),
+                    }
+                    match op {
+                        RvVOp::Sll | RvVOp::Srl | RvVOp::Sra => {
+                            for imm in [0, 31] {
+                                insts.push(RvInstr::VOpVI(v1, v2, imm, op, vm));
+                            }
+                        },
+                        RvVOp::Add | RvVOp::Rsub | RvVOp::And | RvVOp::Or 
+                        | RvVOp::Xor | RvVOp::Mseq | RvVOp::Msne 
+                        | RvVOp::Msleu | RvVOp::Msle | RvVOp::Msgtu 
+                        | RvVOp::Msgt => for imm in [-16, 0, 15] {
+                            insts.push(RvInstr::VOpVI(v1, v2, imm, op, vm));
+                        },
+                        _ => {},
+                    }
+                }
+                insts.push(RvInstr::VId(v1, vm));
+            }
+            insts.push(RvInstr::VOpVV(v1, v2, v3, RvVOp::Merge, false));
+            insts.push(RvInstr::VOpVX(v1, v2, a0, RvVOp::Merge, false));
+            insts.push(RvInstr::VOpVI(v1, v2, -16, RvVOp::Merge, false));
+            insts.push(RvInstr::VOpVV(v1, RvVReg(0), v3, RvVOp::Merge, true));
+            insts.push(RvInstr::VOpVX(v1, RvVReg(0), a0, RvVOp::Merge, true));
+            insts.push(RvInstr::VOpVI(v1, RvVReg(0), 15, RvVOp::Merge, true));
+            insts.push(RvInstr::VMvXS(a0, v2));
+            insts.push(RvInstr::VMvSX(v1, a0));
+
+            // Register fields
+            for r in [0, 1, 31] {
+                insts.push(RvInstr::Op(RvReg(r), RvReg(r), RvReg(r), 
+                    RvALUOp::Add));
+                insts.push(RvInstr::FFma(RvFReg(r), RvFReg(r), RvFReg(r), 
+                    RvFReg(r), RvFmaOp::Madd, RvFpFmt::S, RvRoundingMode::Dyn));
+            }
+
+            for inst in insts {
+                roundtrip(inst, xlen);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_decoded() {
+        // Every decodable word must re-encode to an equivalent instruction.
+        let mut x: u32 = 1;
+        for _ in 0..200_000 {
+            // xorshift32
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            for xlen in [RvXlen::Rv32, RvXlen::Rv64] {
+                if let Ok(inst) = RvEncoding(x | 0b11).decode(xlen) {
+                    roundtrip(inst, xlen);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn display() {
+        let (zero, ra, sp, a0, a1) = 
+            (RvReg(0), RvReg(1), RvReg(2), RvReg(10), RvReg(11));
+        let cases = [
+            (RvInstr::OpImm(zero, zero, 0, RvALUOp::Add), "nop", "nop"),
+            (RvInstr::OpImm(a0, zero, -5, RvALUOp::Add), 
+                "li x10, -5", "li a0, -5"),
+            (RvInstr::OpImm(a0, a1, 0, RvALUOp::Add), 
+                "mv x10, x11", "mv a0, a1"),
+            (RvInstr::OpImm(sp, sp, -16, RvALUOp::Add), 
+                "addi x2, x2, -16", "addi sp, sp, -16"),
+            (RvInstr::OpImm(a0, a1, 7, RvALUOp::Sltu), 
+                "sltiu x10, x11, 7", "sltiu a0, a1, 7"),
+            (RvInstr::OpImmW(a0, a1, 3, RvALUOp::Sll), 
+                "slliw x10, x11, 3", "slliw a0, a1, 3"),
+            (RvInstr::OpImm(a0, a1, 0, RvALUOp::Cpop), 
+                "cpop x10, x11", "cpop a0, a1"),
+            (RvInstr::Op(a0, a1, zero, RvALUOp::ZextH), 
+                "zext.h x10, x11", "zext.h a0, a1"),
+            (RvInstr::Jalr(zero, ra, 0), "ret", "ret"),
+            (RvInstr::Jal(zero, -8), "j -8", "j -8"),
+            (RvInstr::Jal(ra, 16), "jal 16", "jal 16"),
+            (RvInstr::Branch(a0, zero, 12, RvBranchOp::Ne), 
+                "bnez x10, 12", "bnez a0, 12"),
+            (RvInstr::Load(a0, sp, 8, RvWidth::HalfUnsigned), 
+                "lhu x10, 8(x2)", "lhu a0, 8(sp)"),
+            (RvInstr::Store(sp, ra, -4, RvWidth::Word), 
+                "sw x1, -4(x2)", "sw ra, -4(sp)"),
+            (RvInstr::Lui(a0, 0x12345), "lui x10, 0x12345", "lui a0, 0x12345"),
+            (RvInstr::Fence(0b0000, 0b0011, 0b0001), 
+                "fence rw, w", "fence rw, w"),
+            (RvInstr::Csr(a0, zero, RvCsr(crate::csr::MHARTID), RvCsrOp::Rs), 
+                "csrr x10, mhartid", "csrr a0, mhartid"),
+            (RvInstr::CsrImm(zero, 8, RvCsr(0x7c0), RvCsrOp::Rs), 
+                "csrsi 0x7c0, 8", "csrsi 0x7c0, 8"),
+            (RvInstr::SfenceVma(zero, zero), "sfence.vma", "sfence.vma"),
+            (RvInstr::Amo(a0, a1, ra, RvAmoOp::Add, RvWidth::Word, true, true),
+                "amoadd.w.aqrl x10, x1, (x11)", "amoadd.w.aqrl a0, ra, (a1)"),
+            (RvInstr::FArith(RvFReg(0), RvFReg(1), RvFReg(2), 
+                RvFpArithOp::Mul, RvFpFmt::D, RvRoundingMode::Rtz), 
+                "fmul.d f0, f1, f2, rtz", "fmul.d ft0, ft1, ft2, rtz"),
+            (RvInstr::FCvtToInt(a0, RvFReg(10), RvFpInt::Wu, RvFpFmt::S, 
+                RvRoundingMode::Dyn), 
+                "fcvt.wu.s x10, f10", "fcvt.wu.s a0, fa0"),
+            (RvInstr::FOp(RvFReg(10), RvFReg(11), RvFReg(11), RvFpOp::Sgnjx, 
+                RvFpFmt::S), "fabs.s f10, f11", "fabs.s fa0, fa1"),
+            (RvInstr::FMvToInt(a0, RvFReg(10), RvFpFmt::S), 
+                "fmv.x.w x10, f10", "fmv.x.w a0, fa0"),
+            (RvInstr::VSetVli(a0, a1, 0xd0), 
+                "vsetvli x10, x11, e32, m1, ta, ma", 
+                "vsetvli a0, a1, e32, m1, ta, ma"),
+            (RvInstr::VLoad(RvVReg(1), a0, RvWidth::Word, true), 
+                "vle32.v v1, (x10)", "vle32.v v1, (a0)"),
+            (RvInstr::VOpVX(RvVReg(1), RvVReg(2), a0, RvVOp::Add, false), 
+                "vadd.vx v1, v2, x10, v0.t", "vadd.vx v1, v2, a0, v0.t"),
+            (RvInstr::VOpVI(RvVReg(1), RvVReg(2), 3, RvVOp::Merge, false), 
+                "vmerge.vim v1, v2, 3, v0", "vmerge.vim v1, v2, 3, v0"),
+            (RvInstr::VOpVV(RvVReg(1), RvVReg(2), RvVReg(3), RvVOp::Macc, 
+                true), "vmacc.vv v1, v3, v2", "vmacc.vv v1, v3, v2"),
+        ];
+        for (inst, numeric, abi) in cases {
+            assert_eq!(format!("{}", inst), numeric);
+            assert_eq!(format!("{:#}", inst), abi);
+        }
+    }
+
+    #[test]
+    fn encode_errors() {
+        let rv32 = RvXlen::Rv32;
+        let (a0, a1, a2) = (RvReg(10), RvReg(11), RvReg(12));
+        let err = |field, reason| Err(EncodeError { field, reason });
+        assert_eq!(RvInstr::OpImm(a0, a1, 2048, RvALUOp::Add).encode(rv32), 
+            err(RvField::Imm, EncodeReason::OutOfRange));
+        assert_eq!(RvInstr::OpImm(a0, a1, 32, RvALUOp::Sll).encode(rv32), 
+            err(RvField::Imm, EncodeReason::OutOfRange));
+        assert_eq!(RvInstr::OpImm(a0, a1, 1, RvALUOp::Sub).encode(rv32), 
+            err(RvField::Funct3, EncodeReason::Invalid));
+        assert_eq!(RvInstr::Op(a0, a1, a2, RvALUOp::Clz).encode(rv32), 
+            err(RvField::Funct7, EncodeReason::Invalid));
+        assert_eq!(RvInstr::Branch(a0, a1, 4096, RvBranchOp::Eq).encode(rv32), 
+            err(RvField::Imm, EncodeReason::OutOfRange));
+        assert_eq!(RvInstr::Branch(a0, a1, 3, RvBranchOp::Eq).encode(rv32), 
+            err(RvField::Imm, EncodeReason::Misaligned));
+        assert_eq!(RvInstr::Jal(a0, 1 << 20).encode(rv32), 
+            err(RvField::Imm, EncodeReason::OutOfRange));
+        assert_eq!(RvInstr::Lui(a0, 1 << 20).encode(rv32), 
+            err(RvField::Imm, EncodeReason::OutOfRange));
+        assert_eq!(RvInstr::Load(a0, a1, 0, RvWidth::Double).encode(rv32), 
+            err(RvField::Funct3, EncodeReason::RequiresRv64));
+        assert_eq!(RvInstr::OpW(a0, a1, a2, RvALUOp::Add).encode(rv32), 
+            err(RvField::Opcode, EncodeReason::RequiresRv64));
+        assert_eq!(RvInstr::Op(RvReg(32), a1, a2, RvALUOp::Add).encode(rv32), 
+            err(RvField::Rd, EncodeReason::OutOfRange));
+    }
+}
@@ -0,0 +1,760 @@
+//! Software implementation of IEEE 754 binary floating-point arithmetic.
+//!
+//! Values are passed around as raw bits (single-precision values occupy the
+//! low 32 bits of a `u64`). Every operation takes a [RoundingMode] and
+//! accumulates exception flags into a `u32` (see [flags]).
+//!
+//! Following the RISC-V conventions, NaN results are always the canonical
+//! NaN, and tininess is detected after rounding.
+
+/// Rounding modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round to nearest, ties to even
+    NearestEven,
+    /// Round towards zero
+    TowardZero,
+    /// Round down (towards negative infinity)
+    Down,
+    /// Round up (towards positive infinity)
+    Up,
+    /// Round to nearest, ties to max magnitude
+    NearestMaxMagnitude,
+}
+
+/// Exception flags (in the same layout as the RISC-V 'fflags' CSR).
+pub mod flags {
+    /// Inexact
+    pub const NX: u32 = 1 << 0;
+    /// Underflow
+    pub const UF: u32 = 1 << 1;
+    /// Overflow
+    pub const OF: u32 = 1 << 2;
+    /// Divide by zero
+    pub const DZ: u32 = 1 << 3;
+    /// Invalid operation
+    pub const NV: u32 = 1 << 4;
+}
+
+/// Supported binary interchange formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// binary32
+    Single,
+    /// binary64
+    Double,
+}
+impl Format {
+    fn mant_bits(self) -> i32 {
+        match self { Self::Single => 23, Self::Double => 52 }
+    }
+    fn exp_mask(self) -> u64 {
+        match self { Self::Single => 0xff, Self::Double => 0x7ff }
+    }
+    fn bias(self) -> i32 {
+        match self { Self::Single => 127, Self::Double => 1023 }
+    }
+    fn sign_bit(self) -> u64 {
+        match self { Self::Single => 1 << 31, Self::Double => 1 << 63 }
+    }
+    fn mant_mask(self) -> u64 {
+        (1 << self.mant_bits()) - 1
+    }
+
+    /// The canonical NaN.
+    pub fn canonical_nan(self) -> u64 {
+        match self {
+            Self::Single => 0x7fc0_0000,
+            Self::Double => 0x7ff8_0000_0000_0000,
+        }
+    }
+    fn zero(self, sign: bool) -> u64 {
+        if sign { self.sign_bit() } else { 0 }
+    }
+    fn inf(self, sign: bool) -> u64 {
+        self.zero(sign) | (self.exp_mask() << self.mant_bits())
+    }
+    fn max_finite(self, sign: bool) -> u64 {
+        self.inf(sign) - 1
+    }
+}
+
+/// Classes of floating-point values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Class { Zero, Subnormal, Normal, Inf, QuietNaN, SignalingNaN }
+
+fn classify(fmt: Format, x: u64) -> (bool, Class) {
+    let sign = x & fmt.sign_bit() != 0;
+    let exp  = (x >> fmt.mant_bits()) & fmt.exp_mask();
+    let mant = x & fmt.mant_mask();
+    let quiet = 1 << (fmt.mant_bits() - 1);
+    let class = match (exp, mant) {
+        (0, 0) => Class::Zero,
+        (0, _) => Class::Subnormal,
+        (e, 0) if e == fmt.exp_mask() => Class::Inf,
+        (e, m) if e == fmt.exp_mask() => {
+            if m & quiet != 0 { Class::QuietNaN } else { Class::SignalingNaN }
+        },
+        _ => Class::Normal,
+    };
+    (sign, class)
+}
+
+fn is_nan(c: Class) -> bool {
+    matches!(c, Class::QuietNaN | Class::SignalingNaN)
+}
+
+/// A finite value, equal to `(-1)^sign * sig * 2^exp`.
+#[derive(Clone, Copy, Debug)]
+struct Unpacked { sign: bool, exp: i32, sig: u128 }
+
+fn unpack(fmt: Format, x: u64) -> Unpacked {
+    let sign = x & fmt.sign_bit() != 0;
+    let exp  = ((x >> fmt.mant_bits()) & fmt.exp_mask()) as i32;
+    let mant = (x & fmt.mant_mask()) as u128;
+    let emin = 1 - fmt.bias() - fmt.mant_bits();
+    if exp == 0 {
+        Unpacked { sign, exp: emin, sig: mant }
+    } else {
+        Unpacked { sign, exp: emin + exp - 1, sig: mant | 1 << fmt.mant_bits() }
+    }
+}
+
+/// Position of the most-significant set bit.
+fn msb(x: u128) -> i32 {
+    127 - x.leading_zeros() as i32
+}
+
+/// Shift `sig` right by `shift` bits and round the result to an integer.
+///
+/// The `sticky` bit indicates that the true value of `sig` is slightly
+/// larger than its integer value. Returns the rounded value, and whether
+/// or not the result is inexact.
+fn round_shift(sig: u128, sticky: bool, shift: i32, rm: RoundingMode,
+               sign: bool) -> (u128, bool)
+{
+    let (kept, gt_half, eq_half, nonzero) = if shift <= 0 {
+        (sig << -shift, false, false, sticky)
+    } else if shift > 128 {
+        (0, false, false, sig != 0 || sticky)
+    } else {
+        let kept = if shift == 128 { 0 } else { sig >> shift };
+        let rem  = if shift == 128 { sig } else { sig & ((1 << shift) - 1) };
+        let half = 1u128 << (shift - 1);
+        let gt = rem > half || (rem == half && sticky);
+        let eq = rem == half && !sticky;
+        (kept, gt, eq, rem != 0 || sticky)
+    };
+    let inc = match rm {
+        RoundingMode::NearestEven => gt_half || (eq_half && kept & 1 != 0),
+        RoundingMode::NearestMaxMagnitude => gt_half || eq_half,
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => nonzero && sign,
+        RoundingMode::Up => nonzero && !sign,
+    };
+    (kept + inc as u128, nonzero)
+}
+
+/// Round the value `(-1)^sign * (sig + sticky) * 2^exp` to the nearest
+/// representable value in some format.
+fn round_pack(fmt: Format, rm: RoundingMode, u: Unpacked, sticky: bool,
+              fl: &mut u32) -> u64
+{
+    if u.sig == 0 {
+        if sticky {
+            // Some tiny value below the smallest subnormal
+            let (kept, _) = round_shift(0, true, 1, rm, u.sign);
+            *fl |= flags::UF | flags::NX;
+            return fmt.zero(u.sign) | kept as u64;
+        }
+        return fmt.zero(u.sign);
+    }
+    let p    = fmt.mant_bits() + 1;
+    let emin = 1 - fmt.bias();
+    let e    = u.exp + msb(u.sig);
+    let mut q = e.max(emin) - fmt.mant_bits();
+
+    let (mut kept, inexact) = round_shift(u.sig, sticky, q - u.exp, rm, u.sign);
+    if kept >> p != 0 {
+        kept >>= 1;
+        q += 1;
+    }
+
+    // Tininess is detected after rounding (with an unbounded exponent)
+    if e < emin {
+        let shift = e - fmt.mant_bits() - u.exp;
+        let (unb, _) = round_shift(u.sig, sticky, shift, rm, u.sign);
+        let tiny = !(e == emin - 1 && unb >> p != 0);
+        if tiny && inexact {
+            *fl |= flags::UF;
+        }
+    }
+    if inexact {
+        *fl |= flags::NX;
+    }
+
+    if q + fmt.mant_bits() > fmt.bias() {
+        *fl |= flags::OF | flags::NX;
+        let to_inf = match rm {
+            RoundingMode::NearestEven
+            | RoundingMode::NearestMaxMagnitude => true,
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => u.sign,
+            RoundingMode::Up => !u.sign,
+        };
+        return if to_inf { fmt.inf(u.sign) } else { fmt.max_finite(u.sign) };
+    }
+    let exp = (q + fmt.mant_bits() + fmt.bias() - 1) as u64;
+    fmt.zero(u.sign) + (exp << fmt.mant_bits()) + kept as u64
+}
+
+/// Add two nonzero values exactly, returning the sum and a sticky bit.
+fn add_unpacked(a: Unpacked, b: Unpacked) -> (Unpacked, bool) {
+    // Normalize both significands so that the most-significant bit is
+    // bit 110. Bits shifted out of the smaller operand are always below
+    // the rounding position of the result.
+    let norm = |u: Unpacked| {
+        let sh = 110 - msb(u.sig);
+        Unpacked { sig: u.sig << sh, exp: u.exp - sh, ..u }
+    };
+    let (mut x, mut y) = (norm(a), norm(b));
+    if y.exp > x.exp || (y.exp == x.exp && y.sig > x.sig) {
+        std::mem::swap(&mut x, &mut y);
+    }
+    let diff = (x.exp - y.exp) as u32;
+    let (ysig, sticky) = if diff >= 128 {
+        (0, y.sig != 0)
+    } else {
+        (y.sig >> diff, y.sig & ((1 << diff) - 1) != 0)
+    };
+    let sig = if x.sign == y.sign {
+        x.sig + ysig
+    } else {
+        x.sig - ysig - sticky as u128
+    };
+    (Unpacked { sign: x.sign, exp: x.exp, sig }, sticky)
+}
+
+/// Handle NaN operands, returning the canonical NaN if any are present.
+fn propagate_nan(fmt: Format, xs: &[u64], fl: &mut u32) -> Option<u64> {
+    let mut res = None;
+    for x in xs {
+        match classify(fmt, *x).1 {
+            Class::SignalingNaN => {
+                *fl |= flags::NV;
+                res = Some(fmt.canonical_nan());
+            },
+            Class::QuietNaN => res = Some(fmt.canonical_nan()),
+            _ => {},
+        }
+    }
+    res
+}
+
+/// Sign of an exact zero sum of operands with opposite signs.
+fn zero_sum_sign(rm: RoundingMode) -> bool {
+    rm == RoundingMode::Down
+}
+
+pub fn add(fmt: Format, a: u64, b: u64, rm: RoundingMode, fl: &mut u32)
+    -> u64
+{
+    if let Some(nan) = propagate_nan(fmt, &[a, b], fl) {
+        return nan;
+    }
+    let (sa, ca) = classify(fmt, a);
+    let (sb, cb) = classify(fmt, b);
+    match (ca, cb) {
+        (Class::Inf, Class::Inf) if sa != sb => {
+            *fl |= flags::NV;
+            fmt.canonical_nan()
+        },
+        (Class::Inf, _) => a,
+        (_, Class::Inf) => b,
+        (Class::Zero, Class::Zero) => {
+            fmt.zero(if sa == sb { sa } else { zero_sum_sign(rm) })
+        },
+        (Class::Zero, _) => b,
+        (_, Class::Zero) => a,
+        _ => {
+            let (sum, sticky) = add_unpacked(unpack(fmt, a), unpack(fmt, b));
+            if sum.sig == 0 && !sticky {
+                return fmt.zero(zero_sum_sign(rm));
+            }
+            round_pack(fmt, rm, sum, sticky, fl)
+        },
+    }
+}
+
+pub fn sub(fmt: Format, a: u64, b: u64, rm: RoundingMode, fl: &mut u32)
+    -> u64
+{
+    // Negating a NaN doesn't change the result
+    add(fmt, a, b ^ fmt.sign_bit(), rm, fl)
+}
+
+pub fn mul(fmt: Format, a: u64, b: u64, rm: RoundingMode, fl: &mut u32)
+    -> u64
+{
+    if let Some(nan) = propagate_nan(fmt, &[a, b], fl) {
+        return nan;
+    }
+    let (sa, ca) = classify(fmt, a);
+    let (sb, cb) = classify(fmt, b);
+    let sign = sa != sb;
+    match (ca, cb) {
+        (Class::Inf, Class::Zero) | (Class::Zero, Class::Inf) => {
+            *fl |= flags::NV;
+            fmt.canonical_nan()
+        },
+        (Class::Inf, _) | (_, Class::Inf) => fmt.inf(sign),
+        (Class::Zero, _) | (_, Class::Zero) => fmt.zero(sign),
+        _ => {
+            let (ua, ub) = (unpack(fmt, a), unpack(fmt, b));
+            let prod = Unpacked { sign, exp: ua.exp + ub.exp, sig: ua.sig * ub.sig };
+            round_pack(fmt, rm, prod, false, fl)
+        },
+    }
+}
+
+/// Fused multiply-add, computing `(a * b) + c` with a single rounding.
+pub fn fma(fmt: Format, a: u64, b: u64, c: u64, rm: RoundingMode,
+           fl: &mut u32) -> u64
+{
+    let (sa, ca) = classify(fmt, a);
+    let (sb, cb) = classify(fmt, b);
+    let (sc, cc) = classify(fmt, c);
+
+    // The product of infinity and zero is invalid, even when the addend
+    // is a quiet NaN.
+    let inf_zero = matches!((ca, cb),
+        (Class::Inf, Class::Zero) | (Class::Zero, Class::Inf));
+    if let Some(nan) = propagate_nan(fmt, &[a, b, c], fl) {
+        if inf_zero { *fl |= flags::NV; }
+        return nan;
+    }
+    if inf_zero {
+        *fl |= flags::NV;
+        return fmt.canonical_nan();
+    }
+
+    let sp = sa != sb;
+    let prod_inf  = ca == Class::Inf || cb == Class::Inf;
+    let prod_zero = ca == Class::Zero || cb == Class::Zero;
+    match (prod_inf, prod_zero, cc) {
+        (true, _, Class::Inf) if sp != sc => {
+            *fl |= flags::NV;
+            fmt.canonical_nan()
+        },
+        (true, _, _) => fmt.inf(sp),
+        (_, _, Class::Inf) => c,
+        (_, true, Class::Zero) => {
+            fmt.zero(if sp == sc { sp } else { zero_sum_sign(rm) })
+        },
+        (_, true, _) => c,
+        _ => {
+            let (ua, ub) = (unpack(fmt, a), unpack(fmt, b));
+            let prod = Unpacked {
+                sign: sp, exp: ua.exp + ub.exp, sig: ua.sig * ub.sig
+            };
+            if cc == Class::Zero {
+                return round_pack(fmt, rm, prod, false, fl);
+            }
+            let (sum, sticky) = add_unpacked(prod, unpack(fmt, c));
+            if sum.sig == 0 && !sticky {
+                return fmt.zero(zero_sum_sign(rm));
+            }
+            round_pack(fmt, rm, sum, sticky, fl)
+        },
+    }
+}
+
+pub fn div(fmt: Format, a: u64, b: u64, rm: RoundingMode, fl: &mut u32)
+    -> u64
+{
+    if let Some(nan) = propagate_nan(fmt, &[a, b], fl) {
+        return nan;
+    }
+    let (sa, ca) = classify(fmt, a);
+    let (sb, cb) = classify(fmt, b);
+    let sign = sa != sb;
+    match (ca, cb) {
+        (Class::Inf, Class::Inf) | (Class::Zero, Class::Zero) => {
+            *fl |= flags::NV;
+            fmt.canonical_nan()
+        },
+        (Class::Inf, _) => fmt.inf(sign),
+        (_, Class::Inf) => fmt.zero(sign),
+        (Class::Zero, _) => fmt.zero(sign),
+        (_, Class::Zero) => {
+            *fl |= flags::DZ;
+            fmt.inf(sign)
+        },
+        _ => {
+            let (ua, ub) = (unpack(fmt, a), unpack(fmt, b));
+            // Make sure the quotient has enough bits for rounding
+            let sh = 120 - msb(ua.sig);
+            let x  = ua.sig << sh;
+            let quo = Unpacked { sign, exp: ua.exp - sh - ub.exp, sig: x / ub.sig };
+            round_pack(fmt, rm, quo, x % ub.sig != 0, fl)
+        },
+    }
+}
+
+/// Integer square root (rounded down).
+fn isqrt(mut n: u128) -> u128 {
+    let mut res = 0;
+    let mut bit = 1u128 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if n >= res + bit {
+            n -= res + bit;
+            res = (res >> 1) + bit;
+        } else {
+            res >>= 1;
+        }
+        bit >>= 2;
+    }
+    res
+}
+
+pub fn sqrt(fmt: Format, a: u64, rm: RoundingMode, fl: &mut u32) -> u64 {
+    if let Some(nan) = propagate_nan(fmt, &[a], fl) {
+        return nan;
+    }
+    match classify(fmt, a) {
+        (_, Class::Zero) => a,
+        (true, _) => {
+            *fl |= flags::NV;
+            fmt.canonical_nan()
+        },
+        (false, Class::Inf) => a,
+        _ => {
+            let ua = unpack(fmt, a);
+            // Make sure the exponent is even, and that the root has enough
+            // bits for rounding
+            let mut sh = 116 - msb(ua.sig);
+            if (ua.exp - sh) % 2 != 0 {
+                sh += 1;
+            }
+            let x = ua.sig << sh;
+            let root = isqrt(x);
+            let res = Unpacked { sign: false, exp: (ua.exp - sh) / 2, sig: root };
+            round_pack(fmt, rm, res, root * root != x, fl)
+        },
+    }
+}
+
+/// Convert a value between formats.
+pub fn convert(to: Format, from: Format, a: u64, rm: RoundingMode,
+               fl: &mut u32) -> u64
+{
+    if propagate_nan(from, &[a], fl).is_some() {
+        return to.canonical_nan();
+    }
+    match classify(from, a) {
+        (sign, Class::Zero) => to.zero(sign),
+        (sign, Class::Inf) => to.inf(sign),
+        _ => round_pack(to, rm, unpack(from, a), false, fl),
+    }
+}
+
+/// Convert a value to a signed or unsigned integer with some width (in
+/// bits). Signed results are sign-extended to 64 bits.
+pub fn to_int(fmt: Format, a: u64, signed: bool, width: u32,
+              rm: RoundingMode, fl: &mut u32) -> u64
+{
+    let (max, min): (u64, u64) = match (signed, width) {
+        (true, 64)  => (i64::MAX as u64, i64::MIN as u64),
+        (true, w)   => ((1 << (w - 1)) - 1, (-1i64 << (w - 1)) as u64),
+        (false, 64) => (u64::MAX, 0),
+        (false, w)  => ((1 << w) - 1, 0),
+    };
+    let (sign, class) = classify(fmt, a);
+    let invalid = |fl: &mut u32, neg: bool| {
+        *fl |= flags::NV;
+        if neg { min } else { max }
+    };
+    match class {
+        Class::QuietNaN | Class::SignalingNaN => return invalid(fl, false),
+        Class::Inf => return invalid(fl, sign),
+        Class::Zero => return 0,
+        _ => {},
+    }
+    let ua = unpack(fmt, a);
+    if ua.exp + msb(ua.sig) >= 64 {
+        return invalid(fl, sign);
+    }
+    let (mag, inexact) = round_shift(ua.sig, false, -ua.exp, rm, sign);
+    let res = if sign {
+        let lim = if signed { (min as i64).unsigned_abs() as u128 } else { 0 };
+        if mag > lim {
+            return invalid(fl, true);
+        }
+        (mag as u64).wrapping_neg()
+    } else {
+        if mag > max as u128 {
+            return invalid(fl, false);
+        }
+        mag as u64
+    };
+    if inexact {
+        *fl |= flags::NX;
+    }
+    res
+}
+
+/// Convert a signed or unsigned integer with some width (in bits) to a
+/// floating-point value.
+pub fn from_int(fmt: Format, x: u64, signed: bool, width: u32,
+                rm: RoundingMode, fl: &mut u32) -> u64
+{
+    let x = if width == 64 { x } else { x & ((1 << width) - 1) };
+    let (sign, mag) = if signed && (x >> (width - 1)) & 1 != 0 {
+        (true, (x | u64::MAX.checked_shl(width).unwrap_or(0)).wrapping_neg())
+    } else {
+        (false, x)
+    };
+    round_pack(fmt, rm, Unpacked { sign, exp: 0, sig: mag as u128 }, false, fl)
+}
+
+/// Compare two non-NaN values, treating both zeros as equal.
+fn cmp(fmt: Format, a: u64, b: u64) -> std::cmp::Ordering {
+    let key = |x: u64| {
+        let mag = (x & !fmt.sign_bit()) as i128;
+        if x & fmt.sign_bit() != 0 { -mag } else { mag }
+    };
+    key(a).cmp(&key(b))
+}
+
+/// Quiet equality comparison.
+pub fn eq(fmt: Format, a: u64, b: u64, fl: &mut u32) -> bool {
+    if propagate_nan(fmt, &[a, b], fl).is_some() {
+        return false;
+    }
+    cmp(fmt, a, b).is_eq()
+}
+
+/// Signaling less-than comparison.
+pub fn lt(fmt: Format, a: u64, b: u64, fl: &mut u32) -> bool {
+    if is_nan(classify(fmt, a).1) || is_nan(classify(fmt, b).1) {
+        *fl |= flags::NV;
+        return false;
+    }
+    cmp(fmt, a, b).is_lt()
+}
+
+/// Signaling less-than-or-equal comparison.
+pub fn le(fmt: Format, a: u64, b: u64, fl: &mut u32) -> bool {
+    if is_nan(classify(fmt, a).1) || is_nan(classify(fmt, b).1) {
+        *fl |= flags::NV;
+        return false;
+    }
+    cmp(fmt, a, b).is_le()
+}
+
+/// IEEE 754-2019 minimumNumber/maximumNumber.
+///
+/// When only one operand is a NaN, the result is the other operand.
+/// Negative zero is considered to be less than positive zero.
+pub fn min_max(fmt: Format, a: u64, b: u64, max: bool, fl: &mut u32) -> u64 {
+    let nan = propagate_nan(fmt, &[a, b], fl);
+    let (a_nan, b_nan) = (is_nan(classify(fmt, a).1), is_nan(classify(fmt, b).1));
+    match (a_nan, b_nan) {
+        (true, true) => return nan.unwrap(),
+        (true, false) => return b,
+        (false, true) => return a,
+        _ => {},
+    }
+    let ord = match cmp(fmt, a, b) {
+        std::cmp::Ordering::Equal => {
+            // Only the sign of zero can differ here
+            (b & fmt.sign_bit()).cmp(&(a & fmt.sign_bit()))
+        },
+        ord => ord,
+    };
+    match (ord.is_lt(), max) {
+        (true, false) | (false, true) => a,
+        _ => b,
+    }
+}
+
+/// Classify a value, returning a mask in the format used by RISC-V FCLASS.
+pub fn class_mask(fmt: Format, a: u64) -> u32 {
+    let bit = match classify(fmt, a) {
+        (true, Class::Inf) => 0,
+        (true, Class::Normal) => 1,
+        (true, Class::Subnormal) => 2,
+        (true, Class::Zero) => 3,
+        (false, Class::Zero) => 4,
+        (false, Class::Subnormal) => 5,
+        (false, Class::Normal) => 6,
+        (false, Class::Inf) => 7,
+        (_, Class::SignalingNaN) => 8,
+        (_, Class::QuietNaN) => 9,
+    };
+    1 << bit
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::softfloat::*;
+    use RoundingMode::*;
+
+    /// Simple deterministic generator for test inputs
+    struct XorShift(u64);
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        /// Random bits, biased towards interesting exponents
+        fn double(&mut self) -> u64 {
+            let x = self.next();
+            match x % 8 {
+                0 => x & 0x800f_ffff_ffff_ffff,
+                1 => (x & 0x800f_ffff_ffff_ffff) | 0x3ff0_0000_0000_0000,
+                _ => x,
+            }
+        }
+        fn single(&mut self) -> u64 {
+            let x = self.next() & 0xffff_ffff;
+            match x % 8 {
+                0 => x & 0x807f_ffff,
+                1 => (x & 0x807f_ffff) | 0x3f80_0000,
+                _ => x,
+            }
+        }
+    }
+
+    fn same_f64(x: u64, y: f64) -> bool {
+        if y.is_nan() { x == Format::Double.canonical_nan() } else { x == y.to_bits() }
+    }
+    fn same_f32(x: u64, y: f32) -> bool {
+        if y.is_nan() { x == Format::Single.canonical_nan() } else { x == y.to_bits() as u64 }
+    }
+
+    #[test]
+    fn double_matches_host() {
+        let mut rng = XorShift(0x1234_5678_9abc_def1);
+        let fmt = Format::Double;
+        for _ in 0..100_000 {
+            let (a, b, c) = (rng.double(), rng.double(), rng.double());
+            let (x, y, z) = (f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+            let mut fl = 0;
+            assert!(same_f64(add(fmt, a, b, NearestEven, &mut fl), x + y), "{:016x} + {:016x}", a, b);
+            assert!(same_f64(sub(fmt, a, b, NearestEven, &mut fl), x - y), "{:016x} - {:016x}", a, b);
+            assert!(same_f64(mul(fmt, a, b, NearestEven, &mut fl), x * y), "{:016x} * {:016x}", a, b);
+            assert!(same_f64(div(fmt, a, b, NearestEven, &mut fl), x / y), "{:016x} / {:016x}", a, b);
+            assert!(same_f64(sqrt(fmt, a, NearestEven, &mut fl), x.sqrt()), "sqrt {:016x}", a);
+            assert!(same_f64(fma(fmt, a, b, c, NearestEven, &mut fl), x.mul_add(y, z)),
+                "fma {:016x} {:016x} {:016x}", a, b, c);
+        }
+    }
+
+    #[test]
+    fn single_matches_host() {
+        let mut rng = XorShift(0x0fed_cba9_8765_4321);
+        let fmt = Format::Single;
+        for _ in 0..100_000 {
+            let (a, b, c) = (rng.single(), rng.single(), rng.single());
+            let (x, y, z) = (f32::from_bits(a as u32), f32::from_bits(b as u32),
+                f32::from_bits(c as u32));
+            let mut fl = 0;
+            assert!(same_f32(add(fmt, a, b, NearestEven, &mut fl), x + y), "{:08x} + {:08x}", a, b);
+            assert!(same_f32(mul(fmt, a, b, NearestEven, &mut fl), x * y), "{:08x} * {:08x}", a, b);
+            assert!(same_f32(div(fmt, a, b, NearestEven, &mut fl), x / y), "{:08x} / {:08x}", a, b);
+            assert!(same_f32(sqrt(fmt, a, NearestEven, &mut fl), x.sqrt()), "sqrt {:08x}", a);
+            assert!(same_f32(fma(fmt, a, b, c, NearestEven, &mut fl), x.mul_add(y, z)),
+                "fma {:08x} {:08x} {:08x}", a, b, c);
+            let d = convert(Format::Double, fmt, a, NearestEven, &mut fl);
+            assert!(same_f64(d, x as f64));
+            assert!(same_f32(convert(fmt, Format::Double, d, NearestEven, &mut fl), x));
+        }
+    }
+
+    #[test]
+    fn rounding_and_flags() {
+        let fmt = Format::Single;
+        let one   = 0x3f80_0000;
+        let three = 0x4040_0000;
+        let mut fl = 0;
+
+        // 1/3 rounds differently in each direction
+        assert_eq!(div(fmt, one, three, NearestEven, &mut fl), 0x3eaa_aaab);
+        assert_eq!(div(fmt, one, three, TowardZero, &mut fl), 0x3eaa_aaaa);
+        assert_eq!(div(fmt, one, three, Down, &mut fl), 0x3eaa_aaaa);
+        assert_eq!(div(fmt, one, three, Up, &mut fl), 0x3eaa_aaab);
+        assert_eq!(fl, flags::NX);
+
+        // Overflow
+        let mut fl = 0;
+        let max = 0x7f7f_ffff;
+        assert_eq!(mul(fmt, max, three, NearestEven, &mut fl), 0x7f80_0000);
+        assert_eq!(mul(fmt, max, three, TowardZero, &mut fl), max);
+        assert_eq!(fl, flags::OF | flags::NX);
+
+        // Underflow (the smallest normal, squared)
+        let mut fl = 0;
+        assert_eq!(mul(fmt, 0x0080_0000, 0x0080_0000, Up, &mut fl), 0x0000_0001);
+        assert_eq!(fl, flags::UF | flags::NX);
+
+        // Exact subnormal results don't underflow
+        let mut fl = 0;
+        assert_eq!(mul(fmt, 0x0080_0000, 0x3f00_0000, NearestEven, &mut fl), 0x0040_0000);
+        assert_eq!(fl, 0);
+
+        // Invalid operations and division by zero
+        let mut fl = 0;
+        assert_eq!(sqrt(fmt, 0xbf80_0000, NearestEven, &mut fl), 0x7fc0_0000);
+        assert_eq!(fl, flags::NV);
+        let mut fl = 0;
+        assert_eq!(div(fmt, one, 0x8000_0000, NearestEven, &mut fl), 0xff80_0000);
+        assert_eq!(fl, flags::DZ);
+
+        // Exact zero sums are negative only when rounding down
+        let mut fl = 0;
+        assert_eq!(sub(fmt, one, one, NearestEven, &mut fl), 0x0000_0000);
+        assert_eq!(sub(fmt, one, one, Down, &mut fl), 0x8000_0000);
+        assert_eq!(fl, 0);
+
+        // Inf * 0 is invalid even with a quiet NaN addend
+        let mut fl = 0;
+        assert_eq!(fma(fmt, 0x7f80_0000, 0, 0x7fc0_0001, NearestEven, &mut fl), 0x7fc0_0000);
+        assert_eq!(fl, flags::NV);
+    }
+
+    #[test]
+    fn integer_conversions() {
+        let fmt = Format::Single;
+        let mut fl = 0;
+        // 2.5
+        assert_eq!(to_int(fmt, 0x4020_0000, true, 32, NearestEven, &mut fl), 2);
+        assert_eq!(to_int(fmt, 0x4020_0000, true, 32, NearestMaxMagnitude, &mut fl), 3);
+        // -2.5
+        assert_eq!(to_int(fmt, 0xc020_0000, true, 32, Down, &mut fl), -3i64 as u64);
+        assert_eq!(fl, flags::NX);
+        // -1.0 to unsigned
+        let mut fl = 0;
+        assert_eq!(to_int(fmt, 0xbf80_0000, false, 32, NearestEven, &mut fl), 0);
+        assert_eq!(fl, flags::NV);
+        // NaN and large values
+        let mut fl = 0;
+        assert_eq!(to_int(fmt, 0x7fc0_0000, true, 32, NearestEven, &mut fl), 0x7fff_ffff);
+        assert_eq!(to_int(fmt, 0xcf00_0000, true, 32, NearestEven, &mut fl), -(1i64 << 31) as u64);
+        assert_eq!(to_int(fmt, 0x4f80_0000, false, 32, NearestEven, &mut fl), 0xffff_ffff);
+        assert_eq!(fl, flags::NV);
+
+        let mut fl = 0;
+        assert_eq!(from_int(fmt, 0xffff_ffff, true, 32, NearestEven, &mut fl), 0xbf80_0000);
+        assert_eq!(from_int(fmt, 0xffff_ffff, false, 32, NearestEven, &mut fl), 0x4f80_0000);
+        assert_eq!(fl, flags::NX);
+        assert_eq!(from_int(Format::Double, 0xffff_ffff, false, 32, NearestEven, &mut fl),
+            0x41ef_ffff_ffe0_0000);
+    }
+}
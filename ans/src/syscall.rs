@@ -0,0 +1,433 @@
+//! Linux system calls (for running user-mode programs).
+//!
+//! Programs make a system call with `ecall`, passing the number in a7 and
+//! the arguments in a0-a5. The result (or a negated errno) is returned in
+//! a0. Only the calls used by the common C runtimes (newlib, picolibc and
+//! musl) are implemented, and file descriptors are backed by host files.
+
+use crate::mem::Memory;
+use crate::rv32::RvXlen;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub const SYS_OPENAT:        u64 = 56;
+pub const SYS_CLOSE:         u64 = 57;
+pub const SYS_LSEEK:         u64 = 62;
+pub const SYS_READ:          u64 = 63;
+pub const SYS_WRITE:         u64 = 64;
+pub const SYS_FSTAT:         u64 = 80;
+pub const SYS_EXIT:          u64 = 93;
+pub const SYS_EXIT_GROUP:    u64 = 94;
+pub const SYS_CLOCK_GETTIME: u64 = 113;
+pub const SYS_UNAME:         u64 = 160;
+pub const SYS_BRK:           u64 = 214;
+pub const SYS_MUNMAP:        u64 = 215;
+pub const SYS_MMAP:          u64 = 222;
+
+pub const ENOENT: i64 = 2;
+pub const EIO:    i64 = 5;
+pub const EBADF:  i64 = 9;
+pub const ENOMEM: i64 = 12;
+pub const EFAULT: i64 = 14;
+pub const EINVAL: i64 = 22;
+pub const ESPIPE: i64 = 29;
+pub const ENOSYS: i64 = 38;
+
+const AT_FDCWD: i64 = -100;
+
+const O_ACCMODE: u64 = 0o3;
+const O_CREAT:   u64 = 0o100;
+const O_EXCL:    u64 = 0o200;
+const O_TRUNC:   u64 = 0o1000;
+const O_APPEND:  u64 = 0o2000;
+
+const MAP_ANONYMOUS: u64 = 0x20;
+
+/// Size of a page (for `brk` and `mmap`).
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// Default start of the heap (when no ELF file has been loaded).
+pub const BRK_BASE: u64 = 0x0020_0000;
+/// Start of the region used for anonymous mappings. The heap must end
+/// below the stack, which grows down from here.
+pub const MMAP_BASE: u64 = 0x0030_0000;
+/// Size reserved for the stack below [MMAP_BASE].
+pub const STACK_SIZE: u64 = 0x0004_0000;
+
+/// The effect of a system call on the machine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyscallResult {
+    /// Return a value in a0
+    Return(i64),
+    /// Terminate the program with some exit status
+    Exit(i32),
+}
+
+/// An open file descriptor.
+enum FileDesc {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(fs::File),
+}
+
+/// State of the emulated process: open files and memory allocations.
+pub struct Syscalls {
+    /// Open file descriptors (indexed by the descriptor number).
+    files: Vec<Option<FileDesc>>,
+    /// Current end of the heap.
+    pub brk: u64,
+    /// Start of the heap (which can't shrink below this).
+    brk_base: u64,
+    /// Next free address for anonymous mappings.
+    mmap_top: u64,
+}
+impl Syscalls {
+    pub fn new() -> Self {
+        Self {
+            files: vec![
+                Some(FileDesc::Stdin),
+                Some(FileDesc::Stdout),
+                Some(FileDesc::Stderr),
+            ],
+            brk: BRK_BASE,
+            brk_base: BRK_BASE,
+            mmap_top: MMAP_BASE,
+        }
+    }
+
+    /// Set the start of the heap (after the end of the loaded program).
+    pub fn set_brk(&mut self, addr: u64) {
+        let addr = (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        self.brk = addr;
+        self.brk_base = addr;
+    }
+
+    /// Perform the system call 'num' with some arguments.
+    ///
+    /// Unknown system calls return -ENOSYS.
+    pub fn call(&mut self, ram: &mut Memory, xlen: RvXlen, num: u64,
+                args: [u64; 6]) -> SyscallResult
+    {
+        let res = match num {
+            SYS_EXIT | SYS_EXIT_GROUP => {
+                return SyscallResult::Exit(args[0] as i32);
+            },
+            SYS_READ   => self.read(ram, args[0], args[1], args[2]),
+            SYS_WRITE  => self.write(ram, args[0], args[1], args[2]),
+            SYS_OPENAT => self.openat(ram, args[0], args[1], args[2]),
+            SYS_CLOSE  => self.close(args[0]),
+            SYS_LSEEK  => self.lseek(xlen, args[0], args[1], args[2]),
+            SYS_FSTAT  => self.fstat(ram, args[0], args[1]),
+            SYS_BRK    => Ok(self.do_brk(args[0])),
+            // The address is only a hint
+            SYS_MMAP   => self.mmap(ram, args[1], args[3]),
+            // Mappings are never reused
+            SYS_MUNMAP => Ok(0),
+            SYS_CLOCK_GETTIME => Self::clock_gettime(ram, xlen, args[1]),
+            SYS_UNAME  => Self::uname(ram, xlen, args[0]),
+            _ => Err(ENOSYS),
+        };
+        SyscallResult::Return(res.unwrap_or_else(|e| -e))
+    }
+
+    /// Get the range of guest memory [addr, addr + len).
+    fn range(ram: &Memory, addr: u64, len: u64) 
+        -> Result<std::ops::Range<usize>, i64> 
+    {
+        let start = addr.checked_sub(ram.base as u64).ok_or(EFAULT)?;
+        let end = start.checked_add(len).ok_or(EFAULT)?;
+        if end > ram.data.len() as u64 {
+            return Err(EFAULT);
+        }
+        Ok(start as usize..end as usize)
+    }
+
+    /// Read a NUL-terminated string from guest memory.
+    fn string(ram: &Memory, addr: u64) -> Result<String, i64> {
+        let start = Self::range(ram, addr, 0)?.start;
+        let len = ram.data[start..].iter().position(|b| *b == 0)
+            .ok_or(EFAULT)?;
+        Ok(String::from_utf8_lossy(&ram.data[start..start + len]).into_owned())
+    }
+
+    fn file(&mut self, fd: u64) -> Result<&mut FileDesc, i64> {
+        self.files.get_mut(fd as usize)
+            .and_then(|f| f.as_mut())
+            .ok_or(EBADF)
+    }
+
+    /// Convert a host I/O error into an errno.
+    fn errno(e: io::Error) -> i64 {
+        e.raw_os_error().map_or(EIO, |e| e as i64)
+    }
+
+    fn read(&mut self, ram: &mut Memory, fd: u64, buf: u64, count: u64)
+        -> Result<i64, i64>
+    {
+        let range = Self::range(ram, buf, count)?;
+        let dst = &mut ram.data[range];
+        let res = match self.file(fd)? {
+            FileDesc::Stdin => io::stdin().read(dst),
+            FileDesc::File(f) => f.read(dst),
+            _ => return Err(EBADF),
+        };
+        res.map(|n| n as i64).map_err(Self::errno)
+    }
+
+    fn write(&mut self, ram: &Memory, fd: u64, buf: u64, count: u64)
+        -> Result<i64, i64>
+    {
+        let src = &ram.data[Self::range(ram, buf, count)?];
+        let res = match self.file(fd)? {
+            FileDesc::Stdout => io::stdout().write_all(src)
+                .and_then(|_| io::stdout().flush()),
+            FileDesc::Stderr => io::stderr().write_all(src),
+            FileDesc::File(f) => f.write_all(src),
+            FileDesc::Stdin => return Err(EBADF),
+        };
+        res.map(|_| count as i64).map_err(Self::errno)
+    }
+
+    /// Open a file (relative to the host working directory).
+    fn openat(&mut self, ram: &Memory, dirfd: u64, path: u64, flags: u64)
+        -> Result<i64, i64>
+    {
+        let path = Self::string(ram, path)?;
+        if dirfd as i32 as i64 != AT_FDCWD && !path.starts_with('/') {
+            return Err(ENOENT);
+        }
+        let mut opts = fs::OpenOptions::new();
+        match flags & O_ACCMODE {
+            0 => opts.read(true),
+            1 => opts.write(true),
+            2 => opts.read(true).write(true),
+            _ => return Err(EINVAL),
+        };
+        opts.append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0);
+        if flags & O_CREAT != 0 {
+            if flags & O_EXCL != 0 {
+                opts.create_new(true);
+            } else {
+                opts.create(true);
+            }
+        }
+        let file = opts.open(&path).map_err(Self::errno)?;
+
+        // Use the lowest free descriptor
+        let fd = match self.files.iter().position(|f| f.is_none()) {
+            Some(fd) => fd,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            },
+        };
+        self.files[fd] = Some(FileDesc::File(file));
+        Ok(fd as i64)
+    }
+
+    fn close(&mut self, fd: u64) -> Result<i64, i64> {
+        self.file(fd)?;
+        self.files[fd as usize] = None;
+        Ok(0)
+    }
+
+    fn lseek(&mut self, xlen: RvXlen, fd: u64, off: u64, whence: u64)
+        -> Result<i64, i64>
+    {
+        // Relative offsets are signed XLEN-bit values
+        let rel = match xlen {
+            RvXlen::Rv32 => off as u32 as i32 as i64,
+            RvXlen::Rv64 => off as i64,
+        };
+        let pos = match whence {
+            0 => SeekFrom::Start(off),
+            1 => SeekFrom::Current(rel),
+            2 => SeekFrom::End(rel),
+            _ => return Err(EINVAL),
+        };
+        match self.file(fd)? {
+            FileDesc::File(f) => {
+                f.seek(pos).map(|n| n as i64).map_err(Self::errno)
+            },
+            _ => Err(ESPIPE),
+        }
+    }
+
+    /// Write the status of a file, in the layout of the generic 'struct
+    /// stat' (with 64-bit fields, as used by newlib on both RV32 and RV64).
+    fn fstat(&mut self, ram: &mut Memory, fd: u64, buf: u64)
+        -> Result<i64, i64>
+    {
+        use std::os::unix::fs::MetadataExt;
+        let range = Self::range(ram, buf, 128)?;
+        let mut st = [0u8; 128];
+        let mut put = |off: usize, val: u64, len: usize| {
+            st[off..off + len].copy_from_slice(&val.to_le_bytes()[..len]);
+        };
+        match self.file(fd)? {
+            FileDesc::File(f) => {
+                let md = f.metadata().map_err(Self::errno)?;
+                put(0, md.dev(), 8);
+                put(8, md.ino(), 8);
+                put(16, md.mode() as u64, 4);
+                put(20, md.nlink(), 4);
+                put(24, md.uid() as u64, 4);
+                put(28, md.gid() as u64, 4);
+                put(48, md.size(), 8);
+                put(56, md.blksize(), 4);
+                put(64, md.blocks(), 8);
+                put(72, md.atime() as u64, 8);
+                put(88, md.mtime() as u64, 8);
+                put(104, md.ctime() as u64, 8);
+            },
+            // The standard streams are terminals (so stdout is line-buffered)
+            _ => {
+                put(16, 0o020620, 4);
+                put(20, 1, 4);
+                put(56, 1024, 4);
+            },
+        }
+        ram.data[range].copy_from_slice(&st);
+        Ok(0)
+    }
+
+    /// Move the end of the heap, returning the new end. The heap is left
+    /// unchanged if the requested address is out of range.
+    fn do_brk(&mut self, addr: u64) -> i64 {
+        if addr >= self.brk_base && addr <= MMAP_BASE - STACK_SIZE {
+            self.brk = addr;
+        }
+        self.brk as i64
+    }
+
+    /// Create an anonymous mapping (which is always readable and writable).
+    fn mmap(&mut self, ram: &mut Memory, len: u64, flags: u64)
+        -> Result<i64, i64>
+    {
+        if flags & MAP_ANONYMOUS == 0 || len == 0 {
+            return Err(EINVAL);
+        }
+        let len = len.checked_add(PAGE_SIZE - 1).ok_or(ENOMEM)?
+            & !(PAGE_SIZE - 1);
+        let range = Self::range(ram, self.mmap_top, len)
+            .map_err(|_| ENOMEM)?;
+        ram.data[range].fill(0);
+        let res = self.mmap_top;
+        self.mmap_top += len;
+        Ok(res as i64)
+    }
+
+    /// Write the host time to a 'struct timespec' (with XLEN-sized fields).
+    fn clock_gettime(ram: &mut Memory, xlen: RvXlen, buf: u64)
+        -> Result<i64, i64>
+    {
+        let size = xlen.bits() as usize / 8;
+        let range = Self::range(ram, buf, 2 * size as u64)?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let dst = &mut ram.data[range];
+        dst[..size].copy_from_slice(&now.as_secs().to_le_bytes()[..size]);
+        dst[size..].copy_from_slice(
+            &(now.subsec_nanos() as u64).to_le_bytes()[..size]);
+        Ok(0)
+    }
+
+    /// Write a 'struct utsname' (six fields of 65 bytes).
+    fn uname(ram: &mut Memory, xlen: RvXlen, buf: u64) -> Result<i64, i64> {
+        let range = Self::range(ram, buf, 6 * 65)?;
+        let machine = match xlen {
+            RvXlen::Rv32 => "riscv32",
+            RvXlen::Rv64 => "riscv64",
+        };
+        let fields = ["Linux", "ans", "5.15.0", "#1", machine, "(none)"];
+        let dst = &mut ram.data[range];
+        dst.fill(0);
+        for (idx, field) in fields.iter().enumerate() {
+            dst[idx * 65..idx * 65 + field.len()]
+                .copy_from_slice(field.as_bytes());
+        }
+        Ok(0)
+    }
+}
+
+impl Default for Syscalls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::syscall::*;
+
+    #[test]
+    fn file_io() {
+        let path = std::env::temp_dir()
+            .join(format!("ans-syscall-{}", std::process::id()));
+        let mut ram = Memory::new(0x1000);
+        ram.write(0x100, path.to_str().unwrap().as_bytes());
+        ram.write(0x200, b"hello");
+        let mut sys = Syscalls::new();
+        let mut call = |ram: &mut Memory, num, args: &[u64]| {
+            let mut a = [0; 6];
+            a[..args.len()].copy_from_slice(args);
+            sys.call(ram, RvXlen::Rv32, num, a)
+        };
+        let ret = SyscallResult::Return;
+
+        // openat(AT_FDCWD, path, O_RDWR | O_CREAT | O_TRUNC)
+        let flags = 2 | O_CREAT | O_TRUNC;
+        assert_eq!(call(&mut ram, SYS_OPENAT,
+            &[AT_FDCWD as u64, 0x100, flags, 0o644]), ret(3));
+        assert_eq!(call(&mut ram, SYS_WRITE, &[3, 0x200, 5]), ret(5));
+        assert_eq!(call(&mut ram, SYS_LSEEK, &[3, 1, 0]), ret(1));
+        assert_eq!(call(&mut ram, SYS_READ, &[3, 0x300, 16]), ret(4));
+        assert_eq!(&ram.data[0x300..0x304], b"ello");
+        // Negative offsets are sign-extended from 32 bits
+        let neg = |off: i32| off as u32 as u64;
+        assert_eq!(call(&mut ram, SYS_LSEEK, &[3, neg(-3), 1]), ret(2));
+        assert_eq!(call(&mut ram, SYS_LSEEK, &[3, neg(-1), 2]), ret(4));
+        assert_eq!(call(&mut ram, SYS_FSTAT, &[3, 0x400]), ret(0));
+        assert_eq!(ram.load64(0x400 + 48), 5);
+        assert_eq!(call(&mut ram, SYS_CLOSE, &[3]), ret(0));
+        assert_eq!(call(&mut ram, SYS_CLOSE, &[3]), ret(-EBADF));
+        assert_eq!(call(&mut ram, SYS_LSEEK, &[1, 0, 0]), ret(-ESPIPE));
+        assert_eq!(call(&mut ram, SYS_READ, &[0, 0xfff, 16]), ret(-EFAULT));
+        assert_eq!(call(&mut ram, 12345, &[]), ret(-ENOSYS));
+        assert_eq!(call(&mut ram, SYS_EXIT_GROUP, &[7]),
+            SyscallResult::Exit(7));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn memory() {
+        let mut ram = Memory::new(0x0040_0000);
+        let mut sys = Syscalls::new();
+        sys.set_brk(0x0001_2345);
+        let mut call = |num, args: [u64; 6]| {
+            sys.call(&mut ram, RvXlen::Rv64, num, args)
+        };
+        let ret = SyscallResult::Return;
+
+        assert_eq!(call(SYS_BRK, [0; 6]), ret(0x0001_3000));
+        assert_eq!(call(SYS_BRK, [0x0002_0000, 0, 0, 0, 0, 0]),
+            ret(0x0002_0000));
+        // The heap can't grow into the stack
+        assert_eq!(call(SYS_BRK, [MMAP_BASE, 0, 0, 0, 0, 0]),
+            ret(0x0002_0000));
+        assert_eq!(call(SYS_MMAP, [0, 0x1800, 3, 0x22, u64::MAX, 0]),
+            ret(MMAP_BASE as i64));
+        assert_eq!(call(SYS_MMAP, [0, 0x10, 3, 0x22, u64::MAX, 0]),
+            ret(MMAP_BASE as i64 + 0x2000));
+        assert_eq!(call(SYS_MMAP, [0, 0x0100_0000, 3, 0x22, u64::MAX, 0]),
+            ret(-ENOMEM));
+        // Only anonymous mappings are supported
+        assert_eq!(call(SYS_MMAP, [0, 0x1000, 3, 0x02, 3, 0]), ret(-EINVAL));
+        assert_eq!(call(SYS_UNAME, [0x1000, 0, 0, 0, 0, 0]), ret(0));
+        assert_eq!(&ram.data[0x1000 + 4 * 65..0x1000 + 4 * 65 + 8],
+            b"riscv64\0");
+    }
+}
@@ -11,7 +11,9 @@ mod uarch_simple {
     use std::cell::RefCell;
     use crate::mem::*;
     use crate::uarch::common::*;
-    use crate::uarch::simple::SingleCycleMachine;
+    use crate::uarch::simple::*;
+    use crate::rv32::*;
+    use ans::isa::RvIsa;
 
     const INITIAL_REGS: [u32; 8] = [ 0, 1, 2, 3, 4, 5, 6, 7 ];
 
@@ -22,7 +24,7 @@ mod uarch_simple {
                 RegisterFile::new(8, Some(&INITIAL_REGS))
         ));
         let mut p = SingleCycleMachine::new(
-            rf.clone(), dmem.clone()
+            rf.clone(), dmem.clone(), RvIsa::parse("rv32im_zba_zbb_zbs").unwrap()
         );
 
         for _cycle in 0..8 {
@@ -31,6 +33,39 @@ mod uarch_simple {
             println!("");
         }
     }
+
+    #[test]
+    fn decode_isa() {
+        let rf = Rc::new(RefCell::new(
+                RegisterFile::new(8, Some(&INITIAL_REGS))
+        ));
+        let mut id = DecodeStage::new(rf, RvIsa::parse("rv32i_zba").unwrap());
+
+        let add = RvInstr::Op(RvReg(1), RvReg(2), RvReg(3), RvALUOp::Add);
+        assert!(matches!(id.execute(add).op, Op::Alu(1, 2, 3, ALUOp::Add)));
+        let sh1add = RvInstr::Op(RvReg(1), RvReg(2), RvReg(3), RvALUOp::Sh1add);
+        assert!(matches!(id.execute(sh1add).op, Op::Alu(..)));
+
+        // Instructions from the M and Zbb extensions are illegal
+        let mul = RvInstr::Op(RvReg(1), RvReg(2), RvReg(3), RvALUOp::Mul);
+        assert!(matches!(id.execute(mul).op, Op::Illegal));
+        let clz = RvInstr::OpImm(RvReg(1), RvReg(2), 0, RvALUOp::Clz);
+        assert!(matches!(id.execute(clz).op, Op::Illegal));
+    }
+
+    #[test]
+    fn decode_rv32e() {
+        let rf = Rc::new(RefCell::new(RegisterFile::new(16, None)));
+        let mut id = DecodeStage::new(rf, RvIsa::parse("rv32e").unwrap());
+
+        let add = RvInstr::Op(RvReg(15), RvReg(2), RvReg(3), RvALUOp::Add);
+        assert!(matches!(id.execute(add).op, Op::Alu(15, 0, 0, ALUOp::Add)));
+        // x16 doesn't exist (and the register file isn't accessed)
+        let add = RvInstr::Op(RvReg(1), RvReg(2), RvReg(16), RvALUOp::Add);
+        assert!(matches!(id.execute(add).op, Op::Illegal));
+        let lw = RvInstr::Load(RvReg(31), RvReg(0), 0, RvWidth::Word);
+        assert!(matches!(id.execute(lw).op, Op::Illegal));
+    }
 }
 
 
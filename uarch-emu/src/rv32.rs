@@ -1,115 +1,10 @@
 
 use crate::uarch::common;
+use ans::isa::RvExt;
 
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
 
-#[repr(usize)]
-pub enum RvOpcode {
-    LOAD       = 0b00000, // [lb, lh, lw, lbu, lhu]
-    LOAD_FP    = 0b00001,
-    CUSTOM_0   = 0b00010,
-    MISC_MEM   = 0b00011, // [fence, fence.i]
-    OP_IMM     = 0b00100, // [addi, slti, sltiu, xori, ori, andi]
-    AUIPC      = 0b00101, 
-    OP_IMM_32  = 0b00110,
-    STORE      = 0b01000, // [sb, sh, sw]
-    STORE_FP   = 0b01001,
-    CUSTOM_1   = 0b01010,
-    AMO        = 0b01011,
-    OP         = 0b01100, // [add, sub, sll, slt, sltu, xor, srl, sra, or, and]
-    LUI        = 0b01101,
-    OP_32      = 0b01110,
-    MADD       = 0b10000,
-    MSUB       = 0b10001,
-    NMSUB      = 0b10010,
-    NMADD      = 0b10011,
-    OP_FP      = 0b10100,
-    RES_0      = 0b10101,
-    CUSTOM_2   = 0b10110,
-    BRANCH     = 0b11000, // [beq, bne, blt, bge, bltu, bgeu]
-    JALR       = 0b11001,
-    RES_1      = 0b11010,
-    JAL        = 0b11011,
-    SYSTEM     = 0b11100,
-    RES_2      = 0b11101,
-    CUSTOM_3   = 0b11110,
-}
-impl From<u32> for RvOpcode {
-    fn from(x: u32) -> Self {
-        match x {
-         0b00000 => Self::LOAD,
-         0b00001 => Self::LOAD_FP,
-         0b00010 => Self::CUSTOM_0,
-         0b00011 => Self::MISC_MEM,
-         0b00100 => Self::OP_IMM,
-         0b00101 => Self::AUIPC,
-         0b00110 => Self::OP_IMM_32,
-         0b01000 => Self::STORE,
-         0b01001 => Self::STORE_FP,
-         0b01010 => Self::CUSTOM_1,
-         0b01011 => Self::AMO,
-         0b01100 => Self::OP,
-         0b01101 => Self::LUI,
-         0b01110 => Self::OP_32,
-         0b10000 => Self::MADD,
-         0b10001 => Self::MSUB,
-         0b10010 => Self::NMSUB,
-         0b10011 => Self::NMADD,
-         0b10100 => Self::OP_FP,
-         0b10101 => Self::RES_0,
-         0b10110 => Self::CUSTOM_2,
-         0b11000 => Self::BRANCH,
-         0b11001 => Self::JALR,
-         0b11010 => Self::RES_1,
-         0b11011 => Self::JAL,
-         0b11100 => Self::SYSTEM,
-         0b11101 => Self::RES_2,
-         0b11110 => Self::CUSTOM_3,
-         _ => unimplemented!(),
-        }
-    }
-}
-
-
-#[repr(transparent)]
-struct RvEncoding(pub u32);
-impl RvEncoding {
-    fn opcode(&self) -> RvOpcode {
-        RvOpcode::from(
-            (self.0 & 0b0000_000_00000_00000_000_00000_11111_00) >> 2
-        )
-    }
-    fn rd(&self) -> RvReg {
-        RvReg(
-            ((self.0 & 0b0000_000_00000_00000_000_11111_00000_00) >> 7)
-            as usize
-        )
-    }
-    fn f3(&self) -> usize {
-        ((self.0 & 0b0000_000_00000_00000_111_00000_00000_00) >> 12)
-            as usize
-    }
-    fn rs1(&self) -> RvReg {
-        RvReg(
-            ((self.0 & 0b0000_000_00000_11111_000_00000_00000_00) >> 15)
-            as usize
-        )
-    }
-    fn rs2(&self) -> RvReg {
-        RvReg(
-            ((self.0 & 0b0000_000_11111_00000_000_00000_00000_00) >> 20)
-            as usize
-        )
-    }
-    fn f7(&self) -> usize {
-        ((self.0 & 0b1111_111_00000_00000_000_00000_00000_00) >> 25)
-            as usize
-    }
-}
-
-
-
 #[derive(Debug, Clone, Copy)]
 pub enum RvWidth {
     Byte,
@@ -138,6 +33,38 @@ pub enum RvALUOp {
     Sra,
     Or,
     And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+    Sh1add,
+    Sh2add,
+    Sh3add,
+    Andn,
+    Orn,
+    Xnor,
+    Clz,
+    Ctz,
+    Cpop,
+    Max,
+    Maxu,
+    Min,
+    Minu,
+    SextB,
+    SextH,
+    ZextH,
+    Rol,
+    Ror,
+    OrcB,
+    Rev8,
+    Bclr,
+    Bext,
+    Binv,
+    Bset,
 }
 impl common::FrontendALUOp for RvALUOp {
     fn to_alu_op(&self) -> common::ALUOp {
@@ -152,6 +79,38 @@ impl common::FrontendALUOp for RvALUOp {
             Self::Sll => common::ALUOp::Sll,
             Self::Srl => common::ALUOp::Srl,
             Self::Sra => common::ALUOp::Sra,
+            Self::Mul => common::ALUOp::Mul,
+            Self::Mulh => common::ALUOp::MulHighSigned,
+            Self::Mulhsu => common::ALUOp::MulHighSignedUnsigned,
+            Self::Mulhu => common::ALUOp::MulHighUnsigned,
+            Self::Div => common::ALUOp::DivSigned,
+            Self::Divu => common::ALUOp::DivUnsigned,
+            Self::Rem => common::ALUOp::RemSigned,
+            Self::Remu => common::ALUOp::RemUnsigned,
+            Self::Sh1add => common::ALUOp::Sh1Add,
+            Self::Sh2add => common::ALUOp::Sh2Add,
+            Self::Sh3add => common::ALUOp::Sh3Add,
+            Self::Andn => common::ALUOp::AndNot,
+            Self::Orn => common::ALUOp::OrNot,
+            Self::Xnor => common::ALUOp::XorNot,
+            Self::Clz => common::ALUOp::CountLeadingZeros,
+            Self::Ctz => common::ALUOp::CountTrailingZeros,
+            Self::Cpop => common::ALUOp::PopCount,
+            Self::Max => common::ALUOp::MaxSigned,
+            Self::Maxu => common::ALUOp::MaxUnsigned,
+            Self::Min => common::ALUOp::MinSigned,
+            Self::Minu => common::ALUOp::MinUnsigned,
+            Self::SextB => common::ALUOp::SignExtendByte,
+            Self::SextH => common::ALUOp::SignExtendHalf,
+            Self::ZextH => common::ALUOp::ZeroExtendHalf,
+            Self::Rol => common::ALUOp::Rol,
+            Self::Ror => common::ALUOp::Ror,
+            Self::OrcB => common::ALUOp::OrCombineBytes,
+            Self::Rev8 => common::ALUOp::ByteSwap,
+            Self::Bclr => common::ALUOp::BitClear,
+            Self::Bext => common::ALUOp::BitExtract,
+            Self::Binv => common::ALUOp::BitInvert,
+            Self::Bset => common::ALUOp::BitSet,
         }
     }
 }
@@ -175,6 +134,154 @@ pub enum RvInstr {
     Store(RvReg, RvReg, i32, RvWidth),
 }
 
+/// ABI names of the integer registers.
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+impl RvALUOp {
+    /// Returns the mnemonic for the register-register form of this operation.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Add    => "add",
+            Self::Sub    => "sub",
+            Self::Sll    => "sll",
+            Self::Slt    => "slt",
+            Self::Sltu   => "sltu",
+            Self::Xor    => "xor",
+            Self::Srl    => "srl",
+            Self::Sra    => "sra",
+            Self::Or     => "or",
+            Self::And    => "and",
+            Self::Mul    => "mul",
+            Self::Mulh   => "mulh",
+            Self::Mulhsu => "mulhsu",
+            Self::Mulhu  => "mulhu",
+            Self::Div    => "div",
+            Self::Divu   => "divu",
+            Self::Rem    => "rem",
+            Self::Remu   => "remu",
+            Self::Sh1add => "sh1add",
+            Self::Sh2add => "sh2add",
+            Self::Sh3add => "sh3add",
+            Self::Andn   => "andn",
+            Self::Orn    => "orn",
+            Self::Xnor   => "xnor",
+            Self::Clz    => "clz",
+            Self::Ctz    => "ctz",
+            Self::Cpop   => "cpop",
+            Self::Max    => "max",
+            Self::Maxu   => "maxu",
+            Self::Min    => "min",
+            Self::Minu   => "minu",
+            Self::SextB  => "sext.b",
+            Self::SextH  => "sext.h",
+            Self::ZextH  => "zext.h",
+            Self::Rol    => "rol",
+            Self::Ror    => "ror",
+            Self::OrcB   => "orc.b",
+            Self::Rev8   => "rev8",
+            Self::Bclr   => "bclr",
+            Self::Bext   => "bext",
+            Self::Binv   => "binv",
+            Self::Bset   => "bset",
+        }
+    }
+    /// Returns true for operations that only have a single operand.
+    pub fn is_unary(&self) -> bool {
+        matches!(self, Self::Clz | Self::Ctz | Self::Cpop | Self::SextB 
+            | Self::SextH | Self::ZextH | Self::OrcB | Self::Rev8)
+    }
+    /// Returns the extension which defines this operation.
+    pub fn ext(&self) -> RvExt {
+        match self {
+            Self::Mul | Self::Mulh | Self::Mulhsu | Self::Mulhu
+            | Self::Div | Self::Divu | Self::Rem | Self::Remu => RvExt::M,
+            Self::Sh1add | Self::Sh2add | Self::Sh3add => RvExt::Zba,
+            Self::Bclr | Self::Bext | Self::Binv | Self::Bset => RvExt::Zbs,
+            Self::Andn | Self::Orn | Self::Xnor | Self::Clz | Self::Ctz
+            | Self::Cpop | Self::Max | Self::Maxu | Self::Min | Self::Minu
+            | Self::SextB | Self::SextH | Self::ZextH | Self::Rol | Self::Ror
+            | Self::OrcB | Self::Rev8 => RvExt::Zbb,
+            _ => RvExt::I,
+        }
+    }
+}
+
+impl RvInstr {
+    /// Returns the registers named by this instruction.
+    pub fn regs(&self) -> [usize; 3] {
+        match self {
+            Self::Op(rd, rs1, rs2, _) => [rd.0, rs1.0, rs2.0],
+            Self::OpImm(rd, rs1, ..) => [rd.0, rs1.0, 0],
+            Self::Load(rd, rs1, ..) => [rd.0, rs1.0, 0],
+            Self::Store(rs1, rs2, ..) => [rs1.0, rs2.0, 0],
+            Self::Lui(rd, _) => [rd.0, 0, 0],
+        }
+    }
+    /// Returns the extension which defines this instruction.
+    pub fn ext(&self) -> RvExt {
+        match self {
+            Self::Op(.., op) | Self::OpImm(.., op) => op.ext(),
+            _ => RvExt::I,
+        }
+    }
+}
+
+impl std::fmt::Display for RvInstr {
+    /// Format an instruction in GNU assembler syntax.
+    ///
+    /// Registers are printed with their ABI names when the alternate flag 
+    /// is used (`{:#}`), and `nop`, `li` and `mv` are recognized.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let x = |r: &RvReg| if f.alternate() {
+            ABI_NAMES[r.0].to_string()
+        } else {
+            format!("x{}", r.0)
+        };
+        match self {
+            RvInstr::OpImm(RvReg(0), RvReg(0), 0, RvALUOp::Add) => 
+                write!(f, "nop"),
+            RvInstr::OpImm(rd, RvReg(0), imm, RvALUOp::Add) => 
+                write!(f, "li {}, {}", x(rd), imm),
+            RvInstr::OpImm(rd, rs1, 0, RvALUOp::Add) => 
+                write!(f, "mv {}, {}", x(rd), x(rs1)),
+            RvInstr::Op(rd, rs1, _, op) | RvInstr::OpImm(rd, rs1, _, op) 
+                if op.is_unary() => 
+                write!(f, "{} {}, {}", op.name(), x(rd), x(rs1)),
+            RvInstr::Op(rd, rs1, rs2, op) => 
+                write!(f, "{} {}, {}, {}", op.name(), x(rd), x(rs1), x(rs2)),
+            RvInstr::OpImm(rd, rs1, imm, op) => {
+                let name = match op {
+                    RvALUOp::Sltu => "sltiu".to_string(),
+                    _ => format!("{}i", op.name()),
+                };
+                write!(f, "{} {}, {}, {}", name, x(rd), x(rs1), imm)
+            },
+            RvInstr::Lui(rd, imm) => write!(f, "lui {}, {:#x}", x(rd), imm),
+            RvInstr::Load(rd, rs1, imm, w) => {
+                let name = match w {
+                    RvWidth::Byte => "lb",
+                    RvWidth::Half => "lh",
+                    RvWidth::Word => "lw",
+                };
+                write!(f, "{} {}, {}({})", name, x(rd), imm, x(rs1))
+            },
+            RvInstr::Store(rs1, rs2, imm, w) => {
+                let name = match w {
+                    RvWidth::Byte => "sb",
+                    RvWidth::Half => "sh",
+                    RvWidth::Word => "sw",
+                };
+                write!(f, "{} {}, {}({})", name, x(rs2), imm, x(rs1))
+            },
+        }
+    }
+}
+
 impl Distribution<RvWidth> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> RvWidth {
         match rng.gen_range(0..=2) {
@@ -187,7 +294,7 @@ impl Distribution<RvWidth> for Standard {
 
 impl Distribution<RvALUOp> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> RvALUOp {
-        match rng.gen_range(0..=9) {
+        match rng.gen_range(0..=41) {
             0 => RvALUOp::Add,
             1 => RvALUOp::Sub,
             2 => RvALUOp::Sll,
@@ -197,7 +304,39 @@ impl Distribution<RvALUOp> for Standard {
             6 => RvALUOp::Srl,
             7 => RvALUOp::Sra,
             8 => RvALUOp::Or,
-            _ => RvALUOp::And,
+            9 => RvALUOp::And,
+            10 => RvALUOp::Mul,
+            11 => RvALUOp::Mulh,
+            12 => RvALUOp::Mulhsu,
+            13 => RvALUOp::Mulhu,
+            14 => RvALUOp::Div,
+            15 => RvALUOp::Divu,
+            16 => RvALUOp::Rem,
+            17 => RvALUOp::Remu,
+            18 => RvALUOp::Sh1add,
+            19 => RvALUOp::Sh2add,
+            20 => RvALUOp::Sh3add,
+            21 => RvALUOp::Andn,
+            22 => RvALUOp::Orn,
+            23 => RvALUOp::Xnor,
+            24 => RvALUOp::Clz,
+            25 => RvALUOp::Ctz,
+            26 => RvALUOp::Cpop,
+            27 => RvALUOp::Max,
+            28 => RvALUOp::Maxu,
+            29 => RvALUOp::Min,
+            30 => RvALUOp::Minu,
+            31 => RvALUOp::SextB,
+            32 => RvALUOp::SextH,
+            33 => RvALUOp::ZextH,
+            34 => RvALUOp::Rol,
+            35 => RvALUOp::Ror,
+            36 => RvALUOp::OrcB,
+            37 => RvALUOp::Rev8,
+            38 => RvALUOp::Bclr,
+            39 => RvALUOp::Bext,
+            40 => RvALUOp::Binv,
+            _ => RvALUOp::Bset,
         }
     }
 }
@@ -212,15 +351,32 @@ impl Distribution<RvInstr> for Standard {
 
         match rng.gen_range(0..4) {
             0 => {
+                // There are no immediate forms of these operations
+                if matches!(op, RvALUOp::Sub
+                    | RvALUOp::Mul | RvALUOp::Mulh 
+                    | RvALUOp::Mulhsu | RvALUOp::Mulhu
+                    | RvALUOp::Div | RvALUOp::Divu 
+                    | RvALUOp::Rem | RvALUOp::Remu
+                    | RvALUOp::Sh1add | RvALUOp::Sh2add | RvALUOp::Sh3add
+                    | RvALUOp::Andn | RvALUOp::Orn | RvALUOp::Xnor
+                    | RvALUOp::Max | RvALUOp::Maxu 
+                    | RvALUOp::Min | RvALUOp::Minu
+                    | RvALUOp::ZextH | RvALUOp::Rol) {
+                    op = RvALUOp::Add;
+                }
                 let imm = match op {
                     RvALUOp::Sll 
                     | RvALUOp::Srl 
-                    | RvALUOp::Sra => rng.gen_range(0..32),
+                    | RvALUOp::Sra
+                    | RvALUOp::Ror
+                    | RvALUOp::Bclr | RvALUOp::Bext 
+                    | RvALUOp::Binv | RvALUOp::Bset => rng.gen_range(0..32),
+                    // Unary operations have no immediate operand
+                    RvALUOp::Clz | RvALUOp::Ctz | RvALUOp::Cpop
+                    | RvALUOp::SextB | RvALUOp::SextH 
+                    | RvALUOp::OrcB | RvALUOp::Rev8 => 0,
                     _ => rng.gen_range(-0xfff..=0xfff),
                 };
-                if matches!(op, RvALUOp::Sub) {
-                    op = RvALUOp::Add;
-                }
                 RvInstr::OpImm(rd, rs1, imm, op)
             }
             1 => RvInstr::Op(rd, rs1, rs2, op),
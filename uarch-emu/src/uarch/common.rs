@@ -20,6 +20,9 @@ pub trait SimplePipelineStage {
 
 
 /// Architectural register file.
+///
+/// The decode stage never names a register which doesn't exist in the ISA,
+/// so a 16-entry register file is enough for RV32E.
 #[derive(Debug)]
 pub struct RegisterFile {
     data: Vec<u32>,
@@ -74,6 +77,8 @@ pub enum Op {
     Load(usize, u32, i32, Width),
     /// Store operation (value, addr, offset, width)
     Store(u32, u32, i32, Width),
+    /// Instruction which isn't implemented by the machine
+    Illegal,
 }
 
 /// Token for an effect on the state of the machine.
@@ -87,6 +92,8 @@ pub enum Effect {
     MemLoad(usize, u32, Width),
     /// Store a value to memory (value, addr, width).
     MemStore(u32, u32, Width),
+    /// Illegal-instruction exception.
+    IllegalInstruction,
 }
 
 /// Arithmetic/logical operations supported by the emulated machine.
@@ -102,6 +109,38 @@ pub enum ALUOp {
     Sra,
     LtUnsigned,
     LtSigned,
+    Mul,
+    MulHighSigned,
+    MulHighSignedUnsigned,
+    MulHighUnsigned,
+    DivSigned,
+    DivUnsigned,
+    RemSigned,
+    RemUnsigned,
+    Sh1Add,
+    Sh2Add,
+    Sh3Add,
+    AndNot,
+    OrNot,
+    XorNot,
+    CountLeadingZeros,
+    CountTrailingZeros,
+    PopCount,
+    MaxSigned,
+    MaxUnsigned,
+    MinSigned,
+    MinUnsigned,
+    SignExtendByte,
+    SignExtendHalf,
+    ZeroExtendHalf,
+    Rol,
+    Ror,
+    OrCombineBytes,
+    ByteSwap,
+    BitClear,
+    BitExtract,
+    BitInvert,
+    BitSet,
 }
 
 /// Widths of memory access operations supported by the machine.
@@ -5,6 +5,7 @@ use std::ops::{BitAnd, BitOr, BitXor};
 use crate::rv32::*;
 use crate::uarch::common::*;
 use crate::mem::*;
+use ans::isa::RvIsa;
 
 pub struct SingleCycleMachine {
     pub cyc: usize,
@@ -17,12 +18,12 @@ pub struct SingleCycleMachine {
 }
 impl SingleCycleMachine {
     pub fn new(rf: PipelineResource<RegisterFile>,
-               dmem: PipelineResource<DataMemory>) -> Self {
+               dmem: PipelineResource<DataMemory>, isa: RvIsa) -> Self {
         Self {
             cyc: 0,
             pc: 0,
             if_stage: FetchStage::new(),
-            id_stage: DecodeStage::new(rf.clone()),
+            id_stage: DecodeStage::new(rf.clone(), isa),
             ex_stage: ExecutionStage::new(),
             me_stage: MemoryStage::new(dmem.clone()),
             wb_stage: WritebackStage::new(rf.clone()),
@@ -33,7 +34,7 @@ impl SingleCycleMachine {
         println!("[IF] {:08x?}", self.pc);
         let f = self.if_stage.execute(self.pc);
 
-        println!("[ID] {:x?}", &f);
+        println!("[ID] {:#}", &f);
         let d = self.id_stage.execute(f);
 
         println!("[EX] {:x?}", &d);
@@ -72,10 +73,12 @@ pub struct DecodeStage {
     stall: bool,
     next_id: usize,
     rf: PipelineResource<RegisterFile>,
+    /// Instructions from extensions outside of the ISA are illegal.
+    isa: RvIsa,
 }
 impl DecodeStage {
-    pub fn new(rf: PipelineResource<RegisterFile>) -> Self { 
-        Self { stall: false, next_id: 0, rf } 
+    pub fn new(rf: PipelineResource<RegisterFile>, isa: RvIsa) -> Self { 
+        Self { stall: false, next_id: 0, rf, isa } 
     }
 }
 impl SimplePipelineStage for DecodeStage {
@@ -84,6 +87,9 @@ impl SimplePipelineStage for DecodeStage {
     fn execute(&mut self, i: Self::In) -> Self::Out {
         let rf = self.rf.borrow();
         let op = match i {
+            _ if !self.isa.supports(i.ext()) => Op::Illegal,
+            // Only x0-x15 exist on RV32E
+            _ if i.regs().iter().any(|r| *r >= self.isa.regs()) => Op::Illegal,
             RvInstr::Op(rd, rs1, rs2, op) => 
                 Op::Alu(rd.0, rf[rs1.0], rf[rs2.0], op.to_alu_op()),
             RvInstr::OpImm(rd, rs1, imm, op) => 
@@ -94,7 +100,8 @@ impl SimplePipelineStage for DecodeStage {
                 Op::Load(rd.0, rf[rs1.0], imm, w.to_width()),
             RvInstr::Store(rs1, rs2, imm, w) => 
                 Op::Store(rf[rs2.0], rf[rs1.0], imm, w.to_width()),
-            _ => unimplemented!(),
+            // Any other instruction isn't implemented by the machine
+            _ => Op::Illegal,
         };
 
         let id = self.next_id;
@@ -133,6 +140,65 @@ impl SimplePipelineStage for ExecutionStage {
                     ALUOp::Xor => x.bitxor(y),
                     ALUOp::Or => x.bitor(y),
                     ALUOp::And => x.bitand(y),
+                    ALUOp::Mul => x.wrapping_mul(y),
+                    ALUOp::MulHighSigned => {
+                        ((x as i32 as i64 * y as i32 as i64) >> 32) as u32
+                    }
+                    ALUOp::MulHighSignedUnsigned => {
+                        ((x as i32 as i64).wrapping_mul(y as i64) >> 32) 
+                            as u32
+                    }
+                    ALUOp::MulHighUnsigned => {
+                        ((x as u64 * y as u64) >> 32) as u32
+                    }
+                    ALUOp::DivSigned => match (x as i32, y as i32) {
+                        (_, 0) => 0xffff_ffff,
+                        (x, y) => x.wrapping_div(y) as u32,
+                    }
+                    ALUOp::DivUnsigned => match y {
+                        0 => 0xffff_ffff,
+                        y => x / y,
+                    }
+                    ALUOp::RemSigned => match (x as i32, y as i32) {
+                        (x, 0) => x as u32,
+                        (x, y) => x.wrapping_rem(y) as u32,
+                    }
+                    ALUOp::RemUnsigned => match y {
+                        0 => x,
+                        y => x % y,
+                    }
+                    ALUOp::Sh1Add => (x << 1).wrapping_add(y),
+                    ALUOp::Sh2Add => (x << 2).wrapping_add(y),
+                    ALUOp::Sh3Add => (x << 3).wrapping_add(y),
+                    ALUOp::AndNot => x.bitand(!y),
+                    ALUOp::OrNot => x.bitor(!y),
+                    ALUOp::XorNot => !x.bitxor(y),
+                    ALUOp::CountLeadingZeros => x.leading_zeros(),
+                    ALUOp::CountTrailingZeros => x.trailing_zeros(),
+                    ALUOp::PopCount => x.count_ones(),
+                    ALUOp::MaxSigned => (x as i32).max(y as i32) as u32,
+                    ALUOp::MaxUnsigned => x.max(y),
+                    ALUOp::MinSigned => (x as i32).min(y as i32) as u32,
+                    ALUOp::MinUnsigned => x.min(y),
+                    ALUOp::SignExtendByte => x as i8 as i32 as u32,
+                    ALUOp::SignExtendHalf => x as i16 as i32 as u32,
+                    ALUOp::ZeroExtendHalf => x & 0xffff,
+                    ALUOp::Rol => x.rotate_left(y & 0x1f),
+                    ALUOp::Ror => x.rotate_right(y & 0x1f),
+                    ALUOp::OrCombineBytes => {
+                        let mut res = 0;
+                        for i in 0..4 {
+                            if (x >> (i * 8)) & 0xff != 0 {
+                                res |= 0xff << (i * 8);
+                            }
+                        }
+                        res
+                    }
+                    ALUOp::ByteSwap => x.swap_bytes(),
+                    ALUOp::BitClear => x.bitand(!(1 << (y & 0x1f))),
+                    ALUOp::BitExtract => (x >> (y & 0x1f)) & 1,
+                    ALUOp::BitInvert => x.bitxor(1 << (y & 0x1f)),
+                    ALUOp::BitSet => x.bitor(1 << (y & 0x1f)),
                 };
                 Effect::RegWrite(rd, res)
             }
@@ -144,7 +210,7 @@ impl SimplePipelineStage for ExecutionStage {
                 let addr = base.wrapping_add(off as u32);
                 Effect::MemStore(val, addr, w)
             }
-            _ => unimplemented!(),
+            Op::Illegal => Effect::IllegalInstruction,
         }
     }
     fn stall(&mut self) { self.stall = true }
@@ -216,7 +282,10 @@ impl SimplePipelineStage for WritebackStage {
                 rf[rd] = val;
             }
             Effect::None => {}
-            _ => unimplemented!("{:?}", i),
+            // There are no traps, so the instruction has no effect
+            Effect::IllegalInstruction => {}
+            // Memory accesses are performed by the memory stage
+            Effect::MemLoad(..) | Effect::MemStore(..) => {}
         }
     }
     fn stall(&mut self) { self.stall = true }